@@ -1,12 +1,69 @@
 pub mod trap_handler;
 
+use crate::kconfig;
+use core::arch::asm;
 use riscv::sstatus::FS;
-use riscv::{interrupt, sie, sstatus};
+use riscv::{interrupt, sbi, sie, sstatus, time};
 
 pub fn finish_processor_init() {
     unsafe {
         interrupt::enable();
         sie::set_stie();
+        sie::set_ssie();
         sstatus::set_fs(FS::Initial);
+        // only takes effect if the hart implements the vector extension, see
+        // `has_vector_extension`
+        sstatus::set_vs(FS::Initial);
+        // the kernel needs to access guest memory, e.g. when instantiating modules
+        sstatus::set_sum();
+    }
+
+    arm_timer();
+}
+
+/// Returns whether the current hart implements the vector extension.
+///
+/// `sstatus.VS` is hardwired to zero on harts without the vector extension, so this only gives
+/// the right answer after `finish_processor_init` tried to enable the vector unit.
+pub fn has_vector_extension() -> bool {
+    sstatus::read().vs() != FS::Off
+}
+
+/// Returns the current value of the timebase counter.
+pub fn current_ticks() -> u64 {
+    time::read().as_bits() as u64
+}
+
+/// Arms the timer to fire after the next epoch interval.
+pub fn arm_timer() {
+    sbi::time::set_timer(current_ticks() + kconfig::EPOCH_INTERVAL_TICKS).unwrap();
+}
+
+/// Wakes all harts suspended in [`wait_for_interrupt`] by sending them a software interrupt.
+pub fn wake_all_harts() {
+    sbi::ipi::send_ipi(0, usize::MAX).unwrap();
+}
+
+/// Suspends the current hart until the next interrupt, waking up no later than `deadline` (in
+/// timebase ticks).
+///
+/// A deadline before the next epoch interval makes the timer interrupt fire early, the interrupt
+/// handler then re-arms it for the regular interval.
+pub fn wait_for_interrupt(deadline: Option<u64>) {
+    let next_epoch = current_ticks() + kconfig::EPOCH_INTERVAL_TICKS;
+    let wakeup = deadline.map_or(next_epoch, |deadline| deadline.min(next_epoch));
+    sbi::time::set_timer(wakeup).unwrap();
+
+    // Pending interrupts wake the hart up even while they are disabled, so waiting with
+    // interrupts disabled doesn't miss one arriving right before the `wfi`. It is taken once
+    // interrupts are enabled again.
+    let sie = sstatus::read().sie();
+    interrupt::disable();
+    unsafe {
+        asm!("wfi");
+        interrupt::enable();
+    }
+    if !sie {
+        interrupt::disable();
     }
 }
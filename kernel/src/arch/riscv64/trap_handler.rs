@@ -1,7 +1,9 @@
-use crate::kconfig;
+use crate::{kconfig, runtime};
 use core::arch::asm;
-use riscv::scause::{Exception, Trap};
-use riscv::{scause, sepc, sstatus, stval, stvec};
+use kmm::VirtualAddress;
+use riscv::scause::{Exception, Interrupt, Trap};
+use riscv::sstatus::SPP;
+use riscv::{scause, sepc, sip, sstatus, stval, stvec};
 use thread_local::declare_thread_local;
 
 declare_thread_local! {
@@ -15,12 +17,20 @@ pub fn init() {
             .byte_add(kconfig::TRAP_STACK_SIZE_PAGES * kconfig::PAGE_SIZE) as *mut u8
     };
 
-    log::debug!("setting sscratch to {:p}", trap_stack_top);
+    // User mode runs with a cleared `tp`, so the trap handler reloads the kernel's thread pointer
+    // from the topmost slot of the trap stack
+    let trap_frame = unsafe {
+        let slot = trap_stack_top.sub(16);
+        slot.cast::<usize>().write(thread_pointer());
+        slot
+    };
+
+    log::debug!("setting sscratch to {:p}", trap_frame);
 
     unsafe {
         asm!(
         "csrrw x0, sscratch, {trap_frame}", // sscratch points to the trap frame
-        trap_frame = in(reg) trap_stack_top
+        trap_frame = in(reg) trap_frame
         );
     }
 
@@ -28,6 +38,15 @@ pub fn init() {
     unsafe { stvec::write(trap_vec as usize, stvec::Mode::Vectored) };
 }
 
+/// Index of `tp` in [`TrapFrame::gp`].
+const REG_TP: usize = 4;
+
+fn thread_pointer() -> usize {
+    let tp: usize;
+    unsafe { asm!("mv {}, tp", out(reg) tp) };
+    tp
+}
+
 #[repr(C)]
 #[derive(Clone, Default)]
 pub struct TrapFrame {
@@ -70,19 +89,27 @@ unsafe extern "C" fn default_trap_entry() {
     asm! {
     ".align 2",
 
-    "mv t0, sp", // save the correct stack pointer
-    "csrrw sp, sscratch, sp", // t6 points to the TrapFrame
+    "csrrw sp, sscratch, sp", // sp points to the trap stack, sscratch holds the interrupted sp
     "add sp, sp, -0x210",
 
     // save gp
     "
-        sd x0, 0x00(sp)
         sd ra, 0x08(sp)
-        sd t0, 0x10(sp)
         sd gp, 0x18(sp)
         sd tp, 0x20(sp)
+        sd t0, 0x28(sp)
+        sd t1, 0x30(sp)
+        sd t2, 0x38(sp)
         sd s0, 0x40(sp)
         sd s1, 0x48(sp)
+        sd a0, 0x50(sp)
+        sd a1, 0x58(sp)
+        sd a2, 0x60(sp)
+        sd a3, 0x68(sp)
+        sd a4, 0x70(sp)
+        sd a5, 0x78(sp)
+        sd a6, 0x80(sp)
+        sd a7, 0x88(sp)
         sd s2, 0x90(sp)
         sd s3, 0x98(sp)
         sd s4, 0xA0(sp)
@@ -93,12 +120,39 @@ unsafe extern "C" fn default_trap_entry() {
         sd s9, 0xC8(sp)
         sd s10, 0xD0(sp)
         sd s11, 0xD8(sp)
+        sd t3, 0xE0(sp)
+        sd t4, 0xE8(sp)
+        sd t5, 0xF0(sp)
+        sd t6, 0xF8(sp)
         ",
 
+    // the kernel's thread pointer is stored right above the trap frame
+    "ld tp, 0x210(sp)",
+
+    // save the interrupted sp
+    "csrr t0, sscratch",
+    "sd t0, 0x10(sp)",
+
     // save fp
     "
+        fsd ft0, 0x100(sp)
+        fsd ft1, 0x108(sp)
+        fsd ft2, 0x110(sp)
+        fsd ft3, 0x118(sp)
+        fsd ft4, 0x120(sp)
+        fsd ft5, 0x128(sp)
+        fsd ft6, 0x130(sp)
+        fsd ft7, 0x138(sp)
         fsd fs0, 0x140(sp)
         fsd fs1, 0x148(sp)
+        fsd fa0, 0x150(sp)
+        fsd fa1, 0x158(sp)
+        fsd fa2, 0x160(sp)
+        fsd fa3, 0x168(sp)
+        fsd fa4, 0x170(sp)
+        fsd fa5, 0x178(sp)
+        fsd fa6, 0x180(sp)
+        fsd fa7, 0x188(sp)
         fsd fs2, 0x190(sp)
         fsd fs3, 0x198(sp)
         fsd fs4, 0x1A0(sp)
@@ -109,6 +163,10 @@ unsafe extern "C" fn default_trap_entry() {
         fsd fs9, 0x1C8(sp)
         fsd fs10, 0x1D0(sp)
         fsd fs11, 0x1D8(sp)
+        fsd ft8, 0x1E0(sp)
+        fsd ft9, 0x1E8(sp)
+        fsd ft10, 0x1F0(sp)
+        fsd ft11, 0x1F8(sp)
         ",
 
     "mv a0, sp",
@@ -117,73 +175,75 @@ unsafe extern "C" fn default_trap_entry() {
 
     "mv sp, a0",
 
-    // restore gp
-    "ld ra, 0x08(a0)",
-    // skip sp since it is saved in sscratch
-    "ld gp, 0x18(a0)
-        ld tp, 0x20(a0)
-        ld t0, 0x28(a0)
-        ld t1, 0x30(a0)
-        ld t2, 0x38(a0)
-        ld s0, 0x40(a0)
-        ld s1, 0x48(a0)
-        ld a1, 0x58(a0)
-        ld a2, 0x60(a0)
-        ld a3, 0x68(a0)
-        ld a4, 0x70(a0)
-        ld a5, 0x78(a0)
-        ld a6, 0x80(a0)
-        ld a7, 0x88(a0)
-        ld s2, 0x90(a0)
-        ld s3, 0x98(a0)
-        ld s4, 0xA0(a0)
-        ld s5, 0xA8(a0)
-        ld s6, 0xB0(a0)
-        ld s7, 0xB8(a0)
-        ld s8, 0xC0(a0)
-        ld s9, 0xC8(a0)
-        ld s10, 0xD0(a0)
-        ld s11, 0xD8(a0)
-        ld t3, 0xE0(a0)
-        ld t4, 0xE8(a0)
-        ld t5, 0xF0(a0)
-        ld t6, 0xF8(a0)
+    // restore fp
+    "
+        fld ft0, 0x100(sp)
+        fld ft1, 0x108(sp)
+        fld ft2, 0x110(sp)
+        fld ft3, 0x118(sp)
+        fld ft4, 0x120(sp)
+        fld ft5, 0x128(sp)
+        fld ft6, 0x130(sp)
+        fld ft7, 0x138(sp)
+        fld fs0, 0x140(sp)
+        fld fs1, 0x148(sp)
+        fld fa0, 0x150(sp)
+        fld fa1, 0x158(sp)
+        fld fa2, 0x160(sp)
+        fld fa3, 0x168(sp)
+        fld fa4, 0x170(sp)
+        fld fa5, 0x178(sp)
+        fld fa6, 0x180(sp)
+        fld fa7, 0x188(sp)
+        fld fs2, 0x190(sp)
+        fld fs3, 0x198(sp)
+        fld fs4, 0x1A0(sp)
+        fld fs5, 0x1A8(sp)
+        fld fs6, 0x1B0(sp)
+        fld fs7, 0x1B8(sp)
+        fld fs8, 0x1C0(sp)
+        fld fs9, 0x1C8(sp)
+        fld fs10, 0x1D0(sp)
+        fld fs11, 0x1D8(sp)
+        fld ft8, 0x1E0(sp)
+        fld ft9, 0x1E8(sp)
+        fld ft10, 0x1F0(sp)
+        fld ft11, 0x1F8(sp)
         ",
 
-    // restore fp
+    // restore gp
+    // skip sp since it is saved in sscratch
     "
-        fld ft0, 0x100(a0)
-        fld ft1, 0x108(a0)
-        fld ft2, 0x110(a0)
-        fld ft3, 0x118(a0)
-        fld ft4, 0x120(a0)
-        fld ft5, 0x128(a0)
-        fld ft6, 0x130(a0)
-        fld ft7, 0x138(a0)
-        fld fs0, 0x140(a0)
-        fld fs1, 0x148(a0)
-        fld fa0, 0x150(a0)
-        fld fa1, 0x158(a0)
-        fld fa2, 0x160(a0)
-        fld fa3, 0x168(a0)
-        fld fa4, 0x170(a0)
-        fld fa5, 0x178(a0)
-        fld fa6, 0x180(a0)
-        fld fa7, 0x188(a0)
-        fld fs2, 0x190(a0)
-        fld fs3, 0x198(a0)
-        fld fs4, 0x1A0(a0)
-        fld fs5, 0x1A8(a0)
-        fld fs6, 0x1B0(a0)
-        fld fs7, 0x1B8(a0)
-        fld fs8, 0x1C0(a0)
-        fld fs9, 0x1C8(a0)
-        fld fs10, 0x1D0(a0)
-        fld fs11, 0x1D8(a0)
-        fld ft8, 0x1E0(a0)
-        fld ft9, 0x1E8(a0)
-        fld ft10, 0x1F0(a0)
-        fld ft11, 0x1F8(a0)
+        ld ra, 0x08(sp)
+        ld gp, 0x18(sp)
+        ld tp, 0x20(sp)
+        ld t0, 0x28(sp)
+        ld t1, 0x30(sp)
+        ld t2, 0x38(sp)
+        ld s0, 0x40(sp)
+        ld s1, 0x48(sp)
+        ld a0, 0x50(sp)
+        ld a1, 0x58(sp)
+        ld a2, 0x60(sp)
+        ld a3, 0x68(sp)
+        ld a4, 0x70(sp)
+        ld a5, 0x78(sp)
+        ld a6, 0x80(sp)
+        ld a7, 0x88(sp)
+        ld s2, 0x90(sp)
+        ld s3, 0x98(sp)
+        ld s4, 0xA0(sp)
+        ld s5, 0xA8(sp)
+        ld s6, 0xB0(sp)
+        ld s7, 0xB8(sp)
+        ld s8, 0xC0(sp)
+        ld s9, 0xC8(sp)
+        ld s10, 0xD0(sp)
+        ld s11, 0xD8(sp)
+        ld t3, 0xE0(sp)
+        ld t4, 0xE8(sp)
+        ld t5, 0xF0(sp)
+        ld t6, 0xF8(sp)
         ",
 
     "add sp, sp, 0x210",
@@ -218,7 +278,62 @@ fn default_trap_handler(
     log::trace!("{:?}", sstatus::read());
     log::trace!("trap_handler cause {cause:?}, a1 {a1:#x} a2 {a2:#x} a3 {a3:#x} a4 {a4:#x} a5 {a5:#x} a6 {a6:#x} a7 {a7:#x}");
 
+    // Pages of guest memory are mapped on first access, this applies to accesses made by the
+    // guest as well as by the kernel on its behalf.
+    if let Trap::Exception(Exception::LoadPageFault | Exception::StorePageFault) = cause {
+        let tval = VirtualAddress::new(stval::read().as_bits());
+
+        if runtime::handle_page_fault(tval) {
+            // retry the faulting instruction
+            return raw_frame;
+        }
+    }
+
+    // Only WASM runs in user mode, the runtime takes care of all exceptions it raises. This is
+    // how the guest returns to the kernel, calls into it and reports traps. Any other exception
+    // becomes a trap of the running call, the kernel itself is unaffected.
+    if let Trap::Exception(exception) = cause {
+        if sstatus::read().spp() == SPP::User {
+            let epc = VirtualAddress::new(sepc::read().as_bits());
+            let tval = VirtualAddress::new(stval::read().as_bits());
+
+            // Whatever happens, we're not returning to the guest directly
+            unsafe { sstatus::set_spp(SPP::Supervisor) };
+
+            let frame = unsafe { &mut *raw_frame };
+            frame.gp[REG_TP] = thread_pointer();
+            if let Some(resume) =
+                runtime::handle_user_exception(&mut frame.gp, exception, epc, tval)
+            {
+                unsafe { sepc::write(resume) }
+            } else {
+                // user mode without an active call into WASM means the kernel lost track of it
+                log::error!(
+                    "USER EXCEPTION OUTSIDE OF WASM {exception:?}: epc {epc:?} tval {tval:?}"
+                );
+                unsafe { sepc::write(trap_panic_trampoline as usize) }
+            }
+
+            return raw_frame;
+        }
+    }
+
     match cause {
+        Trap::Exception(Exception::Breakpoint) => {
+            let epc = sepc::read().as_bits();
+            log::debug!("breakpoint at {epc:#x}");
+
+            // skip over the `ebreak` (or compressed `c.ebreak`) instruction and resume
+            let insn = unsafe { (epc as *const u16).read() };
+            let len = if insn & 0b11 == 0b11 { 4 } else { 2 };
+            unsafe { sepc::write(epc + len) }
+        }
+        Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            runtime::update_epoch();
+            super::arm_timer();
+        }
+        // software interrupts only wake harts from `wait_for_interrupt`, see `wake_all_harts`
+        Trap::Interrupt(Interrupt::SupervisorSoft) => unsafe { sip::clear_ssip() },
         Trap::Exception(Exception::LoadPageFault) => {
             let epc = sepc::read();
             let tval = stval::read();
@@ -4,6 +4,12 @@ pub const LOG_LEVEL: log::Level = log::Level::Trace;
 pub const STACK_SIZE_PAGES: u32 = 256;
 /// The size of the trap handler stack in pages
 pub const TRAP_STACK_SIZE_PAGES: usize = 16;
+/// The size of the stack WASM runs on in pages
+pub const GUEST_STACK_SIZE_PAGES: usize = 64;
+/// The frequency of the timebase counter in Hz (QEMU's virt machine runs it at 10MHz)
+pub const TIMEBASE_FREQUENCY: u64 = 10_000_000;
+/// The number of timebase ticks between increments of the WASM epoch (10ms at QEMU's 10MHz timebase)
+pub const EPOCH_INTERVAL_TICKS: u64 = 100_000;
 /// The size of the kernel heap in pages
 pub const HEAP_SIZE_PAGES: u32 = 8192; // 32 MiB
 #[allow(non_camel_case_types)]
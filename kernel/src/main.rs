@@ -5,34 +5,39 @@
 extern crate kernel as _;
 
 #[no_mangle]
-extern "Rust" fn kmain(_hartid: usize, boot_info: &'static loader_api::BootInfo) -> ! {
+extern "Rust" fn kmain(hartid: usize, _boot_info: &'static loader_api::BootInfo) -> ! {
     // Eventually this will all be hidden behind other abstractions (the scheduler, etc.) and this
     // function will just jump into the scheduling loop
 
-    use cranelift_codegen::settings::Configurable;
-    use kernel::runtime::{Engine, Linker, Module, Store};
+    // Until then all secondary harts just help out with compiling WASM
+    if hartid != 0 {
+        kernel::runtime::run_compile_worker();
+    }
 
-    let wasm = include_bytes!("../../tests/fib/fib_cpp.wasm");
-
-    let isa_builder = cranelift_codegen::isa::lookup(target_lexicon::HOST).unwrap();
-    let mut b = cranelift_codegen::settings::builder();
-    b.set("opt_level", "speed_and_size").unwrap();
+    use kernel::runtime::{Config, Engine, Linker, Module, Store};
 
-    let target_isa = isa_builder
-        .finish(cranelift_codegen::settings::Flags::new(b))
-        .unwrap();
+    let wasm = include_bytes!("../../tests/fib/fib_cpp.wasm");
 
-    let engine = Engine::new(target_isa);
+    let engine = Engine::new(&Config::default()).unwrap();
 
-    let mut store = Store::new(0, boot_info.physical_memory_offset);
+    let mut store = Store::new(&engine).unwrap();
 
-    let module = Module::from_binary(&engine, &store, wasm);
+    let module = Module::from_binary(&engine, &store, wasm).unwrap();
     log::info!("{module:#?}");
 
     let linker = Linker::new();
-    let instance = linker.instantiate(&mut store, &module);
+    let instance = linker.instantiate(&mut store, &module).unwrap();
     instance.debug_print_vmctx(&store);
 
+    let fib = instance
+        .get_typed_func::<i32, i32>(&mut store, "fib")
+        .unwrap();
+    let n = 10;
+    log::info!(
+        "The {n}th Fibonacci number is {}",
+        fib.call(&mut store, n).unwrap()
+    );
+
     kernel::arch::exit(0);
     // todo!()
 }
@@ -1,10 +1,35 @@
+use crate::runtime::instance::InstanceData;
+use crate::runtime::parking_spot;
+use crate::runtime::table::TableElement;
+use crate::runtime::trap::Trap;
+use crate::runtime::trap_handling::raise_trap;
 use crate::runtime::vmcontext::VMContext;
-use core::arch::asm;
+use core::ptr::NonNull;
+use core::sync::atomic::Ordering;
+use cranelift_wasm::{DataIndex, ElemIndex, FuncIndex, MemoryIndex, TableIndex};
+
+/// Calls `f` with the instance owning the given `VMContext`.
+///
+/// The instance is only borrowed for the duration of `f`, so traps must be raised *after* this
+/// returns.
+unsafe fn with_instance<R>(vmctx: *mut VMContext, f: impl FnOnce(&mut InstanceData) -> R) -> R {
+    let instance = InstanceData::from_vmctx(NonNull::new(vmctx).unwrap());
+    let mut instance = instance.borrow_mut();
+    f(&mut instance)
+}
 
 /// Returns an index for wasm's `memory.grow` builtin function.
 #[link_section = ".text.builtins"]
 pub unsafe extern "C" fn memory32_grow(vmctx: *mut VMContext, delta: u64, index: u32) -> *mut u8 {
-    todo!()
+    let result = with_instance(vmctx, |instance| {
+        instance.memory_grow(MemoryIndex::from_u32(index), delta)
+    });
+
+    // -1 signals failure to WASM
+    match result {
+        Some(old_pages) => usize::try_from(old_pages).unwrap() as *mut u8,
+        None => usize::MAX as *mut u8,
+    }
 }
 
 /// Returns an index for wasm's `table.copy` when both tables are locally
@@ -18,7 +43,16 @@ pub unsafe extern "C" fn table_copy(
     src: u32,
     len: u32,
 ) {
-    todo!()
+    let result = with_instance(vmctx, |instance| {
+        instance.table_copy(
+            TableIndex::from_u32(dst_index),
+            TableIndex::from_u32(src_index),
+            dst,
+            src,
+            len,
+        )
+    });
+    result.unwrap_or_else(|trap| raise_trap(trap));
 }
 
 /// Returns an index for wasm's `table.init`.
@@ -31,13 +65,24 @@ pub unsafe extern "C" fn table_init(
     src: u32,
     len: u32,
 ) {
-    todo!()
+    let result = with_instance(vmctx, |instance| {
+        instance.table_init(
+            TableIndex::from_u32(table),
+            ElemIndex::from_u32(elem),
+            dst,
+            src,
+            len,
+        )
+    });
+    result.unwrap_or_else(|trap| raise_trap(trap));
 }
 
 /// Returns an index for wasm's `elem.drop`.
 #[link_section = ".text.builtins"]
 pub unsafe extern "C" fn elem_drop(vmctx: *mut VMContext, elem: u32) {
-    todo!()
+    with_instance(vmctx, |instance| {
+        instance.elem_drop(ElemIndex::from_u32(elem));
+    });
 }
 
 /// Returns an index for wasm's `memory.copy`
@@ -50,7 +95,16 @@ pub unsafe extern "C" fn memory_copy(
     src: u64,
     len: u64,
 ) {
-    todo!()
+    let result = with_instance(vmctx, |instance| {
+        instance.memory_copy(
+            MemoryIndex::from_u32(dst_index),
+            dst,
+            MemoryIndex::from_u32(src_index),
+            src,
+            len,
+        )
+    });
+    result.unwrap_or_else(|trap| raise_trap(trap));
 }
 
 /// Returns an index for wasm's `memory.fill` instruction.
@@ -62,7 +116,12 @@ pub unsafe extern "C" fn memory_fill(
     val: u32,
     len: u64,
 ) {
-    todo!()
+    let result = with_instance(vmctx, |instance| {
+        // only the lowest byte of `val` is used
+        #[allow(clippy::cast_possible_truncation)]
+        instance.memory_fill(MemoryIndex::from_u32(memory), dst, val as u8, len)
+    });
+    result.unwrap_or_else(|trap| raise_trap(trap));
 }
 
 /// Returns an index for wasm's `memory.init` instruction.
@@ -75,19 +134,32 @@ pub unsafe extern "C" fn memory_init(
     src: u32,
     len: u32,
 ) {
-    todo!()
+    let result = with_instance(vmctx, |instance| {
+        instance.memory_init(
+            MemoryIndex::from_u32(memory),
+            DataIndex::from_u32(data),
+            dst,
+            src,
+            len,
+        )
+    });
+    result.unwrap_or_else(|trap| raise_trap(trap));
 }
 
 /// Returns a value for wasm's `ref.func` instruction.
 #[link_section = ".text.builtins"]
 pub unsafe extern "C" fn ref_func(vmctx: *mut VMContext, func: u32) -> *mut u8 {
-    todo!()
+    with_instance(vmctx, |instance| {
+        instance.ref_func(FuncIndex::from_u32(func)).cast()
+    })
 }
 
 /// Returns an index for wasm's `data.drop` instruction.
 #[link_section = ".text.builtins"]
 pub unsafe extern "C" fn data_drop(vmctx: *mut VMContext, data: u32) {
-    todo!()
+    with_instance(vmctx, |instance| {
+        instance.data_drop(DataIndex::from_u32(data));
+    });
 }
 
 /// Returns a table entry after lazily initializing it.
@@ -97,7 +169,15 @@ pub unsafe extern "C" fn table_get_lazy_init_func_ref(
     table: u32,
     index: u32,
 ) -> *mut u8 {
-    todo!()
+    // tables are eagerly initialized during instantiation, so there is nothing to do here
+    let result = with_instance(vmctx, |instance| {
+        instance.table_get(TableIndex::from_u32(table), index)
+    });
+
+    match result.unwrap_or_else(|trap| raise_trap(trap)) {
+        TableElement::FuncRef(func_ref) => func_ref.cast(),
+        TableElement::GcRef(_) => unreachable!("not a funcref table"),
+    }
 }
 
 /// Returns an index for Wasm's `table.grow` instruction for `funcref`s.
@@ -108,7 +188,16 @@ pub unsafe extern "C" fn table_grow_func_ref(
     delta: u32,
     init: *mut u8,
 ) -> u32 {
-    todo!()
+    let result = with_instance(vmctx, |instance| {
+        instance.table_grow(
+            TableIndex::from_u32(table),
+            delta,
+            TableElement::FuncRef(init.cast()),
+        )
+    });
+
+    // -1 signals failure to WASM
+    result.unwrap_or(u32::MAX)
 }
 
 /// Returns an index for Wasm's `table.fill` instruction for `funcref`s.
@@ -120,7 +209,55 @@ pub unsafe extern "C" fn table_fill_func_ref(
     val: *mut u8,
     len: u32,
 ) {
-    todo!()
+    let result = with_instance(vmctx, |instance| {
+        instance.table_fill(
+            TableIndex::from_u32(table),
+            dst,
+            TableElement::FuncRef(val.cast()),
+            len,
+        )
+    });
+    result.unwrap_or_else(|trap| raise_trap(trap));
+}
+
+/// Returns an index for Wasm's `table.grow` instruction for GC references.
+#[link_section = ".text.builtins"]
+pub unsafe extern "C" fn table_grow_gc_ref(
+    vmctx: *mut VMContext,
+    table: u32,
+    delta: u32,
+    init: u32,
+) -> u32 {
+    let result = with_instance(vmctx, |instance| {
+        instance.table_grow(
+            TableIndex::from_u32(table),
+            delta,
+            TableElement::GcRef(init),
+        )
+    });
+
+    // -1 signals failure to WASM
+    result.unwrap_or(u32::MAX)
+}
+
+/// Returns an index for Wasm's `table.fill` instruction for GC references.
+#[link_section = ".text.builtins"]
+pub unsafe extern "C" fn table_fill_gc_ref(
+    vmctx: *mut VMContext,
+    table: u32,
+    dst: u32,
+    val: u32,
+    len: u32,
+) {
+    let result = with_instance(vmctx, |instance| {
+        instance.table_fill(
+            TableIndex::from_u32(table),
+            dst,
+            TableElement::GcRef(val),
+            len,
+        )
+    });
+    result.unwrap_or_else(|trap| raise_trap(trap));
 }
 
 /// Returns an index for wasm's `memory.atomic.notify` instruction.
@@ -131,7 +268,13 @@ pub unsafe extern "C" fn memory_atomic_notify(
     addr: u64,
     count: u32,
 ) -> u32 {
-    todo!()
+    let result = with_instance(vmctx, |instance| {
+        instance.validate_atomic_addr(MemoryIndex::from_u32(memory), addr, 4)
+    });
+    let addr = result.unwrap_or_else(|trap| raise_trap(trap));
+
+    // non-shared memories have no waiters, so notifying them always wakes up nobody
+    parking_spot::notify(addr, count)
 }
 
 /// Returns an index for wasm's `memory.atomic.wait32` instruction.
@@ -143,7 +286,8 @@ pub unsafe extern "C" fn memory_atomic_wait32(
     expected: u32,
     timeout: u64,
 ) -> u32 {
-    todo!()
+    let addr = atomic_wait_addr(vmctx, memory, addr, 4);
+    parking_spot::wait32(addr.cast(), expected, wait_timeout(timeout)) as u32
 }
 
 /// Returns an index for wasm's `memory.atomic.wait64` instruction.
@@ -155,7 +299,42 @@ pub unsafe extern "C" fn memory_atomic_wait64(
     expected: u64,
     timeout: u64,
 ) -> u32 {
-    todo!()
+    let addr = atomic_wait_addr(vmctx, memory, addr, 8);
+    parking_spot::wait64(addr.cast(), expected, wait_timeout(timeout)) as u32
+}
+
+/// Validates the address of a `memory.atomic.wait` instruction, raising a trap if it is
+/// misaligned, out of bounds or not part of a shared memory.
+unsafe fn atomic_wait_addr(vmctx: *mut VMContext, memory: u32, addr: u64, size: u64) -> *mut u8 {
+    let result = with_instance(vmctx, |instance| {
+        let index = MemoryIndex::from_u32(memory);
+        let addr = instance.validate_atomic_addr(index, addr, size)?;
+        if !instance.is_shared_memory(index) {
+            return Err(Trap::AtomicWaitNonSharedMemory);
+        }
+        Ok(addr)
+    });
+    result.unwrap_or_else(|trap| raise_trap(trap))
+}
+
+/// Converts the signed timeout operand of `memory.atomic.wait` in nanoseconds, negative timeouts
+/// wait forever.
+fn wait_timeout(timeout: u64) -> Option<u64> {
+    i64::try_from(timeout).is_ok().then_some(timeout)
+}
+
+/// Invoked when the store reached its epoch deadline.
+#[link_section = ".text.builtins"]
+pub unsafe extern "C" fn new_epoch(vmctx: *mut VMContext) {
+    let epoch = &mut *with_instance(vmctx, |instance| instance.vmctx_epoch());
+
+    match epoch.extend_delta {
+        Some(delta) => {
+            let current = epoch.current.load(Ordering::Relaxed);
+            epoch.deadline = current.saturating_add(delta);
+        }
+        None => raise_trap(Trap::Interrupt),
+    }
 }
 
 // /// Invoked before malloc returns.
@@ -239,24 +418,3 @@ pub unsafe extern "C" fn memory_atomic_wait64(
 // pub unsafe extern "C" fn gc_ref_global_set(vmctx: *mut VMContext, global: u32, val: *mut u8) {
 //     todo!()
 // }
-//
-// /// Returns an index for Wasm's `table.grow` instruction for GC references.
-// pub unsafe extern "C" fn table_grow_gc_ref(
-//     vmctx: *mut VMContext,
-//     table: u32,
-//     delta: u32,
-//     init: *mut u8,
-// ) -> u32 {
-//     todo!()
-// }
-//
-// /// Returns an index for Wasm's `table.fill` instruction for GC references.
-// pub unsafe extern "C" fn table_fill_gc_ref(
-//     vmctx: *mut VMContext,
-//     table: u32,
-//     dst: u32,
-//     val: *mut u8,
-//     len: u32,
-// ) {
-//     todo!()
-// }
@@ -1,3 +1,4 @@
+#[cfg(target_os = "none")]
 mod impls;
 
 use crate::runtime::vmcontext::VMContext;
@@ -34,12 +35,18 @@ macro_rules! foreach_builtin_function {
             table_grow_func_ref(vmctx: vmctx, table: i32, delta: i32, init: pointer) -> i32;
             // Returns an index for Wasm's `table.fill` instruction for `funcref`s.
             table_fill_func_ref(vmctx: vmctx, table: i32, dst: i32, val: pointer, len: i32);
+            // Returns an index for Wasm's `table.grow` instruction for GC references.
+            table_grow_gc_ref(vmctx: vmctx, table: i32, delta: i32, init: reference) -> i32;
+            // Returns an index for Wasm's `table.fill` instruction for GC references.
+            table_fill_gc_ref(vmctx: vmctx, table: i32, dst: i32, val: reference, len: i32);
             // Returns an index for wasm's `memory.atomic.notify` instruction.
             memory_atomic_notify(vmctx: vmctx, memory: i32, addr: i64, count: i32) -> i32;
             // Returns an index for wasm's `memory.atomic.wait32` instruction.
             memory_atomic_wait32(vmctx: vmctx, memory: i32, addr: i64, expected: i32, timeout: i64) -> i32;
             // Returns an index for wasm's `memory.atomic.wait64` instruction.
             memory_atomic_wait64(vmctx: vmctx, memory: i32, addr: i64, expected: i64, timeout: i64) -> i32;
+            // Invoked when the store reached its epoch deadline.
+            new_epoch(vmctx: vmctx);
             // // Invoked before malloc returns.
             // check_malloc(vmctx: vmctx, addr: i32, len: i32) -> i32;
             // // Invoked before the free returns.
@@ -71,10 +78,6 @@ macro_rules! foreach_builtin_function {
             // // Implementation of Wasm's `global.set` instruction for globals
             // // containing GC references.
             // gc_ref_global_set(vmctx: vmctx, global: i32, val: reference);
-            // // Returns an index for Wasm's `table.grow` instruction for GC references.
-            // table_grow_gc_ref(vmctx: vmctx, table: i32, delta: i32, init: reference) -> i32;
-            // // Returns an index for Wasm's `table.fill` instruction for GC references.
-            // table_fill_gc_ref(vmctx: vmctx, table: i32, dst: i32, val: reference, len: i32);
         }
     };
 }
@@ -187,6 +190,14 @@ impl BuiltinFunctionSignatures {
         AbiParam::new(self.pointer_type)
     }
 
+    /// Returns the AbiParam for builtin functions `reference` arguments/returns.
+    /// This function is used in the `signatures` macro below.
+    #[allow(clippy::unused_self)]
+    fn reference(&self) -> AbiParam {
+        // GC references are 32-bit, see `reference_type`
+        AbiParam::new(ir::types::I32)
+    }
+
     /// Returns the AbiParam for builtin functions `i32` arguments/returns.
     /// This function is used in the `signatures` macro below.
     #[allow(clippy::unused_self)]
@@ -321,14 +332,20 @@ macro_rules! define_builtin_array {
                     $name: crate::runtime::builtins::impls::$name,
                 )*
             };
+
+            /// Returns whether `addr` is the entrypoint of one of the builtin functions.
+            pub fn is_builtin(addr: usize) -> bool {
+                false $( || addr == crate::runtime::builtins::impls::$name as usize )*
+            }
         }
     };
 
     (@ty i32) => (u32);
     (@ty i64) => (u64);
-    (@ty reference) => (*mut u8);
+    (@ty reference) => (u32);
     (@ty pointer) => (*mut u8);
     (@ty vmctx) => (*mut VMContext);
 }
 
+#[cfg(target_os = "none")]
 foreach_builtin_function!(define_builtin_array);
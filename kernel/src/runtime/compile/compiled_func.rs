@@ -1,6 +1,8 @@
 use crate::runtime::builtins::BuiltinFunctionIndex;
-use crate::runtime::trap::{Trap, DEBUG_ASSERT_TRAP_CODE};
-use crate::runtime::NS_WASM_FUNC;
+use crate::runtime::trap::{Trap, DEBUG_ASSERT_TRAP_CODE, OUT_OF_FUEL_TRAP_CODE};
+use crate::runtime::{NS_WASM_BUILTIN, NS_WASM_FUNC};
+use alloc::vec::Vec;
+use cranelift_codegen::ir::types::I32;
 use cranelift_codegen::ir::{
     ExternalName, StackSlots, TrapCode, UserExternalName, UserExternalNameRef,
 };
@@ -59,10 +61,11 @@ impl CompiledFunction {
                 TrapCode::IntegerDivisionByZero => Trap::IntegerDivisionByZero,
                 TrapCode::BadConversionToInteger => Trap::BadConversionToInteger,
                 TrapCode::UnreachableCodeReached => Trap::UnreachableCodeReached,
-                TrapCode::Interrupt => todo!(),
+                TrapCode::Interrupt => Trap::Interrupt,
                 TrapCode::NullReference => Trap::NullReference,
                 TrapCode::NullI31Ref => Trap::NullI31Ref,
                 TrapCode::User(DEBUG_ASSERT_TRAP_CODE) => Trap::DebugAssertionFailed,
+                TrapCode::User(OUT_OF_FUEL_TRAP_CODE) => Trap::OutOfFuel,
                 TrapCode::User(c) => panic!("unknown trap code {c}"),
             };
 
@@ -73,6 +76,25 @@ impl CompiledFunction {
         })
     }
 
+    /// Returns an iterator to the function's stack maps, one for each safepoint with live GC
+    /// references.
+    pub fn stack_maps(&self) -> impl ExactSizeIterator<Item = StackMapInfo> + '_ {
+        self.buffer
+            .user_stack_maps()
+            .iter()
+            .map(|(offset, frame_size, stack_map)| StackMapInfo {
+                offset: *offset,
+                frame_size: *frame_size,
+                slots: stack_map
+                    .entries()
+                    .map(|(ty, slot)| {
+                        debug_assert_eq!(ty, I32, "GC references are `i32`s");
+                        slot
+                    })
+                    .collect(),
+            })
+    }
+
     /// Get a reference to the compiled function metadata.
     pub fn metadata(&self) -> &CompiledFunctionMetadata {
         &self.metadata
@@ -106,6 +128,17 @@ pub struct TrapInfo {
     pub code: Trap,
 }
 
+/// The stack slots holding live GC references at a safepoint.
+pub struct StackMapInfo {
+    /// The offset of the safepoint, i.e. the return address of a call.
+    pub offset: u32,
+    /// The size of the frame below the frame pointer, the stack pointer at the safepoint is the
+    /// frame pointer minus this size.
+    pub frame_size: u32,
+    /// The offsets of the stack slots holding GC references, relative to the stack pointer.
+    pub slots: Vec<u32>,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum RelocationTarget {
     Wasm(FuncIndex),
@@ -139,9 +172,9 @@ impl Relocation {
                     // A reference to another jit'ed WASM function
                     NS_WASM_FUNC => RelocationTarget::Wasm(FuncIndex::from_u32(name.index)),
                     // A reference to a WASM builtin
-                    // NS_WASM_BUILTIN => {
-                    //     RelocationTarget::Builtin(BuiltinFunctionIndex::from_u32(name.index))
-                    // }
+                    NS_WASM_BUILTIN => {
+                        RelocationTarget::Builtin(BuiltinFunctionIndex::from_u32(name.index))
+                    }
                     _ => panic!("unknown namespace {}", name.namespace),
                 }
             }
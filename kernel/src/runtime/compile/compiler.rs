@@ -1,46 +1,61 @@
-use crate::runtime::builtins::BuiltinFunctionIndex;
+use crate::runtime::builtins::{BuiltinFunctionIndex, BuiltinFunctionSignatures};
 use crate::runtime::compile::compiled_func::CompiledFunction;
 use crate::runtime::compile::obj_builder::ELFOSABI_K23;
+use crate::runtime::compile::simd_fallback;
 use crate::runtime::compile::FuncCompileInput;
+use crate::runtime::config::Tunables;
 use crate::runtime::errors::{CompileError, TranslationError};
 use crate::runtime::translate::FunctionEnvironment;
 use crate::runtime::translate::TranslatedModule;
-use crate::runtime::utils::wasm_call_signature;
+use crate::runtime::utils::{native_call_signature, value_type, wasm_call_signature};
+use crate::runtime::vmcontext::{VMContextPlan, VMHostFuncContext, VMVal};
 use crate::runtime::NS_WASM_FUNC;
-use core::mem;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::offset_of;
+use core::{cmp, mem};
 use cranelift_codegen::control::ControlPlane;
 use cranelift_codegen::ir::{
-    Block, Endianness, Function, GlobalValueData, MemFlags, UserExternalName, UserFuncName,
+    Block, Endianness, ExtFuncData, ExternalName, Function, GlobalValueData, InstBuilder, MemFlags,
+    StackSlotData, StackSlotKind, UserExternalName, UserFuncName, Value,
 };
 use cranelift_codegen::isa::{OwnedTargetIsa, TargetIsa};
 use cranelift_codegen::Context;
 use cranelift_entity::PrimaryMap;
 use cranelift_frontend::FunctionBuilder;
 use cranelift_wasm::wasmparser::FuncValidatorAllocations;
-use cranelift_wasm::{DefinedFuncIndex, FuncTranslator, ModuleInternedTypeIndex, WasmSubType};
+use cranelift_wasm::{
+    DefinedFuncIndex, FuncTranslator, ModuleInternedTypeIndex, WasmSubType, WasmValType,
+};
 use object::write::Object;
 use object::{BinaryFormat, FileFlags};
 use target_lexicon::Architecture;
 
+/// Compiles WASM functions and trampolines to machine code.
+///
+/// All per-function state lives in a fresh `CompilationContext`, so a single compiler can be
+/// shared by all harts compiling functions in parallel.
 pub struct Compiler {
     isa: OwnedTargetIsa,
-    func_translator: FuncTranslator,
-    codegen_context: Context,
-    validator_allocations: FuncValidatorAllocations,
+    tunables: Tunables,
+    /// Whether the target has no vector registers and SIMD has to be lowered to scalar code.
+    simd_fallback: bool,
 }
 
 impl Compiler {
-    pub fn new(isa: OwnedTargetIsa) -> Compiler {
+    pub fn new(isa: OwnedTargetIsa, tunables: Tunables, simd_fallback: bool) -> Compiler {
         Self {
             isa,
-            func_translator: FuncTranslator::new(),
-            codegen_context: Context::new(),
-            validator_allocations: FuncValidatorAllocations::default(),
+            tunables,
+            simd_fallback,
         }
     }
     pub fn target_isa(&self) -> &dyn TargetIsa {
         self.isa.as_ref()
     }
+    pub fn tunables(&self) -> &Tunables {
+        &self.tunables
+    }
 
     pub fn create_intermediate_code_object(&self) -> Object {
         let architecture = match self.isa.triple().architecture {
@@ -78,7 +93,7 @@ impl Compiler {
     ) -> Result<CompiledFunction, CompileError> {
         let isa = self.target_isa();
 
-        let mut ctx = CompilationContext::new(isa);
+        let mut ctx = CompilationContext::new(isa, self.simd_fallback);
 
         // Setup function signature
         let func_index = module.function_index(def_func_index);
@@ -94,7 +109,8 @@ impl Compiler {
         // collect debug info
         ctx.codegen_context.func.collect_debug_info();
 
-        let mut func_env = FunctionEnvironment::new(isa, module, types);
+        let mut func_env =
+            FunctionEnvironment::new(isa, module, types, &self.tunables, wasm_func_ty);
 
         // setup stack limit
         let vmctx = ctx
@@ -132,34 +148,226 @@ impl Compiler {
         ctx.finish()
     }
 
-    /// Compiles a trampoline for calling a WASM function from the host
-    #[allow(clippy::unused_self)]
-    pub fn compile_host_to_wasm_trampoline(&self) {}
-    /// Compiles a trampoline for calling a host function from WASM
-    #[allow(clippy::unused_self)]
-    pub fn compile_wasm_to_host_trampoline(&self) {}
+    /// Compiles a trampoline for calling the given WASM function from native code.
+    ///
+    /// The trampoline follows the native calling convention (see `VMNativeCallFunction`), it loads
+    /// the arguments from the `VMVal` array, calls the WASM function and writes the results back
+    /// into the same array.
+    pub fn compile_native_to_wasm_trampoline(
+        &self,
+        module: &TranslatedModule,
+        types: &PrimaryMap<ModuleInternedTypeIndex, WasmSubType>,
+        def_func_index: DefinedFuncIndex,
+    ) -> Result<CompiledFunction, CompileError> {
+        let isa = self.target_isa();
+        let func_index = module.function_index(def_func_index);
+        let sig_index = module.functions[func_index].signature;
+        let wasm_func_ty = types[sig_index].unwrap_func();
+
+        let wasm_call_sig = wasm_call_signature(isa, wasm_func_ty);
+        let native_call_sig = native_call_signature(isa);
+
+        let mut ctx = CompilationContext::new(isa, self.simd_fallback);
+        let func = Function::with_name_signature(UserFuncName::default(), native_call_sig);
+        let (mut builder, block0) = ctx.new_function_builder(func);
+
+        let params = builder.func.dfg.block_params(block0).to_vec();
+        let callee_vmctx = params[0];
+        let caller_vmctx = params[1];
+        let values_vec_ptr = params[2];
+
+        let mut args = vec![callee_vmctx, caller_vmctx];
+        args.extend(load_values_from_array(
+            isa,
+            &mut builder,
+            wasm_func_ty.params(),
+            values_vec_ptr,
+        ));
+
+        // call the actual WASM function
+        let callee_sig = builder.import_signature(wasm_call_sig);
+        let callee_name = builder
+            .func
+            .declare_imported_user_function(UserExternalName {
+                namespace: NS_WASM_FUNC,
+                index: func_index.as_u32(),
+            });
+        let callee = builder.import_function(ExtFuncData {
+            name: ExternalName::User(callee_name),
+            signature: callee_sig,
+            colocated: true,
+        });
+        let call = builder.ins().call(callee, &args);
+        let results = builder.func.dfg.inst_results(call).to_vec();
+
+        store_values_to_array(&mut builder, &results, values_vec_ptr);
+        builder.ins().return_(&[]);
+        builder.finalize();
+
+        ctx.finish()
+    }
+
+    /// Compiles a trampoline for calling a host function of the given signature from WASM.
+    ///
+    /// The trampoline follows the WASM calling convention, spills its arguments into a `VMVal`
+    /// array on the stack and calls the native entrypoint stored in the callee's
+    /// `VMHostFuncContext`. Results are read back from the same array.
+    pub fn compile_wasm_to_native_trampoline(
+        &self,
+        types: &PrimaryMap<ModuleInternedTypeIndex, WasmSubType>,
+        sig_index: ModuleInternedTypeIndex,
+    ) -> Result<CompiledFunction, CompileError> {
+        let isa = self.target_isa();
+        let pointer_type = isa.pointer_type();
+        let wasm_func_ty = types[sig_index].unwrap_func();
+
+        let wasm_call_sig = wasm_call_signature(isa, wasm_func_ty);
+        let native_call_sig = native_call_signature(isa);
+
+        let mut ctx = CompilationContext::new(isa, self.simd_fallback);
+        let func = Function::with_name_signature(UserFuncName::default(), wasm_call_sig);
+        let (mut builder, block0) = ctx.new_function_builder(func);
+
+        let args = builder.func.dfg.block_params(block0).to_vec();
+        let callee_vmctx = args[0];
+        let caller_vmctx = args[1];
+
+        // the array needs to be large enough to hold both the arguments and the results
+        let values_vec_len = cmp::max(wasm_func_ty.params().len(), wasm_func_ty.returns().len());
+        let values_vec_byte_size = u32::try_from(values_vec_len * mem::size_of::<VMVal>()).unwrap();
+        let slot = builder.func.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            values_vec_byte_size,
+            4,
+        ));
+        let values_vec_ptr = builder.ins().stack_addr(pointer_type, slot, 0);
+        store_values_to_array(&mut builder, &args[2..], values_vec_ptr);
+
+        let values_vec_len = builder
+            .ins()
+            .iconst(pointer_type, i64::try_from(values_vec_len).unwrap());
+
+        // load the native entrypoint from the host function context and call it
+        let native_call = builder.ins().load(
+            pointer_type,
+            MemFlags::trusted(),
+            callee_vmctx,
+            i32::try_from(offset_of!(VMHostFuncContext, native_call)).unwrap(),
+        );
+        let native_call_sig = builder.func.import_signature(native_call_sig);
+        builder.ins().call_indirect(
+            native_call_sig,
+            native_call,
+            &[callee_vmctx, caller_vmctx, values_vec_ptr, values_vec_len],
+        );
+
+        let results =
+            load_values_from_array(isa, &mut builder, wasm_func_ty.returns(), values_vec_ptr);
+        builder.ins().return_(&results);
+        builder.finalize();
+
+        ctx.finish()
+    }
+
     /// Compiles a trampoline for calling a builtin function from WASM
+    ///
+    /// The trampoline has the same signature as the builtin, it loads the builtin's address from
+    /// the `VMBuiltinFunctionsArray` referenced by the `vmctx` and calls it.
     pub fn compile_wasm_to_builtin_trampoline(
         &self,
-        _module: &TranslatedModule,
-        _builtin_index: BuiltinFunctionIndex,
+        module: &TranslatedModule,
+        builtin_index: BuiltinFunctionIndex,
     ) -> Result<CompiledFunction, CompileError> {
-        todo!()
+        let isa = self.target_isa();
+        let pointer_type = isa.pointer_type();
+        let vmctx_plan = VMContextPlan::for_module(isa, module);
+        let builtin_sig = BuiltinFunctionSignatures::new(isa).signature(builtin_index);
+
+        let mut ctx = CompilationContext::new(isa, self.simd_fallback);
+        let func = Function::with_name_signature(UserFuncName::default(), builtin_sig.clone());
+        let (mut builder, block0) = ctx.new_function_builder(func);
+
+        let args = builder.func.dfg.block_params(block0).to_vec();
+        let vmctx = args[0];
+
+        // load the builtins address from the builtin functions array
+        let mut flags = MemFlags::trusted();
+        flags.set_readonly();
+        let builtins_array = builder.ins().load(
+            pointer_type,
+            flags,
+            vmctx,
+            i32::try_from(vmctx_plan.vmctx_builtin_functions()).unwrap(),
+        );
+        let builtin_offset = builtin_index.as_u32() * u32::from(isa.pointer_bytes());
+        let builtin_addr = builder.ins().load(
+            pointer_type,
+            flags,
+            builtins_array,
+            i32::try_from(builtin_offset).unwrap(),
+        );
+
+        let builtin_sig = builder.func.import_signature(builtin_sig);
+        let call = builder
+            .ins()
+            .call_indirect(builtin_sig, builtin_addr, &args);
+        let results = builder.func.dfg.inst_results(call).to_vec();
+        builder.ins().return_(&results);
+        builder.finalize();
+
+        ctx.finish()
     }
 }
 
+/// Stores the given values into consecutive `VMVal` slots of the array at `values_vec_ptr`.
+fn store_values_to_array(builder: &mut FunctionBuilder, values: &[Value], values_vec_ptr: Value) {
+    let flags = MemFlags::new()
+        .with_notrap()
+        .with_endianness(Endianness::Little);
+
+    for (i, val) in values.iter().copied().enumerate() {
+        let offset = i32::try_from(i * mem::size_of::<VMVal>()).unwrap();
+        builder.ins().store(flags, val, values_vec_ptr, offset);
+    }
+}
+
+/// Loads values of the given types from consecutive `VMVal` slots of the array at `values_vec_ptr`.
+fn load_values_from_array(
+    isa: &dyn TargetIsa,
+    builder: &mut FunctionBuilder,
+    types: &[WasmValType],
+    values_vec_ptr: Value,
+) -> Vec<Value> {
+    let flags = MemFlags::new()
+        .with_notrap()
+        .with_endianness(Endianness::Little);
+
+    types
+        .iter()
+        .enumerate()
+        .map(|(i, ty)| {
+            let offset = i32::try_from(i * mem::size_of::<VMVal>()).unwrap();
+            builder
+                .ins()
+                .load(value_type(isa, *ty), flags, values_vec_ptr, offset)
+        })
+        .collect()
+}
+
 /// Ad-hoc structure for compiling a single input
 struct CompilationContext<'a> {
     target_isa: &'a dyn TargetIsa,
+    simd_fallback: bool,
     func_translator: FuncTranslator,
     codegen_context: Context,
     validator_allocations: FuncValidatorAllocations,
 }
 
 impl<'a> CompilationContext<'a> {
-    pub fn new(target_isa: &'a dyn TargetIsa) -> Self {
+    pub fn new(target_isa: &'a dyn TargetIsa, simd_fallback: bool) -> Self {
         Self {
             target_isa,
+            simd_fallback,
             func_translator: FuncTranslator::new(),
             codegen_context: Context::new(),
             validator_allocations: FuncValidatorAllocations::default(),
@@ -181,6 +389,10 @@ impl<'a> CompilationContext<'a> {
     }
 
     pub fn finish(mut self) -> Result<CompiledFunction, CompileError> {
+        if self.simd_fallback {
+            simd_fallback::scalarize(&mut self.codegen_context.func)?;
+        }
+
         let compiled_code = self
             .codegen_context
             .compile(self.target_isa, &mut ControlPlane::default())?;
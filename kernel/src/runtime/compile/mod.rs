@@ -1,16 +1,23 @@
 mod compiled_func;
 mod compiler;
 mod obj_builder;
+#[cfg(target_os = "none")]
+mod pool;
+mod simd_fallback;
 
 pub use compiler::Compiler;
 pub use obj_builder::{
-    ELFOSABI_K23, ELF_K23_BTI, ELF_K23_ENGINE, ELF_K23_INFO, ELF_K23_TRAPS, ELF_TEXT,
-    ELF_WASM_DATA, ELF_WASM_DWARF, ELF_WASM_NAMES,
+    ELFOSABI_K23, ELF_K23_BTI, ELF_K23_ENGINE, ELF_K23_INFO, ELF_K23_STACK_MAPS, ELF_K23_TRAPS,
+    ELF_TEXT, ELF_WASM_DATA, ELF_WASM_DWARF, ELF_WASM_NAMES,
 };
+#[cfg(target_os = "none")]
+pub use pool::run_worker as run_compile_worker;
+#[cfg(all(test, target_os = "none"))]
+pub(crate) use pool::{help as help_compile, run_all as run_compile_jobs, Job as CompileJob};
 
 use crate::runtime::builtins::BuiltinFunctionIndex;
 use crate::runtime::compile::compiled_func::{CompiledFunction, RelocationTarget};
-use crate::runtime::compile::obj_builder::ObjectBuilder;
+use crate::runtime::compile::obj_builder::{parse_module_info, EngineInfo, ObjectBuilder};
 use crate::runtime::errors::CompileError;
 use crate::runtime::translate::ModuleEnvironment;
 use crate::runtime::translate::{TranslatedModule, Translation};
@@ -26,6 +33,7 @@ use cranelift_wasm::wasmparser::{
 };
 use cranelift_wasm::{DefinedFuncIndex, ModuleInternedTypeIndex, StaticModuleIndex, WasmSubType};
 use object::write::WritableBuffer;
+use object::{FileFlags, Object, ObjectSection};
 
 pub fn compile_module<'wasm, T: WritableBuffer>(
     engine: &Engine,
@@ -34,7 +42,7 @@ pub fn compile_module<'wasm, T: WritableBuffer>(
 ) -> Result<CompiledModuleInfo<'wasm>, CompileError> {
     let mut validator = Validator::new_with_features(engine.wasm_features());
     let parser = Parser::new(0);
-    let module_env = ModuleEnvironment::new(&mut validator);
+    let module_env = ModuleEnvironment::new(&mut validator, engine.tunables());
 
     // Perform WASM -> Cranelift IR translation
     log::trace!("Translating module to Cranelift IR...");
@@ -45,7 +53,7 @@ pub fn compile_module<'wasm, T: WritableBuffer>(
         types,
     } = translation;
 
-    engine.assert_compatible(&module);
+    engine.check_compatible(&module)?;
 
     // collect all the necessary context and gather the functions that need compiling
     let compile_inputs = CompileInputs::from_module(&module, &types, func_compile_inputs);
@@ -59,6 +67,7 @@ pub fn compile_module<'wasm, T: WritableBuffer>(
 
     log::trace!("Appending info to intermediate code object...");
     obj_builder.append_engine_info(engine);
+    obj_builder.append_wasm_data(wasm);
     obj_builder.append_debug_info(&module.debug_info);
 
     log::trace!("Appending compiled functions to intermediate code object...");
@@ -73,6 +82,68 @@ pub fn compile_module<'wasm, T: WritableBuffer>(
     Ok(info)
 }
 
+/// Recovers the [`CompiledModuleInfo`] of a module previously compiled by [`compile_module`].
+///
+/// The metadata is recovered by parsing the WASM binary embedded in `artifact` again, none of the
+/// functions are recompiled.
+///
+/// # Errors
+///
+/// Returns an error if `artifact` is not a compiled module or was compiled by an engine with an
+/// incompatible configuration.
+pub fn deserialize_module<'wasm>(
+    engine: &Engine,
+    artifact: &'wasm [u8],
+) -> Result<CompiledModuleInfo<'wasm>, CompileError> {
+    let obj = object::File::parse(artifact)
+        .map_err(|_| CompileError::MalformedArtifact("not an object file"))?;
+    if !matches!(
+        obj.flags(),
+        FileFlags::Elf {
+            os_abi: ELFOSABI_K23,
+            ..
+        }
+    ) {
+        return Err(CompileError::MalformedArtifact("not a k23 object file"));
+    }
+
+    let section = |name: &'static str| {
+        obj.section_by_name(name)
+            .and_then(|section| section.data().ok())
+            .ok_or(CompileError::MalformedArtifact(name))
+    };
+
+    let engine_info = EngineInfo::parse(section(ELF_K23_ENGINE)?)
+        .ok_or(CompileError::MalformedArtifact(ELF_K23_ENGINE))?;
+    engine_info.check_compatible(engine)?;
+
+    let (funcs, wasm_to_native_trampolines) = parse_module_info(section(ELF_K23_INFO)?)
+        .ok_or(CompileError::MalformedArtifact(ELF_K23_INFO))?;
+
+    let mut validator = Validator::new_with_features(engine.wasm_features());
+    let module_env = ModuleEnvironment::new(&mut validator, engine.tunables());
+
+    log::trace!("Translating embedded module...");
+    let Translation {
+        module,
+        func_compile_inputs,
+        types,
+    } = module_env.translate(Parser::new(0), section(ELF_WASM_DATA)?)?;
+
+    engine.check_compatible(&module)?;
+
+    if funcs.len() != func_compile_inputs.len() {
+        return Err(CompileError::MalformedArtifact(ELF_K23_INFO));
+    }
+
+    Ok(CompiledModuleInfo {
+        module,
+        funcs,
+        types,
+        wasm_to_native_trampolines,
+    })
+}
+
 pub struct FuncCompileInput<'wasm> {
     pub body: FunctionBody<'wasm>,
     pub validator: FuncToValidate<ValidatorResources>,
@@ -83,6 +154,9 @@ pub struct CompiledModuleInfo<'wasm> {
     pub module: TranslatedModule<'wasm>,
     pub funcs: PrimaryMap<DefinedFuncIndex, CompiledFunctionInfo>,
     pub types: PrimaryMap<ModuleInternedTypeIndex, WasmSubType>,
+    /// Trampolines for calling host functions of the given signature from WASM, sorted by
+    /// signature.
+    pub wasm_to_native_trampolines: Vec<(ModuleInternedTypeIndex, FunctionLoc)>,
 }
 
 #[derive(Debug)]
@@ -118,7 +192,7 @@ impl<'a> CompileInputs<'a> {
         function_body_inputs: PrimaryMap<DefinedFuncIndex, FuncCompileInput<'a>>,
     ) -> Self {
         let mut inputs: Vec<CompileInput> = Vec::new();
-        // let mut num_trampolines = 0;
+        let mut num_trampolines = 0;
         // We only ever compile one module at a time
         let module_index = StaticModuleIndex::from_u32(0);
 
@@ -140,49 +214,84 @@ impl<'a> CompileInputs<'a> {
 
             // Compile a native->wasm trampoline for every function that *could theoretically* be
             // called by native code.
-            // let func_index = module.function_index(def_func_index);
-            // if module.functions[func_index].is_escaping() {
-            //     num_trampolines += 1;
-            //     inputs.push(Box::new(move |compiler| {
-            //         let function = compiler.compile_native_to_wasm_trampoline(
-            //             &module,
-            //             &types,
-            //             def_func_index,
-            //         )?;
-            //
-            //         Ok(CompileOutput {
-            //             key: CompileKey::native_to_wasm_trampoline(module_index, def_func_index),
-            //             function,
-            //             symbol: format!(
-            //                 "wasm[{}]::native_to_wasm_trampoline[{}]",
-            //                 module_index.as_u32(),
-            //                 func_index.as_u32()
-            //             ),
-            //         })
-            //     }));
-            // }
+            let func_index = module.function_index(def_func_index);
+            if module.functions[func_index].is_escaping() {
+                num_trampolines += 1;
+                inputs.push(Box::new(move |compiler| {
+                    let function = compiler.compile_native_to_wasm_trampoline(
+                        module,
+                        types,
+                        def_func_index,
+                    )?;
+
+                    Ok(CompileOutput {
+                        key: CompileKey::native_to_wasm_trampoline(module_index, def_func_index),
+                        function,
+                        symbol: format!(
+                            "wasm[{}]::native_to_wasm_trampoline[{}]",
+                            module_index.as_u32(),
+                            func_index.as_u32()
+                        ),
+                    })
+                }));
+            }
         }
 
-        // log::debug!("Number of native to WASM trampolines to build: {num_trampolines}",);
+        log::debug!("Number of native to WASM trampolines to build: {num_trampolines}",);
+
+        // Compile a wasm->native trampoline for every signature of an imported function, these
+        // are used when an import is satisfied by a host function.
+        let wasm_to_native_sigs: BTreeSet<_> = module
+            .functions
+            .iter()
+            .filter(|(func_index, _)| module.is_imported_function(*func_index))
+            .map(|(_, func)| func.signature)
+            .collect();
+
+        log::debug!(
+            "Number of WASM to native trampolines to build: {}",
+            wasm_to_native_sigs.len()
+        );
+
+        for sig_index in wasm_to_native_sigs {
+            inputs.push(Box::new(move |compiler| {
+                let function = compiler.compile_wasm_to_native_trampoline(types, sig_index)?;
 
-        // TODO collect wasm->native trampolines
+                Ok(CompileOutput {
+                    key: CompileKey::wasm_to_native_trampoline(sig_index),
+                    function,
+                    symbol: format!("wasm_to_native_trampoline[{}]", sig_index.as_u32()),
+                })
+            }));
+        }
 
         Self(inputs)
     }
 
     /// Feed the collected inputs through the compiler, producing [`UnlinkedCompileOutputs`] which holds
     /// the resulting artifacts.
+    ///
+    /// In the kernel the inputs are spread across all idle harts, unless parallel compilation is
+    /// disabled. Outputs are ordered by their [`CompileKey`], so the result is the same no matter
+    /// which hart compiled which input.
     pub fn compile(
         self,
         engine: &Engine,
         module: &TranslatedModule,
     ) -> Result<UnlinkedCompileOutputs, CompileError> {
-        let mut indices = BTreeMap::new();
         let mut outputs: BTreeMap<u32, BTreeMap<CompileKey, CompileOutput>> = BTreeMap::new();
 
-        for (idx, f) in self.0.into_iter().enumerate() {
-            let output = f(engine.compiler())?;
-            indices.insert(output.key, idx);
+        #[cfg(target_os = "none")]
+        let results = if engine.parallel_compilation() {
+            pool::compile_all(engine.compiler(), self.0)
+        } else {
+            self.0.into_iter().map(|f| f(engine.compiler())).collect()
+        };
+        #[cfg(not(target_os = "none"))]
+        let results = self.0.into_iter().map(|f| f(engine.compiler()));
+
+        for result in results {
+            let output = result?;
 
             outputs
                 .entry(output.key.kind())
@@ -190,22 +299,27 @@ impl<'a> CompileInputs<'a> {
                 .insert(output.key, output);
         }
 
-        let mut unlinked_compile_outputs = UnlinkedCompileOutputs { indices, outputs };
-        let flattened: Vec<_> = unlinked_compile_outputs
-            .outputs
-            .values()
-            .flat_map(|inner| inner.values())
-            .collect();
-
         let mut builtins = BTreeMap::new();
 
-        compile_required_builtins(engine, module, flattened.into_iter(), &mut builtins)?;
+        compile_required_builtins(
+            engine,
+            module,
+            outputs.values().flat_map(|inner| inner.values()),
+            &mut builtins,
+        )?;
 
-        unlinked_compile_outputs
-            .outputs
-            .insert(CompileKey::WASM_TO_BUILTIN_TRAMPOLINE_KIND, builtins);
+        outputs.insert(CompileKey::WASM_TO_BUILTIN_TRAMPOLINE_KIND, builtins);
+
+        // The outputs are appended to the text section in key order, record each keys position
+        // so relocations and function locations can be resolved later.
+        let indices = outputs
+            .values()
+            .flat_map(|inner| inner.keys())
+            .enumerate()
+            .map(|(idx, key)| (*key, idx))
+            .collect();
 
-        Ok(unlinked_compile_outputs)
+        Ok(UnlinkedCompileOutputs { indices, outputs })
     }
 }
 
@@ -307,31 +421,42 @@ impl UnlinkedCompileOutputs {
             .unwrap_or_default()
             .into_iter();
 
-        // let mut native_to_wasm_trampolines = self
-        //     .outputs
-        //     .remove(&CompileKey::NATIVE_TO_WASM_TRAMPOLINE_KIND)
-        //     .unwrap_or_default();
+        let mut native_to_wasm_trampolines = self
+            .outputs
+            .remove(&CompileKey::NATIVE_TO_WASM_TRAMPOLINE_KIND)
+            .unwrap_or_default();
 
         let funcs: PrimaryMap<DefinedFuncIndex, CompiledFunctionInfo> = wasm_functions
             .map(|(key, _)| {
                 let wasm_func_index = self.indices[&key];
                 let (_, wasm_func_loc) = symbol_ids_and_locs[wasm_func_index];
 
-                // let native_to_wasm_trampoline_key = CompileKey::native_to_wasm_trampoline(
-                //     key.module(),
-                //     DefinedFuncIndex::from_u32(key.index),
-                // );
-                // let native_to_wasm_trampoline = native_to_wasm_trampolines
-                //     .remove(&native_to_wasm_trampoline_key)
-                //     .map(|output| symbol_ids_and_locs[self.indices[&output.key]].1);
+                let native_to_wasm_trampoline_key = CompileKey::native_to_wasm_trampoline(
+                    key.module(),
+                    DefinedFuncIndex::from_u32(key.index),
+                );
+                let native_to_wasm_trampoline = native_to_wasm_trampolines
+                    .remove(&native_to_wasm_trampoline_key)
+                    .map(|output| symbol_ids_and_locs[self.indices[&output.key]].1);
 
                 CompiledFunctionInfo {
                     wasm_func_loc,
-                    native_to_wasm_trampoline: None,
+                    native_to_wasm_trampoline,
                 }
             })
             .collect();
 
+        let wasm_to_native_trampolines: Vec<_> = self
+            .outputs
+            .remove(&CompileKey::WASM_TO_NATIVE_TRAMPOLINE_KIND)
+            .unwrap_or_default()
+            .into_keys()
+            .map(|key| {
+                let (_, loc) = symbol_ids_and_locs[self.indices[&key]];
+                (ModuleInternedTypeIndex::from_u32(key.index), loc)
+            })
+            .collect();
+
         // If configured attempt to use static memory initialization which
         // can either at runtime be implemented as a single memcpy to
         // initialize memory or otherwise enabling virtual-memory-tricks
@@ -344,12 +469,14 @@ impl UnlinkedCompileOutputs {
         // table lazy init.
         // module.try_func_table_init();
 
+        obj_builder.append_module_info(&funcs, &wasm_to_native_trampolines);
         obj_builder.finish(output_buffer).unwrap();
 
         CompiledModuleInfo {
             module,
             funcs,
             types,
+            wasm_to_native_trampolines,
         }
     }
 }
@@ -380,8 +507,8 @@ impl CompileKey {
 
     pub const WASM_FUNCTION_KIND: u32 = Self::new_kind(0);
     // const ARRAY_TO_WASM_TRAMPOLINE_KIND: u32 = Self::new_kind(1);
-    // const NATIVE_TO_WASM_TRAMPOLINE_KIND: u32 = Self::new_kind(2);
-    // const WASM_TO_NATIVE_TRAMPOLINE_KIND: u32 = Self::new_kind(3);
+    pub const NATIVE_TO_WASM_TRAMPOLINE_KIND: u32 = Self::new_kind(2);
+    pub const WASM_TO_NATIVE_TRAMPOLINE_KIND: u32 = Self::new_kind(3);
     pub const WASM_TO_BUILTIN_TRAMPOLINE_KIND: u32 = Self::new_kind(4);
 
     const fn new_kind(kind: u32) -> u32 {
@@ -404,13 +531,13 @@ impl CompileKey {
         }
     }
 
-    // fn native_to_wasm_trampoline(module: StaticModuleIndex, index: DefinedFuncIndex) -> Self {
-    //     debug_assert_eq!(module.as_u32() & Self::KIND_MASK, 0);
-    //     Self {
-    //         namespace: Self::NATIVE_TO_WASM_TRAMPOLINE_KIND | module.as_u32(),
-    //         index: index.as_u32(),
-    //     }
-    // }
+    pub fn native_to_wasm_trampoline(module: StaticModuleIndex, index: DefinedFuncIndex) -> Self {
+        debug_assert_eq!(module.as_u32() & Self::KIND_MASK, 0);
+        Self {
+            namespace: Self::NATIVE_TO_WASM_TRAMPOLINE_KIND | module.as_u32(),
+            index: index.as_u32(),
+        }
+    }
 
     // fn array_to_wasm_trampoline(module: StaticModuleIndex, index: DefinedFuncIndex) -> Self {
     //     debug_assert_eq!(module.as_u32() & Self::KIND_MASK, 0);
@@ -419,11 +546,11 @@ impl CompileKey {
     //         index: index.as_u32(),
     //     }
     // }
-    //
-    // fn wasm_to_native_trampoline(index: ModuleInternedTypeIndex) -> Self {
-    //     Self {
-    //         namespace: Self::WASM_TO_NATIVE_TRAMPOLINE_KIND,
-    //         index: index.as_u32(),
-    //     }
-    // }
+
+    pub fn wasm_to_native_trampoline(index: ModuleInternedTypeIndex) -> Self {
+        Self {
+            namespace: Self::WASM_TO_NATIVE_TRAMPOLINE_KIND,
+            index: index.as_u32(),
+        }
+    }
 }
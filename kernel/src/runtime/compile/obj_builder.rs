@@ -1,22 +1,35 @@
 //! Support for building and parsing intermediate compilation artifacts in object format
 
 use crate::kconfig;
-use crate::runtime::compile::compiled_func::{CompiledFunction, RelocationTarget, TrapInfo};
-use crate::runtime::compile::{CompileOutput, FunctionLoc};
+use crate::runtime::compile::compiled_func::{
+    CompiledFunction, RelocationTarget, StackMapInfo, TrapInfo,
+};
+use crate::runtime::compile::{CompileOutput, CompiledFunctionInfo, FunctionLoc};
+use crate::runtime::config::Tunables;
+use crate::runtime::errors::CompileError;
 use crate::runtime::translate::DebugInfo;
 use crate::runtime::Engine;
 use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
+use core::fmt::Write;
 use core::ops::Range;
 use cranelift_codegen::control::ControlPlane;
+use cranelift_entity::PrimaryMap;
+use cranelift_wasm::wasmparser::WasmFeatures;
+use cranelift_wasm::{DefinedFuncIndex, ModuleInternedTypeIndex};
 use object::write::{
     Object, SectionId, StandardSegment, Symbol, SymbolId, SymbolSection, WritableBuffer,
 };
-use object::{LittleEndian, SectionKind, SymbolFlags, SymbolKind, SymbolScope, U32Bytes};
+use object::{
+    Bytes, LittleEndian, SectionKind, SymbolFlags, SymbolKind, SymbolScope, U32Bytes, U64Bytes,
+};
 
 pub const ELFOSABI_K23: u8 = 223;
 pub const ELF_K23_TRAPS: &str = ".k23.traps";
+pub const ELF_K23_STACK_MAPS: &str = ".k23.stack_maps";
 pub const ELF_K23_INFO: &str = ".k23.info";
 pub const ELF_K23_BTI: &str = ".k23.bti";
 pub const ELF_K23_ENGINE: &str = ".k23.engine";
@@ -50,8 +63,76 @@ impl<'obj> ObjectBuilder<'obj> {
     }
 
     /// Creates the `ELF_K23_ENGINE` section and writes the current engine configuration into it
-    #[allow(clippy::unused_self)]
-    pub fn append_engine_info(&mut self, _engine: &Engine) {}
+    pub fn append_engine_info(&mut self, engine: &Engine) {
+        let section = self.result.add_section(
+            self.result.segment_name(StandardSegment::Data).to_vec(),
+            ELF_K23_ENGINE.as_bytes().to_vec(),
+            SectionKind::ReadOnlyData,
+        );
+
+        let info = EngineInfo::for_engine(engine);
+        self.result
+            .append_section_data(section, &info.to_bytes(), 1);
+    }
+
+    /// Creates the `ELF_WASM_DATA` section and writes the original WASM binary into it, the module
+    /// metadata is recovered from it when the object is loaded again.
+    pub fn append_wasm_data(&mut self, wasm: &[u8]) {
+        let section = self.result.add_section(
+            self.result.segment_name(StandardSegment::Data).to_vec(),
+            ELF_WASM_DATA.as_bytes().to_vec(),
+            SectionKind::ReadOnlyData,
+        );
+
+        self.result.append_section_data(section, wasm, 1);
+    }
+
+    /// Creates the `ELF_K23_INFO` section and writes the locations of all compiled functions and
+    /// trampolines into it.
+    ///
+    /// The section is laid out as follows (all integers are little-endian `u32`s):
+    ///
+    /// ```text
+    /// num_funcs
+    /// [wasm_start, wasm_length, has_trampoline, trampoline_start, trampoline_length] * num_funcs
+    /// num_wasm_to_native_trampolines
+    /// [sig_index, start, length] * num_wasm_to_native_trampolines
+    /// ```
+    pub fn append_module_info(
+        &mut self,
+        funcs: &PrimaryMap<DefinedFuncIndex, CompiledFunctionInfo>,
+        wasm_to_native_trampolines: &[(ModuleInternedTypeIndex, FunctionLoc)],
+    ) {
+        let section = self.result.add_section(
+            self.result.segment_name(StandardSegment::Data).to_vec(),
+            ELF_K23_INFO.as_bytes().to_vec(),
+            SectionKind::ReadOnlyData,
+        );
+
+        let mut data: Vec<U32Bytes<LittleEndian>> = Vec::new();
+        let mut push = |val: u32| data.push(U32Bytes::new(LittleEndian, val));
+
+        push(u32::try_from(funcs.len()).unwrap());
+        for func in funcs.values() {
+            let trampoline = func.native_to_wasm_trampoline;
+
+            push(func.wasm_func_loc.start);
+            push(func.wasm_func_loc.length);
+            push(u32::from(trampoline.is_some()));
+            push(trampoline.map_or(0, |loc| loc.start));
+            push(trampoline.map_or(0, |loc| loc.length));
+        }
+
+        push(u32::try_from(wasm_to_native_trampolines.len()).unwrap());
+        for (sig_index, loc) in wasm_to_native_trampolines {
+            push(sig_index.as_u32());
+            push(loc.start);
+            push(loc.length);
+        }
+
+        self.result
+            .append_section_data(section, object::bytes_of_slice(&data), 4);
+    }
 
     pub fn append_debug_info(&mut self, info: &DebugInfo) {
         // let names_section = *self.names_section.get_or_insert_with(|| {
@@ -108,9 +189,6 @@ impl<'obj> ObjectBuilder<'obj> {
         self.result.append_section_data(section_id, data, 1);
     }
 
-    // /// Appends various bits of metadata about the current module
-    // pub fn append_module_artifacts(&mut self) {}
-
     /// Finished the object and flushes it into the given buffer
     pub fn finish<T: WritableBuffer>(self, buf: &mut T) -> object::write::Result<()> {
         self.result.emit(buf)
@@ -158,12 +236,14 @@ impl<'a, 'obj> TextSectionBuilder<'a, 'obj> {
     ) -> Vec<(SymbolId, FunctionLoc)> {
         let mut ret = Vec::with_capacity(funcs.len());
         let mut traps = TrapSectionBuilder::default();
+        let mut stack_maps = StackMapSectionBuilder::default();
 
         for output in funcs {
             let (sym, range) =
                 self.push_func(&output.symbol, &output.function, &resolve_reloc_target);
 
             traps.push_traps(&range, output.function.traps());
+            stack_maps.push_stack_maps(&range, output.function.stack_maps());
 
             let info = FunctionLoc {
                 start: u32::try_from(range.start).unwrap(),
@@ -174,6 +254,7 @@ impl<'a, 'obj> TextSectionBuilder<'a, 'obj> {
         }
 
         traps.append(self.obj);
+        stack_maps.append(self.obj);
 
         ret
     }
@@ -284,3 +365,245 @@ impl TrapSectionBuilder {
         obj.append_section_data(traps_section, &self.traps, 1);
     }
 }
+
+/// Builder for the `ELF_K23_STACK_MAPS` section.
+///
+/// The section is laid out as follows (all integers are little-endian `u32`s):
+///
+/// ```text
+/// num_stack_maps
+/// [offset] * num_stack_maps
+/// [position] * num_stack_maps
+/// [frame_size, num_slots, [slot] * num_slots] * num_stack_maps
+/// ```
+///
+/// The offsets are sorted, `position` is the index of the corresponding stack map in the
+/// trailing array of `u32`s. See [`stack_map_for_offset`](crate::runtime::stack_map::stack_map_for_offset).
+#[derive(Default)]
+struct StackMapSectionBuilder {
+    offsets: Vec<U32Bytes<LittleEndian>>,
+    positions: Vec<U32Bytes<LittleEndian>>,
+    stack_maps: Vec<U32Bytes<LittleEndian>>,
+}
+
+impl StackMapSectionBuilder {
+    pub fn push_stack_maps(
+        &mut self,
+        func: &Range<u64>,
+        stack_maps: impl ExactSizeIterator<Item = StackMapInfo>,
+    ) {
+        let func_start = u32::try_from(func.start).unwrap();
+
+        self.offsets.reserve_exact(stack_maps.len());
+        self.positions.reserve_exact(stack_maps.len());
+
+        for stack_map in stack_maps {
+            let position = u32::try_from(self.stack_maps.len()).unwrap();
+            self.offsets
+                .push(U32Bytes::new(LittleEndian, func_start + stack_map.offset));
+            self.positions.push(U32Bytes::new(LittleEndian, position));
+
+            let num_slots = u32::try_from(stack_map.slots.len()).unwrap();
+            self.stack_maps
+                .push(U32Bytes::new(LittleEndian, stack_map.frame_size));
+            self.stack_maps.push(U32Bytes::new(LittleEndian, num_slots));
+            self.stack_maps.extend(
+                stack_map
+                    .slots
+                    .into_iter()
+                    .map(|slot| U32Bytes::new(LittleEndian, slot)),
+            );
+        }
+    }
+
+    pub fn append(self, obj: &mut Object) {
+        let section = obj.add_section(
+            obj.segment_name(StandardSegment::Data).to_vec(),
+            ELF_K23_STACK_MAPS.as_bytes().to_vec(),
+            SectionKind::ReadOnlyData,
+        );
+
+        let amt = u32::try_from(self.offsets.len()).unwrap();
+        obj.append_section_data(section, &amt.to_le_bytes(), 4);
+        obj.append_section_data(section, object::bytes_of_slice(&self.offsets), 4);
+        obj.append_section_data(section, object::bytes_of_slice(&self.positions), 4);
+        obj.append_section_data(section, object::bytes_of_slice(&self.stack_maps), 4);
+    }
+}
+
+/// Parses the `ELF_K23_INFO` section written by [`ObjectBuilder::append_module_info`].
+///
+/// Returns `None` if the section is truncated.
+pub fn parse_module_info(
+    data: &[u8],
+) -> Option<(
+    PrimaryMap<DefinedFuncIndex, CompiledFunctionInfo>,
+    Vec<(ModuleInternedTypeIndex, FunctionLoc)>,
+)> {
+    let mut data = Bytes(data);
+
+    let num_funcs = read_u32(&mut data)?;
+    let mut funcs = PrimaryMap::with_capacity(num_funcs as usize);
+    for _ in 0..num_funcs {
+        let wasm_func_loc = read_loc(&mut data)?;
+        let has_trampoline = read_u32(&mut data)? != 0;
+        let trampoline = read_loc(&mut data)?;
+
+        funcs.push(CompiledFunctionInfo {
+            wasm_func_loc,
+            native_to_wasm_trampoline: has_trampoline.then_some(trampoline),
+        });
+    }
+
+    let num_trampolines = read_u32(&mut data)?;
+    let mut wasm_to_native_trampolines = Vec::with_capacity(num_trampolines as usize);
+    for _ in 0..num_trampolines {
+        let sig_index = ModuleInternedTypeIndex::from_u32(read_u32(&mut data)?);
+        wasm_to_native_trampolines.push((sig_index, read_loc(&mut data)?));
+    }
+
+    Some((funcs, wasm_to_native_trampolines))
+}
+
+/// The engine configuration a module was compiled with, stored in the `ELF_K23_ENGINE` section.
+///
+/// Compiled code bakes in the target, Cranelift settings and tunables, so a compiled module can
+/// only be loaded into an engine with the exact same configuration.
+#[derive(Debug)]
+pub struct EngineInfo {
+    /// The name of the Cranelift ISA. The extensions enabled by the target triple are part of
+    /// `flags`, the rest of the triple doesn't affect the generated code.
+    isa: String,
+    /// The shared and ISA-specific Cranelift settings, one `name = value` pair per line.
+    flags: String,
+    features: WasmFeatures,
+    tunables: Tunables,
+}
+
+impl EngineInfo {
+    pub fn for_engine(engine: &Engine) -> Self {
+        let isa = engine.compiler().target_isa();
+
+        let mut flags = isa.flags().to_string();
+        for flag in isa.isa_flags() {
+            writeln!(flags, "{flag}").unwrap();
+        }
+
+        Self {
+            isa: isa.name().to_string(),
+            flags,
+            features: engine.wasm_features(),
+            tunables: *engine.tunables(),
+        }
+    }
+
+    /// Checks that code compiled with this configuration can be run by `engine`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first mismatch between the two configurations.
+    pub fn check_compatible(&self, engine: &Engine) -> Result<(), CompileError> {
+        let expected = Self::for_engine(engine);
+
+        if self.isa != expected.isa {
+            return Err(CompileError::IncompatibleArtifact(format!(
+                "compiled for `{}`, but engine targets `{}`",
+                self.isa, expected.isa
+            )));
+        }
+
+        if self.flags != expected.flags {
+            return Err(CompileError::IncompatibleArtifact(
+                "compiled with different Cranelift settings".to_string(),
+            ));
+        }
+
+        let missing = self.features.difference(expected.features);
+        if !missing.is_empty() {
+            return Err(CompileError::MissingFeatures(missing));
+        }
+
+        if self.tunables != expected.tunables {
+            return Err(CompileError::IncompatibleArtifact(format!(
+                "compiled with tunables {:?}, but engine uses {:?}",
+                self.tunables, expected.tunables
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        for s in [&self.isa, &self.flags] {
+            out.extend_from_slice(&u32::try_from(s.len()).unwrap().to_le_bytes());
+            out.extend_from_slice(s.as_bytes());
+        }
+
+        out.extend_from_slice(&self.features.bits().to_le_bytes());
+        for val in [
+            self.tunables.static_memory_bound,
+            self.tunables.static_memory_offset_guard_size,
+            self.tunables.dynamic_memory_offset_guard_size,
+            self.tunables.dynamic_memory_growth_reserve,
+        ] {
+            out.extend_from_slice(&val.to_le_bytes());
+        }
+        out.push(u8::from(self.tunables.debug_assertions));
+        out.push(u8::from(self.tunables.consume_fuel));
+        out.push(u8::from(self.tunables.epoch_interruption));
+
+        out
+    }
+
+    /// Parses the contents of the `ELF_K23_ENGINE` section.
+    ///
+    /// Returns `None` if the section is truncated or otherwise malformed.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut data = Bytes(data);
+
+        let isa = read_str(&mut data)?;
+        let flags = read_str(&mut data)?;
+        let features = WasmFeatures::from_bits_truncate(read_u32(&mut data)?);
+        let tunables = Tunables {
+            static_memory_bound: read_u64(&mut data)?,
+            static_memory_offset_guard_size: read_u64(&mut data)?,
+            dynamic_memory_offset_guard_size: read_u64(&mut data)?,
+            dynamic_memory_growth_reserve: read_u64(&mut data)?,
+            debug_assertions: *data.read::<u8>().ok()? != 0,
+            consume_fuel: *data.read::<u8>().ok()? != 0,
+            epoch_interruption: *data.read::<u8>().ok()? != 0,
+        };
+
+        Some(Self {
+            isa,
+            flags,
+            features,
+            tunables,
+        })
+    }
+}
+
+fn read_u32(data: &mut Bytes) -> Option<u32> {
+    let val = data.read::<U32Bytes<LittleEndian>>().ok()?;
+    Some(val.get(LittleEndian))
+}
+
+fn read_u64(data: &mut Bytes) -> Option<u64> {
+    let val = data.read::<U64Bytes<LittleEndian>>().ok()?;
+    Some(val.get(LittleEndian))
+}
+
+fn read_str(data: &mut Bytes) -> Option<String> {
+    let len = read_u32(data)?;
+    let bytes = data.read_bytes(len as usize).ok()?;
+    String::from_utf8(bytes.0.to_vec()).ok()
+}
+
+fn read_loc(data: &mut Bytes) -> Option<FunctionLoc> {
+    Some(FunctionLoc {
+        start: read_u32(data)?,
+        length: read_u32(data)?,
+    })
+}
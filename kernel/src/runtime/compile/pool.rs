@@ -0,0 +1,228 @@
+//! A work-stealing pool that spreads compilation of function bodies across all harts.
+//!
+//! The hart compiling a module publishes its jobs as a [`Batch`] and starts working through
+//! them itself. Idle harts calling [`help`] join the published batch and claim jobs from the same
+//! shared counter until all jobs have been claimed. Results are stored at the index of their job,
+//! so the outcome is independent of which hart ran which job.
+//!
+//! Only one batch is published at a time. Should another hart start compiling while a batch is
+//! already published, it simply runs its own jobs sequentially.
+//!
+//! Idle harts wait for interrupts between attempts to help. Publishing a batch wakes them with a
+//! software interrupt. A hart that finds nothing to do right before the batch is published misses
+//! that interrupt, it checks again after the next timer interrupt at the latest.
+//!
+//! # Safety
+//!
+//! Batches live on the stack of the hart that submitted them and are handed to other harts as a
+//! type-erased [`BatchRef`]. This is sound because of the following invariant:
+//!
+//! A [`BatchRef`] is only dereferenced by a hart that either holds the [`CURRENT`] lock while the
+//! ref is stored in it, or that joined the batch by incrementing [`WORKERS`] under that lock and
+//! hasn't left it yet.
+//!
+//! The batch is published through a [`Published`] guard that borrows it. Dropping the guard,
+//! which also happens when unwinding, removes the batch from [`CURRENT`] and then waits until
+//! [`WORKERS`] drops to zero. Therefore the batch outlives every access made by other harts.
+
+use crate::arch;
+use crate::runtime::compile::{CompileInput, CompileOutput, Compiler};
+use crate::runtime::errors::CompileError;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::hint::spin_loop;
+use core::marker::PhantomData;
+use core::panic::AssertUnwindSafe;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use sync::Mutex;
+
+/// The batch currently open for idle harts to help with.
+static CURRENT: Mutex<Option<BatchRef>> = Mutex::new(None);
+/// The number of harts that joined the published batch and haven't left it yet.
+static WORKERS: AtomicUsize = AtomicUsize::new(0);
+
+/// A unit of work submitted to the pool.
+pub type Job<'a, T> = Box<dyn FnOnce() -> T + Send + 'a>;
+
+type JobResult<T> = Result<T, Box<dyn Any + Send>>;
+
+struct Batch<'a, T> {
+    jobs: Vec<Mutex<Option<Job<'a, T>>>>,
+    results: Vec<Mutex<Option<JobResult<T>>>>,
+    /// The index of the next unclaimed job.
+    next: AtomicUsize,
+}
+
+impl<'a, T> Batch<'a, T> {
+    fn new(jobs: Vec<Job<'a, T>>) -> Self {
+        let results = jobs.iter().map(|_| Mutex::new(None)).collect();
+
+        Self {
+            jobs: jobs.into_iter().map(|f| Mutex::new(Some(f))).collect(),
+            results,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn has_work(&self) -> bool {
+        self.next.load(Ordering::Relaxed) < self.jobs.len()
+    }
+
+    /// Claim and run jobs until there are none left.
+    fn work(&self) {
+        loop {
+            let index = self.next.fetch_add(1, Ordering::Relaxed);
+            let Some(job) = self.jobs.get(index) else {
+                break;
+            };
+
+            let f = job.lock().take().expect("job claimed twice");
+            // A panic must not take down the helping hart and leave the batch unfinished, so it is
+            // caught here and resumed on the hart that submitted the batch.
+            let result = panic_unwind::catch_unwind(AssertUnwindSafe(f));
+
+            *self.results[index].lock() = Some(result);
+        }
+    }
+
+    fn into_results(self) -> Vec<T> {
+        self.results
+            .into_iter()
+            .map(
+                |result| match result.into_inner().expect("all jobs have been run") {
+                    Ok(result) => result,
+                    Err(payload) => panic_unwind::resume_unwind(payload),
+                },
+            )
+            .collect()
+    }
+}
+
+/// A type-erased pointer to the published batch, see the module docs for when it is valid.
+#[derive(Clone, Copy)]
+struct BatchRef {
+    batch: NonNull<()>,
+    has_work: unsafe fn(NonNull<()>) -> bool,
+    work: unsafe fn(NonNull<()>),
+}
+
+// Safety: the batch only contains `Send` jobs and results behind locks, so it can be shared
+// between harts.
+unsafe impl Send for BatchRef {}
+
+impl BatchRef {
+    fn new<T: Send>(batch: &Batch<'_, T>) -> Self {
+        unsafe fn has_work<T>(batch: NonNull<()>) -> bool {
+            batch.cast::<Batch<'_, T>>().as_ref().has_work()
+        }
+
+        unsafe fn work<T>(batch: NonNull<()>) {
+            batch.cast::<Batch<'_, T>>().as_ref().work();
+        }
+
+        Self {
+            batch: NonNull::from(batch).cast(),
+            has_work: has_work::<T>,
+            work: work::<T>,
+        }
+    }
+}
+
+/// Keeps a batch published for as long as the batch is borrowed.
+struct Published<'b> {
+    _batch: PhantomData<&'b ()>,
+}
+
+impl<'b> Published<'b> {
+    /// Publish `batch`, returns `None` if another batch is already published.
+    fn new<T: Send>(batch: &'b Batch<'_, T>) -> Option<Self> {
+        let mut current = CURRENT.lock();
+        if current.is_some() {
+            return None;
+        }
+
+        *current = Some(BatchRef::new(batch));
+        arch::wake_all_harts();
+        Some(Self {
+            _batch: PhantomData,
+        })
+    }
+}
+
+impl Drop for Published<'_> {
+    fn drop(&mut self) {
+        // Harts join while holding the lock, so after clearing `CURRENT` no new hart can join and
+        // we only need to wait for the ones that already did.
+        CURRENT.lock().take();
+        while WORKERS.load(Ordering::Acquire) != 0 {
+            spin_loop();
+        }
+    }
+}
+
+/// Run all `jobs`, with the help of idle harts if there are any.
+///
+/// Returns once every job has finished, the results are in the same order as `jobs`. A panic in
+/// any of the jobs is resumed on the calling hart after all other jobs are done.
+pub fn run_all<'a, T: Send>(jobs: Vec<Job<'a, T>>) -> Vec<T> {
+    let batch = Batch::new(jobs);
+
+    {
+        let _published = Published::new(&batch);
+        batch.work();
+    }
+
+    batch.into_results()
+}
+
+/// Compile all `inputs`, with the help of idle harts if there are any.
+///
+/// The outputs are returned in the same order as `inputs`.
+pub fn compile_all<'a>(
+    compiler: &'a Compiler,
+    inputs: Vec<CompileInput<'a>>,
+) -> Vec<Result<CompileOutput, CompileError>> {
+    let jobs = inputs
+        .into_iter()
+        .map(|f| -> Job<'a, _> { Box::new(move || f(compiler)) })
+        .collect();
+
+    run_all(jobs)
+}
+
+/// Join the published batch, if it has unclaimed jobs, and help to run them.
+///
+/// Returns whether there was a batch to help with.
+pub fn help() -> bool {
+    let batch = {
+        let current = CURRENT.lock();
+        match *current {
+            // Safety: we hold the lock and the batch is published
+            Some(batch) if unsafe { (batch.has_work)(batch.batch) } => {
+                WORKERS.fetch_add(1, Ordering::Relaxed);
+                batch
+            }
+            _ => return false,
+        }
+    };
+
+    // Safety: we joined the batch above and only leave it below
+    unsafe { (batch.work)(batch.batch) };
+    WORKERS.fetch_sub(1, Ordering::Release);
+
+    true
+}
+
+/// Help other harts compile WASM modules, never returns.
+///
+/// This is the idle loop of all secondary harts until there is a scheduler to run them. The hart
+/// is suspended while there is nothing to help with.
+pub fn run_worker() -> ! {
+    loop {
+        if !help() {
+            arch::wait_for_interrupt(None);
+        }
+    }
+}
@@ -0,0 +1,812 @@
+//! A scalar fallback for fixed-width SIMD on harts without the RISC-V vector extension.
+//!
+//! Cranelift can only compile vector types to vector instructions, so functions are rewritten
+//! before they are handed to Cranelift: Every vector value is replaced by an `i128`, which lives
+//! in a pair of integer registers, and every vector instruction is replaced by the equivalent
+//! operations on the individual lanes. Lanes are taken out of the two 64-bit halves of the value
+//! with shifts and masks and operated on as 64-bit integers or as floats, then packed back
+//! together.
+//!
+//! Function signatures are rewritten as well, which is fine since all code calling WASM functions,
+//! including the trampolines, is compiled by the same engine.
+
+use crate::runtime::errors::CompileError;
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use cranelift_codegen::cursor::{Cursor, FuncCursor};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::types::{F32, F64, I128, I64};
+use cranelift_codegen::ir::{
+    Function, Inst, InstBuilder, InstructionData, MemFlags, Opcode, Type, Value,
+};
+use cranelift_codegen::CodegenError;
+use cranelift_entity::packed_option::PackedOption;
+use cranelift_entity::SecondaryMap;
+
+/// Rewrites all vector values and instructions in `func` to scalar code.
+///
+/// # Errors
+///
+/// Returns an error if `func` uses a vector instruction that has no scalar fallback.
+pub fn scalarize(func: &mut Function) -> Result<(), CompileError> {
+    // The original types are needed to lower the instructions, so they are recorded before all
+    // vector values are retyped.
+    let mut insts = Vec::new();
+    for block in func.layout.blocks() {
+        for inst in func.layout.block_insts(block) {
+            let opcode = func.dfg.insts[inst].opcode();
+            // calls and branches just pass the retyped values along
+            if opcode.is_call() || opcode.is_branch() || opcode.is_terminator() {
+                continue;
+            }
+
+            let result_ty = func
+                .dfg
+                .inst_results(inst)
+                .first()
+                .map(|v| func.dfg.value_type(*v));
+            let arg_tys: Vec<_> = func
+                .dfg
+                .inst_args(inst)
+                .iter()
+                .map(|v| func.dfg.value_type(*v))
+                .collect();
+
+            if result_ty.is_some_and(Type::is_vector) || arg_tys.iter().any(|ty| ty.is_vector()) {
+                insts.push(VectorInst {
+                    inst,
+                    result_ty,
+                    arg_tys,
+                });
+            }
+        }
+    }
+
+    retype_values(func);
+
+    let stencil = &mut func.stencil;
+    let signatures =
+        core::iter::once(&mut stencil.signature).chain(stencil.dfg.signatures.values_mut());
+    for sig in signatures {
+        for param in sig.params.iter_mut().chain(sig.returns.iter_mut()) {
+            if param.value_type.is_vector() {
+                param.value_type = I128;
+            }
+        }
+    }
+
+    for inst in &insts {
+        lower(func, inst)?;
+    }
+
+    Ok(())
+}
+
+/// Replaces every vector value in `func` with a new `i128` value and rewrites all uses.
+fn retype_values(func: &mut Function) {
+    func.dfg.resolve_all_aliases();
+
+    let stencil = &mut func.stencil;
+    let mut retyped: SecondaryMap<Value, PackedOption<Value>> = SecondaryMap::new();
+    for block in stencil.layout.blocks() {
+        for param in stencil.dfg.block_params(block).to_vec() {
+            if stencil.dfg.value_type(param).is_vector() {
+                retyped[param] = stencil.dfg.replace_block_param(param, I128).into();
+            }
+        }
+
+        for inst in stencil.layout.block_insts(block) {
+            for result in stencil.dfg.inst_results(inst).to_vec() {
+                if stencil.dfg.value_type(result).is_vector() {
+                    retyped[result] = stencil.dfg.replace_result(result, I128).into();
+                }
+            }
+        }
+    }
+
+    for block in stencil.layout.blocks() {
+        for inst in stencil.layout.block_insts(block) {
+            stencil
+                .dfg
+                .map_inst_values(inst, |v| retyped[v].expand().unwrap_or(v));
+        }
+    }
+}
+
+/// A vector instruction along with the types it had before vector values were retyped.
+struct VectorInst {
+    inst: Inst,
+    result_ty: Option<Type>,
+    arg_tys: Vec<Type>,
+}
+
+/// What a vector instruction is replaced with.
+enum Lowered {
+    /// The low and high half of a vector.
+    Vector([Value; 2]),
+    /// A value of the same type as the result of the instruction.
+    Value(Value),
+    /// Nothing, the instruction has no results.
+    Nothing,
+}
+
+#[allow(clippy::too_many_lines)]
+fn lower(func: &mut Function, vector_inst: &VectorInst) -> Result<(), CompileError> {
+    let VectorInst {
+        inst,
+        result_ty,
+        ref arg_tys,
+    } = *vector_inst;
+    let data = func.dfg.insts[inst];
+    let args = func.dfg.inst_args(inst).to_vec();
+    let opcode = data.opcode();
+    let mut cx = LowerCtx {
+        pos: FuncCursor::new(func).at_inst(inst),
+    };
+
+    // the vector type of the result, or of the first operand for instructions producing scalars
+    let ty = match result_ty {
+        Some(ty) if ty.is_vector() => ty,
+        _ => arg_tys[0],
+    };
+    let bits = ty.lane_bits();
+
+    let lowered = match opcode {
+        Opcode::Bitcast => Lowered::Value(args[0]),
+        Opcode::Vconst => {
+            let InstructionData::UnaryConst {
+                constant_handle, ..
+            } = data
+            else {
+                unreachable!()
+            };
+            let bytes = cx.pos.func.dfg.constants.get(constant_handle).as_slice();
+            let lo = i64::from_le_bytes(bytes[0..8].try_into().unwrap());
+            let hi = i64::from_le_bytes(bytes[8..16].try_into().unwrap());
+            Lowered::Vector([cx.iconst(lo), cx.iconst(hi)])
+        }
+        Opcode::Load => {
+            let flags = data.memflags().unwrap();
+            let offset = i32::from(data.load_store_offset().unwrap());
+            let (addr, offset_hi) = cx.high_half_addr(args[0], offset);
+
+            let lo = cx.pos.ins().load(I64, flags, args[0], offset);
+            let hi = cx.pos.ins().load(I64, flags, addr, offset_hi);
+            Lowered::Vector([lo, hi])
+        }
+        Opcode::Store => {
+            let flags = data.memflags().unwrap();
+            let offset = i32::from(data.load_store_offset().unwrap());
+            let (addr, offset_hi) = cx.high_half_addr(args[1], offset);
+            let [lo, hi] = cx.halves(args[0]);
+
+            // Only the high half can be out of bounds, storing it first makes sure a trapping
+            // store doesn't modify memory.
+            cx.pos.ins().store(flags, hi, addr, offset_hi);
+            cx.pos.ins().store(flags, lo, args[1], offset);
+            Lowered::Nothing
+        }
+        Opcode::Sload8x8
+        | Opcode::Uload8x8
+        | Opcode::Sload16x4
+        | Opcode::Uload16x4
+        | Opcode::Sload32x2
+        | Opcode::Uload32x2 => {
+            let flags = data.memflags().unwrap();
+            let offset = data.load_store_offset().unwrap();
+            let signed = matches!(
+                opcode,
+                Opcode::Sload8x8 | Opcode::Sload16x4 | Opcode::Sload32x2
+            );
+
+            let half = cx.pos.ins().load(I64, flags, args[0], offset);
+            let lanes = (0..ty.lane_count())
+                .map(|i| cx.extract(half, i * bits / 2, bits / 2, signed))
+                .collect::<Vec<_>>();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::Select => {
+            let [x_lo, x_hi] = cx.halves(args[1]);
+            let [y_lo, y_hi] = cx.halves(args[2]);
+            Lowered::Vector([
+                cx.pos.ins().select(args[0], x_lo, y_lo),
+                cx.pos.ins().select(args[0], x_hi, y_hi),
+            ])
+        }
+        Opcode::Band | Opcode::Bor | Opcode::Bxor | Opcode::BandNot => {
+            let x = cx.halves(args[0]);
+            let y = cx.halves(args[1]);
+            Lowered::Vector([0, 1].map(|i| {
+                let ins = cx.pos.ins();
+                match opcode {
+                    Opcode::Band => ins.band(x[i], y[i]),
+                    Opcode::Bor => ins.bor(x[i], y[i]),
+                    Opcode::Bxor => ins.bxor(x[i], y[i]),
+                    _ => ins.band_not(x[i], y[i]),
+                }
+            }))
+        }
+        Opcode::Bnot => {
+            let x = cx.halves(args[0]);
+            Lowered::Vector(x.map(|half| cx.pos.ins().bnot(half)))
+        }
+        Opcode::Bitselect => {
+            let c = cx.halves(args[0]);
+            let x = cx.halves(args[1]);
+            let y = cx.halves(args[2]);
+            Lowered::Vector([0, 1].map(|i| {
+                let x = cx.pos.ins().band(x[i], c[i]);
+                let y = cx.pos.ins().band_not(y[i], c[i]);
+                cx.pos.ins().bor(x, y)
+            }))
+        }
+        Opcode::Splat => {
+            let lane = cx.to_bits(args[0], arg_tys[0]);
+            let lanes = vec![lane; ty.lane_count() as usize];
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::ScalarToVector => {
+            let mut lanes = vec![cx.iconst(0); ty.lane_count() as usize];
+            lanes[0] = cx.to_bits(args[0], arg_tys[0]);
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::Extractlane => {
+            let InstructionData::BinaryImm8 { imm, .. } = data else {
+                unreachable!()
+            };
+            let halves = cx.halves(args[0]);
+            let lane = cx.lane(halves, u32::from(imm), bits, false);
+            Lowered::Value(cx.from_bits(lane, ty.lane_type()))
+        }
+        Opcode::Insertlane => {
+            let InstructionData::TernaryImm8 { imm, .. } = data else {
+                unreachable!()
+            };
+            let mut lanes = cx.lanes(args[0], ty, false);
+            lanes[usize::from(imm)] = cx.to_bits(args[1], arg_tys[1]);
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::Shuffle => {
+            let InstructionData::Shuffle { imm, .. } = data else {
+                unreachable!()
+            };
+            let mask = cx
+                .pos
+                .func
+                .dfg
+                .immediates
+                .get(imm)
+                .unwrap()
+                .as_slice()
+                .to_vec();
+            let mut bytes = cx.lanes(args[0], ty, false);
+            bytes.extend(cx.lanes(args[1], ty, false));
+            let zero = cx.iconst(0);
+
+            let lanes: Vec<_> = mask
+                .iter()
+                .map(|i| bytes.get(usize::from(*i)).copied().unwrap_or(zero))
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::Swizzle => {
+            let indices = cx.lanes(args[1], ty, false);
+            let zero = cx.iconst(0);
+
+            let lanes: Vec<_> = indices
+                .into_iter()
+                .map(|index| {
+                    let amount = cx.pos.ins().ishl_imm(index, 3);
+                    let shifted = cx.pos.ins().ushr(args[0], amount);
+                    let [lo, _] = cx.halves(shifted);
+                    let byte = cx.pos.ins().band_imm(lo, 0xff);
+                    let in_range = cx.pos.ins().icmp_imm(IntCC::UnsignedLessThan, index, 16);
+                    cx.pos.ins().select(in_range, byte, zero)
+                })
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::VanyTrue => {
+            let [lo, hi] = cx.halves(args[0]);
+            let any = cx.pos.ins().bor(lo, hi);
+            Lowered::Value(cx.pos.ins().icmp_imm(IntCC::NotEqual, any, 0))
+        }
+        Opcode::VallTrue => {
+            let lanes = cx.lanes(args[0], ty, false);
+            let mut all = None;
+            for lane in lanes {
+                let is_true = cx.pos.ins().icmp_imm(IntCC::NotEqual, lane, 0);
+                all = Some(match all {
+                    Some(all) => cx.pos.ins().band(all, is_true),
+                    None => is_true,
+                });
+            }
+            Lowered::Value(all.unwrap())
+        }
+        Opcode::VhighBits => {
+            let lanes = cx.lanes(args[0], ty, false);
+            let mut mask = cx.iconst(0);
+            for (i, lane) in (0_i64..).zip(lanes) {
+                let bit = cx.pos.ins().ushr_imm(lane, i64::from(bits - 1));
+                let bit = cx.pos.ins().ishl_imm(bit, i);
+                mask = cx.pos.ins().bor(mask, bit);
+            }
+
+            let result_ty = result_ty.unwrap();
+            if result_ty != I64 {
+                mask = cx.pos.ins().ireduce(result_ty, mask);
+            }
+            Lowered::Value(mask)
+        }
+        Opcode::Iadd
+        | Opcode::Isub
+        | Opcode::Imul
+        | Opcode::Smin
+        | Opcode::Smax
+        | Opcode::Umin
+        | Opcode::Umax
+        | Opcode::SaddSat
+        | Opcode::SsubSat
+        | Opcode::UaddSat
+        | Opcode::UsubSat
+        | Opcode::AvgRound
+        | Opcode::SqmulRoundSat => {
+            let signed = matches!(
+                opcode,
+                Opcode::Smin
+                    | Opcode::Smax
+                    | Opcode::SaddSat
+                    | Opcode::SsubSat
+                    | Opcode::SqmulRoundSat
+            );
+            let x = cx.lanes(args[0], ty, signed);
+            let y = cx.lanes(args[1], ty, signed);
+
+            let lanes: Vec<_> = x
+                .into_iter()
+                .zip(y)
+                .map(|(x, y)| cx.int_binary(opcode, bits, x, y))
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::Ineg | Opcode::Iabs | Opcode::Popcnt => {
+            let lanes: Vec<_> = cx
+                .lanes(args[0], ty, opcode == Opcode::Iabs)
+                .into_iter()
+                .map(|x| match opcode {
+                    Opcode::Ineg => cx.pos.ins().ineg(x),
+                    Opcode::Iabs => cx.pos.ins().iabs(x),
+                    _ => cx.pos.ins().popcnt(x),
+                })
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::IaddPairwise => {
+            let mut x = cx.lanes(args[0], ty, false);
+            x.extend(cx.lanes(args[1], ty, false));
+
+            let lanes: Vec<_> = x
+                .chunks_exact(2)
+                .map(|pair| cx.pos.ins().iadd(pair[0], pair[1]))
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::Ishl | Opcode::Ushr | Opcode::Sshr => {
+            let amount = if arg_tys[1] == I64 {
+                args[1]
+            } else {
+                cx.pos.ins().uextend(I64, args[1])
+            };
+            let amount = cx.pos.ins().band_imm(amount, i64::from(bits - 1));
+
+            let lanes: Vec<_> = cx
+                .lanes(args[0], ty, opcode == Opcode::Sshr)
+                .into_iter()
+                .map(|x| match opcode {
+                    Opcode::Ishl => cx.pos.ins().ishl(x, amount),
+                    Opcode::Ushr => cx.pos.ins().ushr(x, amount),
+                    _ => cx.pos.ins().sshr(x, amount),
+                })
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::Icmp => {
+            let cond = data.cond_code().unwrap();
+            let signed = matches!(
+                cond,
+                IntCC::SignedLessThan
+                    | IntCC::SignedLessThanOrEqual
+                    | IntCC::SignedGreaterThan
+                    | IntCC::SignedGreaterThanOrEqual
+            );
+            let x = cx.lanes(args[0], ty, signed);
+            let y = cx.lanes(args[1], ty, signed);
+
+            let lanes: Vec<_> = x
+                .into_iter()
+                .zip(y)
+                .map(|(x, y)| {
+                    let c = cx.pos.ins().icmp(cond, x, y);
+                    cx.pos.ins().bmask(I64, c)
+                })
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::Fcmp => {
+            let cond = data.fp_cond_code().unwrap();
+            let float_ty = arg_tys[0];
+            let x = cx.float_lanes(args[0], float_ty);
+            let y = cx.float_lanes(args[1], float_ty);
+
+            let lanes: Vec<_> = x
+                .into_iter()
+                .zip(y)
+                .map(|(x, y)| {
+                    let c = cx.pos.ins().fcmp(cond, x, y);
+                    cx.pos.ins().bmask(I64, c)
+                })
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::Fabs
+        | Opcode::Fneg
+        | Opcode::Sqrt
+        | Opcode::Ceil
+        | Opcode::Floor
+        | Opcode::Trunc
+        | Opcode::Nearest => {
+            let lanes: Vec<_> = cx
+                .float_lanes(args[0], ty)
+                .into_iter()
+                .map(|x| {
+                    let ins = cx.pos.ins();
+                    let x = match opcode {
+                        Opcode::Fabs => ins.fabs(x),
+                        Opcode::Fneg => ins.fneg(x),
+                        Opcode::Sqrt => ins.sqrt(x),
+                        Opcode::Ceil => ins.ceil(x),
+                        Opcode::Floor => ins.floor(x),
+                        Opcode::Trunc => ins.trunc(x),
+                        _ => ins.nearest(x),
+                    };
+                    cx.to_bits(x, ty.lane_type())
+                })
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::Fadd | Opcode::Fsub | Opcode::Fmul | Opcode::Fdiv | Opcode::Fmin | Opcode::Fmax => {
+            let x = cx.float_lanes(args[0], ty);
+            let y = cx.float_lanes(args[1], ty);
+
+            let lanes: Vec<_> = x
+                .into_iter()
+                .zip(y)
+                .map(|(x, y)| {
+                    let ins = cx.pos.ins();
+                    let x = match opcode {
+                        Opcode::Fadd => ins.fadd(x, y),
+                        Opcode::Fsub => ins.fsub(x, y),
+                        Opcode::Fmul => ins.fmul(x, y),
+                        Opcode::Fdiv => ins.fdiv(x, y),
+                        Opcode::Fmin => ins.fmin(x, y),
+                        _ => ins.fmax(x, y),
+                    };
+                    cx.to_bits(x, ty.lane_type())
+                })
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::Fma => {
+            let x = cx.float_lanes(args[0], ty);
+            let y = cx.float_lanes(args[1], ty);
+            let z = cx.float_lanes(args[2], ty);
+
+            let lanes: Vec<_> = (0..x.len())
+                .map(|i| {
+                    let x = cx.pos.ins().fma(x[i], y[i], z[i]);
+                    cx.to_bits(x, ty.lane_type())
+                })
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::FcvtFromSint | Opcode::FcvtFromUint => {
+            let signed = opcode == Opcode::FcvtFromSint;
+
+            let lanes: Vec<_> = cx
+                .lanes(args[0], arg_tys[0], signed)
+                .into_iter()
+                .map(|x| {
+                    let x = if signed {
+                        cx.pos.ins().fcvt_from_sint(ty.lane_type(), x)
+                    } else {
+                        cx.pos.ins().fcvt_from_uint(ty.lane_type(), x)
+                    };
+                    cx.to_bits(x, ty.lane_type())
+                })
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::FcvtToSintSat | Opcode::FcvtToUintSat => {
+            let lanes: Vec<_> = cx
+                .float_lanes(args[0], arg_tys[0])
+                .into_iter()
+                .map(|x| {
+                    let x = if opcode == Opcode::FcvtToSintSat {
+                        cx.pos.ins().fcvt_to_sint_sat(ty.lane_type(), x)
+                    } else {
+                        cx.pos.ins().fcvt_to_uint_sat(ty.lane_type(), x)
+                    };
+                    cx.to_bits(x, ty.lane_type())
+                })
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::Fvdemote => {
+            let x = cx.float_lanes(args[0], arg_tys[0]);
+            let zero = cx.iconst(0);
+
+            let mut lanes = vec![zero; ty.lane_count() as usize];
+            for (lane, x) in lanes.iter_mut().zip(x) {
+                let x = cx.pos.ins().fdemote(F32, x);
+                *lane = cx.to_bits(x, F32);
+            }
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::FvpromoteLow => {
+            let x = cx.float_lanes(args[0], arg_tys[0]);
+
+            let lanes: Vec<_> = x[..ty.lane_count() as usize]
+                .iter()
+                .map(|x| {
+                    let x = cx.pos.ins().fpromote(F64, *x);
+                    cx.to_bits(x, F64)
+                })
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::Snarrow | Opcode::Unarrow | Opcode::Uunarrow => {
+            let signed = opcode != Opcode::Uunarrow;
+            let mut x = cx.lanes(args[0], arg_tys[0], signed);
+            x.extend(cx.lanes(args[1], arg_tys[1], signed));
+
+            let (min, max) = if opcode == Opcode::Snarrow {
+                (-(1_i64 << (bits - 1)), (1_i64 << (bits - 1)) - 1)
+            } else {
+                (0, (1_i64 << bits) - 1)
+            };
+            let lanes: Vec<_> = x
+                .into_iter()
+                .map(|x| {
+                    let x = if signed {
+                        let min = cx.iconst(min);
+                        cx.pos.ins().smax(x, min)
+                    } else {
+                        x
+                    };
+                    let max = cx.iconst(max);
+                    if signed {
+                        cx.pos.ins().smin(x, max)
+                    } else {
+                        cx.pos.ins().umin(x, max)
+                    }
+                })
+                .collect();
+            Lowered::Vector(cx.join(&lanes, bits))
+        }
+        Opcode::SwidenLow | Opcode::SwidenHigh | Opcode::UwidenLow | Opcode::UwidenHigh => {
+            let signed = matches!(opcode, Opcode::SwidenLow | Opcode::SwidenHigh);
+            let x = cx.lanes(args[0], arg_tys[0], signed);
+
+            let count = ty.lane_count() as usize;
+            let lanes = if matches!(opcode, Opcode::SwidenLow | Opcode::UwidenLow) {
+                &x[..count]
+            } else {
+                &x[count..]
+            };
+            Lowered::Vector(cx.join(lanes, bits))
+        }
+        _ => {
+            return Err(CompileError::Compile(CodegenError::Unsupported(format!(
+                "no scalar fallback for the vector instruction `{opcode}`"
+            ))))
+        }
+    };
+
+    let mut pos = cx.pos;
+    match lowered {
+        Lowered::Vector([lo, hi]) => {
+            pos.func.dfg.replace(inst).iconcat(lo, hi);
+        }
+        Lowered::Value(value) => {
+            let result = pos.func.dfg.first_result(inst);
+            pos.func.dfg.clear_results(inst);
+            pos.func.dfg.change_to_alias(result, value);
+            pos.remove_inst();
+        }
+        Lowered::Nothing => {
+            pos.remove_inst();
+        }
+    }
+
+    Ok(())
+}
+
+/// Inserts the scalar code in front of the instruction being lowered.
+struct LowerCtx<'f> {
+    pos: FuncCursor<'f>,
+}
+
+impl LowerCtx<'_> {
+    fn iconst(&mut self, imm: i64) -> Value {
+        self.pos.ins().iconst(I64, imm)
+    }
+
+    /// Splits a retyped vector into its low and high 64 bits.
+    fn halves(&mut self, vector: Value) -> [Value; 2] {
+        let (lo, hi) = self.pos.ins().isplit(vector);
+        [lo, hi]
+    }
+
+    /// The address and offset of the high half of the vector at `addr + offset`.
+    fn high_half_addr(&mut self, addr: Value, offset: i32) -> (Value, i32) {
+        match offset.checked_add(8) {
+            Some(offset) => (addr, offset),
+            None => (self.pos.ins().iadd_imm(addr, 8), offset),
+        }
+    }
+
+    /// Extracts the `bits` wide integer starting at bit `start` of `half`, zero or sign extended
+    /// to 64 bits.
+    fn extract(&mut self, half: Value, start: u32, bits: u32, signed: bool) -> Value {
+        if bits == 64 {
+            return half;
+        }
+
+        let ins = self.pos.ins();
+        if signed {
+            let x = ins.ishl_imm(half, i64::from(64 - start - bits));
+            self.pos.ins().sshr_imm(x, i64::from(64 - bits))
+        } else {
+            let x = if start == 0 {
+                half
+            } else {
+                ins.ushr_imm(half, i64::from(start))
+            };
+            self.pos.ins().band_imm(x, lane_mask(bits))
+        }
+    }
+
+    /// Extracts lane `index` of a vector with `bits` wide lanes.
+    fn lane(&mut self, halves: [Value; 2], index: u32, bits: u32, signed: bool) -> Value {
+        let start = index * bits;
+        self.extract(halves[(start / 64) as usize], start % 64, bits, signed)
+    }
+
+    /// Extracts all lanes of a vector of type `ty` as 64-bit integers.
+    fn lanes(&mut self, vector: Value, ty: Type, signed: bool) -> Vec<Value> {
+        let halves = self.halves(vector);
+        (0..ty.lane_count())
+            .map(|i| self.lane(halves, i, ty.lane_bits(), signed))
+            .collect()
+    }
+
+    /// Extracts all lanes of a float vector of type `ty`.
+    fn float_lanes(&mut self, vector: Value, ty: Type) -> Vec<Value> {
+        self.lanes(vector, ty, false)
+            .into_iter()
+            .map(|lane| self.from_bits(lane, ty.lane_type()))
+            .collect()
+    }
+
+    /// Packs `bits` wide lanes, given as 64-bit integers, into the two halves of a vector.
+    fn join(&mut self, lanes: &[Value], bits: u32) -> [Value; 2] {
+        if bits == 64 {
+            return [lanes[0], lanes[1]];
+        }
+
+        let per_half = (64 / bits) as usize;
+        [0, 1].map(|half| {
+            let mut acc = None;
+            for (i, lane) in (0_u32..).zip(&lanes[half * per_half..(half + 1) * per_half]) {
+                let lane = self.pos.ins().band_imm(*lane, lane_mask(bits));
+                let lane = if i == 0 {
+                    lane
+                } else {
+                    self.pos.ins().ishl_imm(lane, i64::from(i * bits))
+                };
+                acc = Some(match acc {
+                    Some(acc) => self.pos.ins().bor(acc, lane),
+                    None => lane,
+                });
+            }
+            acc.unwrap()
+        })
+    }
+
+    /// Converts a lane value of type `ty` to a 64-bit integer.
+    fn to_bits(&mut self, x: Value, ty: Type) -> Value {
+        match ty {
+            I64 => x,
+            F64 => self.pos.ins().bitcast(I64, MemFlags::new(), x),
+            F32 => {
+                let x = self.pos.ins().bitcast(ty.as_int(), MemFlags::new(), x);
+                self.pos.ins().uextend(I64, x)
+            }
+            _ => self.pos.ins().uextend(I64, x),
+        }
+    }
+
+    /// Converts a lane, given as 64-bit integer, to a value of type `ty`.
+    fn from_bits(&mut self, x: Value, ty: Type) -> Value {
+        match ty {
+            I64 => x,
+            F64 => self.pos.ins().bitcast(F64, MemFlags::new(), x),
+            F32 => {
+                let x = self.pos.ins().ireduce(ty.as_int(), x);
+                self.pos.ins().bitcast(F32, MemFlags::new(), x)
+            }
+            _ => self.pos.ins().ireduce(ty, x),
+        }
+    }
+
+    /// Applies a lane-wise binary integer operation to two `bits` wide lanes, extended to 64 bits
+    /// as required by the operation.
+    fn int_binary(&mut self, opcode: Opcode, bits: u32, x: Value, y: Value) -> Value {
+        let signed_min = -(1_i64 << (bits - 1));
+        let signed_max = (1_i64 << (bits - 1)) - 1;
+
+        let ins = self.pos.ins();
+        match opcode {
+            Opcode::Iadd => ins.iadd(x, y),
+            Opcode::Isub => ins.isub(x, y),
+            Opcode::Imul => ins.imul(x, y),
+            Opcode::Smin => ins.smin(x, y),
+            Opcode::Smax => ins.smax(x, y),
+            Opcode::Umin => ins.umin(x, y),
+            Opcode::Umax => ins.umax(x, y),
+            Opcode::SaddSat | Opcode::SsubSat => {
+                let x = if opcode == Opcode::SaddSat {
+                    ins.iadd(x, y)
+                } else {
+                    ins.isub(x, y)
+                };
+                self.clamp_signed(x, signed_min, signed_max)
+            }
+            Opcode::UaddSat => {
+                let x = ins.iadd(x, y);
+                let max = self.iconst(lane_mask(bits));
+                self.pos.ins().umin(x, max)
+            }
+            Opcode::UsubSat => {
+                let x = ins.isub(x, y);
+                let zero = self.iconst(0);
+                self.pos.ins().smax(x, zero)
+            }
+            Opcode::AvgRound => {
+                let x = ins.iadd(x, y);
+                let x = self.pos.ins().iadd_imm(x, 1);
+                self.pos.ins().ushr_imm(x, 1)
+            }
+            Opcode::SqmulRoundSat => {
+                let x = ins.imul(x, y);
+                let x = self.pos.ins().iadd_imm(x, 1_i64 << (bits - 2));
+                let x = self.pos.ins().sshr_imm(x, i64::from(bits - 1));
+                self.clamp_signed(x, signed_min, signed_max)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn clamp_signed(&mut self, x: Value, min: i64, max: i64) -> Value {
+        let min = self.iconst(min);
+        let max = self.iconst(max);
+        let x = self.pos.ins().smax(x, min);
+        self.pos.ins().smin(x, max)
+    }
+}
+
+/// A mask of the low `bits` bits, `bits` must be less than 64.
+fn lane_mask(bits: u32) -> i64 {
+    (1_i64 << bits) - 1
+}
@@ -0,0 +1,316 @@
+use crate::kconfig;
+use crate::runtime::errors::ConfigError;
+use cranelift_codegen::isa::OwnedTargetIsa;
+use cranelift_codegen::settings::Configurable;
+use cranelift_wasm::wasmparser::WasmFeatures;
+use target_lexicon::{Architecture, Triple};
+
+/// How much effort Cranelift should spend on optimizing the generated code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptLevel {
+    /// No optimizations, fastest compilation.
+    None,
+    /// Optimize for speed.
+    Speed,
+    /// Optimize for speed and code size.
+    SpeedAndSize,
+}
+
+/// Parameters that influence how WASM is translated and how the runtime lays out its data
+/// structures. Code compiled with one set of tunables can only run with the same tunables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tunables {
+    /// The size of the address space reserved for static memories, memories with a larger
+    /// maximum are dynamic.
+    pub static_memory_bound: u64,
+    /// The size of the unmapped region following static memories. Accesses with a static offset
+    /// smaller than this need no bounds check at all.
+    pub static_memory_offset_guard_size: u64,
+    /// The size of the unmapped region following dynamic memories.
+    pub dynamic_memory_offset_guard_size: u64,
+    /// The amount of address space reserved beyond the current size of dynamic memories.
+    pub dynamic_memory_growth_reserve: u64,
+    /// Whether to emit runtime checks of the runtime's own invariants into compiled code.
+    pub debug_assertions: bool,
+    /// Whether compiled code consumes fuel from its store and traps once all fuel is consumed.
+    pub consume_fuel: bool,
+    /// Whether compiled code checks the epoch deadline of its store.
+    pub epoch_interruption: bool,
+}
+
+impl Default for Tunables {
+    fn default() -> Self {
+        Self {
+            // 4 GiB covers the entire index space of 32-bit memories
+            static_memory_bound: 0x1_0000_0000,
+            static_memory_offset_guard_size: 0x8000_0000,
+            dynamic_memory_offset_guard_size: 0x1_0000,
+            dynamic_memory_growth_reserve: 0x20_0000,
+            debug_assertions: cfg!(debug_assertions),
+            consume_fuel: false,
+            epoch_interruption: false,
+        }
+    }
+}
+
+/// Global configuration used to create an [`Engine`](crate::runtime::Engine).
+///
+/// All methods follow the builder pattern and can be chained:
+///
+/// ```rust,ignore
+/// let mut config = Config::default();
+/// config
+///     .opt_level(OptLevel::Speed)
+///     .wasm_simd(false);
+///
+/// let engine = Engine::new(&config)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Config {
+    target: Triple,
+    pub(crate) features: WasmFeatures,
+    pub(crate) tunables: Tunables,
+    pub(crate) stack_size: usize,
+    pub(crate) parallel_compilation: bool,
+    opt_level: OptLevel,
+    spectre_mitigations: bool,
+    riscv_vector: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let riscv_vector = host_has_vector_extension();
+
+        let mut features = WasmFeatures::default()
+            | WasmFeatures::TAIL_CALL
+            | WasmFeatures::MULTI_MEMORY
+            | WasmFeatures::MEMORY64
+            | WasmFeatures::THREADS;
+        // The scalar fallback doesn't cover every SIMD instruction, so SIMD has to be enabled
+        // explicitly on harts without the vector extension
+        features.set(
+            WasmFeatures::SIMD | WasmFeatures::RELAXED_SIMD,
+            riscv_vector,
+        );
+
+        Self {
+            target: target_lexicon::HOST,
+            features,
+            tunables: Tunables::default(),
+            stack_size: kconfig::GUEST_STACK_SIZE_PAGES * kconfig::PAGE_SIZE,
+            parallel_compilation: true,
+            opt_level: OptLevel::SpeedAndSize,
+            spectre_mitigations: true,
+            riscv_vector,
+        }
+    }
+}
+
+#[cfg(target_os = "none")]
+fn host_has_vector_extension() -> bool {
+    crate::arch::has_vector_extension()
+}
+
+#[cfg(not(target_os = "none"))]
+fn host_has_vector_extension() -> bool {
+    false
+}
+
+macro_rules! wasm_features {
+    ($($(#[$attr:meta])* $method:ident => $feature:ident;)*) => {
+        $(
+            $(#[$attr])*
+            pub fn $method(&mut self, enable: bool) -> &mut Self {
+                self.wasm_feature(WasmFeatures::$feature, enable)
+            }
+        )*
+    };
+}
+
+impl Config {
+    /// Configures the target to generate code for, defaults to the host.
+    ///
+    /// Code compiled for a different target can't be run by the resulting engine, but it can be
+    /// serialized and loaded on the target.
+    pub fn target(&mut self, target: Triple) -> &mut Self {
+        self.target = target;
+        self
+    }
+
+    /// Enables or disables the given set of WASM features.
+    pub fn wasm_feature(&mut self, feature: WasmFeatures, enable: bool) -> &mut Self {
+        self.features.set(feature, enable);
+        self
+    }
+
+    wasm_features! {
+        /// Configures the [bulk memory operations](https://github.com/WebAssembly/bulk-memory-operations) proposal.
+        wasm_bulk_memory => BULK_MEMORY;
+        /// Configures the [multi-value](https://github.com/WebAssembly/multi-value) proposal.
+        wasm_multi_value => MULTI_VALUE;
+        /// Configures the [reference types](https://github.com/WebAssembly/reference-types) proposal.
+        wasm_reference_types => REFERENCE_TYPES;
+        /// Configures the [fixed-width SIMD](https://github.com/WebAssembly/simd) proposal.
+        wasm_simd => SIMD;
+        /// Configures the [relaxed SIMD](https://github.com/WebAssembly/relaxed-simd) proposal.
+        wasm_relaxed_simd => RELAXED_SIMD;
+        /// Configures the [threads](https://github.com/WebAssembly/threads) proposal.
+        wasm_threads => THREADS;
+        /// Configures the [tail calls](https://github.com/WebAssembly/tail-call) proposal.
+        wasm_tail_call => TAIL_CALL;
+        /// Configures the [multi-memory](https://github.com/WebAssembly/multi-memory) proposal.
+        wasm_multi_memory => MULTI_MEMORY;
+        /// Configures the [64-bit memory](https://github.com/WebAssembly/memory64) proposal.
+        wasm_memory64 => MEMORY64;
+        /// Configures the [typed function references](https://github.com/WebAssembly/function-references) proposal,
+        /// disabled by default.
+        wasm_function_references => FUNCTION_REFERENCES;
+        /// Configures the [garbage collection](https://github.com/WebAssembly/gc) proposal, disabled by
+        /// default.
+        ///
+        /// Only `i31ref`s and structs and arrays created by constant expressions or the host are
+        /// supported, struct and array instructions in function bodies are rejected.
+        wasm_gc => GC;
+    }
+
+    /// Configures the optimization level of the generated code.
+    pub fn opt_level(&mut self, level: OptLevel) -> &mut Self {
+        self.opt_level = level;
+        self
+    }
+
+    /// Configures whether Spectre mitigations are applied to heap and table accesses.
+    pub fn spectre_mitigations(&mut self, enable: bool) -> &mut Self {
+        self.spectre_mitigations = enable;
+        self
+    }
+
+    /// Configures whether the target implements the RISC-V vector extension, defaults to whether
+    /// the host does.
+    ///
+    /// With the vector extension [fixed-width SIMD](https://github.com/WebAssembly/simd) is
+    /// compiled to vector instructions, without it each vector operation is lowered to scalar
+    /// operations on its lanes. The fallback doesn't support every SIMD instruction, which is why
+    /// SIMD is only enabled by default if the host implements the vector extension.
+    pub fn riscv_vector(&mut self, enable: bool) -> &mut Self {
+        self.riscv_vector = enable;
+        self
+    }
+
+    /// Configures whether compiled code checks invariants of the runtime, failed checks raise
+    /// [`Trap::DebugAssertionFailed`](crate::runtime::Trap::DebugAssertionFailed).
+    pub fn debug_assertions(&mut self, enable: bool) -> &mut Self {
+        self.tunables.debug_assertions = enable;
+        self
+    }
+
+    /// Configures whether WASM execution is metered with fuel, defaults to `false`.
+    ///
+    /// When enabled every function call and every loop iteration consumes one unit of fuel from
+    /// the store, see [`Store::set_fuel`](crate::runtime::Store::set_fuel). Once the store runs out
+    /// of fuel execution traps with [`Trap::OutOfFuel`](crate::runtime::Trap::OutOfFuel).
+    pub fn consume_fuel(&mut self, enable: bool) -> &mut Self {
+        self.tunables.consume_fuel = enable;
+        self
+    }
+
+    /// Configures whether WASM execution can be interrupted based on epochs, defaults to `false`.
+    ///
+    /// The global epoch advances once every `kconfig::EPOCH_INTERVAL_TICKS`. When enabled, WASM
+    /// checks on every function call and every loop iteration whether the epoch has reached the
+    /// deadline of the store, see [`Store::set_epoch_deadline`](crate::runtime::Store::set_epoch_deadline).
+    /// This is cheaper than fuel but not deterministic.
+    pub fn epoch_interruption(&mut self, enable: bool) -> &mut Self {
+        self.tunables.epoch_interruption = enable;
+        self
+    }
+
+    /// Configures whether idle harts help compiling the functions of a module, defaults to `true`.
+    ///
+    /// The compiled code is the same either way. This has no effect outside the kernel, where
+    /// functions are always compiled sequentially.
+    pub fn parallel_compilation(&mut self, enable: bool) -> &mut Self {
+        self.parallel_compilation = enable;
+        self
+    }
+
+    /// Configures the size in bytes of the stack WASM executes on.
+    pub fn stack_size(&mut self, size: usize) -> &mut Self {
+        self.stack_size = size;
+        self
+    }
+
+    /// Configures the size of the address space reserved for static memories.
+    pub fn static_memory_bound(&mut self, bound: u64) -> &mut Self {
+        self.tunables.static_memory_bound = bound;
+        self
+    }
+
+    /// Configures the size of the guard region following static memories.
+    pub fn static_memory_guard_size(&mut self, size: u64) -> &mut Self {
+        self.tunables.static_memory_offset_guard_size = size;
+        self
+    }
+
+    /// Configures the size of the guard region following dynamic memories.
+    pub fn dynamic_memory_guard_size(&mut self, size: u64) -> &mut Self {
+        self.tunables.dynamic_memory_offset_guard_size = size;
+        self
+    }
+
+    /// Configures how much address space is reserved for dynamic memories to grow into before
+    /// they have to be moved.
+    pub fn dynamic_memory_growth_reserve(&mut self, reserve: u64) -> &mut Self {
+        self.tunables.dynamic_memory_growth_reserve = reserve;
+        self
+    }
+
+    /// Whether SIMD has to be lowered to scalar code, because the target has no vector registers.
+    pub(crate) fn simd_fallback(&self) -> bool {
+        matches!(self.target.architecture, Architecture::Riscv64(_)) && !self.riscv_vector
+    }
+
+    /// Builds the Cranelift target ISA according to this configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the target architecture is not supported by Cranelift or the settings
+    /// are rejected.
+    pub fn target_isa(&self) -> Result<OwnedTargetIsa, ConfigError> {
+        let is_riscv = matches!(self.target.architecture, Architecture::Riscv64(_));
+
+        let mut isa_builder = cranelift_codegen::isa::lookup(self.target.clone())?;
+        if is_riscv && self.riscv_vector {
+            // the V extension guarantees vector registers of at least 128 bits
+            isa_builder.set("has_v", "true")?;
+            isa_builder.set("has_zvl128b", "true")?;
+        }
+
+        let mut b = cranelift_codegen::settings::builder();
+        b.set(
+            "opt_level",
+            match self.opt_level {
+                OptLevel::None => "none",
+                OptLevel::Speed => "speed",
+                OptLevel::SpeedAndSize => "speed_and_size",
+            },
+        )?;
+
+        let spectre_mitigations = if self.spectre_mitigations {
+            "true"
+        } else {
+            "false"
+        };
+        b.set("enable_heap_access_spectre_mitigation", spectre_mitigations)?;
+        b.set(
+            "enable_table_access_spectre_mitigation",
+            spectre_mitigations,
+        )?;
+        // the collector walks the frame pointer chain of WASM frames to find their stack maps
+        b.set("preserve_frame_pointers", "true")?;
+
+        isa_builder
+            .finish(cranelift_codegen::settings::Flags::new(b))
+            .map_err(ConfigError::Isa)
+    }
+}
@@ -1,7 +1,15 @@
+use crate::runtime::errors::GcError;
+use crate::runtime::gc::AnyRef;
 use crate::runtime::instance::InstanceData;
+use crate::runtime::trap::Trap;
+use crate::runtime::values::Val;
 use crate::runtime::vmcontext::VMVal;
 use alloc::vec::Vec;
-use cranelift_wasm::{ConstExpr, ConstOp, GlobalIndex};
+use cranelift_codegen::entity::Unsigned;
+use cranelift_wasm::{
+    ConstExpr, ConstOp, GlobalIndex, TypeIndex, WasmCompositeType, WasmHeapTopType,
+    WasmStorageType, WasmSubType, WasmValType,
+};
 
 #[derive(Default)]
 pub struct ConstExprEvaluator {
@@ -9,7 +17,15 @@ pub struct ConstExprEvaluator {
 }
 
 impl ConstExprEvaluator {
-    pub fn eval(&mut self, instance: &mut InstanceData, expr: &ConstExpr) -> VMVal {
+    /// Evaluates `expr` in the context of `instance`.
+    ///
+    /// Operators the evaluator doesn't support are rejected when the module is translated, and
+    /// validation ensures the operands on the stack have the right types.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a struct or array can't be allocated.
+    pub fn eval(&mut self, instance: &mut InstanceData, expr: &ConstExpr) -> Result<VMVal, Trap> {
         for op in expr.ops() {
             match op {
                 ConstOp::I32Const(v) => self.push(VMVal { i32: *v }),
@@ -23,26 +39,212 @@ impl ConstExprEvaluator {
                     let val = self.global_get(instance, *global_index);
                     self.push(val);
                 }
-                _ => todo!(),
+                ConstOp::RefNull(_) => self.push(VMVal { v128: [0; 16] }),
+                ConstOp::RefFunc(func_index) => self.push(VMVal {
+                    funcref: instance.ref_func(*func_index).cast(),
+                }),
+                ConstOp::RefI31 => {
+                    let val = self.pop_i32();
+                    self.push(VMVal {
+                        anyref: AnyRef::from_i31(val).as_raw(),
+                    });
+                }
+                ConstOp::I32Add => self.i32_binop(i32::wrapping_add),
+                ConstOp::I32Sub => self.i32_binop(i32::wrapping_sub),
+                ConstOp::I32Mul => self.i32_binop(i32::wrapping_mul),
+                ConstOp::I64Add => self.i64_binop(i64::wrapping_add),
+                ConstOp::I64Sub => self.i64_binop(i64::wrapping_sub),
+                ConstOp::I64Mul => self.i64_binop(i64::wrapping_mul),
+                ConstOp::StructNew { struct_type_index } => {
+                    let ty = lookup_type(instance, *struct_type_index);
+                    let WasmCompositeType::Struct(struct_ty) = &ty.composite_type else {
+                        unreachable!("validation ensures `struct.new` takes a struct type");
+                    };
+
+                    let mut fields: Vec<_> = struct_ty
+                        .fields
+                        .iter()
+                        .rev()
+                        .map(|field| self.pop_field(&field.element_type))
+                        .collect();
+                    fields.reverse();
+
+                    self.alloc(instance, &ty, fields)?;
+                }
+                ConstOp::StructNewDefault { struct_type_index } => {
+                    let ty = lookup_type(instance, *struct_type_index);
+                    let WasmCompositeType::Struct(struct_ty) = &ty.composite_type else {
+                        unreachable!("validation ensures `struct.new_default` takes a struct type");
+                    };
+
+                    let fields = struct_ty
+                        .fields
+                        .iter()
+                        .map(|field| default_field(&field.element_type))
+                        .collect();
+
+                    self.alloc(instance, &ty, fields)?;
+                }
+                ConstOp::ArrayNew { array_type_index } => {
+                    let ty = lookup_type(instance, *array_type_index);
+                    let WasmCompositeType::Array(array_ty) = &ty.composite_type else {
+                        unreachable!("validation ensures `array.new` takes an array type");
+                    };
+
+                    let len = self.pop_i32().unsigned();
+                    let elem = self.pop_field(&array_ty.0.element_type);
+
+                    self.alloc(instance, &ty, filled(elem, len)?)?;
+                }
+                ConstOp::ArrayNewDefault { array_type_index } => {
+                    let ty = lookup_type(instance, *array_type_index);
+                    let WasmCompositeType::Array(array_ty) = &ty.composite_type else {
+                        unreachable!("validation ensures `array.new_default` takes an array type");
+                    };
+
+                    let len = self.pop_i32().unsigned();
+                    let elem = default_field(&array_ty.0.element_type);
+
+                    self.alloc(instance, &ty, filled(elem, len)?)?;
+                }
+                ConstOp::ArrayNewFixed {
+                    array_type_index,
+                    array_size,
+                } => {
+                    let ty = lookup_type(instance, *array_type_index);
+                    let WasmCompositeType::Array(array_ty) = &ty.composite_type else {
+                        unreachable!("validation ensures `array.new_fixed` takes an array type");
+                    };
+
+                    let mut elems: Vec<_> = (0..*array_size)
+                        .map(|_| self.pop_field(&array_ty.0.element_type))
+                        .collect();
+                    elems.reverse();
+
+                    self.alloc(instance, &ty, elems)?;
+                }
+                // the conversions between `externref` and `anyref` are rejected during translation
+                #[allow(unreachable_patterns)]
+                op => unreachable!("unsupported constant expression operator {op:?}"),
             }
         }
 
         assert_eq!(self.stack.len(), 1);
-        self.stack.pop().unwrap()
+        Ok(self.stack.pop().unwrap())
     }
 
     fn push(&mut self, val: VMVal) {
         self.stack.push(val);
     }
 
+    fn pop(&mut self) -> VMVal {
+        self.stack
+            .pop()
+            .expect("validation ensures the stack is balanced")
+    }
+
+    fn pop_i32(&mut self) -> i32 {
+        // Safety: validation ensures the operand is an `i32`
+        unsafe { self.pop().i32 }
+    }
+
+    fn pop_i64(&mut self) -> i64 {
+        // Safety: validation ensures the operand is an `i64`
+        unsafe { self.pop().i64 }
+    }
+
+    /// Pops the value of a struct field or array element of type `ty`.
+    fn pop_field(&mut self, ty: &WasmStorageType) -> Val {
+        let val = self.pop();
+        // Safety: validation ensures the operand has the field's type
+        match ty {
+            WasmStorageType::I8 => Val::I32(unsafe { val.i32 } & 0xff),
+            WasmStorageType::I16 => Val::I32(unsafe { val.i32 } & 0xffff),
+            WasmStorageType::Val(ty) => unsafe { Val::from_vmval(&val, *ty) },
+        }
+    }
+
+    fn i32_binop(&mut self, op: fn(i32, i32) -> i32) {
+        let rhs = self.pop_i32();
+        let lhs = self.pop_i32();
+        self.push(VMVal { i32: op(lhs, rhs) });
+    }
+
+    fn i64_binop(&mut self, op: fn(i64, i64) -> i64) {
+        let rhs = self.pop_i64();
+        let lhs = self.pop_i64();
+        self.push(VMVal { i64: op(lhs, rhs) });
+    }
+
+    /// Allocates an object of type `ty` in the store's GC heap and pushes the reference to it.
+    fn alloc(
+        &mut self,
+        instance: &InstanceData,
+        ty: &WasmSubType,
+        fields: Vec<Val>,
+    ) -> Result<(), Trap> {
+        let obj = instance
+            .gc_heap
+            .alloc_validated(ty, fields.into_boxed_slice())
+            .map_err(alloc_error)?;
+
+        self.push(VMVal {
+            anyref: obj.as_raw(),
+        });
+        Ok(())
+    }
+
     fn global_get(&mut self, instance: &mut InstanceData, index: GlobalIndex) -> VMVal {
         if let Some(def_index) = instance.module_info.module.defined_global_index(index) {
             let global_definition = unsafe { instance.global_ptr(def_index).as_ref().unwrap() };
             let ty = instance.module_info.module.globals[index];
             unsafe { global_definition.to_vmval(&ty.wasm_ty) }
         } else {
-            todo!("imported globals")
-            // self.imported_global(index).from
+            let global_definition =
+                unsafe { instance.imported_global(index).from.as_ref().unwrap() };
+            let ty = instance.module_info.module.globals[index];
+            unsafe { global_definition.to_vmval(&ty.wasm_ty) }
         }
     }
 }
+
+fn lookup_type(instance: &InstanceData, index: TypeIndex) -> WasmSubType {
+    let interned_index = instance.module_info.module.types[index];
+    instance.module_info.types[interned_index].clone()
+}
+
+/// Returns the default value of a struct field or array element, used by `struct.new_default` and
+/// `array.new_default`.
+fn default_field(ty: &WasmStorageType) -> Val {
+    match ty {
+        WasmStorageType::I8 | WasmStorageType::I16 | WasmStorageType::Val(WasmValType::I32) => {
+            Val::I32(0)
+        }
+        WasmStorageType::Val(WasmValType::I64) => Val::I64(0),
+        WasmStorageType::Val(WasmValType::F32) => Val::F32(0),
+        WasmStorageType::Val(WasmValType::F64) => Val::F64(0),
+        WasmStorageType::Val(WasmValType::V128) => Val::V128(0),
+        WasmStorageType::Val(WasmValType::Ref(ty)) => match ty.heap_type.top() {
+            WasmHeapTopType::Func => Val::FuncRef(None),
+            WasmHeapTopType::Extern => Val::ExternRef(None),
+            WasmHeapTopType::Any => Val::AnyRef(None),
+        },
+    }
+}
+
+/// Returns the elements of an array of length `len` filled with `elem`.
+fn filled(elem: Val, len: u32) -> Result<Vec<Val>, Trap> {
+    let mut elems = Vec::new();
+    elems
+        .try_reserve_exact(len as usize)
+        .map_err(|_| Trap::AllocationTooLarge)?;
+    elems.resize(len as usize, elem);
+    Ok(elems)
+}
+
+fn alloc_error(error: GcError) -> Trap {
+    match error {
+        GcError::OutOfMemory => Trap::AllocationTooLarge,
+        error => unreachable!("validated constant expression failed to allocate: {error}"),
+    }
+}
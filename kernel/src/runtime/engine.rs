@@ -1,35 +1,74 @@
 use crate::runtime::compile::Compiler;
+use crate::runtime::config::{Config, Tunables};
+use crate::runtime::errors::{CompileError, ConfigError};
 use crate::runtime::translate::TranslatedModule;
-use cranelift_codegen::isa::OwnedTargetIsa;
 use cranelift_wasm::wasmparser::WasmFeatures;
 
 pub struct Engine {
     features: WasmFeatures,
+    stack_size: usize,
+    parallel_compilation: bool,
     compiler: Compiler,
 }
 
 impl Engine {
-    pub fn new(isa: OwnedTargetIsa) -> Self {
+    /// Creates a new engine according to `config`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the target ISA described by `config` cannot be built.
+    pub fn new(config: &Config) -> Result<Self, ConfigError> {
+        let isa = config.target_isa()?;
         log::trace!("Setting up new Engine instance for ISA: {:?}", isa.name());
-        Self {
-            features: WasmFeatures::default(),
-            compiler: Compiler::new(isa),
-        }
+
+        Ok(Self {
+            features: config.features,
+            stack_size: config.stack_size,
+            parallel_compilation: config.parallel_compilation,
+            compiler: Compiler::new(isa, config.tunables, config.simd_fallback()),
+        })
     }
+
     pub fn wasm_features(&self) -> WasmFeatures {
         self.features
     }
 
+    pub fn tunables(&self) -> &Tunables {
+        self.compiler.tunables()
+    }
+
+    /// Returns the size in bytes of the stack WASM executes on.
+    pub fn stack_size(&self) -> usize {
+        self.stack_size
+    }
+
+    /// Returns whether idle harts help compiling modules.
+    pub fn parallel_compilation(&self) -> bool {
+        self.parallel_compilation
+    }
+
     pub fn compiler(&self) -> &Compiler {
         &self.compiler
     }
 
-    pub fn assert_compatible(&self, module: &TranslatedModule) {
+    /// Checks that all features required by `module` are enabled in this engine.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CompileError::MissingFeatures`] listing the required features that are not
+    /// enabled.
+    pub fn check_compatible(&self, module: &TranslatedModule) -> Result<(), CompileError> {
         log::debug!(
-            "required features {:?}, supported featured {:?}",
+            "required features {:?}, supported features {:?}",
             module.required_features,
             self.features
         );
-        assert!(self.features.contains(module.required_features), "module is incompatible with engine. Expected {:?} features to be enabled, but engine has {:?}.", module.required_features, self.features);
+
+        let missing = module.required_features.difference(self.features);
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(CompileError::MissingFeatures(missing))
+        }
     }
 }
@@ -0,0 +1,60 @@
+//! The global epoch used to interrupt long-running WASM.
+//!
+//! The epoch is the number of epoch intervals counted by the timebase, which all harts share. It
+//! therefore advances once per interval no matter how many harts take timer interrupts, and a
+//! timer that fires early doesn't advance it either.
+//!
+//! WASM running in user mode can't read the timebase. Each store therefore has its own copy of the
+//! epoch in a [`VMEpoch`], the copy of the store currently executing WASM on a hart is kept up to
+//! date by [`update_epoch`].
+
+use crate::arch;
+use crate::kconfig;
+use crate::runtime::vmcontext::VMEpoch;
+use core::cell::Cell;
+use core::ptr;
+use core::sync::atomic::Ordering;
+use thread_local::declare_thread_local;
+
+declare_thread_local! {
+    /// The epoch state of the store executing WASM on this hart.
+    static ACTIVE: Cell<*const VMEpoch> = const { Cell::new(ptr::null()) };
+}
+
+/// Returns the current epoch.
+pub fn current_epoch() -> u64 {
+    arch::current_ticks() / kconfig::EPOCH_INTERVAL_TICKS
+}
+
+/// Updates the epoch copy of the store executing WASM on this hart, interrupting the WASM if the
+/// store reached its epoch deadline.
+///
+/// This is called by the timer interrupt on every hart.
+pub fn update_epoch() {
+    let active = ACTIVE.with(Cell::get);
+    // Safety: `with_active_epoch` keeps the state alive while it is active
+    if let Some(active) = unsafe { active.as_ref() } {
+        active.current.store(current_epoch(), Ordering::Relaxed);
+    }
+}
+
+/// Calls `f` with `epoch` as the active epoch state of this hart, i.e. while `f` executes WASM
+/// on behalf of the store owning `epoch`.
+///
+/// # Safety
+///
+/// `epoch` must be valid for the duration of `f`.
+pub unsafe fn with_active_epoch<R>(epoch: *const VMEpoch, f: impl FnOnce() -> R) -> R {
+    (*epoch).current.store(current_epoch(), Ordering::Relaxed);
+
+    // WASM might call into the kernel which calls into another store, so restore the previous
+    // state afterward
+    let prev = ACTIVE.with(|active| active.replace(epoch));
+    let result = f();
+    ACTIVE.with(|active| active.set(prev));
+    if let Some(prev) = prev.as_ref() {
+        prev.current.store(current_epoch(), Ordering::Relaxed);
+    }
+
+    result
+}
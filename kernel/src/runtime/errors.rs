@@ -1,9 +1,29 @@
+use crate::runtime::trap::Trap;
+use alloc::string::String;
+use cranelift_wasm::wasmparser::WasmFeatures;
+
+#[derive(onlyerror::Error, Debug)]
+pub enum ConfigError {
+    #[error("Unsupported target architecture {0}")]
+    Lookup(#[from] cranelift_codegen::isa::LookupError),
+    #[error("Invalid Cranelift setting {0}")]
+    Setting(#[from] cranelift_codegen::settings::SetError),
+    #[error("Failed to build target ISA {0}")]
+    Isa(cranelift_codegen::CodegenError),
+}
+
 #[derive(onlyerror::Error, Debug)]
 pub enum CompileError {
     #[error("Translation error: {0}")]
     Translate(#[from] TranslationError),
     #[error("Cranelift IR to machine code compilation failed {0}")]
     Compile(cranelift_codegen::CodegenError),
+    #[error("module requires WASM features that are not enabled: {0:?}")]
+    MissingFeatures(WasmFeatures),
+    #[error("malformed compiled module: {0}")]
+    MalformedArtifact(&'static str),
+    #[error("compiled module is incompatible with this engine: {0}")]
+    IncompatibleArtifact(String),
 }
 impl<'a> From<cranelift_codegen::CompileError<'a>> for CompileError {
     fn from(error: cranelift_codegen::CompileError) -> Self {
@@ -30,3 +50,35 @@ impl From<cranelift_wasm::WasmError> for TranslationError {
         Self::Translate(error)
     }
 }
+
+#[derive(onlyerror::Error, Debug)]
+pub enum LinkError {
+    #[error("unknown import `{module}::{name}`")]
+    UnknownImport { module: String, name: String },
+    #[error("incompatible import type for `{module}::{name}`")]
+    IncompatibleImportType { module: String, name: String },
+    #[error("`{module}::{name}` is already defined")]
+    AlreadyDefined { module: String, name: String },
+    #[error("module has no trampoline to call the host function imported as `{module}::{name}`")]
+    MissingTrampoline { module: String, name: String },
+    #[error("Instantiation failed {0}")]
+    Instantiation(#[from] Trap),
+    #[error("failed to allocate the memories of the instance")]
+    OutOfMemory,
+}
+
+#[derive(onlyerror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GcError {
+    #[error("reference is an `i31ref`, not a struct or array")]
+    NotAnObject,
+    #[error("referenced object has been collected")]
+    Dangling,
+    #[error("field or element index out of bounds")]
+    IndexOutOfBounds,
+    #[error("value doesn't match the field or element type")]
+    TypeMismatch,
+    #[error("field or element is immutable")]
+    Immutable,
+    #[error("GC heap is full")]
+    OutOfMemory,
+}
@@ -0,0 +1,55 @@
+use crate::runtime::vmcontext::{
+    VMContext, VMFuncRef, VMGlobalDefinition, VMMemoryDefinition, VMTableDefinition,
+};
+use core::ptr::NonNull;
+use cranelift_wasm::{Global, Memory, Table, WasmFuncType};
+
+/// An entity exported by an instance.
+///
+/// Exports point directly into the `VMContext` of the exporting instance and are therefore only
+/// valid for as long as the owning [`Store`](crate::runtime::Store) is alive.
+#[derive(Debug, Clone)]
+pub enum Export {
+    Function(ExportFunction),
+    Table(ExportTable),
+    Memory(ExportMemory),
+    Global(ExportGlobal),
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportFunction {
+    /// The `VMFuncRef` of the exported function.
+    pub func_ref: NonNull<VMFuncRef>,
+    /// The type of the exported function.
+    pub ty: WasmFuncType,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportTable {
+    /// The address of the table descriptor.
+    pub definition: *mut VMTableDefinition,
+    /// The `VMContext` of the instance that owns the table.
+    pub vmctx: NonNull<VMContext>,
+    /// The declared type of the table.
+    pub table: Table,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportMemory {
+    /// The address of the memory descriptor.
+    pub definition: *mut VMMemoryDefinition,
+    /// The `VMContext` of the instance that owns the memory.
+    pub vmctx: NonNull<VMContext>,
+    /// The declared type of the memory.
+    pub memory: Memory,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportGlobal {
+    /// The address of the global storage.
+    pub definition: *mut VMGlobalDefinition,
+    /// The `VMContext` of the instance that exports the global.
+    pub vmctx: NonNull<VMContext>,
+    /// The declared type of the global.
+    pub global: Global,
+}
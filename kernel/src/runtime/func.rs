@@ -0,0 +1,193 @@
+use crate::runtime::epoch;
+use crate::runtime::export::ExportFunction;
+use crate::runtime::guest_memory::GuestVec;
+use crate::runtime::store::Store;
+use crate::runtime::trap::Trap;
+use crate::runtime::trap_handling;
+use crate::runtime::type_registry;
+use crate::runtime::typed::{WasmParams, WasmResults};
+use crate::runtime::values::Val;
+use crate::runtime::vmcontext::{VMFuncRef, VMVal};
+use alloc::vec::Vec;
+use core::cmp;
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+use cranelift_wasm::WasmFuncType;
+
+/// A WASM function that can be called from the kernel.
+///
+/// Functions are obtained through [`Instance::get_func`](crate::runtime::Instance::get_func) and
+/// are only valid to call with the [`Store`] that owns the exporting instance.
+#[derive(Debug, Clone)]
+pub struct Func(ExportFunction);
+
+impl Func {
+    pub(crate) fn from_export(export: ExportFunction) -> Self {
+        Self(export)
+    }
+
+    /// Creates a `Func` from a funcref produced by WASM.
+    ///
+    /// # Safety
+    ///
+    /// `func_ref` must point to a valid `VMFuncRef` that outlives the returned `Func`.
+    pub(crate) unsafe fn from_vm_func_ref(func_ref: NonNull<VMFuncRef>) -> Self {
+        let ty = type_registry::lookup(func_ref.as_ref().type_index)
+            .unwrap_func()
+            .clone();
+        Self(ExportFunction { func_ref, ty })
+    }
+
+    pub(crate) fn vm_func_ref(&self) -> NonNull<VMFuncRef> {
+        self.0.func_ref
+    }
+
+    /// Returns the type of this function.
+    pub fn ty(&self) -> &WasmFuncType {
+        &self.0.ty
+    }
+
+    /// Calls this function with the given parameters, writing its results into `results`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the trap raised by WASM if execution didn't complete normally, in which case
+    /// `results` is left untouched.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number or types of `params` don't match the function's parameters or if the
+    /// length of `results` doesn't match the number of results.
+    pub fn call(&self, store: &mut Store, params: &[Val], results: &mut [Val]) -> Result<(), Trap> {
+        let ty = self.ty();
+        assert_eq!(
+            params.len(),
+            ty.params().len(),
+            "wrong number of arguments provided"
+        );
+        assert!(
+            params
+                .iter()
+                .zip(ty.params())
+                .all(|(val, ty)| val.matches_ty(*ty)),
+            "argument type mismatch"
+        );
+        assert_eq!(
+            results.len(),
+            ty.returns().len(),
+            "wrong number of results provided"
+        );
+
+        let mut values_vec = values_vec(store, ty);
+        for (slot, val) in values_vec.iter_mut().zip(params) {
+            *slot = val.as_vmval();
+        }
+
+        unsafe {
+            self.call_unchecked(store, &mut values_vec)?;
+
+            for ((result, ty), val) in results.iter_mut().zip(ty.returns()).zip(&values_vec) {
+                *result = Val::from_vmval(val, *ty);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Attempts to statically type this function, returns `None` if `Params` and `Results` don't
+    /// match the function's type.
+    pub fn typed<Params, Results>(&self) -> Option<TypedFunc<Params, Results>>
+    where
+        Params: WasmParams,
+        Results: WasmResults,
+    {
+        let ty = self.ty();
+        if *Params::valtypes() != *ty.params() || *Results::valtypes() != *ty.returns() {
+            return None;
+        }
+
+        Some(TypedFunc {
+            func: self.clone(),
+            _m: PhantomData,
+        })
+    }
+
+    /// Calls this function through its native entrypoint in user mode, catching any traps raised
+    /// by WASM.
+    ///
+    /// # Safety
+    ///
+    /// `values_vec` must be large enough to hold both the parameters and results of the function,
+    /// the parameters must be of the correct types and it must be allocated in guest memory.
+    unsafe fn call_unchecked(
+        &self,
+        store: &mut Store,
+        values_vec: &mut [VMVal],
+    ) -> Result<(), Trap> {
+        store.activate();
+        epoch::with_active_epoch(store.epoch(), || {
+            trap_handling::catch_traps(self.0.func_ref.as_ref(), values_vec, store.stack())
+        })
+    }
+}
+
+impl PartialEq for Func {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.func_ref == other.0.func_ref
+    }
+}
+
+/// A statically typed WASM function, see [`Func::typed`].
+#[derive(Debug)]
+pub struct TypedFunc<Params, Results> {
+    func: Func,
+    _m: PhantomData<fn(Params) -> Results>,
+}
+
+impl<Params, Results> Clone for TypedFunc<Params, Results> {
+    fn clone(&self) -> Self {
+        Self {
+            func: self.func.clone(),
+            _m: PhantomData,
+        }
+    }
+}
+
+impl<Params, Results> TypedFunc<Params, Results>
+where
+    Params: WasmParams,
+    Results: WasmResults,
+{
+    /// Returns the underlying untyped function.
+    pub fn func(&self) -> &Func {
+        &self.func
+    }
+
+    /// Calls this function with the given parameters and returns its results.
+    ///
+    /// # Errors
+    ///
+    /// Returns the trap raised by WASM if execution didn't complete normally.
+    pub fn call(&self, store: &mut Store, params: Params) -> Result<Results, Trap> {
+        let mut values_vec = values_vec(store, self.func.ty());
+        params.store(&mut values_vec);
+
+        // Safety: the types have been checked during construction of the `TypedFunc`
+        unsafe {
+            self.func.call_unchecked(store, &mut values_vec)?;
+            Ok(Results::load(&values_vec))
+        }
+    }
+}
+
+/// Allocates a zeroed `VMVal` array large enough to hold both the parameters and the results of
+/// a function of the given type.
+///
+/// The array is allocated in the store's guest memory, since WASM accesses it from user mode.
+fn values_vec(store: &Store, ty: &WasmFuncType) -> GuestVec<VMVal> {
+    let len = cmp::max(ty.params().len(), ty.returns().len());
+
+    let mut values_vec = Vec::with_capacity_in(len, store.guest_allocator());
+    values_vec.resize_with(len, || VMVal { v128: [0; 16] });
+    values_vec
+}
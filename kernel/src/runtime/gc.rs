@@ -0,0 +1,352 @@
+//! The garbage collected heap of WASM's GC proposal.
+//!
+//! Each store owns a heap holding its structs and arrays. References of the `any` hierarchy are
+//! `u32`s: `0` is the null reference, references tagged with [`VMGCREF_I31_TAG`] are `i31ref`s
+//! carrying their value inline and all other references are indices into the heap's object table
+//! shifted left by one.
+//!
+//! Cranelift has no hooks for translating the struct and array instructions yet, so WASM can't
+//! access the fields of objects directly. Objects therefore live in the kernel heap, they are
+//! allocated by constant expressions or by the host through [`GcHeap`], only the host can access
+//! their fields and WASM functions pass them around as opaque references. Modules using struct or
+//! array instructions in function bodies fail translation, which is why the proposal is disabled
+//! by default.
+//!
+//! The collector is a simple non-moving mark-sweep collector. Its roots are the references stored
+//! in the tables and globals of the store's instances and those held by WASM frames, which are
+//! found through the stack maps Cranelift emits for each call. References only held by the host
+//! are *not* roots, objects only reachable through them are freed by the next collection and their
+//! slots may be reused by later allocations.
+
+use crate::runtime::errors::GcError;
+use crate::runtime::instance::InstanceData;
+use crate::runtime::trap_handling;
+use crate::runtime::type_registry;
+use crate::runtime::values::Val;
+use crate::runtime::vmcontext::{VMContext, VMSharedTypeIndex, VMGCREF_I31_TAG};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem;
+use core::num::NonZeroU32;
+use core::ptr::NonNull;
+use cranelift_wasm::{
+    WasmArrayType, WasmCompositeType, WasmStorageType, WasmStructType, WasmSubType,
+};
+use sync::Mutex;
+
+/// The maximum number of objects a heap can hold, object references have to leave room for the
+/// `i31ref` tag.
+const MAX_OBJECTS: usize = 1 << 30;
+
+/// A non-null reference of the `any` hierarchy, i.e. an `i31ref` or a reference to a struct or
+/// array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AnyRef(NonZeroU32);
+
+impl AnyRef {
+    /// Creates an `i31ref` from the lower 31 bits of `value`, like WASM's `ref.i31`.
+    #[allow(clippy::cast_sign_loss)]
+    pub fn from_i31(value: i32) -> Self {
+        Self(NonZeroU32::new(((value as u32) << 1) | VMGCREF_I31_TAG).unwrap())
+    }
+
+    /// Returns whether this is an `i31ref`.
+    pub fn is_i31(self) -> bool {
+        self.0.get() & VMGCREF_I31_TAG != 0
+    }
+
+    /// Returns the sign-extended value of an `i31ref`, like WASM's `i31.get_s`.
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn i31_get_s(self) -> Option<i32> {
+        self.is_i31().then(|| (self.0.get() as i32) >> 1)
+    }
+
+    /// Returns the zero-extended value of an `i31ref`, like WASM's `i31.get_u`.
+    pub fn i31_get_u(self) -> Option<u32> {
+        self.is_i31().then(|| self.0.get() >> 1)
+    }
+
+    pub(crate) fn from_raw(raw: u32) -> Option<Self> {
+        NonZeroU32::new(raw).map(Self)
+    }
+
+    pub(crate) fn as_raw(self) -> u32 {
+        self.0.get()
+    }
+
+    fn from_object_index(index: usize) -> Self {
+        let raw = u32::try_from(index + 1).unwrap() << 1;
+        Self(NonZeroU32::new(raw).unwrap())
+    }
+
+    fn object_index(self) -> Option<usize> {
+        (!self.is_i31()).then(|| (self.0.get() >> 1) as usize - 1)
+    }
+}
+
+/// A handle to the GC heap of a store.
+///
+/// The handle can be cloned and e.g. moved into host functions to allocate objects or trigger a
+/// collection while WASM is running. All methods require the address space of the store to be
+/// active.
+#[derive(Debug, Clone, Default)]
+pub struct GcHeap(Arc<Mutex<GcHeapInner>>);
+
+#[derive(Debug, Default)]
+struct GcHeapInner {
+    /// All objects by their index, `None` marks free slots.
+    objects: Vec<Option<GcObject>>,
+    /// The indices of free slots in `objects`.
+    free: Vec<usize>,
+    /// The `VMContext`s of all instances in the store, their tables and globals are roots.
+    instances: Vec<NonNull<VMContext>>,
+}
+
+// Safety: The instances and funcrefs referenced by the heap belong to its store, which is only
+// ever used by one hart at a time.
+unsafe impl Send for GcHeapInner {}
+
+#[derive(Debug)]
+struct GcObject {
+    ty: VMSharedTypeIndex,
+    /// The fields of a struct or the elements of an array, packed fields are `Val::I32`s holding
+    /// the zero-extended value.
+    fields: Box<[Val]>,
+}
+
+impl GcHeap {
+    /// Registers an instance, the references stored in its tables and globals keep objects alive.
+    pub(crate) fn register_instance(&self, vmctx: NonNull<VMContext>) {
+        self.0.lock().instances.push(vmctx);
+    }
+
+    /// Allocates a struct of the given type, initialized with `fields`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `fields` don't match the fields of the struct type or the heap is full.
+    pub fn alloc_struct(&self, ty: &WasmStructType, fields: &[Val]) -> Result<AnyRef, GcError> {
+        if fields.len() != ty.fields.len() {
+            return Err(GcError::TypeMismatch);
+        }
+
+        let fields = fields
+            .iter()
+            .zip(ty.fields.iter())
+            .map(|(val, field)| pack(val, &field.element_type))
+            .collect::<Result<_, _>>()?;
+
+        let ty = WasmSubType {
+            is_final: true,
+            supertype: None,
+            composite_type: WasmCompositeType::Struct(ty.clone()),
+        };
+        self.alloc_validated(&ty, fields)
+    }
+
+    /// Allocates an array of the given type with `len` elements, all initialized to `elem`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `elem` doesn't match the element type or the heap is full.
+    pub fn alloc_array(&self, ty: &WasmArrayType, elem: &Val, len: u32) -> Result<AnyRef, GcError> {
+        let elem = pack(elem, &ty.0.element_type)?;
+        let fields = vec![elem; len as usize].into_boxed_slice();
+
+        let ty = WasmSubType {
+            is_final: true,
+            supertype: None,
+            composite_type: WasmCompositeType::Array(ty.clone()),
+        };
+        self.alloc_validated(&ty, fields)
+    }
+
+    /// Allocates a struct or array of type `ty` holding `fields`, which are trusted to match the
+    /// type, e.g. because they were produced by validated WASM. Packed fields have to be truncated
+    /// already.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the heap is full.
+    pub(crate) fn alloc_validated(
+        &self,
+        ty: &WasmSubType,
+        fields: Box<[Val]>,
+    ) -> Result<AnyRef, GcError> {
+        let ty = type_registry::register(ty);
+        self.0.lock().alloc(GcObject { ty, fields })
+    }
+
+    /// Returns the field `index` of a struct or the element `index` of an array.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `obj` doesn't reference a live object or `index` is out of bounds.
+    pub fn get(&self, obj: AnyRef, index: u32) -> Result<Val, GcError> {
+        let inner = self.0.lock();
+        let object = inner.object(obj)?;
+
+        object
+            .fields
+            .get(index as usize)
+            .cloned()
+            .ok_or(GcError::IndexOutOfBounds)
+    }
+
+    /// Sets the field `index` of a struct or the element `index` of an array to `val`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `obj` doesn't reference a live object, `index` is out of bounds or the
+    /// field is immutable or of a different type.
+    pub fn set(&self, obj: AnyRef, index: u32, val: &Val) -> Result<(), GcError> {
+        let mut inner = self.0.lock();
+        let object = inner.object_mut(obj)?;
+
+        let ty = type_registry::lookup(object.ty);
+        let field_ty = match &ty.composite_type {
+            WasmCompositeType::Struct(ty) => ty.fields.get(index as usize),
+            WasmCompositeType::Array(ty) => Some(&ty.0),
+            WasmCompositeType::Func(_) => unreachable!("GC objects are structs or arrays"),
+        };
+        let field = object.fields.get_mut(index as usize);
+
+        let (Some(field_ty), Some(field)) = (field_ty, field) else {
+            return Err(GcError::IndexOutOfBounds);
+        };
+        if !field_ty.mutable {
+            return Err(GcError::Immutable);
+        }
+
+        *field = pack(val, &field_ty.element_type)?;
+        Ok(())
+    }
+
+    /// Returns the number of elements of an array, or fields of a struct.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `obj` doesn't reference a live object.
+    pub fn len(&self, obj: AnyRef) -> Result<u32, GcError> {
+        let inner = self.0.lock();
+        let object = inner.object(obj)?;
+
+        Ok(u32::try_from(object.fields.len()).unwrap())
+    }
+
+    /// Returns the type of the struct or array `obj` references.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `obj` doesn't reference a live object.
+    pub fn ty(&self, obj: AnyRef) -> Result<WasmSubType, GcError> {
+        let inner = self.0.lock();
+        let object = inner.object(obj)?;
+
+        Ok(type_registry::lookup(object.ty))
+    }
+
+    /// Returns the number of live objects, including garbage that hasn't been collected yet.
+    pub fn num_objects(&self) -> usize {
+        let inner = self.0.lock();
+        inner.objects.len() - inner.free.len()
+    }
+
+    /// Frees all objects that aren't reachable from the tables and globals of the store's
+    /// instances or from WASM frames.
+    ///
+    /// This can be called while WASM is running, e.g. from a host function, in which case the
+    /// WASM frames of all activations on this hart are scanned for references.
+    pub fn collect(&self) {
+        self.0.lock().collect();
+    }
+}
+
+impl GcHeapInner {
+    fn alloc(&mut self, object: GcObject) -> Result<AnyRef, GcError> {
+        let index = if let Some(index) = self.free.pop() {
+            self.objects[index] = Some(object);
+            index
+        } else if self.objects.len() < MAX_OBJECTS {
+            self.objects.push(Some(object));
+            self.objects.len() - 1
+        } else {
+            return Err(GcError::OutOfMemory);
+        };
+
+        Ok(AnyRef::from_object_index(index))
+    }
+
+    fn object(&self, obj: AnyRef) -> Result<&GcObject, GcError> {
+        let index = obj.object_index().ok_or(GcError::NotAnObject)?;
+        self.objects
+            .get(index)
+            .and_then(Option::as_ref)
+            .ok_or(GcError::Dangling)
+    }
+
+    fn object_mut(&mut self, obj: AnyRef) -> Result<&mut GcObject, GcError> {
+        let index = obj.object_index().ok_or(GcError::NotAnObject)?;
+        self.objects
+            .get_mut(index)
+            .and_then(Option::as_mut)
+            .ok_or(GcError::Dangling)
+    }
+
+    fn collect(&mut self) {
+        let mut worklist = Vec::new();
+
+        for vmctx in &self.instances {
+            // Safety: instances live as long as their store, which owns this heap
+            let mut instance = unsafe { InstanceData::from_vmctx(*vmctx) }.borrow_mut();
+            instance.visit_gc_roots(|raw| worklist.push(raw));
+        }
+        // Safety: the store's address space is active. References of other stores' heaps can't be
+        // distinguished from ours, they conservatively keep our objects alive.
+        unsafe {
+            trap_handling::visit_wasm_gc_roots(|slot| worklist.push(slot.as_ptr().read()));
+        }
+
+        // mark
+        let mut marked = vec![false; self.objects.len()];
+        while let Some(raw) = worklist.pop() {
+            let Some(index) = AnyRef::from_raw(raw).and_then(AnyRef::object_index) else {
+                continue;
+            };
+            let Some(Some(object)) = self.objects.get(index) else {
+                continue;
+            };
+            if mem::replace(&mut marked[index], true) {
+                continue;
+            }
+
+            worklist.extend(object.fields.iter().filter_map(|field| match field {
+                Val::AnyRef(Some(obj)) => Some(obj.as_raw()),
+                _ => None,
+            }));
+        }
+
+        // sweep
+        let mut freed = 0;
+        for (index, object) in self.objects.iter_mut().enumerate() {
+            if object.is_some() && !marked[index] {
+                *object = None;
+                self.free.push(index);
+                freed += 1;
+            }
+        }
+        log::trace!("GC freed {freed} objects");
+    }
+}
+
+/// Converts `val` to the representation of a field of type `ty`, truncating packed fields.
+fn pack(val: &Val, ty: &WasmStorageType) -> Result<Val, GcError> {
+    match (ty, val) {
+        (WasmStorageType::I8, Val::I32(v)) => Ok(Val::I32(v & 0xff)),
+        (WasmStorageType::I16, Val::I32(v)) => Ok(Val::I32(v & 0xffff)),
+        (WasmStorageType::Val(ty), val) if val.matches_ty(*ty) => Ok(val.clone()),
+        _ => Err(GcError::TypeMismatch),
+    }
+}
@@ -1,10 +1,11 @@
 use crate::frame_alloc::with_frame_alloc;
 use crate::kconfig;
 use crate::runtime::compile::{
-    FunctionLoc, ELF_K23_INFO, ELF_K23_TRAPS, ELF_TEXT, ELF_WASM_DATA, ELF_WASM_DWARF,
-    ELF_WASM_NAMES,
+    FunctionLoc, ELF_K23_INFO, ELF_K23_STACK_MAPS, ELF_K23_TRAPS, ELF_TEXT, ELF_WASM_DATA,
+    ELF_WASM_DWARF, ELF_WASM_NAMES,
 };
 use crate::runtime::guest_memory::AlignedVec;
+use crate::runtime::trap_handling;
 use core::fmt;
 use core::fmt::Formatter;
 use core::ops::Range;
@@ -20,6 +21,7 @@ pub struct CodeMemory {
     wasm_data: Range<VirtualAddress>,
     func_name_data: Range<VirtualAddress>,
     trap_data: Range<VirtualAddress>,
+    stack_map_data: Range<VirtualAddress>,
     dwarf: Range<VirtualAddress>,
     info: Range<VirtualAddress>,
 }
@@ -32,6 +34,7 @@ impl CodeMemory {
         let mut wasm_data = Range::default();
         let mut func_name_data = Range::default();
         let mut trap_data = Range::default();
+        let mut stack_map_data = Range::default();
         let mut dwarf = Range::default();
         let mut info = Range::default();
 
@@ -67,6 +70,7 @@ impl CodeMemory {
                 ELF_WASM_DWARF => dwarf = range,
 
                 ELF_K23_TRAPS => trap_data = range,
+                ELF_K23_STACK_MAPS => stack_map_data = range,
                 ELF_K23_INFO => info = range,
                 _ => {}
             }
@@ -80,6 +84,7 @@ impl CodeMemory {
             wasm_data,
             func_name_data,
             trap_data,
+            stack_map_data,
             dwarf,
             info,
         }
@@ -102,13 +107,21 @@ impl CodeMemory {
 
             mapper.set_flags_for_range(
                 self.text.clone(),
-                EntryFlags::READ | EntryFlags::EXECUTE,
+                EntryFlags::READ | EntryFlags::EXECUTE | EntryFlags::USER,
                 &mut flush,
             )?;
 
             flush.flush()?;
             Ok(())
-        })
+        })?;
+
+        trap_handling::register_code(
+            self.text.clone(),
+            self.trap_data.clone(),
+            self.stack_map_data.clone(),
+        );
+
+        Ok(())
     }
 
     pub fn as_slice(&self) -> &[u8] {
@@ -133,6 +146,14 @@ impl CodeMemory {
     }
 }
 
+impl Drop for CodeMemory {
+    fn drop(&mut self) {
+        if self.published && !self.inner.is_empty() {
+            trap_handling::unregister_code(self.text.clone());
+        }
+    }
+}
+
 impl fmt::Debug for CodeMemory {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("CodeMemory")
@@ -142,6 +163,7 @@ impl fmt::Debug for CodeMemory {
             .field("wasm_data", &self.wasm_data)
             .field("func_name_data", &self.func_name_data)
             .field("trap_data", &self.trap_data)
+            .field("stack_map_data", &self.stack_map_data)
             .field("dwarf", &self.dwarf)
             .field("info", &self.info)
             .finish()
@@ -1,15 +1,79 @@
 use crate::frame_alloc::with_frame_alloc;
 use crate::kconfig;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::alloc::{AllocError, Allocator, Layout};
+use core::cell::Cell;
 use core::fmt;
 use core::fmt::Formatter;
 use core::ops::Range;
+use core::ptr;
 use core::ptr::NonNull;
 use kmm::EntryFlags;
-use kmm::{AddressRangeExt, Flush, Mapper, Mode, VirtualAddress};
+use kmm::{AddressRangeExt, Flush, Mapper, Mode, PhysicalAddress, Table, VirtualAddress};
 use linked_list_allocator::Heap;
-use sync::Mutex;
+use sync::{Mutex, MutexGuard, OnceLock};
+use thread_local::declare_thread_local;
+
+const LEVELS: usize = <kconfig::MEMORY_MODE as Mode>::PAGE_TABLE_LEVELS;
+const ENTRIES: usize = <kconfig::MEMORY_MODE as Mode>::PAGE_TABLE_ENTRIES;
+
+/// Where address space reservations (see [`GuestAllocator::reserve`]) start. This is well above the
+/// regular allocations which grow upwards from the start of the address space.
+const RESERVATIONS_START: usize = 0x10_0000_0000;
+
+declare_thread_local! {
+    /// The allocator whose address space is active on this hart, used to resolve page faults.
+    static ACTIVE: Cell<*const Mutex<GuestAllocatorInner>> = const { Cell::new(ptr::null()) };
+}
+
+/// The root table of the kernel's own address space, recorded when the first guest address space
+/// is created.
+static KERNEL_ROOT_TABLE: OnceLock<PhysicalAddress> = OnceLock::new();
+
+/// The ASIDs of all guest address spaces, ASID 0 belongs to the kernel.
+static ASIDS: Mutex<AsidAllocator> = Mutex::new(AsidAllocator::new());
+
+struct AsidAllocator {
+    /// The largest ASID supported by the hardware, probed on first use.
+    max: Option<usize>,
+    next: usize,
+    free: Vec<usize>,
+}
+
+impl AsidAllocator {
+    const fn new() -> Self {
+        Self {
+            max: None,
+            next: 1,
+            free: Vec::new(),
+        }
+    }
+
+    /// Returns an ASID no other address space is using, `Some(0)` if the hardware doesn't
+    /// support ASIDs and `None` if all of them are in use.
+    fn allocate(&mut self) -> Option<usize> {
+        let max = *self.max.get_or_insert_with(kconfig::MEMORY_MODE::max_asid);
+        if max == 0 {
+            return Some(0);
+        }
+
+        if let Some(asid) = self.free.pop() {
+            return Some(asid);
+        }
+
+        (self.next <= max).then(|| {
+            self.next += 1;
+            self.next - 1
+        })
+    }
+
+    fn deallocate(&mut self, asid: usize) {
+        if asid != 0 {
+            self.free.push(asid);
+        }
+    }
+}
 
 /// A type that knows how to allocate and deallocate memory in userspace.
 ///
@@ -42,7 +106,12 @@ pub struct GuestAllocator(Arc<Mutex<GuestAllocatorInner>>);
 pub struct GuestAllocatorInner {
     asid: usize,
     root_table: VirtualAddress,
+    root_table_phys: PhysicalAddress,
     virt: Range<VirtualAddress>,
+    /// The address range handed out to reservations so far.
+    reserved: Range<VirtualAddress>,
+    /// Ranges that are accessible but only mapped once they are first touched.
+    committed: Vec<Range<VirtualAddress>>,
     // we don't have many allocations, just a few large chunks (e.g. CodeMemory, Stack, Memories)
     // so a simple linked list should suffice.
     // TODO measure and verify this assumption
@@ -50,27 +119,68 @@ pub struct GuestAllocatorInner {
 }
 
 impl GuestAllocator {
-    pub unsafe fn new_in_kernel_space(
-        virt_offset: VirtualAddress,
-        physmem_off: VirtualAddress,
-    ) -> Result<Self, AllocError> {
-        let root_table = kconfig::MEMORY_MODE::get_active_table(0);
+    /// Creates a new allocator backed by a fresh address space with its own ASID.
+    ///
+    /// The kernel half of the currently active address space is shared with the new one, so the
+    /// kernel (and most importantly the trap handler) remains mapped while the guest runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if all ASIDs are in use or the page tables can't be allocated.
+    pub unsafe fn new_in_user_space(virt_offset: VirtualAddress) -> Result<Self, AllocError> {
+        let kernel_root_table =
+            *KERNEL_ROOT_TABLE.get_or_init(|| kconfig::MEMORY_MODE::get_active_table(0));
+
+        let asid = ASIDS.lock().allocate().ok_or(AllocError)?;
+        log::trace!("allocated ASID {asid}");
+
+        let (root_table_phys, root_table) = with_frame_alloc(|frame_alloc| {
+            let Ok(root_table_phys) = frame_alloc.allocate_frame_zeroed() else {
+                ASIDS.lock().deallocate(asid);
+                return Err(AllocError);
+            };
+            let root_table = frame_alloc.phys_to_virt(root_table_phys);
+
+            let kernel = Table::<kconfig::MEMORY_MODE>::new(
+                frame_alloc.phys_to_virt(kernel_root_table),
+                LEVELS - 1,
+            );
+            let mut user = Table::<kconfig::MEMORY_MODE>::new(root_table, LEVELS - 1);
+
+            for index in ENTRIES / 2..ENTRIES {
+                let entry = kernel.entry(index);
+                if !entry.is_vacant() {
+                    user.entry_mut(index)
+                        .set_address_and_flags(entry.get_address(), entry.get_flags());
+                }
+            }
+
+            Ok((root_table_phys, root_table))
+        })?;
 
         let mut inner = GuestAllocatorInner {
-            root_table: physmem_off.add(root_table.as_raw()),
-            asid: 0,
+            root_table,
+            root_table_phys,
+            asid,
             inner: Heap::empty(),
             virt: virt_offset..virt_offset,
+            reserved: VirtualAddress::new(RESERVATIONS_START)
+                ..VirtualAddress::new(RESERVATIONS_START),
+            committed: Vec::new(),
         };
 
         let (mem_virt, flush) = inner.map_additional_pages(16)?;
         flush.flush().unwrap();
 
+        let this = Self(Arc::new(Mutex::new(inner)));
+        // the heap bookkeeping lives in the guest memory itself, so it must be accessible
+        this.activate();
+
         unsafe {
-            inner.inner.init_from_virt_range(&mem_virt);
+            this.0.lock().inner.init_from_virt_range(&mem_virt);
         }
 
-        Ok(Self(Arc::new(Mutex::new(inner))))
+        Ok(this)
     }
 
     pub fn asid(&self) -> usize {
@@ -80,11 +190,152 @@ impl GuestAllocator {
     pub fn root_table(&self) -> VirtualAddress {
         self.0.lock().root_table
     }
+
+    /// Switches the current hart to this allocator's address space.
+    ///
+    /// Guest memory is only accessible - both to the guest and the kernel - while its address
+    /// space is active.
+    pub fn activate(&self) {
+        self.lock_active();
+    }
+
+    /// Locks the allocator and makes sure its address space is active.
+    fn lock_active(&self) -> MutexGuard<'_, GuestAllocatorInner> {
+        let inner = self.0.lock();
+        inner.activate();
+        ACTIVE.with(|active| active.set(Arc::as_ptr(&self.0)));
+        inner
+    }
+
+    /// Reserves `size` bytes of address space without mapping any of it.
+    ///
+    /// Parts of the reservation can then be made accessible through [`Self::map`], everything else
+    /// faults on access. This is used for linear memories that rely on guard pages instead of
+    /// explicit bounds checks.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address space is exhausted.
+    pub fn reserve(&self, size: usize) -> Result<Range<VirtualAddress>, AllocError> {
+        let mut inner = self.0.lock();
+
+        // TODO reuse the address space of dropped reservations
+        let size = size
+            .checked_next_multiple_of(kconfig::PAGE_SIZE)
+            .ok_or(AllocError)?;
+        // the lower half of the address space belongs to the guest
+        let user_end = 1_usize << <kconfig::MEMORY_MODE as Mode>::VA_BITS;
+
+        let start = inner.reserved.end;
+        let end = start
+            .as_raw()
+            .checked_add(size)
+            .filter(|end| *end <= user_end)
+            .ok_or(AllocError)?;
+        inner.reserved.end = VirtualAddress::new(end);
+
+        Ok(start..inner.reserved.end)
+    }
+
+    /// Makes the page-aligned `range`, which must be part of a reservation, accessible.
+    ///
+    /// No physical memory is allocated up front, instead each page gets backed by a zeroed frame
+    /// when it is first touched (see [`handle_page_fault`]).
+    pub fn commit(&self, range: Range<VirtualAddress>) {
+        let mut inner = self.0.lock();
+        debug_assert!(
+            inner.reserved.start <= range.start && range.end <= inner.reserved.end,
+            "{range:?} is not part of a reservation"
+        );
+
+        if range.is_empty() {
+            return;
+        }
+
+        // memories grow at their end, so try extending an existing range first
+        if let Some(committed) = inner
+            .committed
+            .iter_mut()
+            .find(|committed| committed.end == range.start)
+        {
+            committed.end = range.end;
+        } else {
+            inner.committed.push(range);
+        }
+    }
+
+    /// Makes the page-aligned `range` previously passed to [`Self::commit`] inaccessible again,
+    /// freeing all memory that backs it. The address space remains reserved.
+    pub fn decommit(&self, range: Range<VirtualAddress>) {
+        let mut inner = self.0.lock();
+        if range.is_empty() {
+            return;
+        }
+
+        let mut remaining = Vec::with_capacity(inner.committed.len());
+        for committed in inner.committed.drain(..) {
+            if committed.end <= range.start || range.end <= committed.start {
+                remaining.push(committed);
+                continue;
+            }
+
+            if committed.start < range.start {
+                remaining.push(committed.start..range.start);
+            }
+            if range.end < committed.end {
+                remaining.push(range.end..committed.end);
+            }
+        }
+        inner.committed = remaining;
+
+        with_frame_alloc(|frame_alloc| {
+            let mut mapper = Mapper::from_address(inner.asid, inner.root_table, frame_alloc);
+            let mut flush = Flush::empty(inner.asid);
+
+            let num_pages = range.end.sub_addr(range.start) / kconfig::PAGE_SIZE;
+            for i in 0..num_pages {
+                let virt = range.start.add(i * kconfig::PAGE_SIZE);
+
+                // only pages that have been touched are actually mapped
+                if mapper.virt_to_phys(virt).is_some() {
+                    mapper.unmap(virt, &mut flush).expect("failed to unmap");
+                }
+            }
+
+            flush.flush().unwrap();
+        });
+    }
+}
+
+/// Resolves a page fault at `addr` in the address space that is active on this hart.
+///
+/// Returns `true` if the faulting address belongs to a range made accessible through
+/// [`GuestAllocator::commit`], in which case a zeroed frame has been mapped and the faulting
+/// instruction can be retried. Returns `false` for all other faults.
+pub fn handle_page_fault(addr: VirtualAddress) -> bool {
+    let active = ACTIVE.with(Cell::get);
+    if active.is_null() {
+        return false;
+    }
+
+    // Safety: the pointer is cleared before the allocator is freed
+    let Some(mut inner) = (unsafe { &*active }).try_lock() else {
+        return false;
+    };
+
+    let page = addr.align_down(kconfig::PAGE_SIZE);
+    if !inner.committed.iter().any(|range| range.contains(&page)) {
+        return false;
+    }
+
+    log::trace!("mapping committed page {page:?}");
+    inner.map_page(page).is_ok()
 }
 
 unsafe impl Allocator for GuestAllocator {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        let mut inner = self.0.lock();
+        // the heap's bookkeeping is stored in guest memory
+        let mut inner = self.lock_active();
 
         let ptr = if let Ok(ptr) = inner.inner.allocate_first_fit(layout) {
             ptr
@@ -112,12 +363,57 @@ unsafe impl Allocator for GuestAllocator {
         log::trace!("deallocation request {ptr:?} {layout:?}");
         // TODO unmap pages
 
-        let mut inner = self.0.lock();
+        let mut inner = self.lock_active();
         inner.inner.deallocate(ptr, layout);
     }
 }
 
 impl GuestAllocatorInner {
+    fn is_active(&self) -> bool {
+        kconfig::MEMORY_MODE::get_active_table(self.asid) == self.root_table_phys
+    }
+
+    fn activate(&self) {
+        if self.is_active() {
+            return;
+        }
+
+        // `activate_table` expects the physical address of the table
+        kconfig::MEMORY_MODE::activate_table(
+            self.asid,
+            VirtualAddress::new(self.root_table_phys.as_raw()),
+        );
+        // Without ASID support all address spaces share ASID 0, so the cached translations of the
+        // previous one must go. Otherwise they are tagged with their own ASID and remain valid.
+        if self.asid == 0 {
+            kconfig::MEMORY_MODE::invalidate_all().unwrap();
+        }
+    }
+
+    fn map_page(&mut self, virt: VirtualAddress) -> Result<(), AllocError> {
+        with_frame_alloc(|frame_alloc| {
+            let mut mapper = Mapper::from_address(self.asid, self.root_table, frame_alloc);
+            let mut flush = Flush::empty(self.asid);
+
+            let phys = mapper
+                .allocator_mut()
+                .allocate_frame_zeroed()
+                .map_err(|_| AllocError)?;
+
+            mapper
+                .map(
+                    virt,
+                    phys,
+                    EntryFlags::READ | EntryFlags::WRITE | EntryFlags::USER,
+                    &mut flush,
+                )
+                .map_err(|_| AllocError)?;
+
+            flush.flush().unwrap();
+            Ok(())
+        })
+    }
+
     fn map_additional_pages(
         &mut self,
         num_pages: usize,
@@ -141,7 +437,7 @@ impl GuestAllocatorInner {
                 .map_range(
                     mem_virt.clone(),
                     mem_phys,
-                    EntryFlags::READ | EntryFlags::WRITE,
+                    EntryFlags::READ | EntryFlags::WRITE | EntryFlags::USER,
                     &mut flush,
                 )
                 .map_err(|_| AllocError)?;
@@ -155,6 +451,19 @@ impl Drop for GuestAllocatorInner {
     fn drop(&mut self) {
         log::trace!("Unmapping... {:?}", self.virt);
 
+        // never pull the address space out from under the running hart
+        if self.is_active() {
+            let kernel_root_table = *KERNEL_ROOT_TABLE.get().unwrap();
+            kconfig::MEMORY_MODE::activate_table(
+                0,
+                VirtualAddress::new(kernel_root_table.as_raw()),
+            );
+            if self.asid == 0 {
+                kconfig::MEMORY_MODE::invalidate_all().unwrap();
+            }
+            ACTIVE.with(|active| active.set(ptr::null()));
+        }
+
         with_frame_alloc(|frame_alloc| {
             let mut mapper = Mapper::from_address(self.asid, self.root_table, frame_alloc);
             let mut flush = Flush::empty(self.asid);
@@ -162,7 +471,17 @@ impl Drop for GuestAllocatorInner {
             mapper
                 .unmap_range(self.virt.clone(), &mut flush)
                 .expect("failed to unmap");
+
+            // TODO free the intermediate page tables of the user half
+            mapper
+                .allocator_mut()
+                .deallocate_frame(self.root_table_phys)
+                .expect("failed to free root table");
         });
+
+        // the next address space with this ASID must not see any of our translations
+        kconfig::MEMORY_MODE::invalidate_asid(self.asid).unwrap();
+        ASIDS.lock().deallocate(self.asid);
     }
 }
 
@@ -172,7 +491,10 @@ impl fmt::Debug for GuestAllocatorInner {
         f.debug_struct("GuestAllocatorInner")
             .field("asid", &self.asid)
             .field("root_table", &self.root_table)
+            .field("root_table_phys", &self.root_table_phys)
             .field("virt", &self.virt)
+            .field("reserved", &self.reserved)
+            .field("committed", &self.committed)
             .finish()
     }
 }
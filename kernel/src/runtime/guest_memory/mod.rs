@@ -5,6 +5,6 @@ mod guest_allocator;
 pub use aligned_vec::AlignedVec;
 use alloc::vec::Vec;
 pub use code_memory::CodeMemory;
-pub use guest_allocator::GuestAllocator;
+pub use guest_allocator::{handle_page_fault, GuestAllocator};
 
 pub type GuestVec<T> = Vec<T, GuestAllocator>;
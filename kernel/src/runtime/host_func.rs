@@ -0,0 +1,127 @@
+use crate::runtime::guest_memory::GuestAllocator;
+use crate::runtime::typed::{for_each_function_signature, WasmParams, WasmResults, WasmTy};
+use crate::runtime::vmcontext::{
+    VMContext, VMHostFuncContext, VMNativeCallFunction, VMVal, VMHOSTFUNC_MAGIC,
+};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::ptr::NonNull;
+use core::slice;
+use cranelift_wasm::WasmFuncType;
+
+/// A function implemented by the host (i.e. the kernel) that can satisfy a function import.
+///
+/// WASM calls host functions through a WASM to native trampoline compiled for the importing
+/// module, which spills the arguments into a `VMVal` array and calls [`host_func_native_call`]
+/// with the host function's `VMHostFuncContext` as its `vmctx`.
+#[derive(Debug)]
+pub struct HostFunc {
+    ty: WasmFuncType,
+    ctx: VMHostFuncContext,
+}
+
+impl HostFunc {
+    /// Creates a new host function with the given type from an untyped closure.
+    ///
+    /// The closure receives the `VMContext` of the calling instance and a slice of `VMVal`s which
+    /// holds the arguments and must be overwritten with the results.
+    pub fn new(
+        ty: WasmFuncType,
+        func: impl Fn(NonNull<VMContext>, &mut [VMVal]) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            ty,
+            ctx: VMHostFuncContext {
+                magic: VMHOSTFUNC_MAGIC,
+                native_call: host_func_native_call,
+                func: Box::new(func),
+            },
+        }
+    }
+
+    pub fn ty(&self) -> &WasmFuncType {
+        &self.ty
+    }
+
+    /// Returns the native entrypoint of this function.
+    pub fn native_call(&self) -> VMNativeCallFunction {
+        self.ctx.native_call
+    }
+
+    /// Creates a copy of this function's context in guest memory, WASM loads the native entrypoint
+    /// from it so it has to be accessible to the guest. The copy is what should be used as the
+    /// `vmctx` of this function in a `VMFunctionImport`.
+    pub fn guest_context(
+        self: &Arc<Self>,
+        alloc: GuestAllocator,
+    ) -> Box<VMHostFuncContext, GuestAllocator> {
+        let this = self.clone();
+
+        Box::new_in(
+            VMHostFuncContext {
+                magic: VMHOSTFUNC_MAGIC,
+                native_call: host_func_native_call,
+                func: Box::new(move |caller, values| (this.ctx.func)(caller, values)),
+            },
+            alloc,
+        )
+    }
+}
+
+/// The native entrypoint shared by all host functions.
+///
+/// WASM calls this from user mode, see [`handle_user_exception`] for how the call is carried out.
+///
+/// [`handle_user_exception`]: crate::runtime::trap_handling::handle_user_exception
+pub(crate) unsafe extern "C" fn host_func_native_call(
+    callee_vmctx: *mut VMContext,
+    caller_vmctx: *mut VMContext,
+    values: *mut VMVal,
+    values_len: usize,
+) {
+    let ctx = &*callee_vmctx.cast::<VMHostFuncContext>();
+    debug_assert_eq!(ctx.magic, VMHOSTFUNC_MAGIC);
+
+    let values = slice::from_raw_parts_mut(values, values_len);
+    (ctx.func)(NonNull::new(caller_vmctx).unwrap(), values);
+}
+
+/// A Rust closure that can be turned into a [`HostFunc`].
+///
+/// This is implemented for closures whose parameters are [`WasmTy`]s and whose return type is a
+/// [`WasmResults`].
+pub trait IntoFunc<Params, Results>: Send + Sync + 'static {
+    fn into_host_func(self) -> HostFunc;
+}
+
+macro_rules! impl_into_func {
+    ($($args:ident)*) => {
+        impl<F, $($args,)* R> IntoFunc<($($args,)*), R> for F
+        where
+            F: Fn($($args),*) -> R + Send + Sync + 'static,
+            $($args: WasmTy,)*
+            R: WasmResults,
+        {
+            #[allow(non_snake_case, unused_variables, unused_mut, unused_assignments)]
+            fn into_host_func(self) -> HostFunc {
+                let ty = WasmFuncType::new(
+                    Box::new([$($args::valtype()),*]),
+                    R::valtypes(),
+                );
+
+                HostFunc::new(ty, move |_caller, values| {
+                    let mut i = 0;
+                    $(
+                        let $args = unsafe { <$args as WasmTy>::load(&values[i]) };
+                        i += 1;
+                    )*
+
+                    let results = (self)($($args),*);
+                    results.store(values);
+                })
+            }
+        }
+    };
+}
+
+for_each_function_signature!(impl_into_func);
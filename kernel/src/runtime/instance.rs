@@ -1,40 +1,92 @@
+use crate::runtime::builtins::VMBuiltinFunctionsArray;
 use crate::runtime::compile::CompiledModuleInfo;
 use crate::runtime::const_expr::ConstExprEvaluator;
+use crate::runtime::errors::LinkError;
+use crate::runtime::export::{Export, ExportFunction, ExportGlobal, ExportMemory, ExportTable};
+use crate::runtime::func::{Func, TypedFunc};
+use crate::runtime::gc::GcHeap;
 use crate::runtime::guest_memory::CodeMemory;
 use crate::runtime::memory::Memory;
 use crate::runtime::module::Module;
 use crate::runtime::store::Store;
-use crate::runtime::table::Table;
-use crate::runtime::translate::{TableInitialValue, TableSegmentElements, TranslatedModule};
+use crate::runtime::table::{Table, TableElement};
+use crate::runtime::translate::{
+    TableElementType, TableInitialValue, TableSegmentElements, TranslatedModule,
+};
 use crate::runtime::trap::Trap;
+use crate::runtime::type_registry;
+use crate::runtime::typed::{WasmParams, WasmResults};
+use crate::runtime::utils::{is_gc_heap_ref, is_gc_heap_type};
 use crate::runtime::vmcontext::{
-    VMContext, VMContextPlan, VMFuncRef, VMFunctionImport, VMGlobalDefinition, VMGlobalImport,
-    VMMemoryDefinition, VMMemoryImport, VMTableDefinition, VMTableImport, VMCONTEXT_MAGIC,
+    VMContext, VMContextPlan, VMEpoch, VMFuncRef, VMFunctionBody, VMFunctionImport,
+    VMGlobalDefinition, VMGlobalImport, VMMemoryDefinition, VMMemoryImport, VMNativeCallFunction,
+    VMSharedTypeIndex, VMTableDefinition, VMTableImport, VMCONTEXT_INSTANCE_OFFSET,
+    VMCONTEXT_MAGIC,
 };
+use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::cell::RefCell;
 use core::fmt::Formatter;
 use core::ptr::NonNull;
 use core::{fmt, mem, ptr, slice};
+use cranelift_codegen::entity::Unsigned;
 use cranelift_entity::packed_option::ReservedValue;
-use cranelift_entity::{entity_impl, EntityRef, PrimaryMap};
+use cranelift_entity::{entity_impl, EntityRef, EntitySet, PrimaryMap};
 use cranelift_wasm::{
-    DefinedGlobalIndex, DefinedMemoryIndex, DefinedTableIndex, FuncIndex, GlobalIndex, MemoryIndex,
-    ModuleInternedTypeIndex, TableIndex, WasmHeapType,
+    DataIndex, DefinedGlobalIndex, DefinedMemoryIndex, DefinedTableIndex, ElemIndex, EntityIndex,
+    FuncIndex, GlobalIndex, MemoryIndex, ModuleInternedTypeIndex, TableIndex,
 };
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
 pub struct Instance(u32);
 entity_impl!(Instance);
 
+/// The resolved imports of a module, in the order they are declared in the module.
+///
+/// This is produced by the [`Linker`](crate::runtime::Linker) and copied into the `VMContext`
+/// of the new instance.
+#[derive(Debug, Default)]
+pub struct Imports {
+    pub functions: Vec<VMFunctionImport>,
+    pub tables: Vec<VMTableImport>,
+    pub memories: Vec<VMMemoryImport>,
+    pub globals: Vec<VMGlobalImport>,
+}
+
+impl Imports {
+    pub fn with_capacity_for(module: &TranslatedModule) -> Self {
+        Self {
+            functions: Vec::with_capacity(module.num_imported_functions as usize),
+            tables: Vec::with_capacity(module.num_imported_tables as usize),
+            memories: Vec::with_capacity(module.num_imported_memories as usize),
+            globals: Vec::with_capacity(module.num_imported_globals as usize),
+        }
+    }
+}
+
 impl Instance {
-    pub fn new<'wasm>(store: &mut Store<'wasm>, module: &Module<'wasm>) -> Result<Instance, Trap> {
-        let handle = store.allocate_module(module);
+    pub fn new<'wasm>(
+        store: &mut Store<'wasm>,
+        module: &Module<'wasm>,
+        imports: Imports,
+    ) -> Result<Instance, LinkError> {
+        // the instance's state lives in the store's guest memory
+        store.activate();
+        let handle = store
+            .allocate_module(module)
+            .map_err(|_| LinkError::OutOfMemory)?;
 
         let mut const_eval = ConstExprEvaluator::default();
 
         log::trace!("initializing vmctx...");
-        initialize_vmctx(&mut const_eval, store, handle, &module.info.module)?;
+        initialize_vmctx(
+            &mut const_eval,
+            store,
+            handle,
+            &module.info.module,
+            &imports,
+        )?;
         log::trace!("initialized vmctx...");
 
         log::trace!("initializing tables...");
@@ -42,12 +94,53 @@ impl Instance {
         log::trace!("initialized tables...");
 
         log::trace!("initializing memories...");
-        initialize_memories(store, handle, &module.info.module)?;
+        initialize_memories(&mut const_eval, store, handle, &module.info.module)?;
         log::trace!("initialized memories...");
 
+        if let Some(start) = module.info.module.start {
+            log::trace!("running start function {start:?}...");
+            let export = store
+                .instance_data_mut(handle)
+                .get_export(EntityIndex::Function(start));
+            let Export::Function(start) = export else {
+                unreachable!()
+            };
+            Func::from_export(start).call(store, &[], &mut [])?;
+        }
+
         Ok(handle)
     }
 
+    /// Looks up the export with the given name.
+    pub fn get_export(self, store: &mut Store, name: &str) -> Option<Export> {
+        let mut data = store.instance_data_mut(self);
+        let index = *data.module_info.module.exports.get(name)?;
+        Some(data.get_export(index))
+    }
+
+    /// Looks up the exported function with the given name.
+    pub fn get_func(self, store: &mut Store, name: &str) -> Option<Func> {
+        match self.get_export(store, name)? {
+            Export::Function(func) => Some(Func::from_export(func)),
+            _ => None,
+        }
+    }
+
+    /// Looks up the exported function with the given name and attempts to statically type it.
+    ///
+    /// Returns `None` if there is no such function or if its type doesn't match.
+    pub fn get_typed_func<Params, Results>(
+        self,
+        store: &mut Store,
+        name: &str,
+    ) -> Option<TypedFunc<Params, Results>>
+    where
+        Params: WasmParams,
+        Results: WasmResults,
+    {
+        self.get_func(store, name)?.typed()
+    }
+
     pub fn debug_print_vmctx(self, store: &Store) {
         struct Dbg<'a, 'wasm> {
             data: &'a InstanceData<'wasm>,
@@ -94,9 +187,30 @@ pub struct InstanceData<'wasm> {
     pub vmctx_plan: VMContextPlan,
     pub tables: PrimaryMap<DefinedTableIndex, Table>,
     pub memories: PrimaryMap<DefinedMemoryIndex, Memory>,
+    /// Passive element segments that have been dropped through `elem.drop`.
+    pub dropped_elements: EntitySet<ElemIndex>,
+    /// Passive data segments that have been dropped through `data.drop`.
+    pub dropped_data: EntitySet<DataIndex>,
+    /// The GC heap of the store owning this instance, constant expressions allocate their structs
+    /// and arrays in it.
+    pub gc_heap: GcHeap,
 }
 
 impl<'wasm> InstanceData<'wasm> {
+    /// Returns the instance owning the given `VMContext`.
+    ///
+    /// # Safety
+    ///
+    /// `vmctx` must be the `VMContext` of a live instance, and the returned reference must not
+    /// outlive the `Store` owning that instance.
+    pub unsafe fn from_vmctx<'a>(vmctx: NonNull<VMContext>) -> &'a RefCell<InstanceData<'a>> {
+        let ptr = *vmctx
+            .as_ptr()
+            .byte_add(VMCONTEXT_INSTANCE_OFFSET)
+            .cast::<*const RefCell<InstanceData<'a>>>();
+        &*ptr
+    }
+
     unsafe fn vmctx_magic(&self) -> u32 {
         *self.vmctx_plus_offset::<u32>(self.vmctx_plan.vmctx_magic())
     }
@@ -190,6 +304,11 @@ impl<'wasm> InstanceData<'wasm> {
         )
     }
 
+    /// Returns the epoch state of the store owning this instance.
+    pub fn vmctx_epoch(&self) -> *mut VMEpoch {
+        unsafe { *self.vmctx_plus_offset::<*mut VMEpoch>(self.vmctx_plan.vmctx_epoch()) }
+    }
+
     unsafe fn vmctx_plus_offset<T>(&self, offset: u32) -> *const T {
         self.vmctx
             .as_ptr()
@@ -224,14 +343,98 @@ impl<'wasm> InstanceData<'wasm> {
 
     fn construct_func_ref(
         &self,
-        _func_index: FuncIndex,
-        _sig: ModuleInternedTypeIndex,
+        func_index: FuncIndex,
+        sig: ModuleInternedTypeIndex,
         out: *mut VMFuncRef,
     ) {
+        let type_index = type_registry::register(&self.module_info.types[sig]);
+
+        let func_ref =
+            if let Some(def_index) = self.module_info.module.defined_function_index(func_index) {
+                let info = &self.module_info.funcs[def_index];
+                let wasm_call = self.code.resolve_function_loc(info.wasm_func_loc);
+                let native_call = self.code.resolve_function_loc(
+                    info.native_to_wasm_trampoline
+                        .expect("escaping function has a native to WASM trampoline"),
+                );
+
+                VMFuncRef {
+                    native_call: unsafe {
+                        mem::transmute::<usize, VMNativeCallFunction>(native_call.as_raw())
+                    },
+                    wasm_call: wasm_call.as_raw() as *const VMFunctionBody,
+                    type_index,
+                    vmctx: self.vmctx.as_ptr(),
+                }
+            } else {
+                let import = self.imported_function(func_index);
+
+                VMFuncRef {
+                    native_call: import.native_call,
+                    wasm_call: import.wasm_call,
+                    // the linker checked that the import has the declared type
+                    type_index,
+                    vmctx: import.vmctx.as_ptr(),
+                }
+            };
+
         unsafe {
-            out.write(VMFuncRef {
-                vmctx: self.vmctx.as_ptr(),
-            });
+            out.write(func_ref);
+        }
+    }
+
+    /// Returns the [`Export`] for the given entity, the entity may either be defined by this
+    /// instance or imported.
+    pub fn get_export(&mut self, index: EntityIndex) -> Export {
+        match index {
+            EntityIndex::Function(func_index) => {
+                let func_ref = self
+                    .get_func_ref(func_index)
+                    .expect("exported function index is valid");
+                let sig = self.module_info.module.functions[func_index].signature;
+
+                Export::Function(ExportFunction {
+                    func_ref: NonNull::new(func_ref).unwrap(),
+                    ty: self.module_info.types[sig].unwrap_func().clone(),
+                })
+            }
+            EntityIndex::Table(table_index) => {
+                let (definition, vmctx) = if let Some(def_index) =
+                    self.module_info.module.defined_table_index(table_index)
+                {
+                    (self.table_ptr(def_index), self.vmctx)
+                } else {
+                    let import = self.imported_table(table_index);
+                    (import.from, import.vmctx)
+                };
+
+                Export::Table(ExportTable {
+                    definition,
+                    vmctx,
+                    table: self.module_info.module.table_plans[table_index].table,
+                })
+            }
+            EntityIndex::Memory(memory_index) => {
+                let (definition, vmctx) = if let Some(def_index) =
+                    self.module_info.module.defined_memory_index(memory_index)
+                {
+                    (self.memory_ptr(def_index), self.vmctx)
+                } else {
+                    let import = self.imported_memory(memory_index);
+                    (import.from, import.vmctx)
+                };
+
+                Export::Memory(ExportMemory {
+                    definition,
+                    vmctx,
+                    memory: self.module_info.module.memory_plans[memory_index].memory,
+                })
+            }
+            EntityIndex::Global(global_index) => Export::Global(ExportGlobal {
+                definition: self.defined_or_imported_global_ptr(global_index),
+                vmctx: self.vmctx,
+                global: self.module_info.module.globals[global_index],
+            }),
         }
     }
 
@@ -246,10 +449,22 @@ impl<'wasm> InstanceData<'wasm> {
         if let Some(index) = self.module_info.module.defined_global_index(global_index) {
             self.global_ptr(index)
         } else {
-            todo!()
+            self.imported_global(global_index).from
         }
     }
 
+    fn imported_function(&self, index: FuncIndex) -> &VMFunctionImport {
+        unsafe { &*self.vmctx_plus_offset(self.vmctx_plan.vmctx_function_import(index)) }
+    }
+
+    fn imported_memory(&self, index: MemoryIndex) -> &VMMemoryImport {
+        unsafe { &*self.vmctx_plus_offset(self.vmctx_plan.vmctx_memory_import(index)) }
+    }
+
+    pub fn imported_global(&self, index: GlobalIndex) -> &VMGlobalImport {
+        unsafe { &*self.vmctx_plus_offset(self.vmctx_plan.vmctx_global_import(index)) }
+    }
+
     fn table_ptr(&mut self, index: DefinedTableIndex) -> *mut VMTableDefinition {
         unsafe { self.vmctx_plus_offset_mut(self.vmctx_plan.vmctx_table_definition(index)) }
     }
@@ -269,33 +484,360 @@ impl<'wasm> InstanceData<'wasm> {
         index
     }
 
+    /// Returns the `VMMemoryDefinition` of the given defined memory.
+    fn memory_ptr(&self, index: DefinedMemoryIndex) -> *mut VMMemoryDefinition {
+        unsafe { *self.vmctx_plus_offset(self.vmctx_plan.vmctx_memory_pointer(index)) }
+    }
+
+    pub unsafe fn memory_index(&self, memory: &VMMemoryDefinition) -> DefinedMemoryIndex {
+        let index = self
+            .vmctx_memory_pointers()
+            .iter()
+            .position(|ptr| ptr::eq(*ptr, memory))
+            .expect("memory is not defined by this instance");
+        DefinedMemoryIndex::new(index)
+    }
+
+    fn defined_or_imported_memory(&self, index: MemoryIndex) -> &VMMemoryDefinition {
+        unsafe {
+            if let Some(def_index) = self.module_info.module.defined_memory_index(index) {
+                &*self.memory_ptr(def_index)
+            } else {
+                &*self.imported_memory(index).from
+            }
+        }
+    }
+
+    /// Calls `f` with the instance that defines the given memory and the memory's index in that
+    /// instance.
+    fn with_defined_memory<R>(
+        &mut self,
+        index: MemoryIndex,
+        f: impl FnOnce(&mut InstanceData, DefinedMemoryIndex) -> R,
+    ) -> R {
+        if let Some(def_index) = self.module_info.module.defined_memory_index(index) {
+            f(self, def_index)
+        } else {
+            let import = *self.imported_memory(index);
+            unsafe {
+                let mut foreign = InstanceData::from_vmctx(import.vmctx).borrow_mut();
+                let def_index = foreign.memory_index(&*import.from);
+                f(&mut foreign, def_index)
+            }
+        }
+    }
+
+    /// Calls `f` with the instance that defines the given table and the table's index in that
+    /// instance.
+    fn with_defined_table<R>(
+        &mut self,
+        index: TableIndex,
+        f: impl FnOnce(&mut InstanceData, DefinedTableIndex) -> R,
+    ) -> R {
+        if let Some(def_index) = self.module_info.module.defined_table_index(index) {
+            f(self, def_index)
+        } else {
+            let import = *self.imported_table(index);
+            unsafe {
+                let mut foreign = InstanceData::from_vmctx(import.vmctx).borrow_mut();
+                let def_index = foreign.table_index(&*import.from);
+                f(&mut foreign, def_index)
+            }
+        }
+    }
+
+    /// Grows the given memory by `delta` pages, returning the previous size in pages.
+    ///
+    /// Returns `None` if the memory cannot be grown.
+    pub fn memory_grow(&mut self, index: MemoryIndex, delta: u64) -> Option<u64> {
+        self.with_defined_memory(index, |instance, def_index| {
+            let memory = &mut instance.memories[def_index];
+            let old_byte_size = memory.grow(delta)?;
+            let page_size_log2 = memory.page_size_log2;
+
+            // growing may have moved the memory, so update its definition
+            let vmmemory = memory.as_vmmemory();
+            unsafe {
+                instance.memory_ptr(def_index).write(vmmemory);
+            }
+
+            Some(u64::try_from(old_byte_size).unwrap() >> page_size_log2)
+        })
+    }
+
+    /// Implements the `memory.copy` instruction.
+    pub fn memory_copy(
+        &mut self,
+        dst_index: MemoryIndex,
+        dst: u64,
+        src_index: MemoryIndex,
+        src: u64,
+        len: u64,
+    ) -> Result<(), Trap> {
+        let src_memory = self.defined_or_imported_memory(src_index);
+        let dst_memory = self.defined_or_imported_memory(dst_index);
+
+        let src = validate_inbounds(src_memory, src, len)?;
+        let dst = validate_inbounds(dst_memory, dst, len)?;
+        let len = usize::try_from(len).unwrap();
+
+        // Safety: both ranges have been bounds checked above, they may overlap
+        unsafe {
+            ptr::copy(src_memory.base.add(src), dst_memory.base.add(dst), len);
+        }
+
+        Ok(())
+    }
+
+    /// Implements the `memory.fill` instruction.
+    pub fn memory_fill(
+        &mut self,
+        index: MemoryIndex,
+        dst: u64,
+        val: u8,
+        len: u64,
+    ) -> Result<(), Trap> {
+        let memory = self.defined_or_imported_memory(index);
+
+        let dst = validate_inbounds(memory, dst, len)?;
+        let len = usize::try_from(len).unwrap();
+
+        // Safety: the range has been bounds checked above
+        unsafe {
+            ptr::write_bytes(memory.base.add(dst), val, len);
+        }
+
+        Ok(())
+    }
+
+    /// Implements the `memory.init` instruction.
+    pub fn memory_init(
+        &mut self,
+        index: MemoryIndex,
+        data_index: DataIndex,
+        dst: u64,
+        src: u32,
+        len: u32,
+    ) -> Result<(), Trap> {
+        let module_info = self.module_info.clone();
+        // dropped and active segments behave like empty segments
+        let data: &[u8] = if self.dropped_data.contains(data_index) {
+            &[]
+        } else {
+            module_info
+                .module
+                .passive_data_segments
+                .get(&data_index)
+                .copied()
+                .unwrap_or(&[])
+        };
+
+        let bytes = usize::try_from(src)
+            .ok()
+            .and_then(|src| data.get(src..))
+            .and_then(|data| data.get(..usize::try_from(len).ok()?))
+            .ok_or(Trap::MemoryOutOfBounds)?;
+
+        self.memory_write(index, dst, bytes)
+    }
+
+    /// Implements the `data.drop` instruction.
+    pub fn data_drop(&mut self, data_index: DataIndex) {
+        self.dropped_data.insert(data_index);
+    }
+
+    /// Copies `bytes` into the given memory at offset `dst`.
+    fn memory_write(&self, index: MemoryIndex, dst: u64, bytes: &[u8]) -> Result<(), Trap> {
+        let memory = self.defined_or_imported_memory(index);
+
+        let len = u64::try_from(bytes.len()).unwrap();
+        let dst = validate_inbounds(memory, dst, len)?;
+
+        // Safety: the range has been bounds checked above
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), memory.base.add(dst), bytes.len());
+        }
+
+        Ok(())
+    }
+
+    /// Validates the address of the `size` byte access of `memory.atomic.wait` and
+    /// `memory.atomic.notify` instructions at `addr` of the given memory, returns the accessed
+    /// address.
+    ///
+    /// Other atomic instructions check their address inline.
+    pub fn validate_atomic_addr(
+        &self,
+        index: MemoryIndex,
+        addr: u64,
+        size: u64,
+    ) -> Result<*mut u8, Trap> {
+        // memories are page aligned, so it is enough to check the offset
+        if addr % size != 0 {
+            return Err(Trap::HeapMisaligned);
+        }
+
+        let memory = self.defined_or_imported_memory(index);
+        let addr = validate_inbounds(memory, addr, size)?;
+
+        // Safety: the address has been bounds checked above
+        Ok(unsafe { memory.base.add(addr) })
+    }
+
+    /// Returns whether the given memory is shared.
+    pub fn is_shared_memory(&self, index: MemoryIndex) -> bool {
+        self.module_info.module.memory_plans[index].memory.shared
+    }
+
+    /// Grows the given table by `delta` elements initialized to `init`, returning the previous
+    /// size.
+    ///
+    /// Returns `None` if the table cannot be grown.
+    pub fn table_grow(&mut self, index: TableIndex, delta: u32, init: TableElement) -> Option<u32> {
+        self.with_defined_table(index, |instance, def_index| {
+            let old_len = instance.tables[def_index].grow(delta, init)?;
+
+            // growing may have moved the elements, so update the table definition
+            unsafe {
+                let vmtable = instance.tables[def_index].as_vmtable();
+                instance.table_ptr(def_index).write(vmtable);
+            }
+
+            Some(old_len)
+        })
+    }
+
+    /// Implements the `table.fill` instruction.
+    pub fn table_fill(
+        &mut self,
+        index: TableIndex,
+        dst: u32,
+        val: TableElement,
+        len: u32,
+    ) -> Result<(), Trap> {
+        self.with_defined_table(index, |instance, def_index| {
+            instance.tables[def_index].fill(dst, val, len)
+        })
+    }
+
+    /// Implements the `table.copy` instruction.
+    pub fn table_copy(
+        &mut self,
+        dst_index: TableIndex,
+        src_index: TableIndex,
+        dst: u32,
+        src: u32,
+        len: u32,
+    ) -> Result<(), Trap> {
+        let dst_table = self.with_defined_table(dst_index, |instance, def_index| {
+            ptr::from_mut(&mut instance.tables[def_index])
+        });
+        let src_table = self.with_defined_table(src_index, |instance, def_index| {
+            ptr::from_mut(&mut instance.tables[def_index])
+        });
+
+        // Safety: tables are never deallocated while their instance is alive
+        unsafe { Table::copy(dst_table, src_table, dst, src, len) }
+    }
+
+    /// Returns the element at `index` of the given table.
+    pub fn table_get(&mut self, table_index: TableIndex, index: u32) -> Result<TableElement, Trap> {
+        self.with_defined_table(table_index, |instance, def_index| {
+            instance.tables[def_index].get(index)
+        })
+    }
+
+    /// Implements the `table.init` instruction.
+    pub fn table_init(
+        &mut self,
+        table_index: TableIndex,
+        elem_index: ElemIndex,
+        dst: u32,
+        src: u32,
+        len: u32,
+    ) -> Result<(), Trap> {
+        let module_info = self.module_info.clone();
+        // dropped and active segments behave like empty segments
+        let elements = if self.dropped_elements.contains(elem_index) {
+            None
+        } else {
+            module_info.module.passive_element_segments.get(&elem_index)
+        };
+
+        let empty = TableSegmentElements::Functions(Box::default());
+        let elements = elements.unwrap_or(&empty);
+
+        self.table_init_segment(
+            &mut ConstExprEvaluator::default(),
+            table_index,
+            elements,
+            dst,
+            src,
+            len,
+        )
+    }
+
+    /// Implements the `elem.drop` instruction.
+    pub fn elem_drop(&mut self, elem_index: ElemIndex) {
+        self.dropped_elements.insert(elem_index);
+    }
+
+    /// Calls `f` with each GC reference of the `any` hierarchy stored in the tables and globals
+    /// defined by this instance, these are roots of the store's GC heap.
+    pub fn visit_gc_roots(&mut self, mut f: impl FnMut(u32)) {
+        let module_info = self.module_info.clone();
+        let module = &module_info.module;
+
+        for (def_index, table) in &self.tables {
+            let plan = &module.table_plans[module.table_index(def_index)];
+            if let Table::GcRef(table) = table {
+                if is_gc_heap_type(plan.table.wasm_ty.heap_type) {
+                    table.elements.iter().copied().for_each(&mut f);
+                }
+            }
+        }
+
+        for (global_index, global) in &module.globals {
+            let Some(def_index) = module.defined_global_index(global_index) else {
+                continue;
+            };
+            if is_gc_heap_ref(global.wasm_ty) {
+                f(unsafe { *(*self.global_ptr(def_index)).as_u32() });
+            }
+        }
+    }
+
+    /// Returns the `VMFuncRef` for the given function, used to implement `ref.func`.
+    pub fn ref_func(&mut self, func_index: FuncIndex) -> *mut VMFuncRef {
+        self.get_func_ref(func_index)
+            .expect("function index is valid")
+    }
+
+    /// Copies `len` elements starting at `src` of the given segment into the table at `dst`.
     pub fn table_init_segment(
         &mut self,
         const_eval: &mut ConstExprEvaluator,
-        def_table_index: DefinedTableIndex,
+        table_index: TableIndex,
         elements: &TableSegmentElements,
         dst: u32,
         src: u32,
         len: u32,
     ) -> Result<(), Trap> {
-        match elements {
+        // The elements are resolved in this instance, but the table itself may be imported
+        let elements: Vec<_> = match elements {
             TableSegmentElements::Functions(funcs) => {
-                let elements = funcs
+                let funcs = funcs
                     .get(src as usize..)
                     .and_then(|s| s.get(..len as usize))
                     .ok_or(Trap::TableOutOfBounds)?;
 
-                // annoying allocation here for lifetime reasons
-                let elements: Vec<_> = elements
+                funcs
                     .iter()
                     .map(|idx| {
                         log::debug!("table segment elements: func_index {idx:?}");
-                        self.get_func_ref(*idx).unwrap_or(ptr::null_mut())
+                        TableElement::FuncRef(self.get_func_ref(*idx).unwrap_or(ptr::null_mut()))
                     })
-                    .collect();
-
-                let table = &mut self.tables[def_table_index];
-                table.init_func(dst, elements.into_iter())?;
+                    .collect()
             }
             TableSegmentElements::Expressions(exprs) => {
                 let exprs = exprs
@@ -303,53 +845,39 @@ impl<'wasm> InstanceData<'wasm> {
                     .and_then(|s| s.get(..len as usize))
                     .ok_or(Trap::TableOutOfBounds)?;
 
-                let table_index = self.module_info.module.table_index(def_table_index);
-                match self.module_info.module.table_plans[table_index]
+                let ty = self.module_info.module.table_plans[table_index]
                     .table
-                    .wasm_ty
-                    .heap_type
-                {
-                    WasmHeapType::Func | WasmHeapType::ConcreteFunc(_) | WasmHeapType::NoFunc => {
-                        let exprs: Vec<_> = exprs
-                            .iter()
-                            .map(|expr| unsafe { const_eval.eval(self, expr).funcref.cast() })
-                            .collect();
-
-                        self.tables[def_table_index].init_func(dst, exprs.into_iter())?;
-                    }
-                    WasmHeapType::Extern
-                    | WasmHeapType::NoExtern
-                    | WasmHeapType::Eq
-                    | WasmHeapType::Array
-                    | WasmHeapType::ConcreteArray(_)
-                    | WasmHeapType::Struct
-                    | WasmHeapType::ConcreteStruct(_)
-                    | WasmHeapType::Any
-                    | WasmHeapType::I31
-                    | WasmHeapType::None => todo!(),
-                }
+                    .wasm_ty;
+                exprs
+                    .iter()
+                    .map(|expr| {
+                        let val = const_eval.eval(self, expr)?;
+                        // Safety: validation ensures the expressions have the table's type
+                        Ok(unsafe { TableElement::from_vmval(&val, TableElementType::from(ty)) })
+                    })
+                    .collect::<Result<_, Trap>>()?
             }
-        }
+        };
 
-        Ok(())
+        self.with_defined_table(table_index, |instance, def_index| {
+            instance.tables[def_index].init(dst, elements.into_iter())
+        })
     }
+}
 
-    fn defined_or_imported_memory(&self, index: MemoryIndex) -> &VMMemoryDefinition {
-        unsafe {
-            if let Some(def_index) = self.module_info.module.defined_memory_index(index) {
-                &self.vmctx_memory_definitions()[def_index.index()]
+/// Checks that `len` bytes starting at `addr` are in bounds of the given memory and returns `addr`
+/// as a `usize`.
+fn validate_inbounds(memory: &VMMemoryDefinition, addr: u64, len: u64) -> Result<usize, Trap> {
+    let end = addr
+        .checked_add(len)
+        .and_then(|end| usize::try_from(end).ok())
+        .ok_or(Trap::MemoryOutOfBounds)?;
 
-                // *self.vmctx_plus_offset::<*mut VMMemoryDefinition>(
-                //     self.vmctx_plan.vmctx_memory_pointer(def_index),
-                // )
-            } else {
-                let import = &*self.vmctx_plus_offset::<VMMemoryImport>(
-                    self.vmctx_plan.vmctx_memory_import(index),
-                );
-                &*import.from
-            }
-        }
+    if end > memory.current_length() {
+        return Err(Trap::MemoryOutOfBounds);
     }
+
+    Ok(usize::try_from(addr).unwrap())
 }
 
 fn initialize_vmctx(
@@ -357,6 +885,7 @@ fn initialize_vmctx(
     store: &Store,
     instance: Instance,
     module: &TranslatedModule,
+    imports: &Imports,
 ) -> Result<(), Trap> {
     let mut data = store.instance_data_mut(instance);
 
@@ -365,9 +894,76 @@ fn initialize_vmctx(
         let offset = data.vmctx_plan.vmctx_magic();
         *data.vmctx_plus_offset_mut(offset) = VMCONTEXT_MAGIC;
 
+        // initialize the builtin functions array
+        let offset = data.vmctx_plan.vmctx_builtin_functions();
+        *data.vmctx_plus_offset_mut::<*const VMBuiltinFunctionsArray>(offset) =
+            store.builtin_functions();
+
+        // all instances in a store consume fuel from the same counter
+        let offset = data.vmctx_plan.vmctx_fuel();
+        *data.vmctx_plus_offset_mut::<*mut u64>(offset) = store.fuel();
+
+        // likewise for the epoch deadline
+        let offset = data.vmctx_plan.vmctx_epoch();
+        *data.vmctx_plus_offset_mut::<*mut VMEpoch>(offset) = store.epoch();
+
+        // all instances in a store execute on the same stack
+        let offset = data.vmctx_plan.vmctx_stack_limit();
+        *data.vmctx_plus_offset_mut::<usize>(offset) = store.stack().limit().as_raw();
+
+        // record the canonical index of each type, `call_indirect` compares them to the type of
+        // the callee
+        let module_info = data.module_info.clone();
+        for (type_index, interned_index) in &module.types {
+            let type_id = type_registry::register(&module_info.types[*interned_index]);
+
+            let offset = data.vmctx_plan.vmctx_type_id(type_index);
+            *data.vmctx_plus_offset_mut::<VMSharedTypeIndex>(offset) = type_id;
+        }
+
+        // initialize imports, this needs to happen first since global initializers and element
+        // segments may refer to imported entities
+        debug_assert_eq!(
+            imports.functions.len(),
+            module.num_imported_functions as usize
+        );
+        let offset = data.vmctx_plan.vmctx_function_imports_start();
+        ptr::copy_nonoverlapping(
+            imports.functions.as_ptr(),
+            data.vmctx_plus_offset_mut::<VMFunctionImport>(offset),
+            imports.functions.len(),
+        );
+
+        debug_assert_eq!(imports.tables.len(), module.num_imported_tables as usize);
+        let offset = data.vmctx_plan.vmctx_table_imports_start();
+        ptr::copy_nonoverlapping(
+            imports.tables.as_ptr(),
+            data.vmctx_plus_offset_mut::<VMTableImport>(offset),
+            imports.tables.len(),
+        );
+
+        debug_assert_eq!(
+            imports.memories.len(),
+            module.num_imported_memories as usize
+        );
+        let offset = data.vmctx_plan.vmctx_memory_imports_start();
+        ptr::copy_nonoverlapping(
+            imports.memories.as_ptr(),
+            data.vmctx_plus_offset_mut::<VMMemoryImport>(offset),
+            imports.memories.len(),
+        );
+
+        debug_assert_eq!(imports.globals.len(), module.num_imported_globals as usize);
+        let offset = data.vmctx_plan.vmctx_global_imports_start();
+        ptr::copy_nonoverlapping(
+            imports.globals.as_ptr(),
+            data.vmctx_plus_offset_mut::<VMGlobalImport>(offset),
+            imports.globals.len(),
+        );
+
         // initialize defined globals
         for (global_index, expr) in &module.global_initializers {
-            let val = const_eval.eval(&mut data, expr);
+            let val = const_eval.eval(&mut data, expr)?;
 
             log::debug!("global initializer {global_index:?} {:?}", val.v128);
 
@@ -390,9 +986,13 @@ fn initialize_vmctx(
         // initialize defined and owned memories
         let mut memories = mem::take(&mut data.memories);
         for (memory_index, memory) in &mut memories {
-            let owned_memory_index = module.owned_memory_index(memory_index);
-            let offset = data.vmctx_plan.vmctx_memory_definition(owned_memory_index);
-            let ptr = data.vmctx_plus_offset_mut::<VMMemoryDefinition>(offset);
+            let ptr = if let Some(ptr) = memory.shared_definition() {
+                ptr
+            } else {
+                let owned_memory_index = module.owned_memory_index(memory_index);
+                let offset = data.vmctx_plan.vmctx_memory_definition(owned_memory_index);
+                data.vmctx_plus_offset_mut::<VMMemoryDefinition>(offset)
+            };
             ptr.write(memory.as_vmmemory());
 
             let offset = data.vmctx_plan.vmctx_memory_pointer(memory_index);
@@ -400,11 +1000,6 @@ fn initialize_vmctx(
             ptr_ptr.write(ptr);
         }
         data.memories = memories;
-
-        // TODO initialize imported functions
-        // TODO initialize imported tables
-        // TODO initialize imported memories
-        // TODO initialize imported globals
     }
 
     Ok(())
@@ -421,28 +1016,13 @@ fn initialize_tables(
             TableInitialValue::RefNull => {}
             TableInitialValue::ConstExpr(expr) => {
                 let mut data = store.instance_data_mut(instance);
-                let val = const_eval.eval(&mut data, expr);
+                let val = const_eval.eval(&mut data, expr)?;
 
-                let table_index = module.table_index(def_table_index);
                 let table = &mut data.tables[def_table_index];
-                match module.table_plans[table_index].table.wasm_ty.heap_type {
-                    WasmHeapType::Func | WasmHeapType::ConcreteFunc(_) | WasmHeapType::NoFunc => {
-                        let funcref = unsafe { val.funcref.cast::<VMFuncRef>() };
-                        let items = (0..table.len()).map(|_| funcref);
-                        table.init_func(0, items)?;
-                    }
-
-                    WasmHeapType::Extern
-                    | WasmHeapType::NoExtern
-                    | WasmHeapType::Eq
-                    | WasmHeapType::Array
-                    | WasmHeapType::ConcreteArray(_)
-                    | WasmHeapType::Struct
-                    | WasmHeapType::ConcreteStruct(_)
-                    | WasmHeapType::Any
-                    | WasmHeapType::I31
-                    | WasmHeapType::None => todo!(),
-                }
+                // Safety: validation ensures the expression has the table's type
+                let init = unsafe { TableElement::from_vmval(&val, table.element_type()) };
+                let len = u32::try_from(table.len()).unwrap();
+                table.fill(0, init, len)?;
             }
         }
     }
@@ -450,32 +1030,20 @@ fn initialize_tables(
     for segment in &module.table_initializers.segments {
         log::debug!("initializing from segment {segment:?}");
 
-        let start = if let Some(base) = segment.base {
+        let start = {
             let mut data = store.instance_data_mut(instance);
-            let base = unsafe { *(*data.defined_or_imported_global_ptr(base)).as_u32() };
-
-            segment
-                .offset
-                .checked_add(base)
-                .expect("element segment global base overflows")
-        } else {
-            segment.offset
+            let offset = const_eval.eval(&mut data, &segment.offset)?;
+            // Safety: validation ensures the offset is an `i32`
+            unsafe { offset.i32 }.unsigned()
         };
 
-        with_defined_table_index_and_instance(
-            store,
-            instance,
+        store.instance_data_mut(instance).table_init_segment(
+            const_eval,
             segment.table_index,
-            |def_table_index, data| {
-                data.table_init_segment(
-                    const_eval,
-                    def_table_index,
-                    &segment.elements,
-                    start,
-                    0,
-                    u32::try_from(segment.elements.len()).unwrap(),
-                )
-            },
+            &segment.elements,
+            start,
+            0,
+            u32::try_from(segment.elements.len()).unwrap(),
         )?;
     }
 
@@ -483,48 +1051,25 @@ fn initialize_tables(
 }
 
 fn initialize_memories(
+    const_eval: &mut ConstExprEvaluator,
     store: &Store,
     instance: Instance,
     module: &TranslatedModule,
 ) -> Result<(), Trap> {
     for init in &module.memory_initializers.runtime {
-        let def_index = module.defined_memory_index(init.memory_index).unwrap();
         let mut data = store.instance_data_mut(instance);
 
-        data.memories[def_index].inner.extend_from_slice(init.bytes);
-    }
-
-    Ok(())
-}
-
-fn with_defined_table_index_and_instance<R>(
-    store: &Store,
-    instance: Instance,
-    index: TableIndex,
-    f: impl FnOnce(DefinedTableIndex, &mut InstanceData) -> R,
-) -> R {
-    let def_table_index = store
-        .instance_data(instance)
-        .module_info
-        .module
-        .defined_table_index(index);
-
-    if let Some(def_table_index) = def_table_index {
-        let mut data = store.instance_data_mut(instance);
-
-        f(def_table_index, &mut data)
-    } else {
-        let (foreign_instance, foreign_table_def) = {
-            let data = store.instance_data(instance);
-            let import = data.imported_table(index);
-            let foreign_instance = store.instance_for_vmctx(import.vmctx);
-            let foreign_table_def = import.from;
-
-            (foreign_instance, foreign_table_def)
+        let offset = const_eval.eval(&mut data, &init.offset)?;
+        // Safety: validation ensures the offset is an `i64` for 64-bit memories and an `i32`
+        // otherwise
+        let start = if module.memory_plans[init.memory_index].memory.memory64 {
+            unsafe { offset.i64 }.unsigned()
+        } else {
+            u64::from(unsafe { offset.i32 }.unsigned())
         };
 
-        let mut foreign_instance = store.instance_data_mut(foreign_instance);
-        let foreign_table_index = unsafe { foreign_instance.table_index(&*foreign_table_def) };
-        f(foreign_table_index, &mut foreign_instance)
+        data.memory_write(init.memory_index, start, init.bytes)?;
     }
+
+    Ok(())
 }
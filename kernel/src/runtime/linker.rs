@@ -1,17 +1,291 @@
-use crate::runtime::instance::Instance;
+use crate::runtime::errors::LinkError;
+use crate::runtime::export::{Export, ExportMemory, ExportTable};
+use crate::runtime::host_func::{HostFunc, IntoFunc};
+use crate::runtime::instance::{Imports, Instance};
 use crate::runtime::module::Module;
 use crate::runtime::store::Store;
+use crate::runtime::vmcontext::{VMFunctionImport, VMGlobalImport, VMMemoryImport, VMTableImport};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+use cranelift_wasm::{EntityIndex, Global, Memory, Table};
+use hashbrown::HashMap;
 
+/// An item that can satisfy an import.
+#[derive(Debug, Clone)]
+enum Definition {
+    /// An entity exported by an instance.
+    Export(Export),
+    /// A function implemented by the host.
+    HostFunc(Arc<HostFunc>),
+}
+
+/// Resolves the imports of [`Module`]s by name and instantiates them.
+///
+/// Definitions are keyed by the same two-level `module` and `name` namespace that WASM imports use.
+/// Each import of a module is looked up in the linker and type-checked against the definition,
+/// before being written into the new instance's `VMContext`.
 #[derive(Default)]
-pub struct Linker {}
+pub struct Linker {
+    map: HashMap<String, HashMap<String, Definition>>,
+}
 
 impl Linker {
     pub fn new() -> Self {
         Self::default()
     }
 
-    #[allow(clippy::unused_self)]
-    pub fn instantiate<'wasm>(&self, store: &mut Store<'wasm>, module: &Module<'wasm>) -> Instance {
-        Instance::new(store, module).unwrap()
+    /// Defines `module::name` to be the given export.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `module::name` is already defined.
+    pub fn define(
+        &mut self,
+        module: &str,
+        name: &str,
+        export: Export,
+    ) -> Result<&mut Self, LinkError> {
+        self.insert(module, name, Definition::Export(export))?;
+        Ok(self)
     }
+
+    /// Defines `module::name` to be a host function implemented by the given closure.
+    ///
+    /// The WASM type of the function is derived from the closure's parameters and return type.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `module::name` is already defined.
+    pub fn func_wrap<Params, Results>(
+        &mut self,
+        module: &str,
+        name: &str,
+        func: impl IntoFunc<Params, Results>,
+    ) -> Result<&mut Self, LinkError> {
+        let func = Arc::new(func.into_host_func());
+        self.insert(module, name, Definition::HostFunc(func))?;
+        Ok(self)
+    }
+
+    /// Defines all exports of the given instance under the module name `module`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the exports is already defined.
+    pub fn instance(
+        &mut self,
+        store: &mut Store,
+        module: &str,
+        instance: Instance,
+    ) -> Result<&mut Self, LinkError> {
+        let exports: Vec<_> = {
+            let mut data = store.instance_data_mut(instance);
+            let module_info = data.module_info.clone();
+
+            module_info
+                .module
+                .exports
+                .iter()
+                .map(|(name, index)| (*name, data.get_export(*index)))
+                .collect()
+        };
+
+        for (name, export) in exports {
+            self.define(module, name, export)?;
+        }
+
+        Ok(self)
+    }
+
+    /// Instantiates the given module, resolving its imports from the definitions in this linker.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an import is not defined or the definition has an incompatible type or
+    /// if instantiation traps.
+    pub fn instantiate<'wasm>(
+        &self,
+        store: &mut Store<'wasm>,
+        module: &Module<'wasm>,
+    ) -> Result<Instance, LinkError> {
+        let imports = self.resolve_imports(store, module)?;
+        Ok(Instance::new(store, module, imports)?)
+    }
+
+    fn insert(&mut self, module: &str, name: &str, def: Definition) -> Result<(), LinkError> {
+        if !self.map.contains_key(module) {
+            self.map.insert(module.to_string(), HashMap::default());
+        }
+        let names = self.map.get_mut(module).unwrap();
+
+        if names.contains_key(name) {
+            return Err(LinkError::AlreadyDefined {
+                module: module.to_string(),
+                name: name.to_string(),
+            });
+        }
+        names.insert(name.to_string(), def);
+
+        Ok(())
+    }
+
+    fn get(&self, module: &str, name: &str) -> Option<&Definition> {
+        self.map.get(module)?.get(name)
+    }
+
+    fn resolve_imports(&self, store: &mut Store, module: &Module) -> Result<Imports, LinkError> {
+        let translated = &module.info.module;
+        let mut imports = Imports::with_capacity_for(translated);
+
+        for import in &translated.imports {
+            let def =
+                self.get(import.module, import.name)
+                    .ok_or_else(|| LinkError::UnknownImport {
+                        module: import.module.to_string(),
+                        name: import.name.to_string(),
+                    })?;
+
+            let incompatible = || {
+                log::debug!(
+                    "import {}::{} of type {:?} is incompatible with {def:?}",
+                    import.module,
+                    import.name,
+                    translated.type_of(import.ty)
+                );
+
+                LinkError::IncompatibleImportType {
+                    module: import.module.to_string(),
+                    name: import.name.to_string(),
+                }
+            };
+
+            match (import.ty, def) {
+                (EntityIndex::Function(func_index), Definition::HostFunc(func)) => {
+                    let sig_index = translated.functions[func_index].signature;
+                    if module.info.types[sig_index].unwrap_func() != func.ty() {
+                        return Err(incompatible());
+                    }
+
+                    let wasm_call =
+                        module.wasm_to_native_trampoline(sig_index).ok_or_else(|| {
+                            LinkError::MissingTrampoline {
+                                module: import.module.to_string(),
+                                name: import.name.to_string(),
+                            }
+                        })?;
+
+                    // The store needs to keep the host function alive for as long as any instance
+                    // might call it
+                    let vmctx = store.root_host_func(func.clone());
+                    imports.functions.push(VMFunctionImport {
+                        wasm_call,
+                        native_call: func.native_call(),
+                        vmctx,
+                    });
+                }
+                (EntityIndex::Function(func_index), Definition::Export(Export::Function(func))) => {
+                    let sig_index = translated.functions[func_index].signature;
+                    if *module.info.types[sig_index].unwrap_func() != func.ty {
+                        return Err(incompatible());
+                    }
+
+                    let func_ref = unsafe { func.func_ref.as_ref() };
+                    imports.functions.push(VMFunctionImport {
+                        wasm_call: func_ref.wasm_call,
+                        native_call: func_ref.native_call,
+                        vmctx: NonNull::new(func_ref.vmctx).unwrap(),
+                    });
+                }
+                (EntityIndex::Table(table_index), Definition::Export(Export::Table(table))) => {
+                    if !table_ty_matches(table, &translated.table_plans[table_index].table) {
+                        return Err(incompatible());
+                    }
+
+                    imports.tables.push(VMTableImport {
+                        from: table.definition,
+                        vmctx: table.vmctx,
+                    });
+                }
+                (EntityIndex::Memory(memory_index), Definition::Export(Export::Memory(memory))) => {
+                    if !memory_ty_matches(memory, &translated.memory_plans[memory_index].memory) {
+                        return Err(incompatible());
+                    }
+
+                    imports.memories.push(VMMemoryImport {
+                        from: memory.definition,
+                        vmctx: memory.vmctx,
+                    });
+                }
+                (EntityIndex::Global(global_index), Definition::Export(Export::Global(global))) => {
+                    if !global_ty_matches(&global.global, &translated.globals[global_index]) {
+                        return Err(incompatible());
+                    }
+
+                    imports.globals.push(VMGlobalImport {
+                        from: global.definition,
+                    });
+                }
+                _ => return Err(incompatible()),
+            }
+        }
+
+        Ok(imports)
+    }
+}
+
+/// Checks whether an exported table matches the table type of an import.
+///
+/// Like the spec says, the import's minimum is compared against the current size of the table,
+/// which may have grown since it was created.
+fn table_ty_matches(actual: &ExportTable, expected: &Table) -> bool {
+    // Safety: exports stay valid while their store is alive
+    let current = unsafe { (*actual.definition).current_length };
+
+    actual.table.wasm_ty == expected.wasm_ty
+        && limits_match(
+            u64::from(current),
+            actual.table.maximum.map(u64::from),
+            u64::from(expected.minimum),
+            expected.maximum.map(u64::from),
+        )
+}
+
+/// Checks whether an exported memory matches the memory type of an import.
+///
+/// Like the spec says, the import's minimum is compared against the current size of the memory,
+/// which may have grown since it was created.
+fn memory_ty_matches(actual: &ExportMemory, expected: &Memory) -> bool {
+    // Safety: exports stay valid while their store is alive
+    let current_length = unsafe { (*actual.definition).current_length() };
+    let current = current_length as u64 >> actual.memory.page_size_log2;
+
+    actual.memory.shared == expected.shared
+        && actual.memory.memory64 == expected.memory64
+        && actual.memory.page_size_log2 == expected.page_size_log2
+        && limits_match(
+            current,
+            actual.memory.maximum,
+            expected.minimum,
+            expected.maximum,
+        )
+}
+
+fn global_ty_matches(actual: &Global, expected: &Global) -> bool {
+    actual.wasm_ty == expected.wasm_ty && actual.mutability == expected.mutability
+}
+
+/// Checks whether the limits of a definition are a subtype of the limits declared by the import.
+fn limits_match(
+    actual_min: u64,
+    actual_max: Option<u64>,
+    expected_min: u64,
+    expected_max: Option<u64>,
+) -> bool {
+    actual_min >= expected_min
+        && match expected_max {
+            None => true,
+            Some(expected_max) => actual_max.is_some_and(|actual_max| actual_max <= expected_max),
+        }
 }
@@ -1,21 +1,206 @@
-use crate::runtime::guest_memory::GuestVec;
+use crate::kconfig;
+use crate::runtime::guest_memory::GuestAllocator;
+use crate::runtime::translate::{MemoryPlan, MemoryStyle};
 use crate::runtime::vmcontext::VMMemoryDefinition;
+use alloc::boxed::Box;
+use core::alloc::AllocError;
+use core::ops::Range;
+use core::ptr;
+use kmm::VirtualAddress;
 
-#[allow(unused)]
+/// A WebAssembly linear memory.
+///
+/// Each memory lives in its own reservation of the store's address space. Only the first
+/// `committed` bytes of the reservation are accessible, the rest of it - along with the guard
+/// region following it - faults on access. This lets compiled code elide bounds checks for
+/// memories with [`MemoryStyle::Static`].
+///
+/// Accessible pages are mapped lazily when they are first touched, so growing a memory is cheap
+/// and never moves a static memory.
 #[derive(Debug)]
 pub struct Memory {
-    pub inner: GuestVec<u8>,
-    pub current_length: usize,
-    pub maximum: Option<usize>,
+    alloc: GuestAllocator,
+    /// The reserved address range, excluding the guard region.
+    reservation: Range<VirtualAddress>,
+    /// The number of bytes at the start of the reservation that are accessible.
+    committed: usize,
+    /// The current size of this memory in bytes.
+    len: usize,
+    style: MemoryStyle,
+    offset_guard_size: usize,
+    /// The definition of this memory if it is shared. The `VMContext` only has room for the
+    /// definitions of owned memories, so shared memories keep theirs in guest memory here.
+    shared_definition: Option<Box<VMMemoryDefinition, GuestAllocator>>,
+    /// The maximum size of this memory in bytes.
+    pub maximum: usize,
+    pub page_size_log2: u8,
     pub asid: usize,
 }
 
 impl Memory {
+    /// Reserves address space for a new memory according to `plan` and makes its initial
+    /// `minimum` bytes accessible.
+    pub fn new(
+        plan: &MemoryPlan,
+        alloc: GuestAllocator,
+        minimum: usize,
+        maximum: usize,
+    ) -> Result<Self, AllocError> {
+        let offset_guard_size = usize::try_from(plan.offset_guard_size).unwrap();
+        let reservation_size = match plan.style {
+            MemoryStyle::Static { bound } => usize::try_from(bound).unwrap(),
+            MemoryStyle::Dynamic { reserve } => {
+                minimum.saturating_add(usize::try_from(reserve).unwrap())
+            }
+        };
+        let reservation = reserve(&alloc, reservation_size, offset_guard_size)?;
+
+        let mut this = Self {
+            asid: alloc.asid(),
+            alloc,
+            reservation,
+            committed: 0,
+            len: 0,
+            style: plan.style,
+            offset_guard_size,
+            shared_definition: None,
+            maximum,
+            page_size_log2: plan.memory.page_size_log2,
+        };
+        this.commit_up_to(minimum);
+        this.len = minimum;
+
+        if plan.memory.shared {
+            let definition = this.as_vmmemory();
+            this.shared_definition = Some(Box::new_in(definition, this.alloc.clone()));
+        }
+
+        Ok(this)
+    }
+
+    /// Returns the current size of this memory in bytes.
+    pub fn byte_size(&self) -> usize {
+        self.len
+    }
+
+    /// Grows this memory by `delta_pages` pages, returning the previous size in bytes.
+    ///
+    /// Returns `None` if the memory would grow beyond its maximum or the allocation fails, in
+    /// which case the memory is left unchanged.
+    ///
+    /// Note that growing a memory with [`MemoryStyle::Dynamic`] may move it, any
+    /// `VMMemoryDefinition` referring to it must be updated afterward.
+    pub fn grow(&mut self, delta_pages: u64) -> Option<usize> {
+        let old_byte_size = self.byte_size();
+
+        let delta_bytes = usize::try_from(delta_pages)
+            .ok()?
+            .checked_mul(1 << self.page_size_log2)?;
+        let new_byte_size = old_byte_size.checked_add(delta_bytes)?;
+        if new_byte_size > self.maximum {
+            return None;
+        }
+
+        if new_byte_size > self.reservation_size() {
+            match self.style {
+                // static memories reserve their maximum size up front
+                MemoryStyle::Static { .. } => return None,
+                MemoryStyle::Dynamic { reserve } => {
+                    let reserve = usize::try_from(reserve).unwrap();
+                    self.relocate(new_byte_size.saturating_add(reserve)).ok()?;
+                }
+            }
+        }
+
+        self.commit_up_to(new_byte_size);
+        self.len = new_byte_size;
+
+        Some(old_byte_size)
+    }
+
+    /// Returns the definition of this memory if it is shared, the definitions of all other
+    /// memories are stored in the `VMContext` of their instance.
+    pub fn shared_definition(&mut self) -> Option<*mut VMMemoryDefinition> {
+        self.shared_definition
+            .as_deref_mut()
+            .map(|definition| definition as *mut _)
+    }
+
     pub fn as_vmmemory(&mut self) -> VMMemoryDefinition {
         VMMemoryDefinition {
-            base: self.inner.as_mut_ptr(),
-            current_length: self.current_length.into(),
-            asid: 0,
+            base: self.reservation.start.as_raw() as *mut u8,
+            current_length: self.len.into(),
+            asid: self.asid,
         }
     }
+
+    fn reservation_size(&self) -> usize {
+        self.reservation.end.sub_addr(self.reservation.start)
+    }
+
+    /// Makes sure at least the first `len` bytes of the reservation are accessible.
+    ///
+    /// Only whole pages can be committed, so if `len` isn't a multiple of the page size the rest
+    /// of the last page is accessible too. Such memories have no guard region and are always
+    /// bounds checked against their exact size, see [`MemoryStyle::for_memory`].
+    fn commit_up_to(&mut self, len: usize) {
+        let len = len.next_multiple_of(kconfig::PAGE_SIZE);
+        if len <= self.committed {
+            return;
+        }
+
+        self.alloc
+            .commit(self.reservation.start.add(self.committed)..self.reservation.start.add(len));
+        self.committed = len;
+    }
+
+    /// Moves the memory to a new reservation of `size` bytes, copying over its contents.
+    fn relocate(&mut self, size: usize) -> Result<(), AllocError> {
+        let reservation = reserve(&self.alloc, size, self.offset_guard_size)?;
+
+        let committed = self.len.next_multiple_of(kconfig::PAGE_SIZE);
+        self.alloc
+            .commit(reservation.start..reservation.start.add(committed));
+
+        // the memory is accessed through the store's address space
+        self.alloc.activate();
+        unsafe {
+            ptr::copy_nonoverlapping(
+                self.reservation.start.as_raw() as *const u8,
+                reservation.start.as_raw() as *mut u8,
+                self.len,
+            );
+        }
+
+        self.decommit_all();
+        self.reservation = reservation;
+        self.committed = committed;
+
+        Ok(())
+    }
+
+    fn decommit_all(&mut self) {
+        self.alloc
+            .decommit(self.reservation.start..self.reservation.start.add(self.committed));
+        self.committed = 0;
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        self.decommit_all();
+    }
+}
+
+/// Reserves `size` bytes followed by `offset_guard_size` bytes of guard region, returns the
+/// range excluding the guard region.
+fn reserve(
+    alloc: &GuestAllocator,
+    size: usize,
+    offset_guard_size: usize,
+) -> Result<Range<VirtualAddress>, AllocError> {
+    let total = size.checked_add(offset_guard_size).ok_or(AllocError)?;
+    let range = alloc.reserve(total)?;
+
+    Ok(range.start..range.start.add(size))
 }
@@ -1,26 +1,91 @@
 #![allow(unused)]
 
+// The modules shared with the host-side `k23-compile` tool, they only deal with translating and
+// compiling WASM and must not depend on kernel services.
 mod builtins;
 mod compile;
-mod const_expr;
+mod config;
 mod engine;
 mod errors;
+mod stack_map;
+mod translate;
+mod trap;
+mod utils;
+mod vmcontext;
+
+#[cfg(target_os = "none")]
+mod const_expr;
+#[cfg(target_os = "none")]
+mod epoch;
+#[cfg(target_os = "none")]
+mod export;
+#[cfg(target_os = "none")]
+mod func;
+#[cfg(target_os = "none")]
+mod gc;
+#[cfg(target_os = "none")]
 mod guest_memory;
+#[cfg(target_os = "none")]
+mod host_func;
+#[cfg(target_os = "none")]
 mod instance;
+#[cfg(target_os = "none")]
 mod linker;
+#[cfg(target_os = "none")]
 mod memory;
+#[cfg(target_os = "none")]
 mod module;
+#[cfg(target_os = "none")]
+mod parking_spot;
+#[cfg(target_os = "none")]
+mod stack;
+#[cfg(target_os = "none")]
 mod store;
+#[cfg(target_os = "none")]
 mod table;
-mod translate;
-mod trap;
-mod utils;
-mod vmcontext;
+#[cfg(target_os = "none")]
+mod trap_handling;
+#[cfg(target_os = "none")]
+mod type_registry;
+#[cfg(target_os = "none")]
+mod typed;
+#[cfg(target_os = "none")]
+mod values;
 
+pub use compile::compile_module;
+pub use config::{Config, OptLevel, Tunables};
 pub use engine::Engine;
+pub use errors::{CompileError, ConfigError, GcError, LinkError};
+pub use trap::Trap;
+
+#[cfg(target_os = "none")]
+pub use compile::run_compile_worker;
+#[cfg(all(test, target_os = "none"))]
+pub(crate) use compile::{help_compile, run_compile_jobs, CompileJob};
+#[cfg(target_os = "none")]
+pub use epoch::{current_epoch, update_epoch};
+#[cfg(target_os = "none")]
+pub use export::Export;
+#[cfg(target_os = "none")]
+pub use func::{Func, TypedFunc};
+#[cfg(target_os = "none")]
+pub use gc::{AnyRef, GcHeap};
+#[cfg(target_os = "none")]
+pub use guest_memory::handle_page_fault;
+#[cfg(target_os = "none")]
+pub use instance::Instance;
+#[cfg(target_os = "none")]
 pub use linker::Linker;
+#[cfg(target_os = "none")]
 pub use module::Module;
+#[cfg(target_os = "none")]
 pub use store::Store;
+#[cfg(all(test, target_os = "none"))]
+pub(crate) use trap_handling::call_raw_code;
+#[cfg(target_os = "none")]
+pub use trap_handling::handle_user_exception;
+#[cfg(target_os = "none")]
+pub use values::Val;
 
 /// Namespace corresponding to wasm functions, the index is the index of the
 /// defined function that's being referenced.
@@ -39,68 +104,3 @@ pub const WASM32_MAX_PAGES: u64 = 1 << 16;
 /// The number of pages (for 64-bit modules) we can have before we run out of
 /// byte index space.
 pub const WASM64_MAX_PAGES: u64 = 1 << 48;
-
-// pub struct Stack {
-//     inner: GuestVec<u8, 16>,
-// }
-//
-// impl Stack {
-//     pub fn new(stack_size: usize, alloc: GuestAllocator) -> Result<Self, ()> {
-//         let mut inner = GuestVec::try_with_capacity(stack_size, alloc)?;
-//         inner.try_resize(stack_size, 0)?;
-//
-//         Ok(Self { inner })
-//     }
-//
-//     pub fn stack_ptr(&mut self) -> *mut u8 {
-//         self.inner.as_mut_ptr_range().end
-//     }
-//
-//     pub fn stack_limit(&mut self) -> *const u8 {
-//         self.inner.as_ptr_range().start
-//     }
-//
-//     pub unsafe fn on_stack(
-//         &mut self,
-//         vmctx: *mut VMContext,
-//         func: unsafe extern "C" fn(*mut VMContext, usize),
-//     ) {
-//         log::trace!("switching to stack {:?}", self.inner.as_ptr_range());
-//
-//         let arg: usize = 7;
-//         let ret: usize;
-//         asm!(
-//         "csrrw sp, sscratch, sp",
-//
-//         "mv  sp, {wasm_stack_ptr}",
-//         "mv  a0, {vmctx_ptr}",
-//
-//         "mv  a1, {arg}",
-//         "jalr {func}",
-//
-//         "csrrw sp, sscratch, sp",
-//         wasm_stack_ptr = in(reg) self.stack_ptr(),
-//         vmctx_ptr = in(reg) vmctx,
-//         func = in(reg) func,
-//         arg = in(reg) arg - 2,
-//         out("a0") ret
-//         );
-//         log::trace!(
-//             r#"
-//
-// WASM says: The {}th fibonacci number is {ret:?}!
-//
-// "#,
-//             arg
-//         );
-//         log::trace!("switched back from stack {:?}", self.inner.as_ptr_range());
-//     }
-// }
-//
-// impl fmt::Debug for Stack {
-//     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-//         f.debug_struct("Stack")
-//             .field("address_range", &self.inner.as_ptr_range())
-//             .finish()
-//     }
-// }
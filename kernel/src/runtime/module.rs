@@ -1,9 +1,13 @@
-use crate::runtime::compile::{compile_module, CompiledModuleInfo};
+use crate::kconfig;
+use crate::runtime::compile::{compile_module, deserialize_module, CompiledModuleInfo};
 use crate::runtime::engine::Engine;
+use crate::runtime::errors::CompileError;
 use crate::runtime::guest_memory::{AlignedVec, CodeMemory};
 use crate::runtime::store::Store;
-use crate::runtime::vmcontext::VMContextPlan;
+use crate::runtime::vmcontext::{VMContextPlan, VMFunctionBody};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use cranelift_wasm::ModuleInternedTypeIndex;
 
 #[derive(Debug)]
 pub struct Module<'wasm> {
@@ -13,13 +17,65 @@ pub struct Module<'wasm> {
 }
 
 impl<'wasm> Module<'wasm> {
-    pub fn from_binary(engine: &Engine, store: &Store, bytes: &'wasm [u8]) -> Self {
+    /// Compiles the given WASM binary into the guest memory of `store`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the binary is malformed, fails validation or cannot be compiled.
+    pub fn from_binary(
+        engine: &Engine,
+        store: &Store,
+        bytes: &'wasm [u8],
+    ) -> Result<Self, CompileError> {
+        // the module is compiled straight into the store's guest memory
+        store.activate();
+
         log::trace!("Allocating new output buffer for compiled module...");
         let mut guest_vec = AlignedVec::new(store.guest_allocator());
         log::trace!("Compiling module...");
-        let info = compile_module(engine, bytes, &mut guest_vec).unwrap();
+        let info = compile_module(engine, bytes, &mut guest_vec)?;
         log::trace!("compile output {:?}", guest_vec.as_ptr_range());
 
+        Ok(Self::from_parts(engine, info, guest_vec))
+    }
+
+    /// Loads a module previously compiled with [`Module::from_binary`] and turned into bytes with
+    /// [`Module::serialize`] into the guest memory of `store`, without compiling it again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `bytes` is not a compiled module or was compiled by an engine with a
+    /// different configuration.
+    pub fn deserialize(
+        engine: &Engine,
+        store: &Store,
+        bytes: &'wasm [u8],
+    ) -> Result<Self, CompileError> {
+        log::trace!("Loading compiled module...");
+        let info = deserialize_module(engine, bytes)?;
+
+        store.activate();
+
+        let mut guest_vec = AlignedVec::new(store.guest_allocator());
+        guest_vec.try_extend_from_slice(bytes).unwrap();
+
+        Ok(Self::from_parts(engine, info, guest_vec))
+    }
+
+    /// Returns the compiled object backing this module, it can be loaded again with
+    /// [`Module::deserialize`].
+    ///
+    /// The code lives in guest memory so the store this module was created in must be passed.
+    pub fn serialize(&self, store: &Store) -> Vec<u8> {
+        store.activate();
+        self.code.as_slice().to_vec()
+    }
+
+    fn from_parts(
+        engine: &Engine,
+        info: CompiledModuleInfo<'wasm>,
+        guest_vec: AlignedVec<u8, { kconfig::PAGE_SIZE }>,
+    ) -> Self {
         let mut code = CodeMemory::new(guest_vec);
         code.publish().unwrap();
 
@@ -29,4 +85,21 @@ impl<'wasm> Module<'wasm> {
             code: Arc::new(code),
         }
     }
+
+    /// Returns the address of the trampoline used to call host functions of the given signature
+    /// from this module.
+    ///
+    /// Trampolines are only compiled for signatures of imported functions.
+    pub fn wasm_to_native_trampoline(
+        &self,
+        sig_index: ModuleInternedTypeIndex,
+    ) -> Option<*const VMFunctionBody> {
+        let trampolines = &self.info.wasm_to_native_trampolines;
+        let idx = trampolines
+            .binary_search_by_key(&sig_index, |(sig_index, _)| *sig_index)
+            .ok()?;
+        let (_, loc) = trampolines[idx];
+
+        Some(self.code.resolve_function_loc(loc).as_raw() as *const VMFunctionBody)
+    }
 }
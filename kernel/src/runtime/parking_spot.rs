@@ -0,0 +1,157 @@
+//! The wait queue backing WASM's `memory.atomic.wait` and `memory.atomic.notify` instructions.
+//!
+//! Waiters are keyed by the *physical* address they wait on, so waiters and notifiers agree on
+//! the address no matter which address space they access the shared memory through.
+//!
+//! There are no inter-processor interrupts yet, so a waiting hart only notices it was notified
+//! once it wakes up from `wfi`, at the latest after one epoch interval.
+
+use crate::arch;
+use crate::frame_alloc::with_frame_alloc;
+use crate::kconfig;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use kmm::{Mapper, PhysicalAddress, VirtualAddress};
+use sync::Mutex;
+
+/// All waiters in the order they started waiting, keyed by the address they wait on.
+static WAITERS: Mutex<BTreeMap<PhysicalAddress, VecDeque<Arc<Waiter>>>> =
+    Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Default)]
+struct Waiter {
+    notified: AtomicBool,
+}
+
+/// The outcome of a wait, the discriminants are the values returned to WASM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum WaitResult {
+    /// The waiter was woken up by a notification.
+    Ok = 0,
+    /// The value didn't match the expected value, so the waiter didn't block.
+    Mismatch = 1,
+    /// The timeout expired before the waiter was notified.
+    TimedOut = 2,
+}
+
+/// Blocks the current hart until the 32-bit value at `addr` is notified, if it equals
+/// `expected`. Gives up after `timeout` nanoseconds, or never if `timeout` is `None`.
+///
+/// # Safety
+///
+/// `addr` must be aligned and valid for reads for the duration of the wait.
+pub unsafe fn wait32(addr: *mut u32, expected: u32, timeout: Option<u64>) -> WaitResult {
+    let value = AtomicU32::from_ptr(addr);
+    wait(
+        addr.cast(),
+        || value.load(Ordering::SeqCst) == expected,
+        timeout,
+    )
+}
+
+/// Blocks the current hart until the 64-bit value at `addr` is notified, if it equals
+/// `expected`. Gives up after `timeout` nanoseconds, or never if `timeout` is `None`.
+///
+/// # Safety
+///
+/// `addr` must be aligned and valid for reads for the duration of the wait.
+pub unsafe fn wait64(addr: *mut u64, expected: u64, timeout: Option<u64>) -> WaitResult {
+    let value = AtomicU64::from_ptr(addr);
+    wait(
+        addr.cast(),
+        || value.load(Ordering::SeqCst) == expected,
+        timeout,
+    )
+}
+
+/// Wakes up at most `count` of the waiters blocked on `addr` in the order they started waiting,
+/// returns the number of waiters woken up.
+pub fn notify(addr: *mut u8, count: u32) -> u32 {
+    // Waiters read the value before blocking which maps its page, so if the page isn't mapped
+    // nobody can be waiting on it.
+    let Some(key) = virt_to_phys(addr) else {
+        return 0;
+    };
+
+    let mut waiters = WAITERS.lock();
+    let Some(queue) = waiters.get_mut(&key) else {
+        return 0;
+    };
+
+    let mut woken = 0;
+    while woken < count {
+        let Some(waiter) = queue.pop_front() else {
+            break;
+        };
+        waiter.notified.store(true, Ordering::Release);
+        woken += 1;
+    }
+
+    if queue.is_empty() {
+        waiters.remove(&key);
+    }
+
+    woken
+}
+
+fn wait(addr: *mut u8, is_expected: impl Fn() -> bool, timeout: Option<u64>) -> WaitResult {
+    let deadline =
+        timeout.map(|timeout| arch::current_ticks().saturating_add(ns_to_ticks(timeout)));
+
+    // Reading the value maps its page (if it wasn't already), so it has a physical address
+    if !is_expected() {
+        return WaitResult::Mismatch;
+    }
+    let key = virt_to_phys(addr).expect("waited on address is not mapped");
+
+    // Comparing the value and enqueueing the waiter under the lock ensures no notification
+    // that follows a change of the value is missed
+    let waiter = Arc::new(Waiter::default());
+    {
+        let mut waiters = WAITERS.lock();
+        if !is_expected() {
+            return WaitResult::Mismatch;
+        }
+        waiters.entry(key).or_default().push_back(waiter.clone());
+    }
+
+    loop {
+        if waiter.notified.load(Ordering::Acquire) {
+            return WaitResult::Ok;
+        }
+
+        if deadline.is_some_and(|deadline| arch::current_ticks() >= deadline) {
+            let mut waiters = WAITERS.lock();
+            // a notification might have raced with the timeout
+            if waiter.notified.load(Ordering::Acquire) {
+                return WaitResult::Ok;
+            }
+
+            let queue = waiters.get_mut(&key).unwrap();
+            queue.retain(|other| !Arc::ptr_eq(other, &waiter));
+            if queue.is_empty() {
+                waiters.remove(&key);
+            }
+
+            return WaitResult::TimedOut;
+        }
+
+        arch::wait_for_interrupt(deadline);
+    }
+}
+
+/// Translates `addr` using the address space active on this hart, returns `None` if it isn't
+/// mapped.
+fn virt_to_phys(addr: *mut u8) -> Option<PhysicalAddress> {
+    with_frame_alloc(|frame_alloc| {
+        Mapper::<kconfig::MEMORY_MODE>::from_active(0, frame_alloc)
+            .virt_to_phys(VirtualAddress::new(addr as usize))
+    })
+}
+
+fn ns_to_ticks(ns: u64) -> u64 {
+    let ticks = u128::from(ns) * u128::from(kconfig::TIMEBASE_FREQUENCY) / 1_000_000_000;
+    u64::try_from(ticks).unwrap_or(u64::MAX)
+}
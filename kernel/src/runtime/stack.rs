@@ -0,0 +1,67 @@
+use crate::kconfig;
+use crate::runtime::guest_memory::GuestAllocator;
+use core::alloc::AllocError;
+use core::ops::Range;
+use kmm::VirtualAddress;
+
+/// The size of the inaccessible region below each stack.
+const GUARD_SIZE: usize = kconfig::PAGE_SIZE;
+
+/// The stack WASM executes on.
+///
+/// Each stack lives in its own reservation of the store's address space, preceded by a guard
+/// region that faults on access. Compiled code checks the stack limit on entry to each function,
+/// and the guard region catches what slips past these checks, both are reported as
+/// [`Trap::StackOverflow`](crate::runtime::Trap::StackOverflow).
+///
+/// Like linear memories the stack is mapped lazily as it is first touched.
+#[derive(Debug)]
+pub struct Stack {
+    alloc: GuestAllocator,
+    /// The guard region below the stack.
+    guard: Range<VirtualAddress>,
+    /// The accessible part of the stack.
+    stack: Range<VirtualAddress>,
+}
+
+impl Stack {
+    /// Reserves address space for a new stack of `size` bytes.
+    pub fn new(alloc: GuestAllocator, size: usize) -> Result<Self, AllocError> {
+        let size = size
+            .checked_next_multiple_of(kconfig::PAGE_SIZE)
+            .ok_or(AllocError)?;
+        let reservation = alloc.reserve(size.checked_add(GUARD_SIZE).ok_or(AllocError)?)?;
+
+        let guard = reservation.start..reservation.start.add(GUARD_SIZE);
+        let stack = guard.end..reservation.end;
+        alloc.commit(stack.clone());
+
+        Ok(Self {
+            alloc,
+            guard,
+            stack,
+        })
+    }
+
+    /// Returns the (16-byte aligned) top of the stack.
+    pub fn top(&self) -> VirtualAddress {
+        self.stack.end.align_down(16)
+    }
+
+    /// Returns the lowest address the stack pointer may point to, this is the limit written into
+    /// the `VMContext` of all instances executing on this stack.
+    pub fn limit(&self) -> VirtualAddress {
+        self.stack.start
+    }
+
+    /// Returns the guard region below the stack.
+    pub fn guard_range(&self) -> Range<VirtualAddress> {
+        self.guard.clone()
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        self.alloc.decommit(self.stack.clone());
+    }
+}
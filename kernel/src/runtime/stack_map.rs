@@ -0,0 +1,56 @@
+//! Stack maps record which stack slots of a WASM frame hold live GC references at each safepoint,
+//! i.e. at each call. The collector uses them to find the GC references held by WASM frames.
+//!
+//! Cranelift describes stack slots relative to the stack pointer at the safepoint, which is the
+//! frame pointer minus the frame size recorded in the stack map.
+
+use object::{Bytes, LittleEndian, U32};
+
+/// The live GC references of a frame at a safepoint.
+#[derive(Debug, Clone, Copy)]
+pub struct StackMap<'a> {
+    frame_size: u32,
+    slots: &'a [U32<LittleEndian>],
+}
+
+impl<'a> StackMap<'a> {
+    /// Returns the size of the frame below its frame pointer.
+    pub fn frame_size(&self) -> u32 {
+        self.frame_size
+    }
+
+    /// Returns the offsets of the stack slots holding GC references, relative to the stack
+    /// pointer.
+    pub fn slots(&self) -> impl Iterator<Item = u32> + 'a {
+        self.slots.iter().map(|slot| slot.get(LittleEndian))
+    }
+}
+
+/// Looks up the stack map for the given offset into the text section, returns `None` if the
+/// offset isn't a safepoint with live GC references.
+pub fn stack_map_for_offset(stack_map_section: &[u8], offset: u32) -> Option<StackMap<'_>> {
+    let mut section = Bytes(stack_map_section);
+
+    let count = section
+        .read::<U32<LittleEndian>>()
+        .unwrap()
+        .get(LittleEndian) as usize;
+    let offsets = section.read_slice::<U32<LittleEndian>>(count).unwrap();
+    let positions = section.read_slice::<U32<LittleEndian>>(count).unwrap();
+    let stack_maps = section
+        .read_slice::<U32<LittleEndian>>(section.len() / 4)
+        .unwrap();
+
+    let index = offsets
+        .binary_search_by_key(&offset, |val| val.get(LittleEndian))
+        .ok()?;
+
+    let position = positions[index].get(LittleEndian) as usize;
+    let frame_size = stack_maps[position].get(LittleEndian);
+    let num_slots = stack_maps[position + 1].get(LittleEndian) as usize;
+
+    Some(StackMap {
+        frame_size,
+        slots: &stack_maps[position + 2..position + 2 + num_slots],
+    })
+}
@@ -1,36 +1,158 @@
+use crate::runtime::builtins::VMBuiltinFunctionsArray;
+use crate::runtime::engine::Engine;
+use crate::runtime::epoch;
+use crate::runtime::gc::GcHeap;
 use crate::runtime::guest_memory::{GuestAllocator, GuestVec};
+use crate::runtime::host_func::HostFunc;
 use crate::runtime::instance::{Instance, InstanceData};
 use crate::runtime::memory::Memory;
 use crate::runtime::module::Module;
-use crate::runtime::table::{FuncTable, Table, TableElementType};
-use crate::runtime::translate::{MemoryPlan, TablePlan};
-use crate::runtime::vmcontext::{VMContext, VMContextPlan};
-use crate::runtime::WASM_PAGE_SIZE;
-use core::alloc::{Allocator, Layout};
-use core::cell::{Ref, RefCell, RefMut};
+use crate::runtime::stack::Stack;
+use crate::runtime::table::{FuncTable, GcRefTable, Table};
+use crate::runtime::translate::{MemoryPlan, TableElementType, TablePlan};
+use crate::runtime::vmcontext::{VMContext, VMContextPlan, VMEpoch, VMHostFuncContext};
+use crate::runtime::{WASM32_MAX_PAGES, WASM64_MAX_PAGES};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::alloc::{AllocError, Allocator, Layout};
+use core::cell::{Ref, RefCell, RefMut, UnsafeCell};
 use core::ptr::NonNull;
-use cranelift_entity::PrimaryMap;
+use core::sync::atomic::AtomicU64;
+use cranelift_entity::{EntitySet, PrimaryMap};
 use cranelift_wasm::{DefinedMemoryIndex, DefinedTableIndex};
 use hashbrown::HashMap;
 use kmm::VirtualAddress;
 
 pub struct Store<'wasm> {
     allocator: GuestAllocator,
-    instances: PrimaryMap<Instance, RefCell<InstanceData<'wasm>>>,
+    /// The instances are boxed so their address is stable, the `VMContext` of each instance points
+    /// back to its `InstanceData`.
+    instances: PrimaryMap<Instance, Box<RefCell<InstanceData<'wasm>>>>,
     vmctx2instance: HashMap<NonNull<VMContext>, Instance>,
+    /// Host functions along with the copy of their context that is accessible to the guest.
+    host_funcs: Vec<(Arc<HostFunc>, Box<VMHostFuncContext, GuestAllocator>)>,
+    /// The builtin functions array referenced by all `VMContext`s, WASM loads builtin addresses
+    /// from it so it needs to live in guest memory.
+    builtins: Box<VMBuiltinFunctionsArray, GuestAllocator>,
+    /// The stack WASM executes on.
+    stack: Stack,
+    /// The fuel remaining for WASM execution, shared by all instances in this store. WASM
+    /// decrements it directly so it needs to live in guest memory.
+    fuel: Box<UnsafeCell<u64>, GuestAllocator>,
+    /// Whether code compiled by the engine consumes fuel.
+    consume_fuel: bool,
+    /// The epoch deadline shared by all instances in this store, read by WASM.
+    epoch: Box<UnsafeCell<VMEpoch>, GuestAllocator>,
+    /// The heap holding the structs and arrays of all instances in this store.
+    gc_heap: GcHeap,
 }
 
 impl<'wasm> Store<'wasm> {
-    pub fn new(asid: usize, physmem_off: VirtualAddress) -> Self {
-        log::trace!("Setting up new Store instance with ASID: {asid}");
-        Self {
-            allocator: unsafe {
-                GuestAllocator::new_in_kernel_space(VirtualAddress::new(0x1000), physmem_off)
-                    .unwrap()
-            },
+    /// Creates a new store with its own address space.
+    ///
+    /// This switches the current hart to the new address space.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if all ASIDs are in use or there is not enough memory for the address
+    /// space, the stack or the runtime state WASM accesses directly.
+    pub fn new(engine: &Engine) -> Result<Self, AllocError> {
+        let allocator = unsafe { GuestAllocator::new_in_user_space(VirtualAddress::new(0x1000))? };
+        log::trace!("Set up new Store with ASID {}", allocator.asid());
+
+        let builtins = Box::try_new_in(VMBuiltinFunctionsArray::INIT, allocator.clone())?;
+
+        let stack = Stack::new(allocator.clone(), engine.stack_size())?;
+
+        let fuel = Box::try_new_in(UnsafeCell::new(0), allocator.clone())?;
+        let epoch = Box::try_new_in(
+            UnsafeCell::new(VMEpoch {
+                current: AtomicU64::new(epoch::current_epoch()),
+                deadline: 0,
+                extend_delta: None,
+            }),
+            allocator.clone(),
+        )?;
+
+        Ok(Self {
+            allocator,
             instances: PrimaryMap::new(),
             vmctx2instance: HashMap::default(),
-        }
+            host_funcs: Vec::new(),
+            builtins,
+            stack,
+            fuel,
+            consume_fuel: engine.tunables().consume_fuel,
+            epoch,
+            gc_heap: GcHeap::default(),
+        })
+    }
+
+    /// Sets the fuel remaining for WASM execution in this store.
+    ///
+    /// Stores start out without any fuel, so this needs to be called before calling into WASM if
+    /// fuel consumption is enabled. Has no effect otherwise.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        *self.fuel.get_mut() = fuel;
+    }
+
+    /// Returns the fuel remaining for WASM execution in this store or `None` if fuel consumption
+    /// is not enabled, see [`Config::consume_fuel`](crate::runtime::Config::consume_fuel).
+    pub fn get_fuel(&self) -> Option<u64> {
+        // Safety: WASM isn't running while we have a reference to the store
+        self.consume_fuel.then(|| unsafe { *self.fuel.get() })
+    }
+
+    /// Switches the current hart to this store's address space.
+    pub fn activate(&self) {
+        self.allocator.activate();
+    }
+
+    /// Returns the stack WASM executes on.
+    pub(crate) fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    pub(crate) fn builtin_functions(&self) -> *const VMBuiltinFunctionsArray {
+        &*self.builtins
+    }
+
+    pub(crate) fn fuel(&self) -> *mut u64 {
+        self.fuel.get()
+    }
+
+    /// Sets the epoch deadline to `ticks_beyond_current` epochs after the current epoch.
+    ///
+    /// Once the deadline is reached WASM is interrupted, what happens then is configured with
+    /// [`Store::epoch_deadline_trap`] and [`Store::epoch_deadline_extend`]. Stores start
+    /// out with a deadline that has already been reached. Has no effect unless epoch interruption
+    /// is enabled, see [`Config::epoch_interruption`](crate::runtime::Config::epoch_interruption).
+    pub fn set_epoch_deadline(&mut self, ticks_beyond_current: u64) {
+        self.epoch.get_mut().deadline = epoch::current_epoch().saturating_add(ticks_beyond_current);
+    }
+
+    /// Configures WASM to trap with [`Trap::Interrupt`](crate::runtime::Trap::Interrupt) once the
+    /// epoch deadline is reached. This is the default.
+    pub fn epoch_deadline_trap(&mut self) {
+        self.epoch.get_mut().extend_delta = None;
+    }
+
+    /// Configures WASM to keep running once the epoch deadline is reached, with the deadline
+    /// extended by `delta` epochs.
+    ///
+    /// Execution is not suspended, there is no scheduler to yield to yet.
+    pub fn epoch_deadline_extend(&mut self, delta: u64) {
+        self.epoch.get_mut().extend_delta = Some(delta);
+    }
+
+    pub(crate) fn epoch(&self) -> *mut VMEpoch {
+        self.epoch.get()
+    }
+
+    /// Returns a handle to this store's GC heap.
+    pub fn gc_heap(&self) -> GcHeap {
+        self.gc_heap.clone()
     }
 
     pub fn guest_allocator(&self) -> GuestAllocator {
@@ -50,22 +172,53 @@ impl<'wasm> Store<'wasm> {
         self.vmctx2instance[&vmctx]
     }
 
-    pub fn allocate_module(&mut self, module: &Module<'wasm>) -> Instance {
+    /// Keeps the given host function alive for as long as this store and returns the context to
+    /// call it with from WASM.
+    pub fn root_host_func(&mut self, func: Arc<HostFunc>) -> NonNull<VMContext> {
+        let ctx = func.guest_context(self.guest_allocator());
+        let vmctx = NonNull::from(&*ctx).cast();
+        self.host_funcs.push((func, ctx));
+        vmctx
+    }
+
+    /// Allocates the state of a new instance of `module`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address space for the instance's memories can't be reserved.
+    pub fn allocate_module(&mut self, module: &Module<'wasm>) -> Result<Instance, AllocError> {
+        // memories are the only allocations that can reasonably fail, allocate them first so
+        // nothing else has to be cleaned up
+        let memories = self.allocate_memories(module.info.module.defined_memories())?;
         let vmctx = self.allocate_vmctx(&module.vmctx_plan);
         let tables = self.allocate_tables(module.info.module.defined_tables());
-        let memories = self.allocate_memories(module.info.module.defined_memories());
 
-        let handle = self.instances.push(RefCell::new(InstanceData {
+        let handle = self.instances.push(Box::new(RefCell::new(InstanceData {
             module_info: module.info.clone(),
             code: module.code.clone(),
             vmctx,
             vmctx_plan: module.vmctx_plan.clone(),
             tables,
             memories,
-        }));
+            dropped_elements: EntitySet::new(),
+            dropped_data: EntitySet::new(),
+            gc_heap: self.gc_heap.clone(),
+        })));
         self.vmctx2instance.insert(vmctx, handle);
+        self.gc_heap.register_instance(vmctx);
+
+        // record the instance in its vmctx so builtin functions can find their way back to it
+        unsafe {
+            let offset = usize::try_from(module.vmctx_plan.vmctx_instance()).unwrap();
+            let data: *const RefCell<InstanceData> = &*self.instances[handle];
+            vmctx
+                .as_ptr()
+                .byte_add(offset)
+                .cast::<*const RefCell<InstanceData>>()
+                .write(data);
+        }
 
-        handle
+        Ok(handle)
     }
 
     fn allocate_vmctx(&mut self, plan: &VMContextPlan) -> NonNull<VMContext> {
@@ -90,42 +243,63 @@ impl<'wasm> Store<'wasm> {
     fn allocate_table(&mut self, plan: &TablePlan) -> Table {
         let n = usize::try_from(plan.table.minimum).unwrap();
 
-        let mut elements = GuestVec::with_capacity_in(n, self.guest_allocator());
-        elements.resize(n, None);
-
         match TableElementType::from(plan.table.wasm_ty) {
-            TableElementType::Func => Table::Func(FuncTable {
-                elements,
-                maximum: plan.table.maximum,
-            }),
-            TableElementType::GcRef => todo!(),
+            TableElementType::Func => {
+                let mut elements = GuestVec::with_capacity_in(n, self.guest_allocator());
+                elements.resize(n, None);
+
+                Table::Func(FuncTable {
+                    elements,
+                    maximum: plan.table.maximum,
+                })
+            }
+            TableElementType::GcRef => {
+                let mut elements = GuestVec::with_capacity_in(n, self.guest_allocator());
+                elements.resize(n, 0);
+
+                Table::GcRef(GcRefTable {
+                    elements,
+                    maximum: plan.table.maximum,
+                })
+            }
         }
     }
 
     fn allocate_memories<'a>(
         &mut self,
         plans: impl ExactSizeIterator<Item = (DefinedMemoryIndex, &'a MemoryPlan)> + 'a,
-    ) -> PrimaryMap<DefinedMemoryIndex, Memory> {
+    ) -> Result<PrimaryMap<DefinedMemoryIndex, Memory>, AllocError> {
         let mut memories = PrimaryMap::new();
 
         for (_, memory) in plans {
-            memories.push(self.allocate_memory(memory));
+            memories.push(self.allocate_memory(memory)?);
         }
 
-        memories
+        Ok(memories)
     }
 
-    fn allocate_memory(&mut self, plan: &MemoryPlan) -> Memory {
-        let inner = GuestVec::with_capacity_in(
-            usize::try_from(plan.memory.minimum).unwrap() * WASM_PAGE_SIZE as usize,
-            self.guest_allocator(),
-        );
+    fn allocate_memory(&mut self, plan: &MemoryPlan) -> Result<Memory, AllocError> {
+        let page_size = 1_u64 << plan.memory.page_size_log2;
+        let absolute_max_pages = if plan.memory.memory64 {
+            WASM64_MAX_PAGES
+        } else {
+            WASM32_MAX_PAGES
+        };
 
-        Memory {
-            inner,
-            current_length: usize::try_from(plan.memory.minimum).unwrap(),
-            maximum: plan.memory.maximum.map(|v| usize::try_from(v).unwrap()),
-            asid: 0,
-        }
+        let minimum = plan
+            .memory
+            .minimum
+            .checked_mul(page_size)
+            .and_then(|min| usize::try_from(min).ok())
+            .ok_or(AllocError)?;
+        let maximum = plan
+            .memory
+            .maximum
+            .unwrap_or(absolute_max_pages)
+            .checked_mul(page_size)
+            .and_then(|max| usize::try_from(max).ok())
+            .unwrap_or(usize::MAX);
+
+        Memory::new(plan, self.guest_allocator(), minimum, maximum)
     }
 }
@@ -1,49 +1,55 @@
 use crate::runtime::guest_memory::GuestVec;
+use crate::runtime::translate::TableElementType;
 use crate::runtime::trap::Trap;
-use crate::runtime::vmcontext::{VMFuncRef, VMTableDefinition};
+use crate::runtime::vmcontext::{VMFuncRef, VMTableDefinition, VMVal};
 use core::ptr::NonNull;
-use core::slice;
-use cranelift_wasm::{WasmHeapType, WasmRefType};
+use core::{mem, ptr, slice};
 
 pub type FuncTableElem = Option<NonNull<VMFuncRef>>;
 
-pub enum TableElementType {
-    Func,
-    GcRef,
+/// A single element of a table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableElement {
+    /// A `funcref`, the null reference is a null pointer.
+    FuncRef(*mut VMFuncRef),
+    /// A GC reference such as an `externref`, the null reference is `0`.
+    GcRef(u32),
 }
 
-impl From<WasmRefType> for TableElementType {
-    fn from(ty: WasmRefType) -> Self {
-        match ty.heap_type {
-            WasmHeapType::Func | WasmHeapType::ConcreteFunc(_) | WasmHeapType::NoFunc => {
-                TableElementType::Func
-            }
-            WasmHeapType::Extern | WasmHeapType::Any | WasmHeapType::I31 | WasmHeapType::None => {
-                TableElementType::GcRef
-            }
-            WasmHeapType::NoExtern
-            | WasmHeapType::Eq
-            | WasmHeapType::Array
-            | WasmHeapType::ConcreteArray(_)
-            | WasmHeapType::Struct
-            | WasmHeapType::ConcreteStruct(_) => todo!(),
+impl TableElement {
+    /// Reads a table element of the given type from a `VMVal`.
+    ///
+    /// # Safety
+    ///
+    /// The `VMVal` must hold a reference of the given type.
+    pub unsafe fn from_vmval(val: &VMVal, ty: TableElementType) -> Self {
+        match ty {
+            TableElementType::Func => TableElement::FuncRef(val.funcref.cast()),
+            TableElementType::GcRef => TableElement::GcRef(val.externref),
         }
     }
-}
 
-#[derive(Debug)]
-pub enum Table {
-    Func(FuncTable),
-}
+    fn into_func_ref(self) -> *mut VMFuncRef {
+        match self {
+            TableElement::FuncRef(func_ref) => func_ref,
+            TableElement::GcRef(_) => panic!("expected a funcref, found a GC reference"),
+        }
+    }
 
-impl Table {
-    pub fn len(&self) -> usize {
+    fn into_gc_ref(self) -> u32 {
         match self {
-            Table::Func(table) => table.elements.len(),
+            TableElement::GcRef(gc_ref) => gc_ref,
+            TableElement::FuncRef(_) => panic!("expected a GC reference, found a funcref"),
         }
     }
 }
 
+#[derive(Debug)]
+pub enum Table {
+    Func(FuncTable),
+    GcRef(GcRefTable),
+}
+
 #[allow(unused)]
 #[derive(Debug)]
 pub struct FuncTable {
@@ -51,41 +57,241 @@ pub struct FuncTable {
     pub maximum: Option<u32>,
 }
 
+/// A table of GC references.
+///
+/// There is no GC heap yet, so the only GC references are `externref`s which are opaque to WASM
+/// and simply stored as their raw value.
+#[allow(unused)]
+#[derive(Debug)]
+pub struct GcRefTable {
+    pub elements: GuestVec<u32>,
+    pub maximum: Option<u32>,
+}
+
 impl Table {
+    pub fn len(&self) -> usize {
+        match self {
+            Table::Func(table) => table.elements.len(),
+            Table::GcRef(table) => table.elements.len(),
+        }
+    }
+
+    pub fn element_type(&self) -> TableElementType {
+        match self {
+            Table::Func(_) => TableElementType::Func,
+            Table::GcRef(_) => TableElementType::GcRef,
+        }
+    }
+
+    /// Returns the null element of this table's element type.
+    pub fn null_element(&self) -> TableElement {
+        match self {
+            Table::Func(_) => TableElement::FuncRef(ptr::null_mut()),
+            Table::GcRef(_) => TableElement::GcRef(0),
+        }
+    }
+
     pub unsafe fn as_vmtable(&self) -> VMTableDefinition {
         match self {
             Table::Func(FuncTable { elements, .. }) => VMTableDefinition {
                 base: elements.as_ptr() as *mut _,
                 current_length: elements.len().try_into().unwrap(),
             },
+            Table::GcRef(GcRefTable { elements, .. }) => VMTableDefinition {
+                base: elements.as_ptr() as *mut _,
+                current_length: elements.len().try_into().unwrap(),
+            },
         }
     }
 
-    pub fn init_func(
+    /// Writes `items` to this table starting at `dst`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the items don't match the table's element type.
+    pub fn init(
         &mut self,
         dst: u32,
-        items: impl ExactSizeIterator<Item = *mut VMFuncRef>,
+        items: impl ExactSizeIterator<Item = TableElement>,
     ) -> Result<(), Trap> {
-        let dst = usize::try_from(dst).map_err(|_| Trap::TableOutOfBounds)?;
+        let len = u32::try_from(items.len()).map_err(|_| Trap::TableOutOfBounds)?;
 
-        let elements = self
-            .funcrefs_mut()
-            .get_mut(dst..)
-            .and_then(|s| s.get_mut(..items.len()))
-            .ok_or(Trap::TableOutOfBounds)?;
+        match self {
+            Table::Func(_) => {
+                let slots = elements_mut(self.funcrefs_mut(), dst, len)?;
+                for (item, slot) in items.zip(slots) {
+                    *slot = item.into_func_ref();
+                }
+            }
+            Table::GcRef(table) => {
+                let slots = elements_mut(&mut table.elements, dst, len)?;
+                for (item, slot) in items.zip(slots) {
+                    *slot = item.into_gc_ref();
+                }
+            }
+        }
 
-        for (item, slot) in items.zip(elements) {
-            *slot = item;
+        Ok(())
+    }
+
+    /// Returns the element at `index`.
+    pub fn get(&self, index: u32) -> Result<TableElement, Trap> {
+        let index = usize::try_from(index).map_err(|_| Trap::TableOutOfBounds)?;
+
+        match self {
+            Table::Func(_) => self
+                .funcrefs()
+                .get(index)
+                .map(|func_ref| TableElement::FuncRef(*func_ref)),
+            Table::GcRef(table) => table.elements.get(index).copied().map(TableElement::GcRef),
+        }
+        .ok_or(Trap::TableOutOfBounds)
+    }
+
+    /// Sets `len` elements starting at `dst` to `val`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` doesn't match the table's element type.
+    pub fn fill(&mut self, dst: u32, val: TableElement, len: u32) -> Result<(), Trap> {
+        match self {
+            Table::Func(_) => {
+                elements_mut(self.funcrefs_mut(), dst, len)?.fill(val.into_func_ref());
+            }
+            Table::GcRef(table) => {
+                elements_mut(&mut table.elements, dst, len)?.fill(val.into_gc_ref());
+            }
         }
 
         Ok(())
     }
 
+    /// Grows this table by `delta` elements initialized to `init`, returning the previous size.
+    ///
+    /// Returns `None` if the table would grow beyond its maximum or the allocation fails, in which
+    /// case the table is left unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `init` doesn't match the table's element type.
+    pub fn grow(&mut self, delta: u32, init: TableElement) -> Option<u32> {
+        match self {
+            Table::Func(table) => grow_elements(
+                &mut table.elements,
+                table.maximum,
+                delta,
+                NonNull::new(init.into_func_ref()),
+            ),
+            Table::GcRef(table) => grow_elements(
+                &mut table.elements,
+                table.maximum,
+                delta,
+                init.into_gc_ref(),
+            ),
+        }
+    }
+
+    /// Copies `len` elements from `src_table[src..]` to `dst_table[dst..]`.
+    ///
+    /// # Safety
+    ///
+    /// Both pointers must point to valid tables of the same element type, they may point to the
+    /// same table.
+    pub unsafe fn copy(
+        dst_table: *mut Table,
+        src_table: *mut Table,
+        dst: u32,
+        src: u32,
+        len: u32,
+    ) -> Result<(), Trap> {
+        let (dst, src, len) = (
+            usize::try_from(dst).map_err(|_| Trap::TableOutOfBounds)?,
+            usize::try_from(src).map_err(|_| Trap::TableOutOfBounds)?,
+            usize::try_from(len).map_err(|_| Trap::TableOutOfBounds)?,
+        );
+
+        let src_len = (*src_table).len();
+        let dst_len = (*dst_table).len();
+        if src.checked_add(len).map_or(true, |end| end > src_len)
+            || dst.checked_add(len).map_or(true, |end| end > dst_len)
+        {
+            return Err(Trap::TableOutOfBounds);
+        }
+
+        debug_assert_eq!((*dst_table).element_type(), (*src_table).element_type());
+        let element_size = (*dst_table).element_size();
+
+        // The ranges may overlap if both tables are the same, `ptr::copy` handles that case
+        let src_base = (*src_table).elements_ptr();
+        let dst_base = (*dst_table).elements_ptr();
+        ptr::copy(
+            src_base.add(src * element_size),
+            dst_base.add(dst * element_size),
+            len * element_size,
+        );
+
+        Ok(())
+    }
+
+    fn element_size(&self) -> usize {
+        match self {
+            Table::Func(_) => mem::size_of::<FuncTableElem>(),
+            Table::GcRef(_) => mem::size_of::<u32>(),
+        }
+    }
+
+    fn elements_ptr(&mut self) -> *mut u8 {
+        match self {
+            Table::Func(table) => table.elements.as_mut_ptr().cast(),
+            Table::GcRef(table) => table.elements.as_mut_ptr().cast(),
+        }
+    }
+
+    fn funcrefs(&self) -> &[*mut VMFuncRef] {
+        match self {
+            Table::Func(table) => unsafe {
+                slice::from_raw_parts(table.elements.as_ptr().cast(), table.elements.len())
+            },
+            Table::GcRef(_) => panic!("not a funcref table"),
+        }
+    }
+
     fn funcrefs_mut(&mut self) -> &mut [*mut VMFuncRef] {
         match self {
             Table::Func(table) => unsafe {
                 slice::from_raw_parts_mut(table.elements.as_mut_ptr().cast(), table.elements.len())
             },
+            Table::GcRef(_) => panic!("not a funcref table"),
         }
     }
 }
+
+/// Returns the `len` elements starting at `dst`, or a trap if they are out of bounds.
+fn elements_mut<T>(elements: &mut [T], dst: u32, len: u32) -> Result<&mut [T], Trap> {
+    let dst = usize::try_from(dst).map_err(|_| Trap::TableOutOfBounds)?;
+    let len = usize::try_from(len).map_err(|_| Trap::TableOutOfBounds)?;
+
+    elements
+        .get_mut(dst..)
+        .and_then(|s| s.get_mut(..len))
+        .ok_or(Trap::TableOutOfBounds)
+}
+
+fn grow_elements<T: Clone>(
+    elements: &mut GuestVec<T>,
+    maximum: Option<u32>,
+    delta: u32,
+    init: T,
+) -> Option<u32> {
+    let old_len = u32::try_from(elements.len()).unwrap();
+    let new_len = old_len.checked_add(delta)?;
+    if new_len > maximum.unwrap_or(u32::MAX) {
+        return None;
+    }
+
+    let delta = usize::try_from(delta).unwrap();
+    elements.try_reserve_exact(delta).ok()?;
+    elements.resize(usize::try_from(new_len).unwrap(), init);
+
+    Some(old_len)
+}
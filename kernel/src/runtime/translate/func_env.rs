@@ -1,33 +1,49 @@
-use super::TranslatedModule;
-use crate::runtime::utils::{reference_type, value_type, wasm_call_signature};
-use crate::runtime::vmcontext::VMContextPlan;
-use crate::runtime::{NS_WASM_FUNC, WASM_PAGE_SIZE};
+use super::{MemoryStyle, TableElementType, TranslatedModule};
+use crate::runtime::builtins::BuiltinFunctions;
+use crate::runtime::config::Tunables;
+use crate::runtime::trap::{DEBUG_ASSERT_TRAP_CODE, OUT_OF_FUEL_TRAP_CODE};
+use crate::runtime::utils::{
+    is_gc_heap_ref, is_gc_heap_type, reference_type, value_type, wasm_call_signature,
+};
+use crate::runtime::vmcontext::{
+    VMContextPlan, VMMemoryDefinition, VMTableDefinition, VMCONTEXT_MAGIC, VMGCREF_I31_TAG,
+};
+use crate::runtime::NS_WASM_FUNC;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::mem::offset_of;
 use cranelift_codegen::cursor::FuncCursor;
-use cranelift_codegen::entity::PrimaryMap;
+use cranelift_codegen::entity::{PrimaryMap, SecondaryMap};
+use cranelift_codegen::ir::condcodes::IntCC;
 use cranelift_codegen::ir::immediates::Offset32;
 use cranelift_codegen::ir::types::{I32, I64};
 use cranelift_codegen::ir::{
     ExtFuncData, ExternalName, Fact, FuncRef, Function, GlobalValue, GlobalValueData, Inst,
-    InstBuilder, MemFlags, MemoryType, MemoryTypeData, MemoryTypeField, SigRef, Signature, Type,
-    UserExternalName, Value,
+    InstBuilder, MemFlags, MemoryType, MemoryTypeData, MemoryTypeField, SigRef, Signature,
+    TrapCode, Type, UserExternalName, Value,
 };
 use cranelift_codegen::isa::{TargetFrontendConfig, TargetIsa};
 use cranelift_frontend::FunctionBuilder;
 use cranelift_wasm::wasmparser::UnpackedIndex;
 use cranelift_wasm::{
-    FuncIndex, GlobalIndex, GlobalVariable, Heap, HeapData, HeapStyle, MemoryIndex,
-    ModuleInternedTypeIndex, TableIndex, TargetEnvironment, TypeConvert, TypeIndex,
-    WasmHeapTopType, WasmHeapType, WasmResult, WasmSubType,
+    FuncIndex, FuncTranslationState, GlobalIndex, GlobalVariable, Heap, HeapData, HeapStyle,
+    MemoryIndex, ModuleInternedTypeIndex, TableIndex, TargetEnvironment, TypeConvert, TypeIndex,
+    WasmFuncType, WasmHeapType, WasmResult, WasmSubType,
 };
 
 pub struct FunctionEnvironment<'module_env, 'wasm> {
     isa: &'module_env dyn TargetIsa,
     module: &'module_env TranslatedModule<'wasm>,
     types: &'module_env PrimaryMap<ModuleInternedTypeIndex, WasmSubType>,
+    tunables: &'module_env Tunables,
+    /// The type of the function being translated.
+    wasm_func_ty: &'module_env WasmFuncType,
+    /// The WASM types of the signatures imported into the function, so we know which results
+    /// of calls have to be included in stack maps.
+    sig_ref_to_ty: SecondaryMap<SigRef, Option<&'module_env WasmFuncType>>,
 
     heaps: PrimaryMap<Heap, HeapData>,
+    builtin_functions: BuiltinFunctions,
 
     vmctx_plan: VMContextPlan,
     vmctx: Option<GlobalValue>,
@@ -39,13 +55,19 @@ impl<'module_env, 'wasm> FunctionEnvironment<'module_env, 'wasm> {
         isa: &'module_env dyn TargetIsa,
         module: &'module_env TranslatedModule<'wasm>,
         types: &'module_env PrimaryMap<ModuleInternedTypeIndex, WasmSubType>,
+        tunables: &'module_env Tunables,
+        wasm_func_ty: &'module_env WasmFuncType,
     ) -> Self {
         Self {
             isa,
             module,
             types,
+            tunables,
+            wasm_func_ty,
+            sig_ref_to_ty: SecondaryMap::default(),
 
             heaps: PrimaryMap::default(),
+            builtin_functions: BuiltinFunctions::new(isa),
 
             vmctx_plan: VMContextPlan::for_module(isa, module),
             vmctx: None,
@@ -88,6 +110,314 @@ impl<'module_env, 'wasm> FunctionEnvironment<'module_env, 'wasm> {
         let vmctx = self.vmctx(pos.func);
         pos.ins().global_value(pointer_type, vmctx)
     }
+
+    fn memory_index_type(&self, index: MemoryIndex) -> Type {
+        if self.module.memory_plans[index].memory.memory64 {
+            I64
+        } else {
+            I32
+        }
+    }
+
+    /// Zero-extends the given value to `i64` if it is an `i32`, builtins take all addresses and
+    /// lengths as `i64` so they work for both 32-bit and 64-bit memories.
+    fn cast_index_to_i64(pos: &mut FuncCursor<'_>, val: Value) -> Value {
+        if pos.func.dfg.value_type(val) == I32 {
+            pos.ins().uextend(I64, val)
+        } else {
+            val
+        }
+    }
+
+    /// Converts a pointer-sized value returned by a builtin to the given index type.
+    fn convert_pointer_to_index_type(
+        &self,
+        pos: &mut FuncCursor<'_>,
+        val: Value,
+        index_type: Type,
+    ) -> Value {
+        let pointer_type = self.pointer_type();
+        if pointer_type == index_type {
+            val
+        } else if pointer_type.bits() > index_type.bits() {
+            pos.ins().ireduce(index_type, val)
+        } else {
+            pos.ins().uextend(index_type, val)
+        }
+    }
+
+    /// Returns the base pointer and offset of the `VMMemoryDefinition` of the given memory.
+    fn memory_definition(&mut self, pos: &mut FuncCursor<'_>, index: MemoryIndex) -> (Value, i32) {
+        let vmctx = self.vmctx_val(pos);
+
+        let ptr_offset = match self.module.defined_memory_index(index) {
+            Some(def_index) if !self.module.memory_plans[index].memory.shared => {
+                let owned_index = self.module.owned_memory_index(def_index);
+                let offset = self.vmctx_plan.vmctx_memory_definition(owned_index);
+                return (vmctx, i32::try_from(offset).unwrap());
+            }
+            // the definition of shared memories lives outside the vmctx
+            Some(def_index) => self.vmctx_plan.vmctx_memory_pointer(def_index),
+            None => self.vmctx_plan.vmctx_memory_import_from(index),
+        };
+
+        let ptr = pos.ins().load(
+            self.pointer_type(),
+            MemFlags::trusted().with_readonly(),
+            vmctx,
+            i32::try_from(ptr_offset).unwrap(),
+        );
+        (ptr, 0)
+    }
+
+    /// Returns the base pointer and offset of the `VMTableDefinition` of the given table.
+    fn table_definition(&mut self, pos: &mut FuncCursor<'_>, index: TableIndex) -> (Value, i32) {
+        let vmctx = self.vmctx_val(pos);
+
+        if let Some(def_index) = self.module.defined_table_index(index) {
+            let offset = self.vmctx_plan.vmctx_table_definition(def_index);
+            (vmctx, i32::try_from(offset).unwrap())
+        } else {
+            let from_offset = self.vmctx_plan.vmctx_table_import_from(index);
+            let from = pos.ins().load(
+                self.pointer_type(),
+                MemFlags::trusted().with_readonly(),
+                vmctx,
+                i32::try_from(from_offset).unwrap(),
+            );
+            (from, 0)
+        }
+    }
+
+    fn table_element_type(&self, index: TableIndex) -> TableElementType {
+        TableElementType::from(self.module.table_plans[index].table.wasm_ty)
+    }
+
+    /// Returns the address of the element at `index` in the given table, trapping if `index` is
+    /// out of bounds.
+    fn table_element_addr(
+        &mut self,
+        pos: &mut FuncCursor<'_>,
+        table_index: TableIndex,
+        index: Value,
+    ) -> Value {
+        let pointer_type = self.pointer_type();
+        let element_size = match self.table_element_type(table_index) {
+            TableElementType::Func => pointer_type.bytes(),
+            TableElementType::GcRef => I32.bytes(),
+        };
+
+        let (base, offset) = self.table_definition(pos, table_index);
+        let current_length = pos.ins().load(
+            I32,
+            MemFlags::trusted(),
+            base,
+            offset + i32::try_from(offset_of!(VMTableDefinition, current_length)).unwrap(),
+        );
+        let out_of_bounds =
+            pos.ins()
+                .icmp(IntCC::UnsignedGreaterThanOrEqual, index, current_length);
+        pos.ins().trapnz(out_of_bounds, TrapCode::TableOutOfBounds);
+
+        let elements = pos.ins().load(
+            pointer_type,
+            MemFlags::trusted(),
+            base,
+            offset + i32::try_from(offset_of!(VMTableDefinition, base)).unwrap(),
+        );
+        let index = pos.ins().uextend(pointer_type, index);
+        let element_offset = pos.ins().imul_imm(index, i64::from(element_size));
+        pos.ins().iadd(elements, element_offset)
+    }
+
+    /// Loads the funcref at `index` in the given table, trapping if it is null or its type doesn't
+    /// match `sig_index`.
+    fn checked_table_func_ref(
+        &mut self,
+        pos: &mut FuncCursor<'_>,
+        table_index: TableIndex,
+        sig_index: TypeIndex,
+        index: Value,
+    ) -> Value {
+        debug_assert_eq!(self.table_element_type(table_index), TableElementType::Func);
+        let element_addr = self.table_element_addr(pos, table_index, index);
+        let func_ref = pos
+            .ins()
+            .load(self.pointer_type(), MemFlags::trusted(), element_addr, 0);
+        pos.ins().trapz(func_ref, TrapCode::IndirectCallToNull);
+
+        // The canonical type of the callee has to match the canonical type of the expected
+        // signature, these are both `VMSharedTypeIndex`es assigned by the type registry
+        let vmctx = self.vmctx_val(pos);
+        let expected_type = pos.ins().load(
+            I32,
+            MemFlags::trusted().with_readonly(),
+            vmctx,
+            i32::try_from(self.vmctx_plan.vmctx_type_id(sig_index)).unwrap(),
+        );
+        let actual_type = pos.ins().load(
+            I32,
+            MemFlags::trusted().with_readonly(),
+            func_ref,
+            i32::try_from(self.vmctx_plan.vm_func_ref_type_index()).unwrap(),
+        );
+        let mismatch = pos.ins().icmp(IntCC::NotEqual, expected_type, actual_type);
+        pos.ins().trapnz(mismatch, TrapCode::BadSignature);
+
+        func_ref
+    }
+
+    /// Returns the WASM entrypoint of the given imported function and the arguments to call it with.
+    ///
+    /// Imported functions are called indirectly through their `VMFunctionImport`, the callee vmctx
+    /// is that of the defining instance (or the `VMHostFuncContext` for host functions).
+    fn imported_function_call_args(
+        &mut self,
+        pos: &mut FuncCursor<'_>,
+        index: FuncIndex,
+        call_args: &[Value],
+    ) -> (Value, Vec<Value>) {
+        let pointer_type = self.pointer_type();
+        let caller_vmctx = self.vmctx_val(pos);
+        let wasm_call = pos.ins().load(
+            pointer_type,
+            MemFlags::trusted().with_readonly(),
+            caller_vmctx,
+            i32::try_from(self.vmctx_plan.vmctx_function_import_wasm_call(index)).unwrap(),
+        );
+        let callee_vmctx = pos.ins().load(
+            pointer_type,
+            MemFlags::trusted().with_readonly(),
+            caller_vmctx,
+            i32::try_from(self.vmctx_plan.vmctx_function_import_vmctx(index)).unwrap(),
+        );
+
+        (
+            wasm_call,
+            Self::real_call_args(callee_vmctx, caller_vmctx, call_args),
+        )
+    }
+
+    /// Returns the WASM entrypoint of the given non-null funcref and the arguments to call it with.
+    fn func_ref_call_args(
+        &mut self,
+        pos: &mut FuncCursor<'_>,
+        func_ref: Value,
+        call_args: &[Value],
+    ) -> (Value, Vec<Value>) {
+        let pointer_type = self.pointer_type();
+        let wasm_call = pos.ins().load(
+            pointer_type,
+            MemFlags::trusted().with_readonly(),
+            func_ref,
+            i32::try_from(self.vmctx_plan.vm_func_ref_wasm_call()).unwrap(),
+        );
+        let callee_vmctx = pos.ins().load(
+            pointer_type,
+            MemFlags::trusted().with_readonly(),
+            func_ref,
+            i32::try_from(self.vmctx_plan.vm_func_ref_vmctx()).unwrap(),
+        );
+        let caller_vmctx = self.vmctx_val(pos);
+
+        (
+            wasm_call,
+            Self::real_call_args(callee_vmctx, caller_vmctx, call_args),
+        )
+    }
+
+    /// Prepends the callee and caller vmctx to the WASM arguments of a call.
+    fn real_call_args(callee_vmctx: Value, caller_vmctx: Value, call_args: &[Value]) -> Vec<Value> {
+        let mut real_call_args = Vec::with_capacity(call_args.len() + 2);
+        real_call_args.push(callee_vmctx);
+        real_call_args.push(caller_vmctx);
+        real_call_args.extend_from_slice(call_args);
+        real_call_args
+    }
+
+    /// Returns the base pointer and offset of the `VMGlobalDefinition` of the given global.
+    fn global_definition(&mut self, pos: &mut FuncCursor<'_>, index: GlobalIndex) -> (Value, i32) {
+        let vmctx = self.vmctx_val(pos);
+
+        if let Some(def_index) = self.module.defined_global_index(index) {
+            let offset = self.vmctx_plan.vmctx_global_definition(def_index);
+            (vmctx, i32::try_from(offset).unwrap())
+        } else {
+            let from_offset = self.vmctx_plan.vmctx_global_import_from(index);
+            let from = pos.ins().load(
+                self.pointer_type(),
+                MemFlags::trusted().with_readonly(),
+                vmctx,
+                i32::try_from(from_offset).unwrap(),
+            );
+            (from, 0)
+        }
+    }
+
+    /// Consumes one unit of fuel from the store, trapping if there is none left.
+    fn consume_fuel(&mut self, builder: &mut FunctionBuilder) {
+        let pointer_type = self.pointer_type();
+        let vmctx = self.vmctx(builder.func);
+        let base = builder.ins().global_value(pointer_type, vmctx);
+        let fuel_ptr = builder.ins().load(
+            pointer_type,
+            MemFlags::trusted().with_readonly(),
+            base,
+            i32::try_from(self.vmctx_plan.vmctx_fuel()).unwrap(),
+        );
+
+        let fuel = builder.ins().load(I64, MemFlags::trusted(), fuel_ptr, 0);
+        builder
+            .ins()
+            .trapz(fuel, TrapCode::User(OUT_OF_FUEL_TRAP_CODE));
+        let fuel = builder.ins().iadd_imm(fuel, -1);
+        builder.ins().store(MemFlags::trusted(), fuel, fuel_ptr, 0);
+    }
+
+    /// Calls the `new_epoch` builtin if the store reached its epoch deadline.
+    fn check_epoch(&mut self, builder: &mut FunctionBuilder) {
+        let pointer_type = self.pointer_type();
+        let vmctx = self.vmctx(builder.func);
+        let base = builder.ins().global_value(pointer_type, vmctx);
+        let epoch_ptr = builder.ins().load(
+            pointer_type,
+            MemFlags::trusted().with_readonly(),
+            base,
+            i32::try_from(self.vmctx_plan.vmctx_epoch()).unwrap(),
+        );
+
+        let current = builder.ins().load(
+            I64,
+            MemFlags::trusted(),
+            epoch_ptr,
+            i32::try_from(self.vmctx_plan.vmepoch_current()).unwrap(),
+        );
+        let deadline = builder.ins().load(
+            I64,
+            MemFlags::trusted(),
+            epoch_ptr,
+            i32::try_from(self.vmctx_plan.vmepoch_deadline()).unwrap(),
+        );
+        let reached = builder
+            .ins()
+            .icmp(IntCC::UnsignedGreaterThanOrEqual, current, deadline);
+
+        let new_epoch_block = builder.create_block();
+        let continuation_block = builder.create_block();
+        builder.set_cold_block(new_epoch_block);
+        builder
+            .ins()
+            .brif(reached, new_epoch_block, &[], continuation_block, &[]);
+
+        builder.switch_to_block(new_epoch_block);
+        builder.seal_block(new_epoch_block);
+        let new_epoch = self.builtin_functions.new_epoch(builder.func);
+        builder.ins().call(new_epoch, &[base]);
+        builder.ins().jump(continuation_block, &[]);
+
+        builder.switch_to_block(continuation_block);
+        builder.seal_block(continuation_block);
+    }
 }
 
 impl<'module_env, 'wasm> TargetEnvironment for FunctionEnvironment<'module_env, 'wasm> {
@@ -105,36 +435,40 @@ impl<'module_env, 'wasm> TargetEnvironment for FunctionEnvironment<'module_env,
 
     fn reference_type(&self, wasm_ty: WasmHeapType) -> (Type, bool) {
         let ty = reference_type(wasm_ty, self.pointer_type());
-        let needs_stack_map = match wasm_ty.top() {
-            WasmHeapTopType::Extern | WasmHeapTopType::Any => true,
-            WasmHeapTopType::Func => false,
-        };
-        (ty, needs_stack_map)
+        (ty, is_gc_heap_type(wasm_ty))
     }
 }
 
 impl<'module_env, 'wasm> TypeConvert for FunctionEnvironment<'module_env, 'wasm> {
     fn lookup_heap_type(&self, index: UnpackedIndex) -> WasmHeapType {
-        todo!()
+        self.module.lookup_heap_type(index)
     }
 
     fn lookup_type_index(
         &self,
         index: cranelift_wasm::wasmparser::UnpackedIndex,
     ) -> cranelift_wasm::EngineOrModuleTypeIndex {
-        todo!()
+        self.module.lookup_type_index(index)
     }
 }
 
 impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
     for FunctionEnvironment<'module_env, 'wasm>
 {
+    fn is_wasm_parameter(&self, _signature: &Signature, index: usize) -> bool {
+        // The first two parameters are the callee and caller vmctx, the rest are the WASM
+        // parameters.
+        index >= 2
+    }
+
     fn param_needs_stack_map(&self, _signature: &Signature, index: usize) -> bool {
-        false
+        // skip the callee and caller vmctx
+        index >= 2 && is_gc_heap_ref(self.wasm_func_ty.params()[index - 2])
     }
 
     fn sig_ref_result_needs_stack_map(&self, sig_ref: SigRef, index: usize) -> bool {
-        false
+        let wasm_func_ty = self.sig_ref_to_ty[sig_ref].expect("signature not imported");
+        is_gc_heap_ref(wasm_func_ty.returns()[index])
     }
 
     fn func_ref_result_needs_stack_map(
@@ -143,7 +477,54 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
         func_ref: FuncRef,
         index: usize,
     ) -> bool {
-        false
+        let sig_ref = func.dfg.ext_funcs[func_ref].signature;
+        self.sig_ref_result_needs_stack_map(sig_ref, index)
+    }
+
+    fn before_translate_function(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        _state: &FuncTranslationState,
+    ) -> WasmResult<()> {
+        if self.tunables.debug_assertions {
+            // catch calls that pass something other than a VMContext as the callee vmctx
+            let pointer_type = self.pointer_type();
+            let vmctx = self.vmctx(builder.func);
+            let base = builder.ins().global_value(pointer_type, vmctx);
+            let magic = builder.ins().load(
+                I32,
+                MemFlags::trusted().with_readonly(),
+                base,
+                i32::try_from(self.vmctx_plan.vmctx_magic()).unwrap(),
+            );
+            let is_invalid =
+                builder
+                    .ins()
+                    .icmp_imm(IntCC::NotEqual, magic, i64::from(VMCONTEXT_MAGIC));
+            builder
+                .ins()
+                .trapnz(is_invalid, TrapCode::User(DEBUG_ASSERT_TRAP_CODE));
+        }
+
+        if self.tunables.consume_fuel {
+            self.consume_fuel(builder);
+        }
+        if self.tunables.epoch_interruption {
+            self.check_epoch(builder);
+        }
+
+        Ok(())
+    }
+
+    fn translate_loop_header(&mut self, builder: &mut FunctionBuilder) -> WasmResult<()> {
+        if self.tunables.consume_fuel {
+            self.consume_fuel(builder);
+        }
+        if self.tunables.epoch_interruption {
+            self.check_epoch(builder);
+        }
+
+        Ok(())
     }
 
     fn make_global(
@@ -164,14 +545,26 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
             return Ok(GlobalVariable::Custom);
         }
 
-        let offset = if let Some(def_index) = self.module.defined_global_index(global_index) {
-            i32::try_from(self.vmctx_plan.vmctx_global_definition(def_index)).unwrap()
+        let (gv, offset) = if let Some(def_index) = self.module.defined_global_index(global_index) {
+            let offset = i32::try_from(self.vmctx_plan.vmctx_global_definition(def_index)).unwrap();
+            (vmctx, offset)
         } else {
-            todo!("imported memories")
+            // imported globals are accessed through the `from` pointer of their `VMGlobalImport`
+            let from_offset = self.vmctx_plan.vmctx_global_import_from(global_index);
+            let mut flags = MemFlags::trusted();
+            flags.set_readonly();
+
+            let global = func.create_global_value(GlobalValueData::Load {
+                base: vmctx,
+                offset: Offset32::new(i32::try_from(from_offset).unwrap()),
+                global_type: self.pointer_type(),
+                flags,
+            });
+            (global, 0)
         };
 
         Ok(GlobalVariable::Memory {
-            gv: vmctx,
+            gv,
             offset: offset.into(),
             ty: value_type(self.isa, ty),
         })
@@ -182,10 +575,11 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
     }
 
     fn make_heap(&mut self, func: &mut Function, memory_index: MemoryIndex) -> WasmResult<Heap> {
+        let page_size = 1 << self.module.memory_plans[memory_index].memory.page_size_log2;
         let min_size = self.module.memory_plans[memory_index]
             .memory
             .minimum
-            .checked_mul(u64::from(WASM_PAGE_SIZE))
+            .checked_mul(page_size)
             .unwrap_or_else(|| {
                 // The only valid Wasm memory size that won't fit in a 64-bit
                 // integer is the maximum memory64 size (2^64) which is one
@@ -201,19 +595,69 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
         let max_size = self.module.memory_plans[memory_index]
             .memory
             .maximum
-            .and_then(|max| max.checked_mul(u64::from(WASM_PAGE_SIZE)));
+            .and_then(|max| max.checked_mul(page_size));
 
-        let bound_bytes = 0x1_0000 * u64::from(WASM_PAGE_SIZE);
+        let vmctx = self.vmctx(func);
 
-        let def_index = self
-            .module
-            .defined_memory_index(memory_index)
-            .expect("imported memories");
-        let owned_index = self.module.owned_memory_index(def_index);
-        let base_offset =
-            i32::try_from(self.vmctx_plan.vmctx_memory_definition_base(owned_index)).unwrap();
+        // The `VMMemoryDefinition` of owned memories is stored inline in the vmctx, shared and
+        // imported memories are accessed through a pointer to their definition.
+        let ptr_offset = match self.module.defined_memory_index(memory_index) {
+            Some(_) if !self.module.memory_plans[memory_index].memory.shared => None,
+            Some(def_index) => Some(self.vmctx_plan.vmctx_memory_pointer(def_index)),
+            None => Some(self.vmctx_plan.vmctx_memory_import_from(memory_index)),
+        };
+        let (ptr, base_offset, current_length_offset, ptr_memtype) = if let Some(ptr_offset) =
+            ptr_offset
+        {
+            let ptr = func.create_global_value(GlobalValueData::Load {
+                base: vmctx,
+                offset: Offset32::new(i32::try_from(ptr_offset).unwrap()),
+                global_type: self.pointer_type(),
+                flags: MemFlags::trusted().with_readonly(),
+            });
+            let base_offset = i32::try_from(offset_of!(VMMemoryDefinition, base)).unwrap();
+            let current_length_offset =
+                i32::try_from(offset_of!(VMMemoryDefinition, current_length)).unwrap();
+            // the definition isn't part of the vmctx memtype, so PCC doesn't know about it
+            (ptr, base_offset, current_length_offset, None)
+        } else {
+            let def_index = self.module.defined_memory_index(memory_index).unwrap();
+            let owned_index = self.module.owned_memory_index(def_index);
+            let base_offset =
+                i32::try_from(self.vmctx_plan.vmctx_memory_definition_base(owned_index)).unwrap();
+            let current_length_offset = i32::try_from(
+                self.vmctx_plan
+                    .vmctx_memory_definition_current_length(owned_index),
+            )
+            .unwrap();
+            (
+                vmctx,
+                base_offset,
+                current_length_offset,
+                self.pcc_vmctx_memtype,
+            )
+        };
+
+        let plan = &self.module.memory_plans[memory_index];
+        let (style, static_bound) = match plan.style {
+            // Accesses beyond the accessible part of static memories hit unmapped pages, the
+            // resulting page faults are turned into traps by the trap handler.
+            MemoryStyle::Static { bound } => (HeapStyle::Static { bound }, Some(bound)),
+            MemoryStyle::Dynamic { .. } => {
+                let bound_gv = func.create_global_value(GlobalValueData::Load {
+                    base: ptr,
+                    offset: Offset32::new(current_length_offset),
+                    global_type: self.pointer_type(),
+                    flags: MemFlags::trusted(),
+                });
+                (HeapStyle::Dynamic { bound_gv }, None)
+            }
+        };
+        let offset_guard_size = plan.offset_guard_size;
 
-        let (base_fact, data_memtype) = if let Some(ptr_memtype) = self.pcc_vmctx_memtype {
+        let (base_fact, data_memtype) = if let (Some(ptr_memtype), Some(bound_bytes)) =
+            (ptr_memtype, static_bound)
+        {
             // Create a memtype representing the untyped memory region.
             let data_mt = func.create_memory_type(MemoryTypeData::Memory { size: bound_bytes });
             // This fact applies to any pointer to the start of the memory.
@@ -251,12 +695,10 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
             (None, None)
         };
 
-        let vmctx = self.vmctx(func);
-
-        let mut flags = MemFlags::trusted().with_checked();
-        flags.set_readonly();
+        // The base is not readonly, since growing the memory may move it
+        let flags = MemFlags::trusted().with_checked();
         let base = func.create_global_value(GlobalValueData::Load {
-            base: vmctx,
+            base: ptr,
             offset: Offset32::new(base_offset),
             global_type: self.pointer_type(),
             flags,
@@ -273,8 +715,8 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
             base,
             min_size,
             max_size,
-            offset_guard_size: 0,
-            style: HeapStyle::Static { bound: bound_bytes },
+            offset_guard_size,
+            style,
             index_type,
             memory_type: data_memtype,
             page_size_log2: self.module.memory_plans[memory_index].memory.page_size_log2,
@@ -282,15 +724,21 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
     }
 
     fn make_indirect_sig(&mut self, func: &mut Function, index: TypeIndex) -> WasmResult<SigRef> {
-        todo!()
+        let wasm_func_ty = self.types[self.module.types[index]].unwrap_func();
+        let sig = wasm_call_signature(self.isa, wasm_func_ty);
+
+        let sig_ref = func.import_signature(sig);
+        self.sig_ref_to_ty[sig_ref] = Some(wasm_func_ty);
+        Ok(sig_ref)
     }
 
     fn make_direct_func(&mut self, func: &mut Function, index: FuncIndex) -> WasmResult<FuncRef> {
         let sig = self.module.functions[index].signature;
-        let sig = &self.types[sig];
-        let sig = wasm_call_signature(self.isa, sig.unwrap_func());
+        let wasm_func_ty = self.types[sig].unwrap_func();
+        let sig = wasm_call_signature(self.isa, wasm_func_ty);
 
         let sigref = func.import_signature(sig);
+        self.sig_ref_to_ty[sigref] = Some(wasm_func_ty);
         let nameref = func.declare_imported_user_function(UserExternalName {
             namespace: NS_WASM_FUNC,
             index: index.as_u32(),
@@ -311,19 +759,43 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
         callee: FuncRef,
         call_args: &[Value],
     ) -> WasmResult<Inst> {
-        let mut real_call_args = Vec::with_capacity(call_args.len() + 1);
-        let vmctx = self.vmctx_val(&mut builder.cursor());
-
         // Handle direct calls to locally-defined functions.
         if !self.module.is_imported_function(callee_index) {
-            real_call_args.push(vmctx);
-            real_call_args.extend_from_slice(call_args);
-
-            // Finally, make the direct call!
+            // The callee vmctx is the same as the caller vmctx in this case.
+            let vmctx = self.vmctx_val(&mut builder.cursor());
+            let real_call_args = Self::real_call_args(vmctx, vmctx, call_args);
             return Ok(builder.ins().call(callee, &real_call_args));
         }
 
-        todo!()
+        let (wasm_call, real_call_args) =
+            self.imported_function_call_args(&mut builder.cursor(), callee_index, call_args);
+        let sig_ref = builder.func.dfg.ext_funcs[callee].signature;
+        Ok(builder
+            .ins()
+            .call_indirect(sig_ref, wasm_call, &real_call_args))
+    }
+
+    fn translate_return_call(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        callee_index: FuncIndex,
+        callee: FuncRef,
+        call_args: &[Value],
+    ) -> WasmResult<()> {
+        if !self.module.is_imported_function(callee_index) {
+            let vmctx = self.vmctx_val(&mut builder.cursor());
+            let real_call_args = Self::real_call_args(vmctx, vmctx, call_args);
+            builder.ins().return_call(callee, &real_call_args);
+        } else {
+            let (wasm_call, real_call_args) =
+                self.imported_function_call_args(&mut builder.cursor(), callee_index, call_args);
+            let sig_ref = builder.func.dfg.ext_funcs[callee].signature;
+            builder
+                .ins()
+                .return_call_indirect(sig_ref, wasm_call, &real_call_args);
+        }
+
+        Ok(())
     }
 
     fn translate_call_indirect(
@@ -335,7 +807,15 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
         callee: Value,
         call_args: &[Value],
     ) -> WasmResult<Option<Inst>> {
-        todo!()
+        let mut pos = builder.cursor();
+        let func_ref = self.checked_table_func_ref(&mut pos, table_index, sig_index, callee);
+        let (wasm_call, real_call_args) = self.func_ref_call_args(&mut pos, func_ref, call_args);
+
+        Ok(Some(builder.ins().call_indirect(
+            sig_ref,
+            wasm_call,
+            &real_call_args,
+        )))
     }
 
     fn translate_return_call_indirect(
@@ -347,7 +827,15 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
         callee: Value,
         call_args: &[Value],
     ) -> WasmResult<()> {
-        todo!()
+        let mut pos = builder.cursor();
+        let func_ref = self.checked_table_func_ref(&mut pos, table_index, sig_index, callee);
+        let (wasm_call, real_call_args) = self.func_ref_call_args(&mut pos, func_ref, call_args);
+
+        builder
+            .ins()
+            .return_call_indirect(sig_ref, wasm_call, &real_call_args);
+
+        Ok(())
     }
 
     fn translate_return_call_ref(
@@ -357,7 +845,15 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
         callee: Value,
         call_args: &[Value],
     ) -> WasmResult<()> {
-        todo!()
+        let mut pos = builder.cursor();
+        pos.ins().trapz(callee, TrapCode::NullReference);
+        let (wasm_call, real_call_args) = self.func_ref_call_args(&mut pos, callee, call_args);
+
+        builder
+            .ins()
+            .return_call_indirect(sig_ref, wasm_call, &real_call_args);
+
+        Ok(())
     }
 
     fn translate_call_ref(
@@ -367,83 +863,180 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
         callee: Value,
         call_args: &[Value],
     ) -> WasmResult<Inst> {
-        todo!()
+        // typed funcrefs are statically known to match `sig_ref`, only null has to be checked
+        let mut pos = builder.cursor();
+        pos.ins().trapz(callee, TrapCode::NullReference);
+        let (wasm_call, real_call_args) = self.func_ref_call_args(&mut pos, callee, call_args);
+
+        Ok(builder
+            .ins()
+            .call_indirect(sig_ref, wasm_call, &real_call_args))
     }
 
     fn translate_memory_grow(
         &mut self,
-        pos: FuncCursor,
+        mut pos: FuncCursor,
         index: MemoryIndex,
-        heap: Heap,
+        _heap: Heap,
         val: Value,
     ) -> WasmResult<Value> {
-        todo!()
+        let memory_grow = self.builtin_functions.memory32_grow(pos.func);
+        let index_type = self.memory_index_type(index);
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let val = Self::cast_index_to_i64(&mut pos, val);
+        let index_arg = pos.ins().iconst(I32, i64::from(index.as_u32()));
+
+        let call_inst = pos.ins().call(memory_grow, &[vmctx, val, index_arg]);
+        let result = *pos.func.dfg.inst_results(call_inst).first().unwrap();
+
+        Ok(self.convert_pointer_to_index_type(&mut pos, result, index_type))
     }
 
     fn translate_memory_size(
         &mut self,
-        pos: FuncCursor,
+        mut pos: FuncCursor,
         index: MemoryIndex,
-        heap: Heap,
+        _heap: Heap,
     ) -> WasmResult<Value> {
-        todo!()
+        let pointer_type = self.pointer_type();
+        let index_type = self.memory_index_type(index);
+        let page_size_log2 = self.module.memory_plans[index].memory.page_size_log2;
+
+        let (base, offset) = self.memory_definition(&mut pos, index);
+        let current_length_offset =
+            offset + i32::try_from(offset_of!(VMMemoryDefinition, current_length)).unwrap();
+        let current_length = pos.ins().load(
+            pointer_type,
+            MemFlags::trusted(),
+            base,
+            current_length_offset,
+        );
+        let current_pages = pos
+            .ins()
+            .ushr_imm(current_length, i64::from(page_size_log2));
+
+        Ok(self.convert_pointer_to_index_type(&mut pos, current_pages, index_type))
     }
 
     fn translate_memory_copy(
         &mut self,
-        pos: FuncCursor,
+        mut pos: FuncCursor,
         src_index: MemoryIndex,
-        src_heap: Heap,
+        _src_heap: Heap,
         dst_index: MemoryIndex,
-        dst_heap: Heap,
+        _dst_heap: Heap,
         dst: Value,
         src: Value,
         len: Value,
     ) -> WasmResult<()> {
-        todo!()
+        let memory_copy = self.builtin_functions.memory_copy(pos.func);
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let dst_index = pos.ins().iconst(I32, i64::from(dst_index.as_u32()));
+        let dst = Self::cast_index_to_i64(&mut pos, dst);
+        let src_index = pos.ins().iconst(I32, i64::from(src_index.as_u32()));
+        let src = Self::cast_index_to_i64(&mut pos, src);
+        let len = Self::cast_index_to_i64(&mut pos, len);
+
+        pos.ins()
+            .call(memory_copy, &[vmctx, dst_index, dst, src_index, src, len]);
+
+        Ok(())
     }
 
     fn translate_memory_fill(
         &mut self,
-        pos: FuncCursor,
+        mut pos: FuncCursor,
         index: MemoryIndex,
-        heap: Heap,
+        _heap: Heap,
         dst: Value,
         val: Value,
         len: Value,
     ) -> WasmResult<()> {
-        todo!()
+        let memory_fill = self.builtin_functions.memory_fill(pos.func);
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let index_arg = pos.ins().iconst(I32, i64::from(index.as_u32()));
+        let dst = Self::cast_index_to_i64(&mut pos, dst);
+        let len = Self::cast_index_to_i64(&mut pos, len);
+
+        pos.ins()
+            .call(memory_fill, &[vmctx, index_arg, dst, val, len]);
+
+        Ok(())
     }
 
     fn translate_memory_init(
         &mut self,
-        pos: FuncCursor,
+        mut pos: FuncCursor,
         index: MemoryIndex,
-        heap: Heap,
+        _heap: Heap,
         seg_index: u32,
         dst: Value,
         src: Value,
         len: Value,
     ) -> WasmResult<()> {
-        todo!()
+        let memory_init = self.builtin_functions.memory_init(pos.func);
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let index_arg = pos.ins().iconst(I32, i64::from(index.as_u32()));
+        let seg_index_arg = pos.ins().iconst(I32, i64::from(seg_index));
+        let dst = Self::cast_index_to_i64(&mut pos, dst);
+
+        pos.ins().call(
+            memory_init,
+            &[vmctx, index_arg, seg_index_arg, dst, src, len],
+        );
+
+        Ok(())
     }
 
-    fn translate_data_drop(&mut self, pos: FuncCursor, seg_index: u32) -> WasmResult<()> {
-        todo!()
+    fn translate_data_drop(&mut self, mut pos: FuncCursor, seg_index: u32) -> WasmResult<()> {
+        let data_drop = self.builtin_functions.data_drop(pos.func);
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let seg_index_arg = pos.ins().iconst(I32, i64::from(seg_index));
+
+        pos.ins().call(data_drop, &[vmctx, seg_index_arg]);
+
+        Ok(())
     }
 
-    fn translate_table_size(&mut self, pos: FuncCursor, index: TableIndex) -> WasmResult<Value> {
-        todo!()
+    fn translate_table_size(
+        &mut self,
+        mut pos: FuncCursor,
+        index: TableIndex,
+    ) -> WasmResult<Value> {
+        let (base, offset) = self.table_definition(&mut pos, index);
+        let current_length_offset =
+            offset + i32::try_from(offset_of!(VMTableDefinition, current_length)).unwrap();
+
+        Ok(pos
+            .ins()
+            .load(I32, MemFlags::trusted(), base, current_length_offset))
     }
 
     fn translate_table_grow(
         &mut self,
-        pos: FuncCursor,
+        mut pos: FuncCursor,
         table_index: TableIndex,
         delta: Value,
         init_value: Value,
     ) -> WasmResult<Value> {
-        todo!()
+        let table_grow = match self.table_element_type(table_index) {
+            TableElementType::Func => self.builtin_functions.table_grow_func_ref(pos.func),
+            TableElementType::GcRef => self.builtin_functions.table_grow_gc_ref(pos.func),
+        };
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let table_index_arg = pos.ins().iconst(I32, i64::from(table_index.as_u32()));
+
+        let call_inst = pos
+            .ins()
+            .call(table_grow, &[vmctx, table_index_arg, delta, init_value]);
+
+        Ok(*pos.func.dfg.inst_results(call_inst).first().unwrap())
     }
 
     fn translate_table_get(
@@ -452,7 +1045,25 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
         table_index: TableIndex,
         index: Value,
     ) -> WasmResult<Value> {
-        todo!()
+        let mut pos = builder.cursor();
+
+        let table_get = match self.table_element_type(table_index) {
+            TableElementType::Func => self
+                .builtin_functions
+                .table_get_lazy_init_func_ref(pos.func),
+            TableElementType::GcRef => {
+                // GC references need no barriers, so they can simply be read inline
+                let element_addr = self.table_element_addr(&mut pos, table_index, index);
+                return Ok(pos.ins().load(I32, MemFlags::trusted(), element_addr, 0));
+            }
+        };
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let table_index_arg = pos.ins().iconst(I32, i64::from(table_index.as_u32()));
+
+        let call_inst = pos.ins().call(table_get, &[vmctx, table_index_arg, index]);
+
+        Ok(*pos.func.dfg.inst_results(call_inst).first().unwrap())
     }
 
     fn translate_table_set(
@@ -462,58 +1073,124 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
         value: Value,
         index: Value,
     ) -> WasmResult<()> {
-        todo!()
+        // tables are eagerly initialized, so both funcrefs and GC references can be stored inline
+        let mut pos = builder.cursor();
+        let element_addr = self.table_element_addr(&mut pos, table_index, index);
+        pos.ins().store(MemFlags::trusted(), value, element_addr, 0);
+
+        Ok(())
     }
 
     fn translate_table_copy(
         &mut self,
-        pos: FuncCursor,
+        mut pos: FuncCursor,
         dst_table_index: TableIndex,
         src_table_index: TableIndex,
         dst: Value,
         src: Value,
         len: Value,
     ) -> WasmResult<()> {
-        todo!()
+        let table_copy = self.builtin_functions.table_copy(pos.func);
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let dst_table_index_arg = pos.ins().iconst(I32, i64::from(dst_table_index.as_u32()));
+        let src_table_index_arg = pos.ins().iconst(I32, i64::from(src_table_index.as_u32()));
+
+        pos.ins().call(
+            table_copy,
+            &[
+                vmctx,
+                dst_table_index_arg,
+                src_table_index_arg,
+                dst,
+                src,
+                len,
+            ],
+        );
+
+        Ok(())
     }
 
     fn translate_table_fill(
         &mut self,
-        pos: FuncCursor,
+        mut pos: FuncCursor,
         table_index: TableIndex,
         dst: Value,
         val: Value,
         len: Value,
     ) -> WasmResult<()> {
-        todo!()
+        let table_fill = match self.table_element_type(table_index) {
+            TableElementType::Func => self.builtin_functions.table_fill_func_ref(pos.func),
+            TableElementType::GcRef => self.builtin_functions.table_fill_gc_ref(pos.func),
+        };
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let table_index_arg = pos.ins().iconst(I32, i64::from(table_index.as_u32()));
+
+        pos.ins()
+            .call(table_fill, &[vmctx, table_index_arg, dst, val, len]);
+
+        Ok(())
     }
 
     fn translate_table_init(
         &mut self,
-        pos: FuncCursor,
+        mut pos: FuncCursor,
         seg_index: u32,
         table_index: TableIndex,
         dst: Value,
         src: Value,
         len: Value,
     ) -> WasmResult<()> {
-        todo!()
+        let table_init = self.builtin_functions.table_init(pos.func);
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let table_index_arg = pos.ins().iconst(I32, i64::from(table_index.as_u32()));
+        let seg_index_arg = pos.ins().iconst(I32, i64::from(seg_index));
+
+        pos.ins().call(
+            table_init,
+            &[vmctx, table_index_arg, seg_index_arg, dst, src, len],
+        );
+
+        Ok(())
     }
 
-    fn translate_elem_drop(&mut self, pos: FuncCursor, seg_index: u32) -> WasmResult<()> {
-        todo!()
+    fn translate_elem_drop(&mut self, mut pos: FuncCursor, seg_index: u32) -> WasmResult<()> {
+        let elem_drop = self.builtin_functions.elem_drop(pos.func);
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let seg_index_arg = pos.ins().iconst(I32, i64::from(seg_index));
+
+        pos.ins().call(elem_drop, &[vmctx, seg_index_arg]);
+
+        Ok(())
     }
 
     fn translate_ref_null(&mut self, mut pos: FuncCursor, ht: WasmHeapType) -> WasmResult<Value> {
-        todo!()
+        // both null funcrefs and null GC references are represented as zero
+        let ty = reference_type(ht, self.pointer_type());
+        Ok(pos.ins().iconst(ty, 0))
     }
 
     fn translate_ref_is_null(&mut self, mut pos: FuncCursor, value: Value) -> WasmResult<Value> {
-        todo!()
+        let is_null = pos.ins().icmp_imm(IntCC::Equal, value, 0);
+        Ok(pos.ins().uextend(I32, is_null))
     }
 
-    fn translate_ref_func(&mut self, pos: FuncCursor, func_index: FuncIndex) -> WasmResult<Value> {
-        todo!()
+    fn translate_ref_func(
+        &mut self,
+        mut pos: FuncCursor,
+        func_index: FuncIndex,
+    ) -> WasmResult<Value> {
+        let ref_func = self.builtin_functions.ref_func(pos.func);
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let func_index_arg = pos.ins().iconst(I32, i64::from(func_index.as_u32()));
+
+        let call_inst = pos.ins().call(ref_func, &[vmctx, func_index_arg]);
+
+        Ok(*pos.func.dfg.inst_results(call_inst).first().unwrap())
     }
 
     fn translate_custom_global_get(
@@ -521,7 +1198,12 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
         builder: &mut FunctionBuilder,
         global_index: GlobalIndex,
     ) -> WasmResult<Value> {
-        todo!()
+        // The collector neither moves objects nor counts references, so GC references need no
+        // barriers and are plain `i32`s
+        let mut pos = builder.cursor();
+        let (base, offset) = self.global_definition(&mut pos, global_index);
+
+        Ok(pos.ins().load(I32, MemFlags::trusted(), base, offset))
     }
 
     fn translate_custom_global_set(
@@ -530,41 +1212,76 @@ impl<'module_env, 'wasm> cranelift_wasm::FuncEnvironment
         global_index: GlobalIndex,
         val: Value,
     ) -> WasmResult<()> {
-        todo!()
+        let mut pos = builder.cursor();
+        let (base, offset) = self.global_definition(&mut pos, global_index);
+        pos.ins().store(MemFlags::trusted(), val, base, offset);
+
+        Ok(())
     }
 
     fn translate_atomic_wait(
         &mut self,
-        pos: FuncCursor,
+        mut pos: FuncCursor,
         index: MemoryIndex,
-        heap: Heap,
+        _heap: Heap,
         addr: Value,
         expected: Value,
         timeout: Value,
     ) -> WasmResult<Value> {
-        todo!()
+        let memory_atomic_wait = match pos.func.dfg.value_type(expected) {
+            I32 => self.builtin_functions.memory_atomic_wait32(pos.func),
+            I64 => self.builtin_functions.memory_atomic_wait64(pos.func),
+            ty => unreachable!("unexpected type for atomic wait {ty}"),
+        };
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let index_arg = pos.ins().iconst(I32, i64::from(index.as_u32()));
+        let addr = Self::cast_index_to_i64(&mut pos, addr);
+
+        // the builtin checks the alignment, bounds and sharedness of the memory
+        let call_inst = pos.ins().call(
+            memory_atomic_wait,
+            &[vmctx, index_arg, addr, expected, timeout],
+        );
+
+        Ok(*pos.func.dfg.inst_results(call_inst).first().unwrap())
     }
 
     fn translate_atomic_notify(
         &mut self,
-        pos: FuncCursor,
+        mut pos: FuncCursor,
         index: MemoryIndex,
-        heap: Heap,
+        _heap: Heap,
         addr: Value,
         count: Value,
     ) -> WasmResult<Value> {
-        todo!()
+        let memory_atomic_notify = self.builtin_functions.memory_atomic_notify(pos.func);
+
+        let vmctx = self.vmctx_val(&mut pos);
+        let index_arg = pos.ins().iconst(I32, i64::from(index.as_u32()));
+        let addr = Self::cast_index_to_i64(&mut pos, addr);
+
+        let call_inst = pos
+            .ins()
+            .call(memory_atomic_notify, &[vmctx, index_arg, addr, count]);
+
+        Ok(*pos.func.dfg.inst_results(call_inst).first().unwrap())
     }
 
-    fn translate_ref_i31(&mut self, pos: FuncCursor, val: Value) -> WasmResult<Value> {
-        todo!()
+    fn translate_ref_i31(&mut self, mut pos: FuncCursor, val: Value) -> WasmResult<Value> {
+        // `i31ref`s carry their value in the upper 31 bits and are tagged by their lowest bit,
+        // so they can never be mistaken for null or a reference into the GC heap
+        let shifted = pos.ins().ishl_imm(val, 1);
+        Ok(pos.ins().bor_imm(shifted, i64::from(VMGCREF_I31_TAG)))
     }
 
-    fn translate_i31_get_s(&mut self, pos: FuncCursor, i31ref: Value) -> WasmResult<Value> {
-        todo!()
+    fn translate_i31_get_s(&mut self, mut pos: FuncCursor, i31ref: Value) -> WasmResult<Value> {
+        pos.ins().trapz(i31ref, TrapCode::NullI31Ref);
+        Ok(pos.ins().sshr_imm(i31ref, 1))
     }
 
-    fn translate_i31_get_u(&mut self, pos: FuncCursor, i31ref: Value) -> WasmResult<Value> {
-        todo!()
+    fn translate_i31_get_u(&mut self, mut pos: FuncCursor, i31ref: Value) -> WasmResult<Value> {
+        pos.ins().trapz(i31ref, TrapCode::NullI31Ref);
+        Ok(pos.ins().ushr_imm(i31ref, 1))
     }
 }
@@ -5,17 +5,20 @@ pub use func_env::FunctionEnvironment;
 pub use module_env::ModuleEnvironment;
 
 use super::compile::FuncCompileInput;
+use super::config::Tunables;
 use super::vmcontext::FuncRefIndex;
+use crate::kconfig;
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use cranelift_entity::packed_option::ReservedValue;
 use cranelift_entity::{EntityRef, PrimaryMap};
-use cranelift_wasm::wasmparser::WasmFeatures;
+use cranelift_wasm::wasmparser::{UnpackedIndex, WasmFeatures};
 use cranelift_wasm::{
     ConstExpr, DataIndex, DefinedFuncIndex, DefinedGlobalIndex, DefinedMemoryIndex,
     DefinedTableIndex, ElemIndex, EngineOrModuleTypeIndex, EntityIndex, EntityType, FuncIndex,
     Global, GlobalIndex, Memory, MemoryIndex, ModuleInternedTypeIndex, OwnedMemoryIndex, Table,
-    TableIndex, TypeIndex, WasmSubType,
+    TableIndex, TypeIndex, WasmHeapType, WasmRefType, WasmSubType,
 };
 use hashbrown::HashMap;
 
@@ -32,6 +35,9 @@ pub struct TranslatedModule<'wasm> {
     pub required_features: WasmFeatures,
 
     pub types: PrimaryMap<TypeIndex, ModuleInternedTypeIndex>,
+    /// The kind of each type, recorded for a whole recursion group before any of its types is
+    /// converted since types may refer to types defined later in their group.
+    pub type_kinds: PrimaryMap<TypeIndex, TypeKind>,
 
     pub start: Option<FuncIndex>,
 
@@ -45,10 +51,12 @@ pub struct TranslatedModule<'wasm> {
     pub global_initializers: PrimaryMap<DefinedGlobalIndex, ConstExpr>,
 
     pub table_initializers: TableInitializers,
-    pub passive_element_segments: PrimaryMap<ElemIndex, TableSegmentElements>,
+    /// Passive element segments keyed by their index in the element section.
+    pub passive_element_segments: BTreeMap<ElemIndex, TableSegmentElements>,
 
     pub memory_initializers: MemoryInitializers<'wasm>,
-    pub passive_data_segments: PrimaryMap<DataIndex, &'wasm [u8]>,
+    /// Passive data segments keyed by their index in the data section.
+    pub passive_data_segments: BTreeMap<DataIndex, &'wasm [u8]>,
 
     pub num_imported_functions: u32,
     pub num_imported_tables: u32,
@@ -163,6 +171,41 @@ pub struct TablePlan {
     pub table: Table,
 }
 
+/// The kind of a type defined in the type section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeKind {
+    Func,
+    Struct,
+    Array,
+}
+
+/// The kind of elements stored in a table, which decides how they are represented at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableElementType {
+    Func,
+    GcRef,
+}
+
+impl From<WasmRefType> for TableElementType {
+    fn from(ty: WasmRefType) -> Self {
+        match ty.heap_type {
+            WasmHeapType::Func | WasmHeapType::ConcreteFunc(_) | WasmHeapType::NoFunc => {
+                TableElementType::Func
+            }
+            WasmHeapType::Extern
+            | WasmHeapType::NoExtern
+            | WasmHeapType::Any
+            | WasmHeapType::Eq
+            | WasmHeapType::I31
+            | WasmHeapType::Array
+            | WasmHeapType::ConcreteArray(_)
+            | WasmHeapType::Struct
+            | WasmHeapType::ConcreteStruct(_)
+            | WasmHeapType::None => TableElementType::GcRef,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct TableInitializers {
     pub initial_values: PrimaryMap<DefinedTableIndex, TableInitialValue>,
@@ -178,8 +221,8 @@ pub enum TableInitialValue {
 #[derive(Debug)]
 pub struct TableSegment {
     pub table_index: TableIndex,
-    pub base: Option<GlobalIndex>,
-    pub offset: u32,
+    /// The offset into the table, evaluated when the module is instantiated.
+    pub offset: ConstExpr,
     pub elements: TableSegmentElements,
 }
 
@@ -192,6 +235,30 @@ pub enum TableSegmentElements {
 #[derive(Debug, Clone)]
 pub struct MemoryPlan {
     pub memory: Memory,
+    /// How the memory is laid out in the address space.
+    pub style: MemoryStyle,
+    /// The size in bytes of the unmapped region following the memory. Accesses with offsets
+    /// smaller than this don't need to take the offset into account when bounds checking.
+    pub offset_guard_size: u64,
+}
+
+/// How a linear memory is implemented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryStyle {
+    /// The address space for the memory's maximum size is reserved up front, so the memory never
+    /// moves. Only the accessible part is mapped, everything else faults on access which
+    /// lets the compiler elide all bounds checks below `bound`.
+    Static {
+        /// The size of the reservation in bytes, excluding the guard region.
+        bound: u64,
+    },
+    /// The memory is explicitly bounds checked against its current length and may move when
+    /// it grows beyond its reservation.
+    Dynamic {
+        /// Extra bytes to reserve beyond the current size, growing into them doesn't move the
+        /// memory.
+        reserve: u64,
+    },
 }
 
 #[derive(Debug, Default)]
@@ -203,8 +270,8 @@ pub struct MemoryInitializers<'wasm> {
 #[derive(Debug)]
 pub struct MemoryInitializer<'wasm> {
     pub memory_index: MemoryIndex,
-    pub base: Option<GlobalIndex>,
-    pub offset: u32,
+    /// The offset into the memory, evaluated when the module is instantiated.
+    pub offset: ConstExpr,
     pub bytes: &'wasm [u8],
 }
 
@@ -243,6 +310,37 @@ impl<'wasm> TranslatedModule<'wasm> {
     pub fn num_escaped_funcs(&self) -> u32 {
         self.num_escaped_funcs
     }
+    pub fn num_types(&self) -> u32 {
+        u32::try_from(self.types.len()).unwrap()
+    }
+
+    /// Returns the interned type of a type index found in the module's code or types.
+    ///
+    /// Types are interned in the order they are defined, so this also works for types of the
+    /// recursion group currently being converted which haven't been interned yet.
+    pub fn lookup_type_index(&self, index: UnpackedIndex) -> EngineOrModuleTypeIndex {
+        match index {
+            UnpackedIndex::Module(index) => {
+                EngineOrModuleTypeIndex::Module(ModuleInternedTypeIndex::from_u32(index))
+            }
+            UnpackedIndex::RecGroup(_) | UnpackedIndex::Id(_) => {
+                unreachable!("type indices are canonicalized to module indices")
+            }
+        }
+    }
+
+    pub fn lookup_heap_type(&self, index: UnpackedIndex) -> WasmHeapType {
+        let UnpackedIndex::Module(type_index) = index else {
+            unreachable!("type indices are canonicalized to module indices")
+        };
+        let ty = self.lookup_type_index(index);
+
+        match self.type_kinds[TypeIndex::from_u32(type_index)] {
+            TypeKind::Func => WasmHeapType::ConcreteFunc(ty),
+            TypeKind::Struct => WasmHeapType::ConcreteStruct(ty),
+            TypeKind::Array => WasmHeapType::ConcreteArray(ty),
+        }
+    }
 
     #[inline]
     pub fn function_index(&self, defined_func: DefinedFuncIndex) -> FuncIndex {
@@ -389,20 +487,79 @@ impl TablePlan {
 }
 
 impl MemoryPlan {
-    pub fn for_memory(ty: wasmparser::MemoryType) -> Self {
+    pub fn for_memory(ty: wasmparser::MemoryType, tunables: &Tunables) -> Self {
         let page_size_log2 = u8::try_from(ty.page_size_log2.unwrap_or(16)).unwrap();
         debug_assert!(
             page_size_log2 == 16 || page_size_log2 == 0,
             "invalid page_size_log2: {page_size_log2}; must be 16 or 0"
         );
+        let memory = Memory {
+            minimum: ty.initial,
+            maximum: ty.maximum,
+            shared: ty.shared,
+            memory64: ty.memory64,
+            page_size_log2,
+        };
+        let (style, offset_guard_size) = MemoryStyle::for_memory(&memory, tunables);
+
         Self {
-            memory: Memory {
-                minimum: ty.initial,
-                maximum: ty.maximum,
-                shared: ty.shared,
-                memory64: ty.memory64,
-                page_size_log2,
-            },
+            memory,
+            style,
+            offset_guard_size,
+        }
+    }
+}
+
+impl MemoryStyle {
+    /// Decides on a style for the given memory, returns the style and the size of the guard
+    /// region in bytes.
+    pub fn for_memory(memory: &Memory, tunables: &Tunables) -> (Self, u64) {
+        let maximum = memory
+            .maximum
+            .and_then(|max| max.checked_mul(1 << memory.page_size_log2));
+
+        // Guard regions rely on the memory size being a multiple of the host page size, so that
+        // the first byte past the end is guaranteed to fault. Otherwise the rest of the last
+        // committed page is accessible, and every access has to be checked against the exact
+        // size of the memory instead.
+        let page_aligned = u32::from(memory.page_size_log2) >= kconfig::PAGE_SIZE.ilog2();
+        let dynamic_guard_size = if page_aligned {
+            tunables.dynamic_memory_offset_guard_size
+        } else {
+            0
+        };
+
+        let is_static = !memory.memory64
+            && page_aligned
+            && maximum.map_or(true, |max| max <= tunables.static_memory_bound);
+
+        if is_static {
+            (
+                Self::Static {
+                    bound: tunables.static_memory_bound,
+                },
+                tunables.static_memory_offset_guard_size,
+            )
+        } else if memory.shared {
+            // Shared memories are accessed concurrently and must never move, so reserve their
+            // entire maximum size up front. Validation ensures they have a maximum.
+            let minimum = memory
+                .minimum
+                .checked_mul(1 << memory.page_size_log2)
+                .unwrap_or(u64::MAX);
+            (
+                Self::Dynamic {
+                    reserve: maximum.unwrap_or(u64::MAX).saturating_sub(minimum),
+                },
+                dynamic_guard_size,
+            )
+        } else {
+            (
+                Self::Dynamic {
+                    reserve: tunables.dynamic_memory_growth_reserve,
+                },
+                dynamic_guard_size,
+            )
         }
     }
 }
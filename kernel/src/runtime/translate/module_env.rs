@@ -1,46 +1,49 @@
 use crate::runtime::compile::FuncCompileInput;
+use crate::runtime::config::Tunables;
 use crate::runtime::errors::TranslationError;
 use crate::runtime::translate::{
     FunctionType, Import, MemoryInitializer, MemoryPlan, ProducersLanguage, ProducersLanguageField,
     ProducersSdk, ProducersSdkField, ProducersTool, ProducersToolField, TableInitialValue,
-    TablePlan, TableSegment, TableSegmentElements, Translation,
+    TablePlan, TableSegment, TableSegmentElements, Translation, TypeKind,
 };
 use crate::runtime::vmcontext::FuncRefIndex;
+use alloc::format;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use cranelift_codegen::entity::Unsigned;
 use cranelift_entity::packed_option::ReservedValue;
 use cranelift_wasm::wasmparser::{
-    BinaryReader, CustomSectionReader, DataKind, ElementItems, ElementKind, Encoding, ExternalKind,
-    Operator, Parser, Payload, ProducersFieldValue, ProducersSectionReader, TableInit, TypeRef,
-    UnpackedIndex, Validator, ValidatorResources, WasmFeatures,
+    BinaryReader, CompositeInnerType, CustomSectionReader, DataKind, ElementItems, ElementKind,
+    Encoding, ExternalKind, Operator, Parser, Payload, ProducersFieldValue, ProducersSectionReader,
+    TableInit, TypeRef, UnpackedIndex, Validator, ValidatorResources, WasmFeatures,
 };
 use cranelift_wasm::{
-    ConstExpr, EntityIndex, FuncIndex, GlobalIndex, MemoryIndex, TableIndex, TypeConvert,
-    TypeIndex, WasmCompositeType, WasmHeapType, WasmSubType,
+    ConstExpr, DataIndex, ElemIndex, EntityIndex, FuncIndex, GlobalIndex, MemoryIndex, TableIndex,
+    TypeConvert, TypeIndex, WasmError, WasmHeapType,
 };
 use object::Bytes;
 
 pub struct ModuleEnvironment<'a, 'wasm> {
     result: Translation<'wasm>,
     validator: &'a mut Validator,
+    tunables: &'a Tunables,
 }
 
 impl<'a, 'wasm> TypeConvert for ModuleEnvironment<'a, 'wasm> {
-    fn lookup_heap_type(&self, _index: UnpackedIndex) -> WasmHeapType {
-        todo!()
+    fn lookup_heap_type(&self, index: UnpackedIndex) -> WasmHeapType {
+        self.result.module.lookup_heap_type(index)
     }
 
-    fn lookup_type_index(&self, _index: UnpackedIndex) -> cranelift_wasm::EngineOrModuleTypeIndex {
-        todo!()
+    fn lookup_type_index(&self, index: UnpackedIndex) -> cranelift_wasm::EngineOrModuleTypeIndex {
+        self.result.module.lookup_type_index(index)
     }
 }
 
 impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
-    pub fn new(validator: &'a mut Validator) -> Self {
+    pub fn new(validator: &'a mut Validator, tunables: &'a Tunables) -> Self {
         Self {
             result: Translation::default(),
             validator,
+            tunables,
         }
     }
 
@@ -54,6 +57,51 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
         self.result.module.num_escaped_funcs += 1;
     }
 
+    /// Converts a constant expression, rejecting operators the runtime can't evaluate.
+    fn translate_const_expr(
+        &mut self,
+        expr: wasmparser::ConstExpr<'wasm>,
+    ) -> Result<ConstExpr, TranslationError> {
+        for op in expr.get_operators_reader() {
+            match op? {
+                Operator::I32Const { .. }
+                | Operator::I64Const { .. }
+                | Operator::F32Const { .. }
+                | Operator::F64Const { .. }
+                | Operator::V128Const { .. }
+                | Operator::GlobalGet { .. }
+                | Operator::RefNull { .. }
+                | Operator::RefFunc { .. }
+                | Operator::RefI31
+                | Operator::I32Add
+                | Operator::I32Sub
+                | Operator::I32Mul
+                | Operator::I64Add
+                | Operator::I64Sub
+                | Operator::I64Mul
+                | Operator::StructNew { .. }
+                | Operator::StructNewDefault { .. }
+                | Operator::ArrayNew { .. }
+                | Operator::ArrayNewDefault { .. }
+                | Operator::ArrayNewFixed { .. }
+                | Operator::End => {}
+                // converting between `externref`s and `anyref`s would require boxing host
+                // references in the GC heap
+                op => {
+                    return Err(TranslationError::Translate(WasmError::Unsupported(
+                        format!("constant expression operator {op:?}"),
+                    )))
+                }
+            }
+        }
+
+        let (expr, escaped) = ConstExpr::from_wasmparser(expr)?;
+        for func in escaped {
+            self.mark_function_as_escaped(func);
+        }
+        Ok(expr)
+    }
+
     pub fn translate(
         mut self,
         parser: Parser,
@@ -85,15 +133,28 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
                     .types
                     .reserve_exact(types.count() as usize);
                 self.result.types.reserve_exact(types.count() as usize);
+                self.result
+                    .module
+                    .type_kinds
+                    .reserve_exact(types.count() as usize);
 
-                for ty in types.into_iter_err_on_gc_types() {
-                    let wasm_func_type = self.convert_func_type(&ty?);
-                    let idx = self.result.types.push(WasmSubType {
-                        is_final: true,
-                        supertype: None,
-                        composite_type: WasmCompositeType::Func(wasm_func_type),
-                    });
-                    self.result.module.types.push(idx);
+                for rec_group in types {
+                    let rec_group = rec_group?;
+
+                    for ty in rec_group.types() {
+                        let kind = match &ty.composite_type.inner {
+                            CompositeInnerType::Func(_) => TypeKind::Func,
+                            CompositeInnerType::Struct(_) => TypeKind::Struct,
+                            CompositeInnerType::Array(_) => TypeKind::Array,
+                        };
+                        self.result.module.type_kinds.push(kind);
+                    }
+
+                    for ty in rec_group.into_types() {
+                        let sub_type = self.convert_sub_type(&ty);
+                        let idx = self.result.types.push(sub_type);
+                        self.result.module.types.push(idx);
+                    }
                 }
             }
             Payload::ImportSection(imports) => {
@@ -134,7 +195,7 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
                                 .result
                                 .module
                                 .memory_plans
-                                .push(MemoryPlan::for_memory(ty));
+                                .push(MemoryPlan::for_memory(ty, self.tunables));
 
                             EntityIndex::Memory(memory_index)
                         }
@@ -147,7 +208,7 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
 
                             EntityIndex::Global(global_index)
                         }
-                        TypeRef::Tag(_) => todo!(),
+                        TypeRef::Tag(_) => return Err(unsupported_tags()),
                     };
 
                     self.result.module.imports.push(Import {
@@ -192,11 +253,7 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
                     let init = match table.init {
                         TableInit::RefNull => TableInitialValue::RefNull,
                         TableInit::Expr(expr) => {
-                            let (expr, escaped) = ConstExpr::from_wasmparser(expr)?;
-                            for f in escaped {
-                                self.mark_function_as_escaped(f);
-                            }
-                            TableInitialValue::ConstExpr(expr)
+                            TableInitialValue::ConstExpr(self.translate_const_expr(expr)?)
                         }
                     };
                     self.result
@@ -220,12 +277,12 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
                     self.result
                         .module
                         .memory_plans
-                        .push(MemoryPlan::for_memory(ty));
+                        .push(MemoryPlan::for_memory(ty, self.tunables));
                 }
             }
             Payload::TagSection(tags) => {
                 self.validator.tag_section(&tags)?;
-                todo!()
+                return Err(unsupported_tags());
             }
             Payload::GlobalSection(globals) => {
                 self.validator.global_section(&globals)?;
@@ -242,10 +299,7 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
                         .globals
                         .push(self.convert_global_type(&global.ty));
 
-                    let (expr, escaped) = ConstExpr::from_wasmparser(global.init_expr)?;
-                    for func in escaped {
-                        self.mark_function_as_escaped(func);
-                    }
+                    let expr = self.translate_const_expr(global.init_expr)?;
                     self.result.module.global_initializers.push(expr);
                 }
             }
@@ -270,7 +324,7 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
                         ExternalKind::Global => {
                             EntityIndex::Global(GlobalIndex::from_u32(export.index))
                         }
-                        ExternalKind::Tag => todo!(),
+                        ExternalKind::Tag => return Err(unsupported_tags()),
                     };
 
                     self.result.module.exports.insert(export.name, index);
@@ -278,12 +332,15 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
             }
             Payload::StartSection { func, range } => {
                 self.validator.start_section(func, &range)?;
-                self.result.module.start = Some(FuncIndex::from_u32(func));
+                let func_index = FuncIndex::from_u32(func);
+                // the start function is invoked by the host, so it needs a `VMFuncRef`
+                self.mark_function_as_escaped(func_index);
+                self.result.module.start = Some(func_index);
             }
             Payload::ElementSection(elements) => {
                 self.validator.element_section(&elements)?;
 
-                for element in elements {
+                for (index, element) in elements.into_iter().enumerate() {
                     let element = element?;
 
                     let elements = match element.items {
@@ -299,11 +356,7 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
                         ElementItems::Expressions(_, exprs) => {
                             let mut out = Vec::with_capacity(exprs.count() as usize);
                             for expr in exprs {
-                                let (expr, escaped) = ConstExpr::from_wasmparser(expr?)?;
-                                for func in escaped {
-                                    self.mark_function_as_escaped(func);
-                                }
-                                out.push(expr);
+                                out.push(self.translate_const_expr(expr?)?);
                             }
                             TableSegmentElements::Expressions(out.into_boxed_slice())
                         }
@@ -311,23 +364,18 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
 
                     match element.kind {
                         ElementKind::Passive => {
-                            self.result.module.passive_element_segments.push(elements);
+                            let index = ElemIndex::from_u32(u32::try_from(index).unwrap());
+                            self.result
+                                .module
+                                .passive_element_segments
+                                .insert(index, elements);
                         }
                         ElementKind::Active {
                             table_index,
                             offset_expr,
                         } => {
                             let table_index = TableIndex::from_u32(table_index.unwrap_or(0));
-                            let mut offset_expr_reader = offset_expr.get_binary_reader();
-                            let (base, offset) = match offset_expr_reader.read_operator()? {
-                                Operator::I32Const { value } => (None, value.unsigned()),
-                                Operator::GlobalGet { global_index } => {
-                                    (Some(GlobalIndex::from_u32(global_index)), 0)
-                                }
-                                ref s => {
-                                    panic!("unsupported init expr in element section: {:?}", s);
-                                }
-                            };
+                            let offset = self.translate_const_expr(offset_expr)?;
 
                             self.result
                                 .module
@@ -335,7 +383,6 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
                                 .segments
                                 .push(TableSegment {
                                     table_index,
-                                    base,
                                     offset,
                                     elements,
                                 });
@@ -350,32 +397,26 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
             Payload::DataSection(section) => {
                 self.validator.data_section(&section)?;
 
-                for data in section {
+                for (index, data) in section.into_iter().enumerate() {
                     let data = data?;
                     match data.kind {
                         DataKind::Passive => {
-                            self.result.module.passive_data_segments.push(data.data);
+                            let index = DataIndex::from_u32(u32::try_from(index).unwrap());
+                            self.result
+                                .module
+                                .passive_data_segments
+                                .insert(index, data.data);
                         }
                         DataKind::Active {
                             memory_index,
                             offset_expr,
                         } => {
                             let memory_index = MemoryIndex::from_u32(memory_index);
-                            let mut offset_expr_reader = offset_expr.get_binary_reader();
-                            let (base, offset) = match offset_expr_reader.read_operator()? {
-                                Operator::I32Const { value } => (None, value.unsigned()),
-                                Operator::GlobalGet { global_index } => {
-                                    (Some(GlobalIndex::from_u32(global_index)), 0)
-                                }
-                                ref s => {
-                                    panic!("unsupported init expr in element section: {:?}", s);
-                                }
-                            };
+                            let offset = self.translate_const_expr(offset_expr)?;
 
                             self.result.module.memory_initializers.runtime.push(
                                 MemoryInitializer {
                                     memory_index,
-                                    base,
                                     offset,
                                     bytes: data.data,
                                 },
@@ -675,3 +716,10 @@ impl<'a, 'wasm> ModuleEnvironment<'a, 'wasm> {
         info.dwarf = dwarf;
     }
 }
+
+/// Exception handling tags pass validation when the proposal is enabled through
+/// [`Config::wasm_feature`](crate::runtime::Config::wasm_feature), but `throw` and `try_table`
+/// can't be compiled yet.
+fn unsupported_tags() -> TranslationError {
+    TranslationError::Translate(WasmError::Unsupported("exception handling tags".into()))
+}
@@ -2,8 +2,10 @@ use object::{Bytes, LittleEndian, U32};
 
 /// Trap code used for debug assertions we emit in our JIT code.
 pub const DEBUG_ASSERT_TRAP_CODE: u16 = u16::MAX;
+/// Trap code used when a function runs out of fuel.
+pub const OUT_OF_FUEL_TRAP_CODE: u16 = u16::MAX - 1;
 
-#[derive(onlyerror::Error, Debug)]
+#[derive(onlyerror::Error, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Trap {
     /// The current stack space was exhausted.
     #[error("call stack exhausted")]
@@ -47,6 +49,19 @@ pub enum Trap {
     /// Debug assertion failed
     #[error("debug assertion failed")]
     DebugAssertionFailed,
+    /// The store ran out of fuel while executing WASM.
+    #[error("all fuel consumed by WebAssembly")]
+    OutOfFuel,
+    /// Execution was interrupted because the store reached its epoch deadline.
+    #[error("interrupt")]
+    Interrupt,
+    /// The guest raised a CPU exception outside of any trap site, e.g. by executing an illegal
+    /// instruction or accessing memory it has no access to.
+    #[error("unexpected exception in guest code")]
+    UnexpectedException,
+    /// A struct or array couldn't be allocated because it is too large or the GC heap is full.
+    #[error("allocation size too large")]
+    AllocationTooLarge,
 }
 
 impl From<Trap> for u8 {
@@ -66,6 +81,10 @@ impl From<Trap> for u8 {
             Trap::NullReference => 11,
             Trap::NullI31Ref => 12,
             Trap::DebugAssertionFailed => 13,
+            Trap::OutOfFuel => 14,
+            Trap::Interrupt => 15,
+            Trap::UnexpectedException => 16,
+            Trap::AllocationTooLarge => 17,
         }
     }
 }
@@ -89,13 +108,18 @@ impl TryFrom<u8> for Trap {
             11 => Ok(Self::NullReference),
             12 => Ok(Self::NullI31Ref),
             13 => Ok(Self::DebugAssertionFailed),
+            14 => Ok(Self::OutOfFuel),
+            15 => Ok(Self::Interrupt),
+            16 => Ok(Self::UnexpectedException),
+            17 => Ok(Self::AllocationTooLarge),
             _ => Err(()),
         }
     }
 }
 
-#[allow(unused)]
-pub fn trap_for_offset(trap_section: &[u8], offset: u32) -> Trap {
+/// Looks up the trap code for the given offset into the text section, returns `None` if the
+/// offset isn't a trap site.
+pub fn trap_for_offset(trap_section: &[u8], offset: u32) -> Option<Trap> {
     let mut section = Bytes(trap_section);
 
     let count = section.read::<U32<LittleEndian>>().unwrap();
@@ -106,7 +130,7 @@ pub fn trap_for_offset(trap_section: &[u8], offset: u32) -> Trap {
 
     let index = offsets
         .binary_search_by_key(&offset, |val| val.get(LittleEndian))
-        .unwrap();
+        .ok()?;
 
-    Trap::try_from(traps[index]).unwrap()
+    Some(Trap::try_from(traps[index]).unwrap())
 }
@@ -0,0 +1,736 @@
+//! Running WASM in user mode and recovering from the traps it raises.
+//!
+//! WASM is always entered through [`catch_traps`] which spills all callee-saved registers onto the
+//! kernel stack, records the resulting stack pointer in the callee's `last_wasm_entry_sp`
+//! `VMContext` field and drops into user mode on the store's stack through `sret`. There is no
+//! direct way back into supervisor mode, so every transition goes through the kernel trap handler
+//! which calls [`handle_user_exception`]:
+//!
+//! - **Returns**: The guest's return address points to [`wasm_exit`] which is not accessible from
+//!   user mode, so returning from the entry function raises an instruction page fault. The kernel
+//!   then restores the spilled registers and returns from [`catch_traps`].
+//! - **Calls into the kernel**: Likewise, builtin functions and host functions are not accessible
+//!   from user mode. Calling one raises an instruction page fault for its address, the kernel then
+//!   executes the function in supervisor mode on the kernel stack and resumes the guest at its
+//!   return address.
+//! - **Traps**: Faults at a trap site of any published [`CodeMemory`] are turned into the
+//!   corresponding [`Trap`], the kernel restores the spilled registers and returns the trap from
+//!   [`catch_traps`]. Faults in the guard region below the stack are reported as
+//!   [`Trap::StackOverflow`], all other exceptions as [`Trap::UnexpectedException`] (or
+//!   [`Trap::HeapMisaligned`] for misaligned accesses) so a misbehaving guest never takes down
+//!   the kernel.
+//!
+//! Builtin functions raise traps through the same mechanism by calling [`raise_trap`].
+//!
+//! While the kernel executes a call from WASM, the WASM frames on the guest's stack can be walked
+//! by following the frame pointer chain, see [`visit_wasm_gc_roots`].
+//!
+//! [`CodeMemory`]: crate::runtime::guest_memory::CodeMemory
+
+use crate::runtime::builtins::VMBuiltinFunctionsArray;
+use crate::runtime::host_func::host_func_native_call;
+use crate::runtime::instance::InstanceData;
+use crate::runtime::stack::Stack;
+use crate::runtime::stack_map::{stack_map_for_offset, StackMap};
+use crate::runtime::trap::{trap_for_offset, Trap};
+use crate::runtime::vmcontext::{
+    VMContext, VMFuncRef, VMNativeCallFunction, VMVal, VMCONTEXT_MAGIC,
+};
+use alloc::collections::BTreeMap;
+use core::arch::asm;
+use core::cell::Cell;
+use core::ops::{Bound, Range};
+use core::ptr::NonNull;
+use core::{ptr, slice};
+use kmm::{AddressRangeExt, VirtualAddress};
+use riscv::scause::Exception;
+use sync::RwLock;
+use thread_local::declare_thread_local;
+
+/// Indices of the registers we care about in the general purpose register file.
+const REG_RA: usize = 1;
+const REG_SP: usize = 2;
+const REG_FP: usize = 8;
+const REG_T0: usize = 5;
+const REG_T1: usize = 6;
+
+/// All published code, keyed by the *end* address of its text section.
+static CODE_REGISTRY: RwLock<BTreeMap<VirtualAddress, RegisteredCode>> =
+    RwLock::new(BTreeMap::new());
+
+declare_thread_local! {
+    /// The innermost active call into WASM on this hart.
+    static ACTIVATION: Cell<*const Activation> = const { Cell::new(ptr::null()) };
+}
+
+struct RegisteredCode {
+    text_start: VirtualAddress,
+    trap_data: Range<VirtualAddress>,
+    stack_map_data: Range<VirtualAddress>,
+}
+
+impl RegisteredCode {
+    /// Returns the stack map for the safepoint at `pc`, if it has live GC references.
+    fn stack_map(&self, pc: VirtualAddress) -> Option<StackMap<'_>> {
+        if self.stack_map_data.is_empty() {
+            return None;
+        }
+
+        let offset = u32::try_from(pc.sub_addr(self.text_start)).ok()?;
+        let stack_map_data = unsafe {
+            slice::from_raw_parts(
+                self.stack_map_data.start.as_raw() as *const u8,
+                self.stack_map_data.size(),
+            )
+        };
+
+        stack_map_for_offset(stack_map_data, offset)
+    }
+}
+
+/// State of an active call into WASM, linked to the previous activation to support reentrancy.
+struct Activation {
+    /// The kernel stack pointer at entry into WASM, the spilled registers are stored there.
+    ///
+    /// This is the authoritative copy, the one in the `VMContext` is writable by the guest.
+    entry_sp: Cell<usize>,
+    /// The trap that unwound this activation, if any.
+    trap: Cell<Option<Trap>>,
+    /// Where to resume the guest once the call into the kernel currently in progress returns.
+    upcall: Cell<Option<UserContext>>,
+    /// The guest's frame pointer at the call into the kernel currently in progress, where walking
+    /// the WASM frames starts.
+    upcall_fp: Cell<usize>,
+    /// The guard region below the stack WASM executes on.
+    stack_guard: Range<VirtualAddress>,
+    prev: *const Activation,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UserContext {
+    pc: usize,
+    sp: usize,
+}
+
+/// Registers the text section, trap data and stack maps of published code, so traps raised by it
+/// can be recognized by [`handle_user_exception`] and its frames walked by
+/// [`visit_wasm_gc_roots`].
+pub fn register_code(
+    text: Range<VirtualAddress>,
+    trap_data: Range<VirtualAddress>,
+    stack_map_data: Range<VirtualAddress>,
+) {
+    let prev = CODE_REGISTRY.write().insert(
+        text.end,
+        RegisteredCode {
+            text_start: text.start,
+            trap_data,
+            stack_map_data,
+        },
+    );
+    debug_assert!(prev.is_none(), "code registered twice");
+}
+
+/// Removes code previously registered with [`register_code`].
+pub fn unregister_code(text: Range<VirtualAddress>) {
+    let prev = CODE_REGISTRY.write().remove(&text.end);
+    debug_assert!(prev.is_some(), "code wasn't registered");
+}
+
+/// Looks up the trap code for a faulting `pc`, returns `None` if `pc` isn't a trap site of any
+/// published code.
+fn lookup_trap_code(pc: VirtualAddress) -> Option<Trap> {
+    // Taking the lock might fail if we trapped while registering code, in which case the fault
+    // can't have been caused by WASM.
+    let registry = CODE_REGISTRY.try_read()?;
+    let code = lookup_code(&registry, pc)?;
+
+    let offset = u32::try_from(pc.sub_addr(code.text_start)).ok()?;
+    let trap_data = unsafe {
+        slice::from_raw_parts(
+            code.trap_data.start.as_raw() as *const u8,
+            code.trap_data.size(),
+        )
+    };
+
+    trap_for_offset(trap_data, offset)
+}
+
+/// Returns the published code containing `pc`.
+fn lookup_code(
+    registry: &BTreeMap<VirtualAddress, RegisteredCode>,
+    pc: VirtualAddress,
+) -> Option<&RegisteredCode> {
+    let (_, code) = registry
+        .range((Bound::Excluded(pc), Bound::Unbounded))
+        .next()?;
+
+    (pc >= code.text_start).then_some(code)
+}
+
+/// Calls `f` with each stack slot holding a live GC reference in the WASM frames of all
+/// activations on this hart.
+///
+/// WASM frames can only be walked while the kernel executes a call from WASM, the frames of the
+/// innermost activation are therefore only visited if this is called from e.g. a host function.
+///
+/// # Safety
+///
+/// Must not be called while a guest's stack is in use by anything but WASM, the addresses of the
+/// stack slots are only valid while the address space of the store is active.
+pub unsafe fn visit_wasm_gc_roots(mut f: impl FnMut(NonNull<u32>)) {
+    let registry = CODE_REGISTRY.read();
+
+    let mut activation = ACTIVATION.with(Cell::get);
+    while let Some(current) = activation.as_ref() {
+        activation = current.prev;
+
+        let Some(upcall) = current.upcall.get() else {
+            continue;
+        };
+
+        let mut pc = VirtualAddress::new(upcall.pc);
+        let mut fp = current.upcall_fp.get();
+        // Cranelift preserves frame pointers, each frame saves the return address and frame
+        // pointer of its caller right at its frame pointer. The walk ends at the first frame that
+        // wasn't called from WASM, i.e. the trampoline called by `enter_wasm`.
+        while let Some(code) = lookup_code(&registry, pc) {
+            if let Some(stack_map) = code.stack_map(pc) {
+                let sp = fp - stack_map.frame_size() as usize;
+                for slot in stack_map.slots() {
+                    f(NonNull::new_unchecked((sp + slot as usize) as *mut u32));
+                }
+            }
+
+            pc = VirtualAddress::new(*(fp as *const usize).add(1));
+            fp = *(fp as *const usize);
+        }
+    }
+}
+
+/// Called by the kernel trap handler for synchronous exceptions raised in user mode, i.e. by
+/// WASM.
+///
+/// `regs` are the general purpose registers of the guest and may be modified. Returns the
+/// address execution should resume at in *supervisor mode*, or `None` if there is no active call
+/// into WASM, in which case the guest must not be resumed.
+pub fn handle_user_exception(
+    regs: &mut [usize; 32],
+    exception: Exception,
+    pc: VirtualAddress,
+    fault_addr: VirtualAddress,
+) -> Option<usize> {
+    let activation = ACTIVATION.with(Cell::get);
+    if activation.is_null() {
+        return None;
+    }
+    // Safety: activations are only linked while their `catch_traps` call is on the stack
+    let activation = unsafe { &*activation };
+
+    let pc = pc.as_raw();
+    if pc == wasm_exit as usize {
+        return Some(wasm_exit_trampoline as usize);
+    }
+
+    if VMBuiltinFunctionsArray::is_builtin(pc) || pc == host_func_native_call as usize {
+        log::trace!("guest called into the kernel at {pc:#x}");
+        activation.upcall.set(Some(UserContext {
+            pc: regs[REG_RA],
+            sp: regs[REG_SP],
+        }));
+        activation.upcall_fp.set(regs[REG_FP]);
+
+        regs[REG_T0] = activation.entry_sp.get();
+        regs[REG_T1] = pc;
+        return Some(upcall_trampoline as usize);
+    }
+
+    let is_page_fault = matches!(
+        exception,
+        Exception::LoadPageFault | Exception::StorePageFault
+    );
+
+    // Functions check the stack limit in their prologue, but that doesn't catch e.g. calls from a
+    // function without a stack frame
+    let trap = if is_page_fault && activation.stack_guard.contains(&fault_addr) {
+        Trap::StackOverflow
+    } else if let Some(trap) = lookup_trap_code(VirtualAddress::new(pc)) {
+        trap
+    } else {
+        // Compiled code only faults at trap sites, so this is either a bug in the compiler or code
+        // that isn't ours. Either way only the guest is affected.
+        log::warn!("unexpected {exception:?} in WASM at pc {pc:#x} (fault address {fault_addr:?})");
+        match exception {
+            Exception::LoadMisaligned | Exception::StoreMisaligned => Trap::HeapMisaligned,
+            _ => Trap::UnexpectedException,
+        }
+    };
+    log::trace!("WASM trap {trap:?} at pc {pc:#x} (fault address {fault_addr:?})");
+    activation.trap.set(Some(trap));
+
+    Some(wasm_trap_trampoline as usize)
+}
+
+/// Raises a trap from native code called by WASM, e.g. a builtin function, unwinding back to the
+/// innermost [`catch_traps`].
+///
+/// # Panics
+///
+/// Panics if there is no active call into WASM.
+///
+/// # Safety
+///
+/// All frames between the caller and the WASM entry are discarded without running their
+/// destructors, so they must not hold any resources (e.g. `RefCell` borrows).
+pub unsafe fn raise_trap(trap: Trap) -> ! {
+    let activation = ACTIVATION.with(Cell::get);
+    assert!(!activation.is_null(), "raised trap {trap} outside of WASM");
+
+    (*activation).trap.set(Some(trap));
+    leave_wasm((*activation).entry_sp.get(), true)
+}
+
+/// Calls the function referenced by `func_ref`, catching any traps raised while it executes.
+///
+/// WASM executes in user mode on `stack`, the address space it runs in must be active.
+///
+/// # Safety
+///
+/// `values` must be large enough to hold both the parameters and results of the function and the
+/// parameters must be of the correct types. `values` must be accessible from user mode.
+pub unsafe fn catch_traps(
+    func_ref: &VMFuncRef,
+    values: &mut [VMVal],
+    stack: &Stack,
+) -> Result<(), Trap> {
+    // Host functions can't raise traps themselves, so there is nothing to catch. Note that both
+    // `VMContext`s and `VMHostFuncContext`s start with their magic value.
+    if *func_ref.vmctx.cast::<u32>() != VMCONTEXT_MAGIC {
+        // When called from native code the callee is also its own caller
+        (func_ref.native_call)(
+            func_ref.vmctx,
+            func_ref.vmctx,
+            values.as_mut_ptr(),
+            values.len(),
+        );
+        return Ok(());
+    }
+
+    let prev = ACTIVATION.with(Cell::get);
+    // If WASM called into the kernel which now calls back into WASM the stack is still in use
+    let stack_top = prev
+        .as_ref()
+        .and_then(|prev| prev.upcall.get())
+        .map_or(stack.top().as_raw(), |upcall| upcall.sp & !0xf);
+
+    let vmctx_entry_sp = last_wasm_entry_sp(NonNull::new_unchecked(func_ref.vmctx));
+    // Calls into the same instance might be nested, so restore the outer entry afterward
+    let prev_entry_sp = *vmctx_entry_sp;
+
+    let activation = Activation {
+        entry_sp: Cell::new(0),
+        trap: Cell::new(None),
+        upcall: Cell::new(None),
+        upcall_fp: Cell::new(0),
+        stack_guard: stack.guard_range(),
+        prev,
+    };
+    ACTIVATION.with(|current| current.set(&activation));
+
+    let trapped = enter_wasm(
+        activation.entry_sp.as_ptr(),
+        func_ref.native_call,
+        func_ref.vmctx,
+        func_ref.vmctx,
+        values.as_mut_ptr(),
+        values.len(),
+        stack_top,
+        vmctx_entry_sp,
+    );
+
+    ACTIVATION.with(|current| current.set(activation.prev));
+    *vmctx_entry_sp = prev_entry_sp;
+
+    if trapped {
+        Err(activation
+            .trap
+            .take()
+            .expect("WASM was unwound without a trap"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns a pointer to the `last_wasm_entry_sp` field of the given `VMContext`.
+unsafe fn last_wasm_entry_sp(vmctx: NonNull<VMContext>) -> *mut usize {
+    let instance = InstanceData::from_vmctx(vmctx).borrow();
+    let offset = usize::try_from(instance.vmctx_plan.vmctx_last_wasm_entry_sp()).unwrap();
+
+    vmctx.as_ptr().byte_add(offset).cast()
+}
+
+/// The return address of the guest's entry function.
+///
+/// This is never actually executed, the guest faults when returning here since the kernel's
+/// text isn't accessible from user mode.
+extern "C" fn wasm_exit() {
+    unreachable!("wasm_exit must only be used as a return address")
+}
+
+/// The trap handler resumes execution here after the guest returned from its entry function.
+extern "C" fn wasm_exit_trampoline() -> ! {
+    let activation = ACTIVATION.with(Cell::get);
+    // Safety: `handle_user_exception` checked there is an activation
+    unsafe { leave_wasm((*activation).entry_sp.get(), false) }
+}
+
+/// The trap handler resumes execution here after a WASM exception, still on the guest's stack.
+extern "C" fn wasm_trap_trampoline() -> ! {
+    let activation = ACTIVATION.with(Cell::get);
+    // Safety: `handle_user_exception` checked there is an activation
+    unsafe { leave_wasm((*activation).entry_sp.get(), true) }
+}
+
+/// Returns where to resume the guest after a call into the kernel.
+extern "C" fn upcall_return() -> UserContext {
+    let activation = ACTIVATION.with(Cell::get);
+    // Safety: upcalls only happen while an activation is linked
+    unsafe { (*activation).upcall.take() }.expect("not in an upcall")
+}
+
+/// Spills all callee-saved registers onto the stack, records the resulting stack pointer in both
+/// `entry_sp` and `vmctx_entry_sp` and calls `native_call` in user mode on the stack ending at
+/// `stack_top`.
+///
+/// Returns `false` if the call returned normally and `true` if it was unwound by a trap, in
+/// both cases through [`leave_wasm`].
+#[allow(clippy::too_many_arguments)]
+#[naked]
+unsafe extern "C" fn enter_wasm(
+    entry_sp: *mut usize,
+    native_call: VMNativeCallFunction,
+    callee_vmctx: *mut VMContext,
+    caller_vmctx: *mut VMContext,
+    values: *mut VMVal,
+    values_len: usize,
+    stack_top: usize,
+    vmctx_entry_sp: *mut usize,
+) -> bool {
+    asm! {
+    "add sp, sp, -0xD0",
+
+    "
+        sd ra, 0x00(sp)
+        sd s0, 0x08(sp)
+        sd s1, 0x10(sp)
+        sd s2, 0x18(sp)
+        sd s3, 0x20(sp)
+        sd s4, 0x28(sp)
+        sd s5, 0x30(sp)
+        sd s6, 0x38(sp)
+        sd s7, 0x40(sp)
+        sd s8, 0x48(sp)
+        sd s9, 0x50(sp)
+        sd s10, 0x58(sp)
+        sd s11, 0x60(sp)
+        ",
+
+    "
+        fsd fs0, 0x68(sp)
+        fsd fs1, 0x70(sp)
+        fsd fs2, 0x78(sp)
+        fsd fs3, 0x80(sp)
+        fsd fs4, 0x88(sp)
+        fsd fs5, 0x90(sp)
+        fsd fs6, 0x98(sp)
+        fsd fs7, 0xA0(sp)
+        fsd fs8, 0xA8(sp)
+        fsd fs9, 0xB0(sp)
+        fsd fs10, 0xB8(sp)
+        fsd fs11, 0xC0(sp)
+        ",
+
+    "sd sp, 0(a0)",
+    "sd sp, 0(a7)",
+
+    // no interrupts until we're in user mode, they would clobber sepc
+    "csrci sstatus, 0x2",
+    "csrw sepc, a1",
+    "li t1, 0x100",
+    "csrc sstatus, t1", // SPP = User
+    "li t1, 0x20",
+    "csrs sstatus, t1", // SPIE = 1
+
+    // shift the arguments into place for `native_call`
+    "
+        mv t0, a6
+        mv a0, a2
+        mv a1, a3
+        mv a2, a4
+        mv a3, a5
+        ",
+    "la ra, {wasm_exit}",
+    "mv sp, t0",
+
+    // don't leak kernel state into the guest, everything but the arguments, `ra` and `sp` is
+    // cleared
+    "
+        li gp, 0
+        li tp, 0
+        li t0, 0
+        li t1, 0
+        li t2, 0
+        li s0, 0
+        li s1, 0
+        li a4, 0
+        li a5, 0
+        li a6, 0
+        li a7, 0
+        li s2, 0
+        li s3, 0
+        li s4, 0
+        li s5, 0
+        li s6, 0
+        li s7, 0
+        li s8, 0
+        li s9, 0
+        li s10, 0
+        li s11, 0
+        li t3, 0
+        li t4, 0
+        li t5, 0
+        li t6, 0
+        ",
+    "
+        fmv.d.x ft0, zero
+        fmv.d.x ft1, zero
+        fmv.d.x ft2, zero
+        fmv.d.x ft3, zero
+        fmv.d.x ft4, zero
+        fmv.d.x ft5, zero
+        fmv.d.x ft6, zero
+        fmv.d.x ft7, zero
+        fmv.d.x fs0, zero
+        fmv.d.x fs1, zero
+        fmv.d.x fa0, zero
+        fmv.d.x fa1, zero
+        fmv.d.x fa2, zero
+        fmv.d.x fa3, zero
+        fmv.d.x fa4, zero
+        fmv.d.x fa5, zero
+        fmv.d.x fa6, zero
+        fmv.d.x fa7, zero
+        fmv.d.x fs2, zero
+        fmv.d.x fs3, zero
+        fmv.d.x fs4, zero
+        fmv.d.x fs5, zero
+        fmv.d.x fs6, zero
+        fmv.d.x fs7, zero
+        fmv.d.x fs8, zero
+        fmv.d.x fs9, zero
+        fmv.d.x fs10, zero
+        fmv.d.x fs11, zero
+        fmv.d.x ft8, zero
+        fmv.d.x ft9, zero
+        fmv.d.x ft10, zero
+        fmv.d.x ft11, zero
+        ",
+
+    "sret",
+
+    wasm_exit = sym wasm_exit,
+    options(noreturn)
+    }
+}
+
+/// Unwinds to the [`enter_wasm`] call that recorded `entry_sp`, restoring the spilled registers
+/// and returning `trapped` from it.
+#[naked]
+unsafe extern "C" fn leave_wasm(entry_sp: usize, trapped: bool) -> ! {
+    asm! {
+    "mv sp, a0",
+
+    "
+        ld ra, 0x00(sp)
+        ld s0, 0x08(sp)
+        ld s1, 0x10(sp)
+        ld s2, 0x18(sp)
+        ld s3, 0x20(sp)
+        ld s4, 0x28(sp)
+        ld s5, 0x30(sp)
+        ld s6, 0x38(sp)
+        ld s7, 0x40(sp)
+        ld s8, 0x48(sp)
+        ld s9, 0x50(sp)
+        ld s10, 0x58(sp)
+        ld s11, 0x60(sp)
+        ",
+
+    "
+        fld fs0, 0x68(sp)
+        fld fs1, 0x70(sp)
+        fld fs2, 0x78(sp)
+        fld fs3, 0x80(sp)
+        fld fs4, 0x88(sp)
+        fld fs5, 0x90(sp)
+        fld fs6, 0x98(sp)
+        fld fs7, 0xA0(sp)
+        fld fs8, 0xA8(sp)
+        fld fs9, 0xB0(sp)
+        fld fs10, 0xB8(sp)
+        fld fs11, 0xC0(sp)
+        ",
+
+    "add sp, sp, 0xD0",
+    "mv a0, a1",
+    "ret",
+    options(noreturn)
+    }
+}
+
+/// The trap handler resumes execution here when the guest called into the kernel, with `t0`
+/// holding the kernel stack pointer, `t1` the called function and the argument registers as set
+/// up by the guest.
+///
+/// Calls the function on the kernel stack and returns its results to the guest. The callee-saved
+/// registers are preserved by the function itself, all other registers but the return values are
+/// cleared so no kernel state leaks into the guest.
+#[naked]
+unsafe extern "C" fn upcall_trampoline() -> ! {
+    asm! {
+    "mv sp, t0",
+    "jalr t1",
+
+    "add sp, sp, -0x10",
+    "sd a0, 0x00(sp)",
+    "sd a1, 0x08(sp)",
+    "call {upcall_return}", // guest pc in a0, guest sp in a1
+
+    // no interrupts until we're back in user mode, they would clobber sepc
+    "csrci sstatus, 0x2",
+    "csrw sepc, a0",
+    "mv t0, a1",
+    "ld a0, 0x00(sp)",
+    "ld a1, 0x08(sp)",
+    "li t1, 0x100",
+    "csrc sstatus, t1", // SPP = User
+    "li t1, 0x20",
+    "csrs sstatus, t1", // SPIE = 1
+
+    "mv sp, t0",
+
+    "
+        li gp, 0
+        li tp, 0
+        li t0, 0
+        li t1, 0
+        li t2, 0
+        li a2, 0
+        li a3, 0
+        li a4, 0
+        li a5, 0
+        li a6, 0
+        li a7, 0
+        li t3, 0
+        li t4, 0
+        li t5, 0
+        li t6, 0
+        ",
+    "
+        fmv.d.x ft0, zero
+        fmv.d.x ft1, zero
+        fmv.d.x ft2, zero
+        fmv.d.x ft3, zero
+        fmv.d.x ft4, zero
+        fmv.d.x ft5, zero
+        fmv.d.x ft6, zero
+        fmv.d.x ft7, zero
+        fmv.d.x fa2, zero
+        fmv.d.x fa3, zero
+        fmv.d.x fa4, zero
+        fmv.d.x fa5, zero
+        fmv.d.x fa6, zero
+        fmv.d.x fa7, zero
+        fmv.d.x ft8, zero
+        fmv.d.x ft9, zero
+        fmv.d.x ft10, zero
+        fmv.d.x ft11, zero
+        ",
+
+    "sret",
+
+    upcall_return = sym upcall_return,
+    options(noreturn)
+    }
+}
+
+/// Calls the raw machine `code` in user mode in place of `func`, catching any traps it raises.
+///
+/// Compiled WASM only faults at trap sites, this is how tests exercise exceptions raised anywhere
+/// else.
+#[cfg(test)]
+pub(crate) fn call_raw_code(
+    store: &mut crate::runtime::Store,
+    func: &crate::runtime::Func,
+    code: &[u32],
+) -> Result<(), Trap> {
+    use crate::frame_alloc::with_frame_alloc;
+    use crate::kconfig;
+    use crate::runtime::guest_memory::{AlignedVec, GuestAllocator};
+    use kmm::{EntryFlags, Flush, Mapper};
+
+    fn set_flags(alloc: &GuestAllocator, range: Range<VirtualAddress>, flags: EntryFlags) {
+        with_frame_alloc(|frame_alloc| {
+            let mut mapper: Mapper<kconfig::MEMORY_MODE> =
+                Mapper::from_address(alloc.asid(), alloc.root_table(), frame_alloc);
+            let mut flush = Flush::empty(alloc.asid());
+            mapper
+                .set_flags_for_range(range, flags, &mut flush)
+                .unwrap();
+            flush.flush().unwrap();
+        });
+    }
+
+    store.activate();
+    let alloc = store.guest_allocator();
+
+    // the code gets a page of its own, so it can be made executable
+    let mut text = AlignedVec::<u8, { kconfig::PAGE_SIZE }>::try_with_capacity(
+        kconfig::PAGE_SIZE,
+        alloc.clone(),
+    )
+    .unwrap();
+    for insn in code {
+        text.try_extend_from_slice(&insn.to_le_bytes()).unwrap();
+    }
+    let start = VirtualAddress::new(text.as_ptr() as usize);
+    let range = start..start.add(kconfig::PAGE_SIZE);
+
+    set_flags(
+        &alloc,
+        range.clone(),
+        EntryFlags::READ | EntryFlags::EXECUTE | EntryFlags::USER,
+    );
+    unsafe { asm!("fence.i") };
+
+    let func_ref = unsafe { func.vm_func_ref().as_ref() };
+    let raw_func_ref = VMFuncRef {
+        native_call: unsafe {
+            core::mem::transmute::<*const u8, VMNativeCallFunction>(text.as_ptr())
+        },
+        wasm_call: func_ref.wasm_call,
+        type_index: func_ref.type_index,
+        vmctx: func_ref.vmctx,
+    };
+    let result = unsafe { catch_traps(&raw_func_ref, &mut [], store.stack()) };
+
+    // the allocator keeps its bookkeeping in freed memory, so the page must be writable again
+    set_flags(
+        &alloc,
+        range,
+        EntryFlags::READ | EntryFlags::WRITE | EntryFlags::USER,
+    );
+
+    result
+}
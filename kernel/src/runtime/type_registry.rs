@@ -0,0 +1,50 @@
+//! The kernel-wide registry of types.
+//!
+//! Types are only unique within the module declaring them, but funcrefs and GC objects flow
+//! freely between instances through imported tables and exports. To check the type of a funcref
+//! in `call_indirect` every type is therefore assigned a canonical [`VMSharedTypeIndex`], which
+//! is stored in each `VMFuncRef` and in the header of each GC object. Each instance records the
+//! canonical indices of its own types in its `VMContext` so compiled code can compare the two.
+//!
+//! Types are compared structurally, references to other types inside struct and array types
+//! are still module-relative though, so such types are only canonical within their module.
+//!
+//! Types are never unregistered, there is only a handful of distinct types in practice.
+
+use crate::runtime::vmcontext::VMSharedTypeIndex;
+use alloc::vec::Vec;
+use cranelift_entity::EntityRef;
+use cranelift_wasm::WasmSubType;
+use sync::RwLock;
+
+static REGISTRY: RwLock<Vec<WasmSubType>> = RwLock::new(Vec::new());
+
+/// Returns the canonical index of the given type, registering it if necessary.
+pub fn register(ty: &WasmSubType) -> VMSharedTypeIndex {
+    if let Some(index) = lookup_index(&REGISTRY.read(), ty) {
+        return index;
+    }
+
+    let mut types = REGISTRY.write();
+    // another hart might have registered the type in the meantime
+    lookup_index(&types, ty).unwrap_or_else(|| {
+        types.push(ty.clone());
+        VMSharedTypeIndex::new(types.len() - 1)
+    })
+}
+
+/// Returns the type with the given canonical index.
+///
+/// # Panics
+///
+/// Panics if `index` has not been returned by [`register`].
+pub fn lookup(index: VMSharedTypeIndex) -> WasmSubType {
+    REGISTRY.read()[index.index()].clone()
+}
+
+fn lookup_index(types: &[WasmSubType], ty: &WasmSubType) -> Option<VMSharedTypeIndex> {
+    types
+        .iter()
+        .position(|registered| registered == ty)
+        .map(VMSharedTypeIndex::new)
+}
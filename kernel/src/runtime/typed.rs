@@ -0,0 +1,155 @@
+//! Conversions between Rust types and WASM values.
+//!
+//! These traits are used to give host functions (see [`Linker::func_wrap`]) statically typed
+//! signatures.
+//!
+//! [`Linker::func_wrap`]: crate::runtime::Linker::func_wrap
+
+use crate::runtime::gc::AnyRef;
+use crate::runtime::vmcontext::VMVal;
+use alloc::boxed::Box;
+use cranelift_wasm::{WasmHeapType, WasmRefType, WasmValType};
+
+/// A Rust type that maps directly to a WASM value type.
+pub trait WasmTy: Copy + Send + Sync + 'static {
+    /// The WASM value type corresponding to this Rust type.
+    fn valtype() -> WasmValType;
+    /// Reads a value of this type from the given `VMVal`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure the `VMVal` actually holds a value of this type.
+    unsafe fn load(val: &VMVal) -> Self;
+    /// Writes this value into the given `VMVal`.
+    fn store(self, val: &mut VMVal);
+}
+
+macro_rules! impl_wasm_ty {
+    ($($ty:ty => $valtype:ident, $field:ident, |$load:ident| $load_expr:expr, |$store:ident| $store_expr:expr;)*) => {
+        $(
+            #[allow(clippy::cast_sign_loss, clippy::cast_possible_wrap)]
+            impl WasmTy for $ty {
+                fn valtype() -> WasmValType {
+                    WasmValType::$valtype
+                }
+                unsafe fn load(val: &VMVal) -> Self {
+                    let $load = val.$field;
+                    $load_expr
+                }
+                fn store(self, val: &mut VMVal) {
+                    let $store = self;
+                    *val = VMVal { $field: $store_expr };
+                }
+            }
+        )*
+    };
+}
+
+impl_wasm_ty! {
+    i32 => I32, i32, |v| v, |v| v;
+    u32 => I32, i32, |v| v as u32, |v| v as i32;
+    i64 => I64, i64, |v| v, |v| v;
+    u64 => I64, i64, |v| v as u64, |v| v as i64;
+    f32 => F32, f32, |v| f32::from_bits(v), |v| v.to_bits();
+    f64 => F64, f64, |v| f64::from_bits(v), |v| v.to_bits();
+    u128 => V128, v128, |v| u128::from_le_bytes(v), |v| v.to_le_bytes();
+}
+
+impl WasmTy for Option<AnyRef> {
+    fn valtype() -> WasmValType {
+        WasmValType::Ref(WasmRefType {
+            nullable: true,
+            heap_type: WasmHeapType::Any,
+        })
+    }
+    unsafe fn load(val: &VMVal) -> Self {
+        AnyRef::from_raw(val.anyref)
+    }
+    fn store(self, val: &mut VMVal) {
+        *val = VMVal {
+            anyref: self.map_or(0, AnyRef::as_raw),
+        };
+    }
+}
+
+/// A list of [`WasmTy`]s used as the parameters of a function.
+///
+/// This is implemented for single [`WasmTy`]s and tuples of them.
+pub trait WasmParams: Send {
+    /// The WASM value types of the list.
+    fn valtypes() -> Box<[WasmValType]>;
+    /// Reads the values of this list from consecutive `VMVal`s.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure the `VMVal`s actually hold values of the corresponding types.
+    unsafe fn load(values: &[VMVal]) -> Self;
+    /// Writes the values of this list into consecutive `VMVal`s.
+    fn store(self, values: &mut [VMVal]);
+}
+
+/// A list of [`WasmTy`]s used as the results of a function.
+pub trait WasmResults: WasmParams {}
+
+impl<T: WasmParams> WasmResults for T {}
+
+impl<T: WasmTy> WasmParams for T {
+    fn valtypes() -> Box<[WasmValType]> {
+        Box::new([T::valtype()])
+    }
+
+    unsafe fn load(values: &[VMVal]) -> Self {
+        <T as WasmTy>::load(&values[0])
+    }
+
+    fn store(self, values: &mut [VMVal]) {
+        WasmTy::store(self, &mut values[0]);
+    }
+}
+
+/// Invokes the given macro once for every supported arity of function parameters.
+macro_rules! for_each_function_signature {
+    ($mac:ident) => {
+        $mac!();
+        $mac!(A1);
+        $mac!(A1 A2);
+        $mac!(A1 A2 A3);
+        $mac!(A1 A2 A3 A4);
+        $mac!(A1 A2 A3 A4 A5);
+        $mac!(A1 A2 A3 A4 A5 A6);
+        $mac!(A1 A2 A3 A4 A5 A6 A7);
+        $mac!(A1 A2 A3 A4 A5 A6 A7 A8);
+    };
+}
+pub(crate) use for_each_function_signature;
+
+macro_rules! impl_wasm_params {
+    ($($t:ident)*) => {
+        #[allow(non_snake_case, unused_variables, unused_mut, unused_assignments)]
+        impl<$($t: WasmTy,)*> WasmParams for ($($t,)*) {
+            fn valtypes() -> Box<[WasmValType]> {
+                Box::new([$($t::valtype()),*])
+            }
+
+            unsafe fn load(values: &[VMVal]) -> Self {
+                let mut i = 0;
+                $(
+                    let $t = <$t as WasmTy>::load(&values[i]);
+                    i += 1;
+                )*
+                ($($t,)*)
+            }
+
+            fn store(self, values: &mut [VMVal]) {
+                let ($($t,)*) = self;
+                let mut i = 0;
+                $(
+                    WasmTy::store($t, &mut values[i]);
+                    i += 1;
+                )*
+            }
+        }
+    };
+}
+
+for_each_function_signature!(impl_wasm_params);
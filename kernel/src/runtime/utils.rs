@@ -23,6 +23,23 @@ pub fn reference_type(wasm_ht: WasmHeapType, pointer_type: Type) -> Type {
     }
 }
 
+/// Returns whether values of the provided heap type may point into the GC heap.
+///
+/// Such values must be reported in stack maps so the collector finds them, `externref`s are opaque
+/// host values and `i31ref`s carry their value inline, so neither is managed by the collector.
+pub fn is_gc_heap_type(wasm_ht: WasmHeapType) -> bool {
+    wasm_ht.top() == WasmHeapTopType::Any && !matches!(wasm_ht, WasmHeapType::I31)
+}
+
+/// Returns whether values of the provided type may point into the GC heap, see
+/// [`is_gc_heap_type`].
+pub fn is_gc_heap_ref(ty: WasmValType) -> bool {
+    match ty {
+        WasmValType::Ref(rt) => is_gc_heap_type(rt.heap_type),
+        _ => false,
+    }
+}
+
 fn blank_sig(isa: &dyn TargetIsa, call_conv: CallConv) -> Signature {
     let pointer_type = isa.pointer_type();
     let mut sig = Signature::new(call_conv);
@@ -30,12 +47,17 @@ fn blank_sig(isa: &dyn TargetIsa, call_conv: CallConv) -> Signature {
     // Add the caller/callee `vmctx` parameters.
     sig.params
         .push(AbiParam::special(pointer_type, ArgumentPurpose::VMContext));
+    sig.params.push(AbiParam::new(pointer_type));
 
     sig
 }
 
+/// Returns the signature of functions following the WASM calling convention.
+///
+/// WASM functions use Cranelift's `tail` calling convention so `return_call` instructions can be
+/// compiled to proper tail calls, this includes trampolines called from or calling into WASM.
 pub fn wasm_call_signature(target_isa: &dyn TargetIsa, wasm_func_ty: &WasmFuncType) -> Signature {
-    let mut sig = blank_sig(target_isa, CallConv::Fast);
+    let mut sig = blank_sig(target_isa, CallConv::Tail);
 
     let cvt = |ty: &WasmValType| AbiParam::new(value_type(target_isa, *ty));
     sig.params.extend(wasm_func_ty.params().iter().map(&cvt));
@@ -44,13 +66,18 @@ pub fn wasm_call_signature(target_isa: &dyn TargetIsa, wasm_func_ty: &WasmFuncTy
     sig
 }
 
-#[allow(unused)]
-pub fn native_call_signature(target_isa: &dyn TargetIsa, wasm_func_ty: &WasmFuncType) -> Signature {
+/// Returns the signature of functions following the "native" calling convention
+/// (see [`VMNativeCallFunction`](crate::runtime::vmcontext::VMNativeCallFunction)).
+///
+/// Native functions take the callee and caller `vmctx` followed by a pointer to and the length of
+/// a `VMVal` array holding the arguments and, upon return, the results.
+pub fn native_call_signature(target_isa: &dyn TargetIsa) -> Signature {
     let mut sig = blank_sig(target_isa, CallConv::triple_default(target_isa.triple()));
 
-    let cvt = |ty: &WasmValType| AbiParam::new(value_type(target_isa, *ty));
-    sig.params.extend(wasm_func_ty.params().iter().map(&cvt));
-    sig.returns.extend(wasm_func_ty.returns().iter().map(&cvt));
+    // Add the `values_vec` and `values_len` parameters.
+    let pointer_type = target_isa.pointer_type();
+    sig.params.push(AbiParam::new(pointer_type));
+    sig.params.push(AbiParam::new(pointer_type));
 
     sig
 }
@@ -0,0 +1,215 @@
+use crate::runtime::func::Func;
+use crate::runtime::gc::AnyRef;
+use crate::runtime::vmcontext::VMVal;
+use core::num::NonZeroU32;
+use core::ptr::{self, NonNull};
+use cranelift_wasm::{WasmHeapTopType, WasmHeapType, WasmRefType, WasmValType};
+
+/// A dynamically typed WASM value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Val {
+    I32(i32),
+    I64(i64),
+    /// A 32-bit float, stored as its raw bits so NaN payloads are preserved.
+    F32(u32),
+    /// A 64-bit float, stored as its raw bits so NaN payloads are preserved.
+    F64(u64),
+    V128(u128),
+    /// A `funcref`, `None` is the null reference.
+    FuncRef(Option<Func>),
+    /// An `externref`, `None` is the null reference.
+    ///
+    /// Externrefs are opaque to WASM, the runtime doesn't attach any meaning to their value.
+    ExternRef(Option<NonZeroU32>),
+    /// A reference of the `any` hierarchy, i.e. an `i31ref`, struct or array, `None` is the null
+    /// reference.
+    AnyRef(Option<AnyRef>),
+}
+
+impl Val {
+    /// Returns the WASM type of this value.
+    pub fn ty(&self) -> WasmValType {
+        match self {
+            Val::I32(_) => WasmValType::I32,
+            Val::I64(_) => WasmValType::I64,
+            Val::F32(_) => WasmValType::F32,
+            Val::F64(_) => WasmValType::F64,
+            Val::V128(_) => WasmValType::V128,
+            Val::FuncRef(_) => WasmValType::Ref(WasmRefType::FUNCREF),
+            Val::ExternRef(_) => WasmValType::Ref(WasmRefType::EXTERNREF),
+            Val::AnyRef(_) => WasmValType::Ref(WasmRefType {
+                nullable: true,
+                heap_type: WasmHeapType::Any,
+            }),
+        }
+    }
+
+    /// Returns whether this value can be used where a value of type `ty` is expected.
+    ///
+    /// The host doesn't track the static type of GC references, so `anyref`s match all reference
+    /// types of the `any` hierarchy.
+    pub fn matches_ty(&self, ty: WasmValType) -> bool {
+        match (self, ty) {
+            (Val::AnyRef(val), WasmValType::Ref(ty)) => {
+                ty.heap_type.top() == WasmHeapTopType::Any && (val.is_some() || ty.nullable)
+            }
+            _ => self.ty() == ty,
+        }
+    }
+
+    pub fn as_vmval(&self) -> VMVal {
+        match self {
+            Val::I32(v) => VMVal { i32: *v },
+            Val::I64(v) => VMVal { i64: *v },
+            Val::F32(v) => VMVal { f32: *v },
+            Val::F64(v) => VMVal { f64: *v },
+            Val::V128(v) => VMVal {
+                v128: v.to_le_bytes(),
+            },
+            Val::FuncRef(func) => VMVal {
+                funcref: func
+                    .as_ref()
+                    .map_or(ptr::null_mut(), |func| func.vm_func_ref().as_ptr().cast()),
+            },
+            Val::ExternRef(v) => VMVal {
+                externref: v.map_or(0, NonZeroU32::get),
+            },
+            Val::AnyRef(v) => VMVal {
+                anyref: v.map_or(0, AnyRef::as_raw),
+            },
+        }
+    }
+
+    /// Reads a value of type `ty` from the given `VMVal`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure the `VMVal` actually holds a value of type `ty`.
+    pub unsafe fn from_vmval(val: &VMVal, ty: WasmValType) -> Self {
+        match ty {
+            WasmValType::I32 => Val::I32(val.i32),
+            WasmValType::I64 => Val::I64(val.i64),
+            WasmValType::F32 => Val::F32(val.f32),
+            WasmValType::F64 => Val::F64(val.f64),
+            WasmValType::V128 => Val::V128(u128::from_le_bytes(val.v128)),
+            WasmValType::Ref(ty) => match ty.heap_type.top() {
+                WasmHeapTopType::Func => Val::FuncRef(
+                    NonNull::new(val.funcref.cast())
+                        .map(|func_ref| Func::from_vm_func_ref(func_ref)),
+                ),
+                WasmHeapTopType::Extern => Val::ExternRef(NonZeroU32::new(val.externref)),
+                WasmHeapTopType::Any => Val::AnyRef(AnyRef::from_raw(val.anyref)),
+            },
+        }
+    }
+
+    pub fn i32(&self) -> Option<i32> {
+        match self {
+            Val::I32(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn i64(&self) -> Option<i64> {
+        match self {
+            Val::I64(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn f32(&self) -> Option<f32> {
+        match self {
+            Val::F32(v) => Some(f32::from_bits(*v)),
+            _ => None,
+        }
+    }
+
+    pub fn f64(&self) -> Option<f64> {
+        match self {
+            Val::F64(v) => Some(f64::from_bits(*v)),
+            _ => None,
+        }
+    }
+
+    pub fn v128(&self) -> Option<u128> {
+        match self {
+            Val::V128(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn funcref(&self) -> Option<Option<&Func>> {
+        match self {
+            Val::FuncRef(v) => Some(v.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn externref(&self) -> Option<Option<NonZeroU32>> {
+        match self {
+            Val::ExternRef(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn anyref(&self) -> Option<Option<AnyRef>> {
+        match self {
+            Val::AnyRef(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+impl From<i32> for Val {
+    fn from(v: i32) -> Self {
+        Val::I32(v)
+    }
+}
+
+impl From<i64> for Val {
+    fn from(v: i64) -> Self {
+        Val::I64(v)
+    }
+}
+
+impl From<f32> for Val {
+    fn from(v: f32) -> Self {
+        Val::F32(v.to_bits())
+    }
+}
+
+impl From<f64> for Val {
+    fn from(v: f64) -> Self {
+        Val::F64(v.to_bits())
+    }
+}
+
+impl From<u128> for Val {
+    fn from(v: u128) -> Self {
+        Val::V128(v)
+    }
+}
+
+impl From<Option<Func>> for Val {
+    fn from(v: Option<Func>) -> Self {
+        Val::FuncRef(v)
+    }
+}
+
+impl From<Func> for Val {
+    fn from(v: Func) -> Self {
+        Val::FuncRef(Some(v))
+    }
+}
+
+impl From<Option<AnyRef>> for Val {
+    fn from(v: Option<AnyRef>) -> Self {
+        Val::AnyRef(v)
+    }
+}
+
+impl From<AnyRef> for Val {
+    fn from(v: AnyRef) -> Self {
+        Val::AnyRef(Some(v))
+    }
+}
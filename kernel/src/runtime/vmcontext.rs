@@ -1,6 +1,9 @@
 //! struct VMContext {
 //!     magic: usize,
+//!     instance: *const RefCell<InstanceData>,
 //!     builtins: *mut VMBuiltinFunctionsArray,
+//!     fuel: *mut u64,
+//!     epoch: *mut VMEpoch,
 //!     tables: [VMTableDefinition; module.num_defined_tables],
 //!     memories: [*mut VMMemoryDefinition; module.num_defined_memories],
 //!     owned_memories: [VMMemoryDefinition; module.num_owned_memories],
@@ -14,10 +17,12 @@
 //!     last_wasm_exit_fp: usize,
 //!     last_wasm_exit_pc: usize,
 //!     last_wasm_entry_sp: usize,
+//!     type_ids: [VMSharedTypeIndex; module.types.len()],
 //! }
 #![allow(clippy::cast_possible_truncation)] // All offsets are smaller than `u32::MAX`
 
 use crate::runtime::translate::TranslatedModule;
+use alloc::boxed::Box;
 use alloc::fmt;
 use core::ffi::c_void;
 use core::fmt::Formatter;
@@ -25,17 +30,28 @@ use core::marker::PhantomPinned;
 use core::mem;
 use core::mem::offset_of;
 use core::ptr::NonNull;
-use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use cranelift_codegen::isa::TargetIsa;
 use cranelift_entity::entity_impl;
 use cranelift_entity::packed_option::ReservedValue;
 use cranelift_wasm::{
     DefinedGlobalIndex, DefinedMemoryIndex, DefinedTableIndex, FuncIndex, GlobalIndex, MemoryIndex,
-    OwnedMemoryIndex, TableIndex, WasmValType,
+    OwnedMemoryIndex, TableIndex, TypeIndex, WasmHeapTopType, WasmValType,
 };
 
 pub const VMCONTEXT_MAGIC: u32 = u32::from_le_bytes(*b"vmcx");
 
+/// The offset of the `instance` field in the `VMContext`.
+///
+/// This is the same for all modules so that builtin functions, which only receive a raw
+/// `*mut VMContext`, can find their way back to the owning `InstanceData`.
+pub const VMCONTEXT_INSTANCE_OFFSET: usize = mem::size_of::<usize>();
+
+/// The tag of `i31ref`s, which carry their value inline in the upper 31 bits of a GC reference
+/// instead of pointing into the GC heap.
+pub const VMGCREF_I31_TAG: u32 = 1;
+
+#[repr(C)]
 pub union VMVal {
     pub i32: i32,
     pub i64: i64,
@@ -59,6 +75,47 @@ pub struct VMContext {
     _m: PhantomPinned,
 }
 
+/// A placeholder byte-sized type which is just used to provide some amount of type
+/// safety when dealing with pointers to JIT-compiled function bodies.
+#[repr(C)]
+pub struct VMFunctionBody(u8);
+
+/// The signature of functions following the "native" calling convention.
+///
+/// Arguments and results are passed through an array of `VMVal`s that must be large enough to
+/// hold both the parameters and the results of the function. This is how host functions are
+/// invoked from WASM (through a WASM to native trampoline).
+pub type VMNativeCallFunction = unsafe extern "C" fn(
+    callee_vmctx: *mut VMContext,
+    caller_vmctx: *mut VMContext,
+    values: *mut VMVal,
+    values_len: usize,
+);
+
+pub const VMHOSTFUNC_MAGIC: u32 = u32::from_le_bytes(*b"host");
+
+/// The context passed to host functions in place of a `VMContext`.
+///
+/// WASM calls imported functions with the `vmctx` recorded in the corresponding `VMFunctionImport`,
+/// for host functions this is a pointer to this structure instead.
+#[repr(C)]
+pub struct VMHostFuncContext {
+    /// Always [`VMHOSTFUNC_MAGIC`], placed first so it overlaps the magic of a `VMContext`.
+    pub magic: u32,
+    /// The native entrypoint of the host function, this is loaded by WASM to native trampolines.
+    pub native_call: VMNativeCallFunction,
+    pub func: Box<dyn Fn(NonNull<VMContext>, &mut [VMVal]) + Send + Sync>,
+}
+
+impl fmt::Debug for VMHostFuncContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VMHostFuncContext")
+            .field("magic", &self.magic)
+            .field("native_call", &(self.native_call as *const ()))
+            .finish_non_exhaustive()
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct VMTableDefinition {
@@ -75,6 +132,27 @@ pub struct VMMemoryDefinition {
     pub asid: usize,
 }
 
+impl VMMemoryDefinition {
+    /// Returns the current length of this memory in bytes.
+    pub fn current_length(&self) -> usize {
+        self.current_length.load(Ordering::Relaxed)
+    }
+}
+
+/// Epoch interruption state shared by all instances of a store.
+#[derive(Debug)]
+#[repr(C)]
+pub struct VMEpoch {
+    /// A copy of the global epoch, which is derived from the timebase WASM can't read. It is kept
+    /// up to date while the store executes WASM.
+    pub current: AtomicU64,
+    /// WASM calls the `new_epoch` builtin once `current` reaches this epoch.
+    pub deadline: u64,
+    /// How many epochs to extend the deadline by when it is reached, or `None` to trap with
+    /// [`Trap::Interrupt`](crate::runtime::Trap::Interrupt) instead.
+    pub extend_delta: Option<u64>,
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct VMGlobalDefinition {
@@ -102,7 +180,14 @@ impl VMGlobalDefinition {
                 f64: *self.as_f64_bits(),
             },
             WasmValType::V128 => VMVal { v128: self.data },
-            WasmValType::Ref(_) => todo!(),
+            WasmValType::Ref(ty) => match ty.heap_type.top() {
+                WasmHeapTopType::Func => VMVal {
+                    funcref: *self.as_func_ref(),
+                },
+                WasmHeapTopType::Extern | WasmHeapTopType::Any => VMVal {
+                    externref: *self.as_u32(),
+                },
+            },
         }
     }
 
@@ -196,6 +281,11 @@ impl VMGlobalDefinition {
         &mut *(self.data.as_mut().as_mut_ptr().cast::<u128>())
     }
 
+    /// Return a reference to the value as a funcref.
+    pub unsafe fn as_func_ref(&self) -> &*mut c_void {
+        &*(self.data.as_ref().as_ptr().cast::<*mut c_void>())
+    }
+
     /// Return a reference to the value as u128 bits.
     pub unsafe fn as_u128_bits(&self) -> &[u8; 16] {
         &*(self.data.as_ref().as_ptr().cast::<[u8; 16]>())
@@ -212,17 +302,37 @@ impl VMGlobalDefinition {
 pub struct FuncRefIndex(u32);
 entity_impl!(FuncRefIndex);
 
+/// A kernel-wide canonical index of a function type, see
+/// [`type_registry`](crate::runtime::type_registry).
+///
+/// Two functions have the same type if and only if their `VMSharedTypeIndex`es are equal, which
+/// is what `call_indirect` checks.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+#[repr(transparent)]
+pub struct VMSharedTypeIndex(u32);
+entity_impl!(VMSharedTypeIndex);
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct VMFuncRef {
-    // pub type_index: VMSharedTypeIndex,
+    /// Function pointer for this funcref if being called via the native calling convention.
+    pub native_call: VMNativeCallFunction,
+    /// Function pointer for this funcref if being called via the WASM calling convention.
+    pub wasm_call: *const VMFunctionBody,
+    /// The type of the function.
+    pub type_index: VMSharedTypeIndex,
     pub vmctx: *mut VMContext,
 }
 
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct VMFunctionImport {
-    pub from: *mut VMFuncRef,
+    /// Function pointer to use when calling this imported function from WASM.
+    pub wasm_call: *const VMFunctionBody,
+    /// Function pointer to use when calling this imported function from native code.
+    pub native_call: VMNativeCallFunction,
+    /// The `VMContext` of the defining instance or, for host functions, a pointer to the
+    /// functions `VMHostFuncContext`.
     pub vmctx: NonNull<VMContext>,
 }
 
@@ -257,13 +367,17 @@ pub struct VMContextPlan {
     num_owned_memories: u32,
     num_defined_globals: u32,
     num_escaped_funcs: u32,
+    num_types: u32,
     /// target ISA pointer size in bytes
     ptr_size: u32,
     size: u32,
 
     // offsets
     magic: u32,
-    // builtins: u32,
+    instance: u32,
+    builtins: u32,
+    fuel: u32,
+    epoch: u32,
     tables: u32,
     memories: u32,
     owned_memories: u32,
@@ -277,6 +391,7 @@ pub struct VMContextPlan {
     last_wasm_exit_fp: u32,
     last_wasm_exit_pc: u32,
     last_wasm_entry_sp: u32,
+    type_ids: u32,
 }
 
 impl VMContextPlan {
@@ -291,7 +406,7 @@ impl VMContextPlan {
 
         let ptr_size = u32::from(isa.pointer_bytes());
 
-        Self {
+        let plan = Self {
             num_imported_funcs: module.num_imported_functions(),
             num_imported_tables: module.num_imported_tables(),
             num_imported_memories: module.num_imported_memories(),
@@ -301,11 +416,15 @@ impl VMContextPlan {
             num_owned_memories: module.num_owned_memories(),
             num_defined_globals: module.num_defined_globals(),
             num_escaped_funcs: module.num_escaped_funcs(),
+            num_types: module.num_types(),
             ptr_size,
 
             // offsets
             magic: member_offset(ptr_size),
-            // builtins: member_offset(ptr_size),
+            instance: member_offset(ptr_size),
+            builtins: member_offset(ptr_size),
+            fuel: member_offset(ptr_size),
+            epoch: member_offset(ptr_size),
             tables: member_offset(size_of_u32::<VMTableDefinition>() * module.num_defined_tables()),
             memories: member_offset(ptr_size * module.num_defined_memories()),
             owned_memories: member_offset(
@@ -331,9 +450,14 @@ impl VMContextPlan {
             last_wasm_exit_fp: member_offset(ptr_size),
             last_wasm_exit_pc: member_offset(ptr_size),
             last_wasm_entry_sp: member_offset(ptr_size),
+            // placed last since its size isn't a multiple of the pointer size
+            type_ids: member_offset(size_of_u32::<VMSharedTypeIndex>() * module.num_types()),
 
             size: offset,
-        }
+        };
+
+        debug_assert_eq!(plan.instance as usize, VMCONTEXT_INSTANCE_OFFSET);
+        plan
     }
 
     #[inline]
@@ -362,6 +486,10 @@ impl VMContextPlan {
         self.num_escaped_funcs
     }
     #[inline]
+    pub fn num_types(&self) -> u32 {
+        self.num_types
+    }
+    #[inline]
     pub fn num_imported_funcs(&self) -> u32 {
         self.num_imported_funcs
     }
@@ -383,6 +511,22 @@ impl VMContextPlan {
         self.magic
     }
     #[inline]
+    pub fn vmctx_instance(&self) -> u32 {
+        self.instance
+    }
+    #[inline]
+    pub fn vmctx_builtin_functions(&self) -> u32 {
+        self.builtins
+    }
+    #[inline]
+    pub fn vmctx_fuel(&self) -> u32 {
+        self.fuel
+    }
+    #[inline]
+    pub fn vmctx_epoch(&self) -> u32 {
+        self.epoch
+    }
+    #[inline]
     pub fn vmctx_stack_limit(&self) -> u32 {
         self.stack_limit
     }
@@ -445,6 +589,15 @@ impl VMContextPlan {
         self.func_refs + index.as_u32() * size_of_u32::<VMFuncRef>()
     }
     #[inline]
+    pub fn vmctx_type_ids_start(&self) -> u32 {
+        self.type_ids
+    }
+    #[inline]
+    pub fn vmctx_type_id(&self, index: TypeIndex) -> u32 {
+        assert!(index.as_u32() < self.num_types);
+        self.type_ids + index.as_u32() * size_of_u32::<VMSharedTypeIndex>()
+    }
+    #[inline]
     pub fn vmctx_function_imports_start(&self) -> u32 {
         self.imported_functions
     }
@@ -454,6 +607,14 @@ impl VMContextPlan {
         self.imported_functions + index.as_u32() * size_of_u32::<VMFunctionImport>()
     }
     #[inline]
+    pub fn vmctx_function_import_wasm_call(&self, index: FuncIndex) -> u32 {
+        self.vmctx_function_import(index) + offset_of!(VMFunctionImport, wasm_call) as u32
+    }
+    #[inline]
+    pub fn vmctx_function_import_vmctx(&self, index: FuncIndex) -> u32 {
+        self.vmctx_function_import(index) + offset_of!(VMFunctionImport, vmctx) as u32
+    }
+    #[inline]
     pub fn vmctx_table_imports_start(&self) -> u32 {
         self.imported_tables
     }
@@ -463,6 +624,10 @@ impl VMContextPlan {
         self.imported_tables + index.as_u32() * size_of_u32::<VMTableImport>()
     }
     #[inline]
+    pub fn vmctx_table_import_from(&self, index: TableIndex) -> u32 {
+        self.vmctx_table_import(index) + offset_of!(VMTableImport, from) as u32
+    }
+    #[inline]
     pub fn vmctx_memory_imports_start(&self) -> u32 {
         self.imported_memories
     }
@@ -472,6 +637,14 @@ impl VMContextPlan {
         self.imported_memories + index.as_u32() * size_of_u32::<VMMemoryImport>()
     }
     #[inline]
+    pub fn vmctx_memory_import_from(&self, index: MemoryIndex) -> u32 {
+        self.vmctx_memory_import(index) + offset_of!(VMMemoryImport, from) as u32
+    }
+    #[inline]
+    pub fn vmctx_global_import_from(&self, index: GlobalIndex) -> u32 {
+        self.vmctx_global_import(index) + offset_of!(VMGlobalImport, from) as u32
+    }
+    #[inline]
     pub fn vmctx_global_imports_start(&self) -> u32 {
         self.imported_globals
     }
@@ -508,6 +681,41 @@ impl VMContextPlan {
     pub fn vmctx_memory_definition_current_length(&self, index: OwnedMemoryIndex) -> u32 {
         self.vmctx_memory_definition(index) + offset_of!(VMMemoryDefinition, current_length) as u32
     }
+    /// Return the offset to the `current` field in `VMEpoch`.
+    #[inline]
+    #[allow(clippy::unused_self)]
+    pub fn vmepoch_current(&self) -> u32 {
+        offset_of!(VMEpoch, current) as u32
+    }
+    /// Return the offset to the `deadline` field in `VMEpoch`.
+    #[inline]
+    #[allow(clippy::unused_self)]
+    pub fn vmepoch_deadline(&self) -> u32 {
+        offset_of!(VMEpoch, deadline) as u32
+    }
+    /// Return the offset to the `wasm_call` field in `VMFuncRef`.
+    #[inline]
+    #[allow(clippy::unused_self)]
+    pub fn vm_func_ref_wasm_call(&self) -> u32 {
+        offset_of!(VMFuncRef, wasm_call) as u32
+    }
+    /// Return the offset to the `type_index` field in `VMFuncRef`.
+    #[inline]
+    #[allow(clippy::unused_self)]
+    pub fn vm_func_ref_type_index(&self) -> u32 {
+        offset_of!(VMFuncRef, type_index) as u32
+    }
+    /// Return the offset to the `vmctx` field in `VMFuncRef`.
+    #[inline]
+    #[allow(clippy::unused_self)]
+    pub fn vm_func_ref_vmctx(&self) -> u32 {
+        offset_of!(VMFuncRef, vmctx) as u32
+    }
+    /// Return the offset to the `current_length` field in `VMTableDefinition` index `index`.
+    #[inline]
+    pub fn vmctx_table_definition_current_length(&self, index: DefinedTableIndex) -> u32 {
+        self.vmctx_table_definition(index) + offset_of!(VMTableDefinition, current_length) as u32
+    }
 }
 
 /// # Panics
@@ -48,7 +48,7 @@ fn start(hartid: usize, boot_info: &'static loader_api::BootInfo) -> ! {
 
 fn pre_init_hart(hartid: usize) {
     semihosting_logger::hartid::set(hartid);
-    // setup trap handler
+    arch::trap_handler::init();
 }
 
 fn init(boot_info: &'static loader_api::BootInfo) {
@@ -93,7 +93,7 @@ fn init(boot_info: &'static loader_api::BootInfo) {
 }
 
 fn post_init_hart() {
-    // enable interrupts
+    arch::finish_processor_init();
 }
 
 fn unmap_loader(
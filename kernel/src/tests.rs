@@ -1,53 +1,1396 @@
+#[cfg(test)]
+mod wast_runner;
+
 #[cfg(test)]
 pub mod compile_tests {
-    use crate::runtime::{Engine, Linker, Module, Store};
-    use cranelift_codegen::settings::Configurable;
-    use kmm::VirtualAddress;
-    use wast::WastDirective;
+    use crate::runtime::{
+        compile_module, AnyRef, CompileError, Config, Engine, GcError, LinkError, Linker, Module,
+        Store, Trap, Val,
+    };
+    use crate::{arch, kconfig};
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use cranelift_wasm::wasmparser::WasmFeatures;
+    use cranelift_wasm::{WasmFieldType, WasmStorageType, WasmStructType, WasmValType};
 
-    fn build_and_run_wasm(wasm: &[u8], physmem_off: VirtualAddress) {
-        let isa_builder = cranelift_codegen::isa::lookup(target_lexicon::HOST).unwrap();
-        let mut b = cranelift_codegen::settings::builder();
-        b.set("opt_level", "speed_and_size").unwrap();
+    fn build_engine() -> Engine {
+        Engine::new(&Config::default()).unwrap()
+    }
 
-        let target_isa = isa_builder
-            .finish(cranelift_codegen::settings::Flags::new(b))
-            .unwrap();
+    fn build_gc_engine() -> Engine {
+        let mut config = Config::default();
+        config.wasm_gc(true).wasm_function_references(true);
+        Engine::new(&config).unwrap()
+    }
 
-        let engine = Engine::new(target_isa);
+    fn wat_to_wasm(wat: &str) -> Vec<u8> {
+        use wast::parser::{self, ParseBuffer};
 
-        let mut store = Store::new(0, physmem_off);
+        let buf = ParseBuffer::new(wat).unwrap();
+        let mut wat = parser::parse::<wast::Wat>(&buf).unwrap();
+        wat.encode().unwrap()
+    }
+
+    fn build_and_run_wasm(wasm: &[u8]) {
+        let engine = build_engine();
+
+        let mut store = Store::new(&engine).unwrap();
 
-        let module = Module::from_binary(&engine, &store, wasm);
+        let module = Module::from_binary(&engine, &store, wasm).unwrap();
         log::debug!("{module:#?}");
 
-        let linker = Linker::new();
-        let instance = linker.instantiate(&mut store, &module);
+        let mut linker = Linker::new();
+        // the host functions expected by modules compiled with porffor
+        linker.func_wrap("", "p", |_: i32| {}).unwrap();
+        linker.func_wrap("", "c", |_: i32| {}).unwrap();
+        linker.func_wrap("", "t", || 0_i32).unwrap();
+        linker.func_wrap("", "u", || 0_i32).unwrap();
+        linker.func_wrap("", "y", |_: i32| {}).unwrap();
+        linker.func_wrap("", "z", |_: i32| {}).unwrap();
+
+        let instance = linker.instantiate(&mut store, &module).unwrap();
         instance.debug_print_vmctx(&store);
+
+        // All fixtures export a `fib` function whose first parameter is `n` and whose first
+        // result is the nth Fibonacci number. Additional parameters are zeroed.
+        if let Some(fib) = instance.get_func(&mut store, "fib") {
+            let params: Vec<_> = fib
+                .ty()
+                .params()
+                .iter()
+                .enumerate()
+                .map(|(i, _)| Val::I32(if i == 0 { 10 } else { 0 }))
+                .collect();
+            let mut results = vec![Val::I32(0); fib.ty().returns().len()];
+
+            fib.call(&mut store, &params, &mut results).unwrap();
+            assert_eq!(results[0], Val::I32(55));
+        }
     }
 
-    fn build_and_run_wast(wast: &str, physmem_off: VirtualAddress) {
-        use wast::{
-            parser::{self, ParseBuffer},
-            Wast,
+    fn build_and_run_wast(filename: &str, wast: &str) {
+        let engine = build_engine();
+
+        if let Err(err) = super::wast_runner::run_wast(&engine, filename, wast) {
+            panic!("{err}");
+        }
+    }
+
+    #[ktest::test]
+    fn link_host_func(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm =
+            wat_to_wasm(r#"(module (import "env" "add" (func (param i32 i32) (result i32))))"#);
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+
+        let mut linker = Linker::new();
+        linker
+            .func_wrap("env", "add", |a: i32, b: i32| a + b)
+            .unwrap();
+
+        assert!(linker.instantiate(&mut store, &module).is_ok());
+    }
+
+    #[ktest::test]
+    fn typed_func_call(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (func (export "add") (param i32 i32) (result i32)
+                    local.get 0
+                    local.get 1
+                    i32.add)
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+
+        let add = instance
+            .get_typed_func::<(i32, i32), i32>(&mut store, "add")
+            .unwrap();
+        assert_eq!(add.call(&mut store, (2, 3)).unwrap(), 5);
+
+        // mismatched signatures must be rejected
+        assert!(instance
+            .get_typed_func::<(i64, i64), i64>(&mut store, "add")
+            .is_none());
+    }
+
+    #[ktest::test]
+    fn module_serialize_roundtrip(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (import "env" "double" (func $double (param i32) (result i32)))
+                (func (export "quad") (param i32) (result i32)
+                    (call $double (call $double (local.get 0))))
+            )"#,
+        );
+        let artifact = {
+            let store = Store::new(&engine).unwrap();
+            let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+            module.serialize(&store)
         };
 
-        let buf = ParseBuffer::new(wast).unwrap();
-        let module = parser::parse::<Wast>(&buf).unwrap();
-        for dir in module.directives {
-            if let WastDirective::Wat(mut wat) = dir {
-                let wasm = wat.encode().unwrap();
-                build_and_run_wasm(&wasm, physmem_off);
-            }
+        let mut store = Store::new(&engine).unwrap();
+        let module = Module::deserialize(&engine, &store, &artifact).unwrap();
+
+        let mut linker = Linker::new();
+        linker.func_wrap("env", "double", |x: i32| x * 2).unwrap();
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+
+        let quad = instance
+            .get_typed_func::<i32, i32>(&mut store, "quad")
+            .unwrap();
+        assert_eq!(quad.call(&mut store, 3).unwrap(), 12);
+
+        // artifacts can only be loaded by engines with the same configuration
+        let mut config = Config::default();
+        config.static_memory_bound(0x1000_0000);
+        let other_engine = Engine::new(&config).unwrap();
+        assert!(matches!(
+            Module::deserialize(&other_engine, &store, &artifact),
+            Err(CompileError::IncompatibleArtifact(_))
+        ));
+        assert!(matches!(
+            Module::deserialize(&engine, &store, &wasm),
+            Err(CompileError::MalformedArtifact(_))
+        ));
+    }
+
+    #[ktest::test]
+    fn compile_is_deterministic(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (import "env" "log" (func $log (param i32)))
+                (func $a (export "a") (param i32) (result i32)
+                    (i32.mul (local.get 0) (i32.const 3)))
+                (func $b (export "b") (param i32) (result i32)
+                    (call $a (i32.add (local.get 0) (i32.const 1))))
+                (func (export "c") (param i32)
+                    (call $log (call $b (local.get 0))))
+            )"#,
+        );
+
+        let mut artifact = Vec::new();
+        compile_module(&engine, &wasm, &mut artifact).unwrap();
+
+        for _ in 0..4 {
+            let mut other = Vec::new();
+            compile_module(&engine, &wasm, &mut other).unwrap();
+            assert_eq!(artifact, other);
         }
     }
 
+    #[ktest::test]
+    fn compile_pool_spreads_jobs(_boot_info: &'static loader_api::BootInfo) {
+        use crate::runtime::{help_compile, run_compile_jobs, CompileJob};
+        use alloc::boxed::Box;
+
+        // Every job first joins the batch like an idle hart would, so the remaining jobs are run
+        // by nested workers even when there is only a single hart.
+        let jobs = (0..16)
+            .map(|i| -> CompileJob<'_, usize> {
+                Box::new(move || {
+                    help_compile();
+                    i * i
+                })
+            })
+            .collect();
+
+        let results = run_compile_jobs(jobs);
+
+        assert_eq!(results, (0..16).map(|i| i * i).collect::<Vec<_>>());
+    }
+
+    #[ktest::test]
+    fn parallel_compile_matches_sequential(_boot_info: &'static loader_api::BootInfo) {
+        use alloc::format;
+        use alloc::string::String;
+
+        let parallel = build_engine();
+        let mut config = Config::default();
+        config.parallel_compilation(false);
+        let sequential = Engine::new(&config).unwrap();
+
+        // enough functions to keep all harts busy
+        let funcs: String = (0..64)
+            .map(|i| {
+                format!(
+                    "(func (export \"f{i}\") (param i32) (result i32)
+                        (i32.add (i32.mul (local.get 0) (i32.const {i})) (call $base)))"
+                )
+            })
+            .collect();
+        let wasm = wat_to_wasm(&format!(
+            "(module (func $base (result i32) (i32.const 7)) {funcs})"
+        ));
+
+        let mut expected = Vec::new();
+        compile_module(&sequential, &wasm, &mut expected).unwrap();
+
+        for _ in 0..4 {
+            let mut actual = Vec::new();
+            compile_module(&parallel, &wasm, &mut actual).unwrap();
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[ktest::test]
+    fn memory_grow_and_size(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (memory 1 3)
+                (func (export "grow") (param i32) (result i32)
+                    local.get 0
+                    memory.grow)
+                (func (export "size") (result i32)
+                    memory.size)
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+
+        let grow = instance
+            .get_typed_func::<i32, i32>(&mut store, "grow")
+            .unwrap();
+        let size = instance
+            .get_typed_func::<(), i32>(&mut store, "size")
+            .unwrap();
+
+        assert_eq!(size.call(&mut store, ()).unwrap(), 1);
+        assert_eq!(grow.call(&mut store, 1).unwrap(), 1);
+        assert_eq!(size.call(&mut store, ()).unwrap(), 2);
+        // growing beyond the maximum must fail and leave the memory untouched
+        assert_eq!(grow.call(&mut store, 2).unwrap(), -1);
+        assert_eq!(size.call(&mut store, ()).unwrap(), 2);
+    }
+
+    #[ktest::test]
+    fn memory_grow_lazy(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        // 1 GiB is far more than we have physical memory for, only touched pages get mapped
+        let wasm = wat_to_wasm(
+            r#"(module
+                (memory 1)
+                (func (export "run") (result i32)
+                    (drop (memory.grow (i32.const 0x3fff)))
+                    (i32.store (i32.const 0x3ffffffc) (i32.const 42))
+                    (i32.add
+                        (i32.load (i32.const 0x3ffffffc))
+                        (i32.load (i32.const 0x20000000))))
+                (func (export "size") (result i32)
+                    memory.size)
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+
+        let run = instance
+            .get_typed_func::<(), i32>(&mut store, "run")
+            .unwrap();
+        let size = instance
+            .get_typed_func::<(), i32>(&mut store, "size")
+            .unwrap();
+
+        assert_eq!(run.call(&mut store, ()), Ok(42));
+        assert_eq!(size.call(&mut store, ()), Ok(0x4000));
+    }
+
+    #[ktest::test]
+    fn memory_guard_pages(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        // accesses aren't bounds checked, they hit unmapped pages instead
+        let wasm = wat_to_wasm(
+            r#"(module
+                (memory 1 2)
+                (func (export "load") (param i32) (result i32)
+                    (i32.load (local.get 0)))
+                (func (export "grow") (result i32)
+                    (memory.grow (i32.const 1)))
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+
+        let load = instance
+            .get_typed_func::<i32, i32>(&mut store, "load")
+            .unwrap();
+        let grow = instance
+            .get_typed_func::<(), i32>(&mut store, "grow")
+            .unwrap();
+
+        assert_eq!(load.call(&mut store, 0xfffc), Ok(0));
+        assert_eq!(load.call(&mut store, 0x10000), Err(Trap::MemoryOutOfBounds));
+        assert_eq!(load.call(&mut store, -4), Err(Trap::MemoryOutOfBounds));
+
+        // the newly accessible page must be mapped
+        assert_eq!(grow.call(&mut store, ()), Ok(1));
+        assert_eq!(load.call(&mut store, 0x10000), Ok(0));
+        assert_eq!(load.call(&mut store, 0x20000), Err(Trap::MemoryOutOfBounds));
+    }
+
+    #[ktest::test]
+    fn memory_exact_bounds(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        // the 32-bit memory is static and relies on guard pages, the 64-bit one is dynamic and
+        // bounds checked
+        let wasm = wat_to_wasm(
+            r#"(module
+                (memory $m32 1)
+                (memory $m64 i64 1)
+                (func (export "load8_32") (param i32) (result i32)
+                    (i32.load8_u $m32 (local.get 0)))
+                (func (export "load_32") (param i32) (result i32)
+                    (i32.load $m32 (local.get 0)))
+                (func (export "load8_64") (param i64) (result i32)
+                    (i32.load8_u $m64 (local.get 0)))
+                (func (export "load_64") (param i64) (result i32)
+                    (i32.load $m64 (local.get 0)))
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+
+        let load8_32 = instance
+            .get_typed_func::<i32, i32>(&mut store, "load8_32")
+            .unwrap();
+        let load_32 = instance
+            .get_typed_func::<i32, i32>(&mut store, "load_32")
+            .unwrap();
+        let load8_64 = instance
+            .get_typed_func::<i64, i32>(&mut store, "load8_64")
+            .unwrap();
+        let load_64 = instance
+            .get_typed_func::<i64, i32>(&mut store, "load_64")
+            .unwrap();
+
+        let len = 0x10000;
+        assert_eq!(load8_32.call(&mut store, len - 1), Ok(0));
+        assert_eq!(load8_32.call(&mut store, len), Err(Trap::MemoryOutOfBounds));
+        assert_eq!(load_32.call(&mut store, len - 4), Ok(0));
+        assert_eq!(
+            load_32.call(&mut store, len - 3),
+            Err(Trap::MemoryOutOfBounds)
+        );
+
+        let len = i64::from(len);
+        assert_eq!(load8_64.call(&mut store, len - 1), Ok(0));
+        assert_eq!(load8_64.call(&mut store, len), Err(Trap::MemoryOutOfBounds));
+        assert_eq!(load_64.call(&mut store, len - 4), Ok(0));
+        assert_eq!(
+            load_64.call(&mut store, len - 3),
+            Err(Trap::MemoryOutOfBounds)
+        );
+    }
+
+    #[ktest::test]
+    fn store_asids(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+
+        // every store gets its own address space, ASID 0 belongs to the kernel
+        let a = Store::new(&engine).unwrap();
+        let b = Store::new(&engine).unwrap();
+        let asid_a = a.guest_allocator().asid();
+        let asid_b = b.guest_allocator().asid();
+        assert_ne!(asid_a, 0);
+        assert_ne!(asid_b, 0);
+        assert_ne!(asid_a, asid_b);
+
+        // ASIDs are freed along with their store
+        drop(b);
+        let c = Store::new(&engine).unwrap();
+        assert_eq!(c.guest_allocator().asid(), asid_b);
+    }
+
+    #[ktest::test]
+    fn bulk_memory(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (memory 1)
+                (data "\01\02\03\04")
+                (func (export "run") (result i32)
+                    ;; [0..4] = 0xaa
+                    (memory.fill (i32.const 0) (i32.const 0xaa) (i32.const 4))
+                    ;; [1..3] = 0x02 0x03
+                    (memory.init 0 (i32.const 1) (i32.const 1) (i32.const 2))
+                    data.drop 0
+                    ;; [4..8] = [0..4]
+                    (memory.copy (i32.const 4) (i32.const 0) (i32.const 4))
+                    (i32.load (i32.const 4)))
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+
+        let run = instance
+            .get_typed_func::<(), i32>(&mut store, "run")
+            .unwrap();
+        assert_eq!(run.call(&mut store, ()).unwrap(), 0xaa03_02aa_u32 as i32);
+    }
+
+    #[ktest::test]
+    fn unreachable_trap(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        // `unreachable` is compiled to an illegal instruction, so this goes through the trap handler
+        let wasm = wat_to_wasm(
+            r#"(module
+                (func (export "run") (param i32) (result i32)
+                    local.get 0
+                    if
+                        unreachable
+                    end
+                    i32.const 42)
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+
+        let run = instance
+            .get_typed_func::<i32, i32>(&mut store, "run")
+            .unwrap();
+        assert_eq!(run.call(&mut store, 1), Err(Trap::UnreachableCodeReached));
+        // the hart must have fully recovered from the trap
+        assert_eq!(run.call(&mut store, 0), Ok(42));
+    }
+
+    #[ktest::test]
+    fn builtin_trap(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        // the out-of-bounds check happens inside the `memory_fill` builtin, not in guest code
+        let wasm = wat_to_wasm(
+            r#"(module
+                (memory 1)
+                (func (export "fill") (param i32)
+                    (memory.fill (local.get 0) (i32.const 0) (i32.const 0x10)))
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+
+        let fill = instance
+            .get_typed_func::<i32, ()>(&mut store, "fill")
+            .unwrap();
+        assert_eq!(fill.call(&mut store, 0x10000), Err(Trap::MemoryOutOfBounds));
+        assert_eq!(fill.call(&mut store, 0), Ok(()));
+    }
+
+    #[ktest::test]
+    fn link_unknown_import(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(r#"(module (import "env" "missing" (func)))"#);
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+
+        let linker = Linker::new();
+        assert!(matches!(
+            linker.instantiate(&mut store, &module),
+            Err(LinkError::UnknownImport { .. })
+        ));
+    }
+
+    #[ktest::test]
+    fn link_incompatible_import(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(r#"(module (import "env" "f" (func (param i64))))"#);
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+
+        let mut linker = Linker::new();
+        linker.func_wrap("env", "f", |_: i32| {}).unwrap();
+
+        assert!(matches!(
+            linker.instantiate(&mut store, &module),
+            Err(LinkError::IncompatibleImportType { .. })
+        ));
+    }
+
+    #[ktest::test]
+    fn link_grown_memory_and_table(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (memory (export "mem") 1 4)
+                (table (export "table") 1 4 funcref)
+                (func (export "grow")
+                    (drop (memory.grow (i32.const 2)))
+                    (drop (table.grow (ref.null func) (i32.const 2))))
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let exporter = Linker::new().instantiate(&mut store, &module).unwrap();
+
+        let mut linker = Linker::new();
+        linker.instance(&mut store, "env", exporter).unwrap();
+
+        // imports are checked against the current size of the definition, not its declared minimum
+        let importer = wat_to_wasm(
+            r#"(module (import "env" "mem" (memory 3 4)) (import "env" "table" (table 3 4 funcref)))"#,
+        );
+        let importer = Module::from_binary(&engine, &store, &importer).unwrap();
+        assert!(matches!(
+            linker.instantiate(&mut store, &importer),
+            Err(LinkError::IncompatibleImportType { .. })
+        ));
+
+        let grow = exporter
+            .get_typed_func::<(), ()>(&mut store, "grow")
+            .unwrap();
+        grow.call(&mut store, ()).unwrap();
+        linker.instantiate(&mut store, &importer).unwrap();
+    }
+
+    #[ktest::test]
+    fn disabled_feature_rejected(_boot_info: &'static loader_api::BootInfo) {
+        let mut config = Config::default();
+        config.wasm_multi_memory(false);
+        let engine = Engine::new(&config).unwrap();
+        let store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(r#"(module (memory 1) (memory 1))"#);
+        assert!(matches!(
+            Module::from_binary(&engine, &store, &wasm),
+            Err(CompileError::Translate(_))
+        ));
+
+        // typed function references are disabled by default
+        let engine = build_engine();
+        let wasm = wat_to_wasm(r#"(module (type $t (func)) (func (param (ref $t))))"#);
+        assert!(matches!(
+            Module::from_binary(&engine, &store, &wasm),
+            Err(CompileError::Translate(_))
+        ));
+    }
+
+    #[ktest::test]
+    fn exception_tags_rejected(_boot_info: &'static loader_api::BootInfo) {
+        let mut config = Config::default();
+        config.wasm_feature(WasmFeatures::EXCEPTIONS, true);
+        let engine = Engine::new(&config).unwrap();
+        let store = Store::new(&engine).unwrap();
+
+        let defined = wat_to_wasm(r#"(module (tag $e (param i32)) (export "e" (tag $e)))"#);
+        let imported = wat_to_wasm(r#"(module (import "env" "e" (tag (param i32))))"#);
+        for wasm in [defined, imported] {
+            assert!(matches!(
+                Module::from_binary(&engine, &store, &wasm),
+                Err(CompileError::Translate(_))
+            ));
+        }
+    }
+
+    #[ktest::test]
+    fn fuel_consumption(_boot_info: &'static loader_api::BootInfo) {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).unwrap();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (func (export "count") (param i32)
+                    (loop $l
+                        (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+                        (br_if $l (local.get 0))))
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+        let count = instance
+            .get_typed_func::<i32, ()>(&mut store, "count")
+            .unwrap();
+
+        // stores start out without fuel
+        assert_eq!(store.get_fuel(), Some(0));
+        assert_eq!(count.call(&mut store, 10), Err(Trap::OutOfFuel));
+
+        // one unit for the call and one for each loop iteration
+        store.set_fuel(100);
+        assert_eq!(count.call(&mut store, 10), Ok(()));
+        assert_eq!(store.get_fuel(), Some(89));
+
+        store.set_fuel(5);
+        assert_eq!(count.call(&mut store, 10), Err(Trap::OutOfFuel));
+        assert_eq!(store.get_fuel(), Some(0));
+
+        // without fuel consumption there is no fuel to query
+        let engine = build_engine();
+        let store = Store::new(&engine).unwrap();
+        assert_eq!(store.get_fuel(), None);
+    }
+
+    #[ktest::test]
+    fn stack_overflow(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (func $recurse (export "recurse") (param i32) (result i32)
+                    (call $recurse (i32.add (local.get 0) (i32.const 1))))
+                (func (export "answer") (result i32)
+                    (i32.const 42))
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+        let recurse = instance
+            .get_typed_func::<i32, i32>(&mut store, "recurse")
+            .unwrap();
+        let answer = instance
+            .get_typed_func::<(), i32>(&mut store, "answer")
+            .unwrap();
+
+        assert_eq!(recurse.call(&mut store, 0), Err(Trap::StackOverflow));
+        // the stack is unwound and can be used again
+        assert_eq!(recurse.call(&mut store, 0), Err(Trap::StackOverflow));
+        assert_eq!(answer.call(&mut store, ()), Ok(42));
+    }
+
+    #[ktest::test]
+    fn reference_types(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (type $unary (func (param i32) (result i32)))
+                (type $nullary (func (result i32)))
+                (table $funcs 3 funcref)
+                (table $externs 1 externref)
+                (global $extern (mut externref) (ref.null extern))
+                (elem (table $funcs) (i32.const 0) func $double $answer)
+                (func $double (type $unary) (i32.mul (local.get 0) (i32.const 2)))
+                (func $answer (type $nullary) (i32.const 42))
+                (func (export "call") (param i32 i32) (result i32)
+                    (call_indirect $funcs (type $unary) (local.get 0) (local.get 1)))
+                (func (export "externs") (result i32)
+                    (drop (table.grow $externs (table.get $externs (i32.const 0)) (i32.const 2)))
+                    (table.fill $externs (i32.const 1) (global.get $extern) (i32.const 2))
+                    (global.set $extern (table.get $externs (i32.const 2)))
+                    (i32.add
+                        (table.size $externs)
+                        (i32.mul (ref.is_null (global.get $extern)) (i32.const 10))))
+                (func (export "funcref_is_null") (param i32) (result i32)
+                    (ref.is_null (table.get $funcs (local.get 0))))
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+        let call = instance
+            .get_typed_func::<(i32, i32), i32>(&mut store, "call")
+            .unwrap();
+        let externs = instance
+            .get_typed_func::<(), i32>(&mut store, "externs")
+            .unwrap();
+        let funcref_is_null = instance
+            .get_typed_func::<i32, i32>(&mut store, "funcref_is_null")
+            .unwrap();
+
+        assert_eq!(call.call(&mut store, (21, 0)), Ok(42));
+        assert_eq!(call.call(&mut store, (21, 1)), Err(Trap::BadSignature));
+        assert_eq!(
+            call.call(&mut store, (21, 2)),
+            Err(Trap::IndirectCallToNull)
+        );
+        assert_eq!(call.call(&mut store, (21, 3)), Err(Trap::TableOutOfBounds));
+
+        assert_eq!(externs.call(&mut store, ()), Ok(13));
+
+        assert_eq!(funcref_is_null.call(&mut store, 0), Ok(0));
+        assert_eq!(funcref_is_null.call(&mut store, 2), Ok(1));
+        assert_eq!(
+            funcref_is_null.call(&mut store, 3),
+            Err(Trap::TableOutOfBounds)
+        );
+    }
+
+    #[ktest::test]
+    fn cross_instance_calls(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm_a = wat_to_wasm(
+            r#"(module
+                (table (export "table") 3 funcref)
+                (elem (i32.const 0) func $double $answer)
+                (func $double (export "double") (param i32) (result i32)
+                    (i32.mul (local.get 0) (i32.const 2)))
+                (func $answer (result i32) (i32.const 42))
+            )"#,
+        );
+        let wasm_b = wat_to_wasm(
+            r#"(module
+                (type $unary (func (param i32) (result i32)))
+                (import "a" "double" (func $double (type $unary)))
+                (import "a" "table" (table $table 3 funcref))
+                (import "env" "inc" (func $inc (type $unary)))
+                (elem (table $table) (i32.const 2) func $inc)
+                (func (export "call_import") (param i32) (result i32)
+                    (call $inc (call $double (local.get 0))))
+                (func (export "call_table") (param i32 i32) (result i32)
+                    (call_indirect $table (type $unary) (local.get 0) (local.get 1)))
+            )"#,
+        );
+
+        let module_a = Module::from_binary(&engine, &store, &wasm_a).unwrap();
+        let module_b = Module::from_binary(&engine, &store, &wasm_b).unwrap();
+
+        let mut linker = Linker::new();
+        linker.func_wrap("env", "inc", |x: i32| x + 1).unwrap();
+        let instance_a = linker.instantiate(&mut store, &module_a).unwrap();
+        linker.instance(&mut store, "a", instance_a).unwrap();
+        let instance_b = linker.instantiate(&mut store, &module_b).unwrap();
+
+        let call_import = instance_b
+            .get_typed_func::<i32, i32>(&mut store, "call_import")
+            .unwrap();
+        let call_table = instance_b
+            .get_typed_func::<(i32, i32), i32>(&mut store, "call_table")
+            .unwrap();
+
+        assert_eq!(call_import.call(&mut store, 20), Ok(41));
+        // the table and its functions are owned by the other instance, the signature check uses
+        // the canonical type of `$unary` which is shared between both modules
+        assert_eq!(call_table.call(&mut store, (21, 0)), Ok(42));
+        assert_eq!(
+            call_table.call(&mut store, (21, 1)),
+            Err(Trap::BadSignature)
+        );
+        assert_eq!(call_table.call(&mut store, (21, 2)), Ok(22));
+    }
+
+    #[ktest::test]
+    fn tail_calls(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (type $countdown (func (param i32 i32) (result i32)))
+                (table 2 funcref)
+                (elem (i32.const 0) func $even $odd)
+                (func $count (export "count") (param i32 i32) (result i32)
+                    (if (result i32) (i32.eqz (local.get 0))
+                        (then (local.get 1))
+                        (else (return_call $count
+                            (i32.sub (local.get 0) (i32.const 1))
+                            (i32.add (local.get 1) (i32.const 1))))))
+                (func $even (type $countdown)
+                    (if (result i32) (i32.eqz (local.get 0))
+                        (then (local.get 1))
+                        (else (return_call_indirect (type $countdown)
+                            (i32.sub (local.get 0) (i32.const 1))
+                            (local.get 1)
+                            (i32.const 1)))))
+                (func $odd (type $countdown)
+                    (if (result i32) (i32.eqz (local.get 0))
+                        (then (i32.add (local.get 1) (i32.const 1)))
+                        (else (return_call_indirect (type $countdown)
+                            (i32.sub (local.get 0) (i32.const 1))
+                            (local.get 1)
+                            (i32.const 0)))))
+                (func (export "parity") (param i32) (result i32)
+                    (return_call $even (local.get 0) (i32.const 0)))
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+        let count = instance
+            .get_typed_func::<(i32, i32), i32>(&mut store, "count")
+            .unwrap();
+        let parity = instance
+            .get_typed_func::<i32, i32>(&mut store, "parity")
+            .unwrap();
+
+        // far deeper than the guest stack could hold if these weren't proper tail calls
+        assert_eq!(count.call(&mut store, (1_000_000, 0)), Ok(1_000_000));
+        assert_eq!(parity.call(&mut store, 1_000_000), Ok(0));
+        assert_eq!(parity.call(&mut store, 1_000_001), Ok(1));
+    }
+
+    #[ktest::test]
+    fn multi_memory(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm_a = wat_to_wasm(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 0) "hello")
+            )"#,
+        );
+        let wasm_b = wat_to_wasm(
+            r#"(module
+                (import "a" "memory" (memory $imported 1))
+                (memory $own 1 2)
+                (func (export "copy") (param i32) (result i32)
+                    (memory.copy $own $imported (local.get 0) (i32.const 0) (i32.const 5))
+                    (i32.load8_u $own (i32.add (local.get 0) (i32.const 4))))
+                (func (export "store") (param i32 i32)
+                    (i32.store8 $imported (local.get 0) (local.get 1)))
+                (func (export "load") (param i32) (result i32)
+                    (i32.load8_u $imported (local.get 0)))
+                (func (export "grow") (result i32)
+                    (memory.grow $own (i32.const 1)))
+                (func (export "sizes") (result i32)
+                    (i32.add
+                        (memory.size $imported)
+                        (i32.mul (memory.size $own) (i32.const 10))))
+            )"#,
+        );
+
+        let module_a = Module::from_binary(&engine, &store, &wasm_a).unwrap();
+        let module_b = Module::from_binary(&engine, &store, &wasm_b).unwrap();
+
+        let mut linker = Linker::new();
+        let instance_a = linker.instantiate(&mut store, &module_a).unwrap();
+        linker.instance(&mut store, "a", instance_a).unwrap();
+        let instance_b = linker.instantiate(&mut store, &module_b).unwrap();
+
+        let copy = instance_b
+            .get_typed_func::<i32, i32>(&mut store, "copy")
+            .unwrap();
+        let store8 = instance_b
+            .get_typed_func::<(i32, i32), ()>(&mut store, "store")
+            .unwrap();
+        let load8 = instance_b
+            .get_typed_func::<i32, i32>(&mut store, "load")
+            .unwrap();
+        let grow = instance_b
+            .get_typed_func::<(), i32>(&mut store, "grow")
+            .unwrap();
+        let sizes = instance_b
+            .get_typed_func::<(), i32>(&mut store, "sizes")
+            .unwrap();
+
+        assert_eq!(copy.call(&mut store, 100), Ok(i32::from(b'o')));
+        assert_eq!(load8.call(&mut store, 0), Ok(i32::from(b'h')));
+        assert_eq!(store8.call(&mut store, (0, i32::from(b'j'))), Ok(()));
+        assert_eq!(copy.call(&mut store, 200), Ok(i32::from(b'o')));
+        assert_eq!(load8.call(&mut store, 0), Ok(i32::from(b'j')));
+
+        assert_eq!(sizes.call(&mut store, ()), Ok(11));
+        assert_eq!(grow.call(&mut store, ()), Ok(1));
+        assert_eq!(grow.call(&mut store, ()), Ok(-1));
+        assert_eq!(sizes.call(&mut store, ()), Ok(21));
+        assert_eq!(
+            load8.call(&mut store, 0x1_0000),
+            Err(Trap::MemoryOutOfBounds)
+        );
+    }
+
+    #[ktest::test]
+    fn simd(_boot_info: &'static loader_api::BootInfo) {
+        if crate::arch::has_vector_extension() {
+            run_simd(&Engine::new(Config::default().riscv_vector(true)).unwrap());
+        } else {
+            log::warn!("hart has no vector extension, only testing the scalar fallback");
+        }
+    }
+
+    #[ktest::test]
+    fn simd_scalar_fallback(_boot_info: &'static loader_api::BootInfo) {
+        run_simd(&Engine::new(Config::default().riscv_vector(false).wasm_simd(true)).unwrap());
+    }
+
+    fn run_simd(engine: &Engine) {
+        let mut store = Store::new(engine).unwrap();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (global $ones (mut v128) (v128.const i32x4 1 1 1 1))
+                (memory 1)
+                (func (export "add") (param v128 v128) (result v128)
+                    (i32x4.add (local.get 0) (local.get 1)))
+                (func (export "add_ones") (param v128) (result v128)
+                    (global.set $ones (i32x4.add (global.get $ones) (local.get 0)))
+                    (global.get $ones))
+                (func (export "sum") (param i32) (result i32)
+                    (v128.store (i32.const 0) (i32x4.splat (local.get 0)))
+                    (i32x4.extract_lane 3
+                        (i32x4.mul (v128.load (i32.const 0)) (i32x4.splat (i32.const 3)))))
+            )"#,
+        );
+        let module = Module::from_binary(engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+        let add = instance
+            .get_typed_func::<(u128, u128), u128>(&mut store, "add")
+            .unwrap();
+        let add_ones = instance
+            .get_typed_func::<u128, u128>(&mut store, "add_ones")
+            .unwrap();
+        let sum = instance
+            .get_typed_func::<i32, i32>(&mut store, "sum")
+            .unwrap();
+
+        let lanes = |l: [u32; 4]| {
+            l.iter()
+                .rev()
+                .fold(0_u128, |acc, lane| (acc << 32) | u128::from(*lane))
+        };
+
+        assert_eq!(
+            add.call(
+                &mut store,
+                (lanes([1, 2, 3, 4]), lanes([10, 20, 30, u32::MAX]))
+            ),
+            Ok(lanes([11, 22, 33, 3]))
+        );
+        assert_eq!(
+            add_ones.call(&mut store, lanes([0, 1, 2, 3])),
+            Ok(lanes([1, 2, 3, 4]))
+        );
+        assert_eq!(
+            add_ones.call(&mut store, lanes([0, 1, 2, 3])),
+            Ok(lanes([1, 3, 5, 7]))
+        );
+        assert_eq!(sum.call(&mut store, 14), Ok(42));
+
+        // the untyped API passes `v128`s as `Val::V128`
+        let mut results = [Val::I32(0)];
+        add.func()
+            .call(
+                &mut store,
+                &[
+                    Val::V128(lanes([1, 1, 1, 1])),
+                    Val::V128(lanes([1, 2, 3, 4])),
+                ],
+                &mut results,
+            )
+            .unwrap();
+        assert_eq!(results[0], Val::V128(lanes([2, 3, 4, 5])));
+    }
+
+    #[ktest::test]
+    fn threads(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm_a = wat_to_wasm(
+            r#"(module
+                (memory (export "memory") 1 1 shared)
+                (func (export "load") (param i32) (result i32)
+                    (i32.atomic.load (local.get 0)))
+            )"#,
+        );
+        let wasm_b = wat_to_wasm(
+            r#"(module
+                (import "a" "memory" (memory 1 1 shared))
+                (func (export "store") (param i32 i32)
+                    (i32.atomic.store (local.get 0) (local.get 1)))
+                (func (export "wait") (param i32 i32 i64) (result i32)
+                    (memory.atomic.wait32 (local.get 0) (local.get 1) (local.get 2)))
+                (func (export "notify") (param i32 i32) (result i32)
+                    (memory.atomic.notify (local.get 0) (local.get 1)))
+            )"#,
+        );
+        let wasm_c = wat_to_wasm(
+            r#"(module
+                (memory 1)
+                (func (export "wait") (result i32)
+                    (memory.atomic.wait32 (i32.const 0) (i32.const 0) (i64.const 0)))
+            )"#,
+        );
+
+        let module_a = Module::from_binary(&engine, &store, &wasm_a).unwrap();
+        let module_b = Module::from_binary(&engine, &store, &wasm_b).unwrap();
+        let module_c = Module::from_binary(&engine, &store, &wasm_c).unwrap();
+
+        let mut linker = Linker::new();
+        let instance_a = linker.instantiate(&mut store, &module_a).unwrap();
+        linker.instance(&mut store, "a", instance_a).unwrap();
+        let instance_b = linker.instantiate(&mut store, &module_b).unwrap();
+        let instance_c = linker.instantiate(&mut store, &module_c).unwrap();
+
+        let load = instance_a
+            .get_typed_func::<i32, i32>(&mut store, "load")
+            .unwrap();
+        let store32 = instance_b
+            .get_typed_func::<(i32, i32), ()>(&mut store, "store")
+            .unwrap();
+        let wait = instance_b
+            .get_typed_func::<(i32, i32, i64), i32>(&mut store, "wait")
+            .unwrap();
+        let notify = instance_b
+            .get_typed_func::<(i32, i32), i32>(&mut store, "notify")
+            .unwrap();
+        let wait_unshared = instance_c
+            .get_typed_func::<(), i32>(&mut store, "wait")
+            .unwrap();
+
+        // both instances access the same memory
+        assert_eq!(store32.call(&mut store, (8, 7)), Ok(()));
+        assert_eq!(load.call(&mut store, 8), Ok(7));
+
+        // not-equal, timed-out and no waiters woken up
+        assert_eq!(wait.call(&mut store, (8, 0, -1)), Ok(1));
+        assert_eq!(wait.call(&mut store, (8, 7, 1_000_000)), Ok(2));
+        assert_eq!(notify.call(&mut store, (8, 1)), Ok(0));
+
+        assert_eq!(load.call(&mut store, 9), Err(Trap::HeapMisaligned));
+        assert_eq!(wait.call(&mut store, (10, 0, 0)), Err(Trap::HeapMisaligned));
+        assert_eq!(notify.call(&mut store, (10, 1)), Err(Trap::HeapMisaligned));
+        assert_eq!(
+            wait.call(&mut store, (0x1_0000, 0, 0)),
+            Err(Trap::MemoryOutOfBounds)
+        );
+        assert_eq!(
+            wait_unshared.call(&mut store, ()),
+            Err(Trap::AtomicWaitNonSharedMemory)
+        );
+    }
+
+    #[ktest::test]
+    fn memory64(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_engine();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (memory i64 1 4)
+                (data (i64.const 8) "\2a")
+                (func (export "load") (param i64) (result i32)
+                    (i32.load8_u (local.get 0)))
+                (func (export "grow") (param i64) (result i64)
+                    (memory.grow (local.get 0)))
+                (func (export "size") (result i64)
+                    (memory.size))
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+        let load = instance
+            .get_typed_func::<i64, i32>(&mut store, "load")
+            .unwrap();
+        let grow = instance
+            .get_typed_func::<i64, i64>(&mut store, "grow")
+            .unwrap();
+        let size = instance
+            .get_typed_func::<(), i64>(&mut store, "size")
+            .unwrap();
+
+        assert_eq!(load.call(&mut store, 8), Ok(42));
+        assert_eq!(
+            load.call(&mut store, 0x1_0000),
+            Err(Trap::MemoryOutOfBounds)
+        );
+        // addresses beyond 4GiB are bounds checked, not truncated
+        assert_eq!(
+            load.call(&mut store, 0x1_0000_0008),
+            Err(Trap::MemoryOutOfBounds)
+        );
+        assert_eq!(load.call(&mut store, -1), Err(Trap::MemoryOutOfBounds));
+
+        assert_eq!(grow.call(&mut store, 1), Ok(1));
+        assert_eq!(size.call(&mut store, ()), Ok(2));
+        assert_eq!(load.call(&mut store, 0x1_0000), Ok(0));
+        assert_eq!(grow.call(&mut store, 3), Ok(-1));
+        assert_eq!(grow.call(&mut store, 1 << 48), Ok(-1));
+        assert_eq!(size.call(&mut store, ()), Ok(2));
+
+        // the minimum size of valid 64-bit memories can exceed the address space
+        let wasm = wat_to_wasm(r#"(module (memory i64 0x1_0000_0000_0000))"#);
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        assert!(matches!(
+            Linker::new().instantiate(&mut store, &module),
+            Err(LinkError::OutOfMemory)
+        ));
+    }
+
+    #[ktest::test]
+    fn gc(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_gc_engine();
+        let mut store = Store::new(&engine).unwrap();
+        let heap = store.gc_heap();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (import "host" "collect" (func $collect))
+                (global $kept (mut anyref) (ref.null any))
+                (func (export "i31_get_s") (param i32) (result i32)
+                    (i31.get_s (ref.i31 (local.get 0))))
+                (func (export "i31_get_u") (param i32) (result i32)
+                    (i31.get_u (ref.i31 (local.get 0))))
+                (func (export "i31_null") (result i32)
+                    (i31.get_u (ref.null i31)))
+                (func (export "keep") (param anyref)
+                    (global.set $kept (local.get 0)))
+                (func (export "hold") (param anyref) (result anyref)
+                    (call $collect)
+                    (local.get 0))
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let mut linker = Linker::new();
+        let collect_heap = heap.clone();
+        linker
+            .func_wrap("host", "collect", move || collect_heap.collect())
+            .unwrap();
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+
+        let i31_get_s = instance
+            .get_typed_func::<i32, i32>(&mut store, "i31_get_s")
+            .unwrap();
+        let i31_get_u = instance
+            .get_typed_func::<i32, i32>(&mut store, "i31_get_u")
+            .unwrap();
+        let i31_null = instance
+            .get_typed_func::<(), i32>(&mut store, "i31_null")
+            .unwrap();
+        let keep = instance
+            .get_typed_func::<Option<AnyRef>, ()>(&mut store, "keep")
+            .unwrap();
+        let hold = instance
+            .get_typed_func::<Option<AnyRef>, Option<AnyRef>>(&mut store, "hold")
+            .unwrap();
+
+        assert_eq!(i31_get_s.call(&mut store, -1), Ok(-1));
+        assert_eq!(i31_get_u.call(&mut store, -1), Ok(0x7fff_ffff));
+        assert_eq!(i31_null.call(&mut store, ()), Err(Trap::NullI31Ref));
+        assert_eq!(AnyRef::from_i31(-5).i31_get_s(), Some(-5));
+
+        let point = WasmStructType {
+            fields: [
+                WasmFieldType {
+                    element_type: WasmStorageType::I8,
+                    mutable: false,
+                },
+                WasmFieldType {
+                    element_type: WasmStorageType::Val(WasmValType::I32),
+                    mutable: true,
+                },
+            ]
+            .into(),
+        };
+        let a = heap
+            .alloc_struct(&point, &[Val::I32(0x1ff), Val::I32(2)])
+            .unwrap();
+        let b = heap
+            .alloc_struct(&point, &[Val::I32(3), Val::I32(4)])
+            .unwrap();
+        assert!(!a.is_i31());
+        assert_eq!(heap.get(a, 0), Ok(Val::I32(0xff)));
+        assert_eq!(heap.set(a, 1, &Val::I32(5)), Ok(()));
+        assert_eq!(heap.get(a, 1), Ok(Val::I32(5)));
+        assert_eq!(heap.set(a, 0, &Val::I32(5)), Err(GcError::Immutable));
+        assert_eq!(heap.get(a, 2), Err(GcError::IndexOutOfBounds));
+        assert_eq!(heap.set(a, 1, &Val::I64(5)), Err(GcError::TypeMismatch));
+
+        // `a` is rooted by the global, `b` only by the frame of `hold`
+        assert_eq!(keep.call(&mut store, Some(a)), Ok(()));
+        assert_eq!(hold.call(&mut store, Some(b)), Ok(Some(b)));
+        assert_eq!(heap.num_objects(), 2);
+        assert_eq!(heap.get(b, 1), Ok(Val::I32(4)));
+
+        // once unreachable, both are freed
+        heap.collect();
+        assert_eq!(heap.num_objects(), 1);
+        assert_eq!(heap.get(b, 0), Err(GcError::Dangling));
+        assert_eq!(keep.call(&mut store, None), Ok(()));
+        heap.collect();
+        assert_eq!(heap.num_objects(), 0);
+        assert_eq!(heap.get(a, 0), Err(GcError::Dangling));
+    }
+
+    #[ktest::test]
+    fn gc_const_exprs(_boot_info: &'static loader_api::BootInfo) {
+        let engine = build_gc_engine();
+        let mut store = Store::new(&engine).unwrap();
+        let heap = store.gc_heap();
+
+        let host = wat_to_wasm(r#"(module (global (export "base") i32 (i32.const 5)))"#);
+        let host = Module::from_binary(&engine, &store, &host).unwrap();
+        let host = Linker::new().instantiate(&mut store, &host).unwrap();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (type $vec (array (mut i32)))
+                (type $bytes (array i8))
+                (type $point (struct (field i8) (field (mut i64)) (field (ref null $vec))))
+                (import "host" "base" (global $base i32))
+                (global $point (ref $point)
+                    (struct.new $point
+                        (i32.const 0x1ff)
+                        (i64.mul (i64.const 6) (i64.const 7))
+                        (array.new_fixed $vec 3
+                            (i32.const 1)
+                            (i32.const 2)
+                            (i32.add (global.get $base) (i32.const 1)))))
+                (global $empty (ref $point) (struct.new_default $point))
+                (global $zeroes (ref $vec) (array.new_default $vec (i32.const 4)))
+                (global $bytes (ref $bytes) (array.new $bytes (i32.const 0x1234) (i32.const 2)))
+                (table $small 2 i31ref (ref.i31 (i32.sub (global.get $base) (i32.const 8))))
+                (memory 1)
+                (data (i32.add (global.get $base) (i32.const 2)) "\2a")
+                (func (export "point") (result anyref) (global.get $point))
+                (func (export "empty") (result anyref) (global.get $empty))
+                (func (export "zeroes") (result anyref) (global.get $zeroes))
+                (func (export "bytes") (result anyref) (global.get $bytes))
+                (func (export "small") (param i32) (result i32)
+                    (i31.get_s (table.get $small (local.get 0))))
+                (func (export "load") (param i32) (result i32)
+                    (i32.load8_u (local.get 0)))
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let mut linker = Linker::new();
+        linker.instance(&mut store, "host", host).unwrap();
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+
+        let mut get = |name: &str| {
+            instance
+                .get_typed_func::<(), Option<AnyRef>>(&mut store, name)
+                .unwrap()
+                .call(&mut store, ())
+                .unwrap()
+                .unwrap()
+        };
+        let point = get("point");
+        let empty = get("empty");
+        let zeroes = get("zeroes");
+        let bytes = get("bytes");
+
+        // the nested array is only reachable through the struct's field
+        heap.collect();
+        assert_eq!(heap.num_objects(), 5);
+
+        assert_eq!(heap.get(point, 0), Ok(Val::I32(0xff)));
+        assert_eq!(heap.get(point, 1), Ok(Val::I64(42)));
+        let Ok(Val::AnyRef(Some(vec))) = heap.get(point, 2) else {
+            panic!("expected the nested array");
+        };
+        assert_eq!(heap.len(vec), Ok(3));
+        assert_eq!(heap.get(vec, 2), Ok(Val::I32(6)));
+
+        assert_eq!(heap.get(empty, 1), Ok(Val::I64(0)));
+        assert_eq!(heap.get(empty, 2), Ok(Val::AnyRef(None)));
+        assert_eq!(heap.len(zeroes), Ok(4));
+        assert_eq!(heap.get(zeroes, 3), Ok(Val::I32(0)));
+        assert_eq!(heap.len(bytes), Ok(2));
+        assert_eq!(heap.get(bytes, 1), Ok(Val::I32(0x34)));
+
+        let small = instance
+            .get_typed_func::<i32, i32>(&mut store, "small")
+            .unwrap();
+        assert_eq!(small.call(&mut store, 1), Ok(-3));
+        let load = instance
+            .get_typed_func::<i32, i32>(&mut store, "load")
+            .unwrap();
+        assert_eq!(load.call(&mut store, 7), Ok(42));
+
+        // converting host references can't be evaluated and is rejected up front
+        let wasm =
+            wat_to_wasm(r#"(module (global anyref (any.convert_extern (ref.null extern))))"#);
+        assert!(matches!(
+            Module::from_binary(&engine, &store, &wasm),
+            Err(CompileError::Translate(_))
+        ));
+    }
+
+    #[ktest::test]
+    fn epoch_interruption(_boot_info: &'static loader_api::BootInfo) {
+        let mut config = Config::default();
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).unwrap();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(
+            r#"(module
+                (func (export "nop"))
+                (func (export "spin") (loop $l (br $l)))
+            )"#,
+        );
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+        let nop = instance
+            .get_typed_func::<(), ()>(&mut store, "nop")
+            .unwrap();
+        let spin = instance
+            .get_typed_func::<(), ()>(&mut store, "spin")
+            .unwrap();
+
+        // stores start out with a deadline that has already been reached
+        assert_eq!(nop.call(&mut store, ()), Err(Trap::Interrupt));
+        store.set_epoch_deadline(u64::MAX);
+        assert_eq!(nop.call(&mut store, ()), Ok(()));
+
+        // the timer interrupt advances the epoch while WASM is running
+        store.set_epoch_deadline(1);
+        assert_eq!(spin.call(&mut store, ()), Err(Trap::Interrupt));
+
+        // reaching the deadline can extend it instead of trapping
+        store.epoch_deadline_extend(1);
+        store.set_epoch_deadline(0);
+        assert_eq!(nop.call(&mut store, ()), Ok(()));
+    }
+
+    #[ktest::test]
+    fn epoch_tick_rate(_boot_info: &'static loader_api::BootInfo) {
+        let mut config = Config::default();
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).unwrap();
+        let mut store = Store::new(&engine).unwrap();
+
+        let wasm = wat_to_wasm(r#"(module (func (export "spin") (loop $l (br $l))))"#);
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+        let spin = instance
+            .get_typed_func::<(), ()>(&mut store, "spin")
+            .unwrap();
+
+        // All harts take timer interrupts while this runs, the deadline must still be reached
+        // after the configured number of intervals. The current interval has already partially
+        // elapsed, so the deadline is reached between 4 and 5 intervals from now.
+        let start = arch::current_ticks();
+        store.set_epoch_deadline(5);
+        assert_eq!(spin.call(&mut store, ()), Err(Trap::Interrupt));
+        let elapsed = arch::current_ticks() - start;
+
+        assert!(
+            elapsed >= 4 * kconfig::EPOCH_INTERVAL_TICKS,
+            "elapsed {elapsed} ticks"
+        );
+        // leave some slack for the interrupt that updates the store's epoch
+        assert!(
+            elapsed < 7 * kconfig::EPOCH_INTERVAL_TICKS,
+            "elapsed {elapsed} ticks"
+        );
+    }
+
     macro_rules! wasm_test_case {
         ($name:ident, $fixture:expr) => {
             #[ktest::test]
-            fn $name(boot_info: &'static loader_api::BootInfo) {
+            fn $name(_boot_info: &'static loader_api::BootInfo) {
                 let bytes = include_bytes!($fixture);
-                build_and_run_wasm(bytes, boot_info.physical_memory_offset)
+                build_and_run_wasm(bytes)
             }
         };
     }
@@ -55,15 +1398,115 @@ pub mod compile_tests {
     macro_rules! wast_test_case {
         ($name:ident, $fixture:expr) => {
             #[ktest::test]
-            fn $name(boot_info: &'static loader_api::BootInfo) {
+            fn $name(_boot_info: &'static loader_api::BootInfo) {
                 let bytes = include_str!($fixture);
-                build_and_run_wast(bytes, boot_info.physical_memory_offset)
+                build_and_run_wast($fixture, bytes)
             }
         };
     }
 
     ktest::for_each_fixture!("../tests/fib", wasm_test_case);
-    ktest::for_each_fixture!("../tests/testsuite", wast_test_case);
+    ktest::for_each_fixture!("../tests/wast", wast_test_case);
+
+    mod tail_call {
+        use super::build_and_run_wast;
+
+        ktest::for_each_fixture!("../tests/wast/tail-call", wast_test_case);
+    }
+
+    mod multi_memory {
+        use super::build_and_run_wast;
+
+        ktest::for_each_fixture!("../tests/wast/multi-memory", wast_test_case);
+    }
+
+    mod memory64 {
+        use super::build_and_run_wast;
+
+        ktest::for_each_fixture!("../tests/wast/memory64", wast_test_case);
+    }
+}
+
+#[cfg(test)]
+mod trap_tests {
+    use crate::runtime::{call_raw_code, Config, Engine, Linker, Module, Store, Trap};
+    use core::arch::asm;
+
+    /// Runs the machine `code` in user mode in place of a WASM function and checks that it traps
+    /// with `trap`, after which the store must still be able to run WASM.
+    fn assert_raw_code_traps(code: &[u32], trap: Trap) {
+        let engine = Engine::new(&Config::default()).unwrap();
+        let mut store = Store::new(&engine).unwrap();
+
+        let buf = wast::parser::ParseBuffer::new(
+            r#"(module (func (export "run") (result i32) (i32.const 42)))"#,
+        )
+        .unwrap();
+        let mut wat = wast::parser::parse::<wast::Wat>(&buf).unwrap();
+        let wasm = wat.encode().unwrap();
+        let module = Module::from_binary(&engine, &store, &wasm).unwrap();
+        let instance = Linker::new().instantiate(&mut store, &module).unwrap();
+        let run = instance.get_func(&mut store, "run").unwrap();
+
+        assert_eq!(call_raw_code(&mut store, &run, code), Err(trap));
+
+        let run = run.typed::<(), i32>().unwrap();
+        assert_eq!(run.call(&mut store, ()), Ok(42));
+    }
+
+    #[ktest::test]
+    fn guest_illegal_instruction(_boot_info: &'static loader_api::BootInfo) {
+        // the all-zero instruction is defined to be illegal
+        assert_raw_code_traps(&[0x0000_0000], Trap::UnexpectedException);
+    }
+
+    #[ktest::test]
+    fn guest_faulting_access(_boot_info: &'static loader_api::BootInfo) {
+        // ld a0, 0(zero)
+        assert_raw_code_traps(&[0x0000_3503], Trap::UnexpectedException);
+        // lui a0, 0x80000 (an address in the kernel half)
+        // ld a0, 0(a0)
+        assert_raw_code_traps(&[0x8000_0537, 0x0005_3503], Trap::UnexpectedException);
+    }
+
+    #[ktest::test]
+    fn guest_misaligned_access(_boot_info: &'static loader_api::BootInfo) {
+        // addi a0, sp, -7
+        // amoadd.w zero, zero, (a0)
+        assert_raw_code_traps(&[0xff91_0513, 0x0005_202f], Trap::HeapMisaligned);
+    }
+
+    #[ktest::test]
+    fn guest_jump_into_kernel(_boot_info: &'static loader_api::BootInfo) {
+        // lui a0, 0x80000 (an address in the kernel half)
+        // jr a0
+        assert_raw_code_traps(&[0x8000_0537, 0x0005_0067], Trap::UnexpectedException);
+    }
+
+    #[ktest::test]
+    fn breakpoint(_boot_info: &'static loader_api::BootInfo) {
+        let a0: usize;
+        let t0: usize;
+        let t6: usize;
+
+        // the trap handler must skip the `ebreak` and restore all registers, including the
+        // caller-saved ones
+        unsafe {
+            asm!(
+                "li a0, 0x1111",
+                "li t0, 0x2222",
+                "li t6, 0x3333",
+                "ebreak",
+                out("a0") a0,
+                out("t0") t0,
+                out("t6") t6,
+            );
+        }
+
+        assert_eq!(a0, 0x1111);
+        assert_eq!(t0, 0x2222);
+        assert_eq!(t6, 0x3333);
+    }
 }
 
 // #[cfg(test)]
@@ -0,0 +1,499 @@
+//! A runner for `.wast` scripts, the script format of the WebAssembly spec testsuite.
+//!
+//! Each script is a sequence of directives that define modules and make assertions about them.
+//! All modules of a script are instantiated in the same [`Store`] so they can import from each
+//! other through `register`.
+//!
+//! The scripts live in `tests/wast` and are written for k23, they only cover the features k23
+//! implements and are not a conformance test. The following directive kinds are skipped, every
+//! skipped directive is logged with its location:
+//!
+//! - `assert_exception`, k23 doesn't implement the exception handling proposal.
+//! - `thread` and `wait`, scripts of the threads proposal would need to run on several harts.
+
+use crate::runtime::{
+    AnyRef, Engine, Export, Instance, LinkError, Linker, Module, Store, Trap, Val,
+};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::num::NonZeroU32;
+use hashbrown::HashMap;
+use wast::core::{AbstractHeapType, HeapType, NanPattern, V128Pattern, WastArgCore, WastRetCore};
+use wast::parser::{self, ParseBuffer};
+use wast::token::{Id, F32, F64};
+use wast::{QuoteWat, Wast, WastArg, WastDirective, WastExecute, WastInvoke, WastRet, Wat};
+
+/// The globals, table and memory of the `spectest` module that spec tests import from.
+const SPECTEST: &str = r#"(module
+    (global (export "global_i32") i32 (i32.const 666))
+    (global (export "global_i64") i64 (i64.const 666))
+    (global (export "global_f32") f32 (f32.const 666.6))
+    (global (export "global_f64") f64 (f64.const 666.6))
+    (table (export "table") 10 20 funcref)
+    (memory (export "memory") 1 2)
+)"#;
+
+/// Runs all directives of the given `.wast` script.
+///
+/// # Errors
+///
+/// Returns a description of the first directive that failed, prefixed by its location.
+pub fn run_wast(engine: &Engine, filename: &str, wast: &str) -> Result<(), String> {
+    let with_location = |mut err: wast::Error| {
+        err.set_text(wast);
+        format!("{filename}: {err}")
+    };
+
+    let buf = ParseBuffer::new(wast).map_err(with_location)?;
+    let mut script = parser::parse::<Wast>(&buf).map_err(with_location)?;
+
+    // Modules borrow their binary for as long as the store is alive, so encode all of them up
+    // front.
+    let binaries: Vec<_> = script.directives.iter_mut().map(encode_directive).collect();
+    let spectest = wat_to_binary(SPECTEST)?;
+
+    let mut cx = WastContext::new(engine, &spectest)?;
+    for (directive, binary) in script.directives.iter().zip(&binaries) {
+        if is_skipped(directive) {
+            let (line, col) = directive.span().linecol_in(wast);
+            log::warn!(
+                "{filename}:{}:{}: skipping unsupported directive",
+                line + 1,
+                col + 1
+            );
+            continue;
+        }
+
+        cx.run_directive(directive, binary.as_ref())
+            .map_err(|err| {
+                let (line, col) = directive.span().linecol_in(wast);
+                format!("{filename}:{}:{}: {err}", line + 1, col + 1)
+            })?;
+    }
+
+    Ok(())
+}
+
+/// The outcome of executing a directive, either its results or the trap it raised.
+type Outcome = Result<Vec<Val>, Trap>;
+
+struct WastContext<'a, 'wasm> {
+    engine: &'a Engine,
+    store: Store<'wasm>,
+    linker: Linker,
+    /// The most recently instantiated module, directives that don't name a module refer to it.
+    current: Option<Instance>,
+    /// Instances that have been given a name with `(module $name ...)`.
+    named: HashMap<String, Instance>,
+}
+
+impl<'a, 'wasm> WastContext<'a, 'wasm> {
+    fn new(engine: &'a Engine, spectest: &'wasm [u8]) -> Result<Self, String> {
+        let mut cx = Self {
+            engine,
+            store: Store::new(engine).map_err(|err| err.to_string())?,
+            linker: Linker::new(),
+            current: None,
+            named: HashMap::default(),
+        };
+
+        cx.linker
+            .func_wrap("spectest", "print", || {})
+            .and_then(|l| l.func_wrap("spectest", "print_i32", |_: i32| {}))
+            .and_then(|l| l.func_wrap("spectest", "print_i64", |_: i64| {}))
+            .and_then(|l| l.func_wrap("spectest", "print_f32", |_: f32| {}))
+            .and_then(|l| l.func_wrap("spectest", "print_f64", |_: f64| {}))
+            .and_then(|l| l.func_wrap("spectest", "print_i32_f32", |_: i32, _: f32| {}))
+            .and_then(|l| l.func_wrap("spectest", "print_f64_f64", |_: f64, _: f64| {}))
+            .map_err(|err| err.to_string())?;
+
+        let module =
+            Module::from_binary(cx.engine, &cx.store, spectest).map_err(|err| err.to_string())?;
+        let instance = cx
+            .linker
+            .instantiate(&mut cx.store, &module)
+            .map_err(|err| err.to_string())?;
+        cx.linker
+            .instance(&mut cx.store, "spectest", instance)
+            .map_err(|err| err.to_string())?;
+
+        Ok(cx)
+    }
+
+    fn run_directive(
+        &mut self,
+        directive: &WastDirective,
+        binary: Option<&'wasm Result<Vec<u8>, String>>,
+    ) -> Result<(), String> {
+        match directive {
+            WastDirective::Wat(module) => {
+                let instance = self.instantiate(binary)?;
+                if let Some(id) = module_id(module) {
+                    self.named.insert(id.to_string(), instance);
+                }
+            }
+            WastDirective::Register { name, module, .. } => {
+                let instance = self.instance(module.as_ref())?;
+                self.linker
+                    .instance(&mut self.store, name, instance)
+                    .map_err(|err| err.to_string())?;
+            }
+            WastDirective::Invoke(invoke) => {
+                self.invoke(invoke)?
+                    .map_err(|trap| format!("unexpected trap: {trap}"))?;
+            }
+            WastDirective::AssertReturn { exec, results, .. } => {
+                let actual = self
+                    .execute(exec, binary)?
+                    .map_err(|trap| format!("unexpected trap: {trap}"))?;
+                match_results(&actual, results)?;
+            }
+            WastDirective::AssertTrap { exec, message, .. } => {
+                match_trap(self.execute(exec, binary)?, message)?;
+            }
+            WastDirective::AssertExhaustion { call, message, .. } => {
+                match_trap(self.invoke(call)?, message)?;
+            }
+            WastDirective::AssertInvalid { message, .. }
+            | WastDirective::AssertMalformed { message, .. } => {
+                // modules may already fail to encode, e.g. when quoted text is malformed
+                let Ok(bytes) = binary.expect("module wasn't encoded") else {
+                    return Ok(());
+                };
+
+                if Module::from_binary(self.engine, &self.store, bytes).is_ok() {
+                    return Err(format!("expected module to be rejected with `{message}`"));
+                }
+            }
+            WastDirective::AssertUnlinkable { message, .. } => {
+                let module = self.compile(binary)?;
+                match self.linker.instantiate(&mut self.store, &module) {
+                    Ok(_) => return Err(format!("expected link error `{message}`")),
+                    Err(LinkError::Instantiation(trap)) => {
+                        return Err(format!("expected link error `{message}`, got trap {trap}"));
+                    }
+                    Err(_) => {}
+                }
+            }
+            WastDirective::AssertException { .. }
+            | WastDirective::Thread(_)
+            | WastDirective::Wait { .. } => unreachable!("skipped directive"),
+        }
+
+        Ok(())
+    }
+
+    fn execute(
+        &mut self,
+        exec: &WastExecute,
+        binary: Option<&'wasm Result<Vec<u8>, String>>,
+    ) -> Result<Outcome, String> {
+        match exec {
+            WastExecute::Invoke(invoke) => self.invoke(invoke),
+            WastExecute::Wat(_) => {
+                let module = self.compile(binary)?;
+                match self.linker.instantiate(&mut self.store, &module) {
+                    Ok(_) => Ok(Ok(vec![])),
+                    Err(LinkError::Instantiation(trap)) => Ok(Err(trap)),
+                    Err(err) => Err(err.to_string()),
+                }
+            }
+            WastExecute::Get { module, global, .. } => {
+                let instance = self.instance(module.as_ref())?;
+                let Some(Export::Global(global)) = instance.get_export(&mut self.store, global)
+                else {
+                    return Err(format!("no global named `{global}`"));
+                };
+
+                let val = unsafe {
+                    let vmval = (*global.definition).to_vmval(&global.global.wasm_ty);
+                    Val::from_vmval(&vmval, global.global.wasm_ty)
+                };
+                Ok(Ok(vec![val]))
+            }
+        }
+    }
+
+    fn invoke(&mut self, invoke: &WastInvoke) -> Result<Outcome, String> {
+        let instance = self.instance(invoke.module.as_ref())?;
+        let func = instance
+            .get_func(&mut self.store, invoke.name)
+            .ok_or_else(|| format!("no function named `{}`", invoke.name))?;
+
+        let params = invoke
+            .args
+            .iter()
+            .map(arg_to_val)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut results = vec![Val::I32(0); func.ty().returns().len()];
+
+        Ok(func
+            .call(&mut self.store, &params, &mut results)
+            .map(|()| results))
+    }
+
+    fn compile(
+        &mut self,
+        binary: Option<&'wasm Result<Vec<u8>, String>>,
+    ) -> Result<Module<'wasm>, String> {
+        let bytes = binary
+            .expect("module wasn't encoded")
+            .as_ref()
+            .map_err(Clone::clone)?;
+        Module::from_binary(self.engine, &self.store, bytes).map_err(|err| err.to_string())
+    }
+
+    fn instantiate(
+        &mut self,
+        binary: Option<&'wasm Result<Vec<u8>, String>>,
+    ) -> Result<Instance, String> {
+        let module = self.compile(binary)?;
+        let instance = self
+            .linker
+            .instantiate(&mut self.store, &module)
+            .map_err(|err| err.to_string())?;
+
+        self.current = Some(instance);
+        Ok(instance)
+    }
+
+    fn instance(&self, id: Option<&Id>) -> Result<Instance, String> {
+        match id {
+            Some(id) => self
+                .named
+                .get(id.name())
+                .copied()
+                .ok_or_else(|| format!("unknown module `{}`", id.name())),
+            None => self
+                .current
+                .ok_or_else(|| "no module has been instantiated".to_string()),
+        }
+    }
+}
+
+/// Whether the directive is of a kind the runner skips, see the module docs.
+fn is_skipped(directive: &WastDirective) -> bool {
+    matches!(
+        directive,
+        WastDirective::AssertException { .. }
+            | WastDirective::Thread(_)
+            | WastDirective::Wait { .. }
+    )
+}
+
+/// Encodes the module of the given directive, if it has one.
+fn encode_directive(directive: &mut WastDirective) -> Option<Result<Vec<u8>, String>> {
+    let result = match directive {
+        WastDirective::Wat(module)
+        | WastDirective::AssertInvalid { module, .. }
+        | WastDirective::AssertMalformed { module, .. } => module.encode(),
+        WastDirective::AssertUnlinkable { module, .. }
+        | WastDirective::AssertTrap {
+            exec: WastExecute::Wat(module),
+            ..
+        }
+        | WastDirective::AssertReturn {
+            exec: WastExecute::Wat(module),
+            ..
+        } => module.encode(),
+        _ => return None,
+    };
+
+    Some(result.map_err(|err| err.to_string()))
+}
+
+fn wat_to_binary(wat: &str) -> Result<Vec<u8>, String> {
+    let buf = ParseBuffer::new(wat).map_err(|err| err.to_string())?;
+    let mut wat = parser::parse::<Wat>(&buf).map_err(|err| err.to_string())?;
+    wat.encode().map_err(|err| err.to_string())
+}
+
+fn module_id<'a>(module: &QuoteWat<'a>) -> Option<&'a str> {
+    match module {
+        QuoteWat::Wat(Wat::Module(module)) => module.id.map(|id| id.name()),
+        _ => None,
+    }
+}
+
+fn arg_to_val(arg: &WastArg) -> Result<Val, String> {
+    match arg {
+        WastArg::Core(WastArgCore::I32(v)) => Ok(Val::I32(*v)),
+        WastArg::Core(WastArgCore::I64(v)) => Ok(Val::I64(*v)),
+        WastArg::Core(WastArgCore::F32(v)) => Ok(Val::F32(v.bits)),
+        WastArg::Core(WastArgCore::F64(v)) => Ok(Val::F64(v.bits)),
+        WastArg::Core(WastArgCore::V128(v)) => Ok(Val::V128(u128::from_le_bytes(v.to_le_bytes()))),
+        WastArg::Core(WastArgCore::RefNull(HeapType::Abstract { ty, .. })) => match ty {
+            AbstractHeapType::Func | AbstractHeapType::NoFunc => Ok(Val::FuncRef(None)),
+            AbstractHeapType::Extern | AbstractHeapType::NoExtern => Ok(Val::ExternRef(None)),
+            AbstractHeapType::Any
+            | AbstractHeapType::Eq
+            | AbstractHeapType::I31
+            | AbstractHeapType::Struct
+            | AbstractHeapType::Array
+            | AbstractHeapType::None => Ok(Val::AnyRef(None)),
+            _ => Err(format!("unsupported argument {arg:?}")),
+        },
+        WastArg::Core(WastArgCore::RefExtern(v)) => Ok(Val::ExternRef(Some(externref(*v)?))),
+        _ => Err(format!("unsupported argument {arg:?}")),
+    }
+}
+
+/// Converts the value of a `ref.extern` in a script to an externref, externrefs are non-zero so
+/// the value is offset by one.
+fn externref(v: u32) -> Result<NonZeroU32, String> {
+    v.checked_add(1)
+        .and_then(NonZeroU32::new)
+        .ok_or_else(|| format!("unsupported externref {v}"))
+}
+
+fn match_results(actual: &[Val], expected: &[WastRet]) -> Result<(), String> {
+    if actual.len() != expected.len() {
+        return Err(format!(
+            "expected {} results, got {actual:?}",
+            expected.len()
+        ));
+    }
+
+    for (actual, expected) in actual.iter().zip(expected) {
+        let WastRet::Core(expected) = expected else {
+            return Err(format!("unsupported result {expected:?}"));
+        };
+
+        if !val_matches(actual, expected)? {
+            return Err(format!("expected {expected:?}, got {actual:?}"));
+        }
+    }
+
+    Ok(())
+}
+
+fn val_matches(actual: &Val, expected: &WastRetCore) -> Result<bool, String> {
+    Ok(match (actual, expected) {
+        (Val::I32(a), WastRetCore::I32(e)) => a == e,
+        (Val::I64(a), WastRetCore::I64(e)) => a == e,
+        (Val::F32(a), WastRetCore::F32(e)) => f32_matches(*a, e),
+        (Val::F64(a), WastRetCore::F64(e)) => f64_matches(*a, e),
+        (Val::V128(a), WastRetCore::V128(e)) => v128_matches(*a, e),
+        (Val::FuncRef(a), WastRetCore::RefNull(_)) => a.is_none(),
+        (Val::ExternRef(a), WastRetCore::RefNull(_)) => a.is_none(),
+        (Val::ExternRef(a), WastRetCore::RefExtern(e)) => match e {
+            Some(e) => *a == Some(externref(*e)?),
+            None => a.is_some(),
+        },
+        // the function index isn't checked since it has no meaning outside the module
+        (Val::FuncRef(a), WastRetCore::RefFunc(_)) => a.is_some(),
+        (Val::AnyRef(a), WastRetCore::RefNull(_)) => a.is_none(),
+        (Val::AnyRef(a), WastRetCore::RefAny | WastRetCore::RefEq) => a.is_some(),
+        (Val::AnyRef(a), WastRetCore::RefI31) => a.is_some_and(AnyRef::is_i31),
+        // the host can't tell structs and arrays apart without their heap
+        (Val::AnyRef(a), WastRetCore::RefStruct | WastRetCore::RefArray) => {
+            a.is_some_and(|a| !a.is_i31())
+        }
+        (_, WastRetCore::Either(options)) => {
+            for option in options {
+                if val_matches(actual, option)? {
+                    return Ok(true);
+                }
+            }
+            false
+        }
+        (
+            _,
+            WastRetCore::I32(_)
+            | WastRetCore::I64(_)
+            | WastRetCore::F32(_)
+            | WastRetCore::F64(_)
+            | WastRetCore::V128(_)
+            | WastRetCore::RefNull(_)
+            | WastRetCore::RefExtern(_)
+            | WastRetCore::RefFunc(_)
+            | WastRetCore::RefAny
+            | WastRetCore::RefEq
+            | WastRetCore::RefI31
+            | WastRetCore::RefStruct
+            | WastRetCore::RefArray,
+        ) => false,
+        _ => return Err(format!("unsupported result {expected:?}")),
+    })
+}
+
+fn f32_matches(actual: u32, expected: &NanPattern<F32>) -> bool {
+    match expected {
+        NanPattern::CanonicalNan => actual & 0x7fff_ffff == 0x7fc0_0000,
+        NanPattern::ArithmeticNan => actual & 0x7fc0_0000 == 0x7fc0_0000,
+        NanPattern::Value(expected) => actual == expected.bits,
+    }
+}
+
+fn f64_matches(actual: u64, expected: &NanPattern<F64>) -> bool {
+    match expected {
+        NanPattern::CanonicalNan => actual & 0x7fff_ffff_ffff_ffff == 0x7ff8_0000_0000_0000,
+        NanPattern::ArithmeticNan => actual & 0x7ff8_0000_0000_0000 == 0x7ff8_0000_0000_0000,
+        NanPattern::Value(expected) => actual == expected.bits,
+    }
+}
+
+#[allow(clippy::cast_sign_loss)]
+fn v128_matches(actual: u128, expected: &V128Pattern) -> bool {
+    let bytes = actual.to_le_bytes();
+
+    match expected {
+        V128Pattern::I8x16(lanes) => bytes.iter().zip(lanes).all(|(a, e)| *a == *e as u8),
+        V128Pattern::I16x8(lanes) => bytes
+            .chunks_exact(2)
+            .zip(lanes)
+            .all(|(a, e)| i16::from_le_bytes(a.try_into().unwrap()) == *e),
+        V128Pattern::I32x4(lanes) => bytes
+            .chunks_exact(4)
+            .zip(lanes)
+            .all(|(a, e)| i32::from_le_bytes(a.try_into().unwrap()) == *e),
+        V128Pattern::I64x2(lanes) => bytes
+            .chunks_exact(8)
+            .zip(lanes)
+            .all(|(a, e)| i64::from_le_bytes(a.try_into().unwrap()) == *e),
+        V128Pattern::F32x4(lanes) => bytes
+            .chunks_exact(4)
+            .zip(lanes)
+            .all(|(a, e)| f32_matches(u32::from_le_bytes(a.try_into().unwrap()), e)),
+        V128Pattern::F64x2(lanes) => bytes
+            .chunks_exact(8)
+            .zip(lanes)
+            .all(|(a, e)| f64_matches(u64::from_le_bytes(a.try_into().unwrap()), e)),
+    }
+}
+
+fn match_trap(outcome: Outcome, expected: &str) -> Result<(), String> {
+    let trap = match outcome {
+        Ok(results) => return Err(format!("expected trap `{expected}`, got {results:?}")),
+        Err(trap) => trap,
+    };
+
+    // Our trap messages mostly follow the ones used by the testsuite, these are the exceptions.
+    let spec_messages: &[&str] = match trap {
+        Trap::IntegerDivisionByZero => &["integer divide by zero"],
+        Trap::BadSignature => &["indirect call type mismatch"],
+        Trap::IndirectCallToNull => &["uninitialized element"],
+        Trap::TableOutOfBounds => &["undefined element"],
+        Trap::UnreachableCodeReached => &["unreachable"],
+        // the reference scripts name the kind of reference that was null
+        Trap::NullReference => &[
+            "null function reference",
+            "null structure reference",
+            "null array reference",
+        ],
+        _ => &[],
+    };
+
+    let actual = trap.to_string();
+    let matches = actual.contains(expected)
+        || spec_messages
+            .iter()
+            .any(|message| expected.starts_with(message));
+
+    if matches {
+        Ok(())
+    } else {
+        Err(format!("expected trap `{expected}`, got `{actual}`"))
+    }
+}
@@ -84,6 +84,14 @@ impl Mode for EmulateMode {
         Ok(())
     }
 
+    fn invalidate_asid(_asid: usize) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn max_asid() -> usize {
+        0
+    }
+
     fn get_active_table(_asid: usize) -> PhysicalAddress {
         PhysicalAddress(0)
     }
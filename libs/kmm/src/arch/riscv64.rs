@@ -51,6 +51,21 @@ fn invalidate_address_range(
     Ok(())
 }
 
+fn invalidate_asid(asid: usize) -> crate::Result<()> {
+    sfence_vma_asid(0, usize::MAX, 0, usize::MAX, asid)?;
+    Ok(())
+}
+
+/// Probes how many ASID bits the hart implements by writing all ones to the ASID field of `satp`
+/// and reading back what sticks.
+unsafe fn max_asid() -> usize {
+    let satp = satp::read();
+    satp::set(satp.mode(), 0xffff, satp.ppn());
+    let max_asid = satp::read().asid();
+    satp::set(satp.mode(), satp.asid(), satp.ppn());
+    max_asid
+}
+
 unsafe fn get_active_table() -> PhysicalAddress {
     let satp = satp::read();
     PhysicalAddress(satp.ppn() << 12)
@@ -84,6 +99,14 @@ impl Mode for Riscv64Sv39 {
         invalidate_address_range(asid, address_range)
     }
 
+    fn invalidate_asid(asid: usize) -> crate::Result<()> {
+        invalidate_asid(asid)
+    }
+
+    fn max_asid() -> usize {
+        unsafe { max_asid() }
+    }
+
     fn get_active_table(_asid: usize) -> PhysicalAddress {
         unsafe { get_active_table() }
     }
@@ -134,6 +157,14 @@ impl Mode for Riscv64Sv48 {
         invalidate_address_range(asid, address_range)
     }
 
+    fn invalidate_asid(asid: usize) -> crate::Result<()> {
+        invalidate_asid(asid)
+    }
+
+    fn max_asid() -> usize {
+        unsafe { max_asid() }
+    }
+
     fn get_active_table(_asid: usize) -> PhysicalAddress {
         unsafe { get_active_table() }
     }
@@ -184,6 +215,14 @@ impl Mode for Riscv64Sv57 {
         invalidate_address_range(asid, address_range)
     }
 
+    fn invalidate_asid(asid: usize) -> crate::Result<()> {
+        invalidate_asid(asid)
+    }
+
+    fn max_asid() -> usize {
+        unsafe { max_asid() }
+    }
+
     fn get_active_table(_asid: usize) -> PhysicalAddress {
         unsafe { get_active_table() }
     }
@@ -92,6 +92,17 @@ pub trait Mode {
     /// Should return an error if the underlying operation failed and the range could not be flushed.
     fn invalidate_range(asid: usize, address_range: Range<VirtualAddress>) -> Result<()>;
 
+    /// Invalidate all address translation caches of the given `address_space`
+    ///
+    /// # Errors
+    ///
+    /// Should return an error if the underlying operation failed.
+    fn invalidate_asid(asid: usize) -> Result<()>;
+
+    /// Returns the largest address space identifier supported by the hardware, 0 if address spaces
+    /// can't be told apart.
+    fn max_asid() -> usize;
+
     fn get_active_table(asid: usize) -> PhysicalAddress;
     fn activate_table(asid: usize, table: VirtualAddress);
 
@@ -83,6 +83,7 @@ pub(crate) fn crate_path(attrs: &mut Vec<Attribute>) -> syn::Result<Path> {
 #[derive(Debug)]
 struct ForEachFixtureInput {
     folder: PathBuf,
+    folder_span: proc_macro2::Span,
     macroo: Ident,
 }
 
@@ -93,6 +94,7 @@ impl Parse for ForEachFixtureInput {
         let macroo = input.parse()?;
         Ok(Self {
             folder: parse_path(&folder),
+            folder_span: folder.span(),
             macroo,
         })
     }
@@ -108,7 +110,17 @@ fn parse_path(path: &syn::LitStr) -> PathBuf {
 pub fn for_each_fixture(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as ForEachFixtureInput);
 
-    let folder = fs::read_dir(input.folder).unwrap();
+    // A missing folder must not silently generate zero tests
+    let folder = match fs::read_dir(&input.folder) {
+        Ok(folder) => folder,
+        Err(err) => {
+            let msg = format!(
+                "failed to read fixture folder `{}`: {err}",
+                input.folder.display()
+            );
+            return Error::new(input.folder_span, msg).to_compile_error().into();
+        }
+    };
 
     let cases = folder.filter_map(|entry| {
         let entry = entry.unwrap();
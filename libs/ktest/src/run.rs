@@ -8,7 +8,12 @@ use core::{fmt, hint, mem, slice};
 use dtb_parser::{DevTree, Node, Visitor};
 
 #[no_mangle]
-extern "Rust" fn kmain(_hartid: usize, boot_info: &'static loader_api::BootInfo) -> ! {
+extern "Rust" fn kmain(hartid: usize, boot_info: &'static loader_api::BootInfo) -> ! {
+    // Tests run on hart 0, all other harts help out with compiling WASM just like in the kernel
+    if hartid != 0 {
+        kernel::runtime::run_compile_worker();
+    }
+
     struct Log;
 
     impl fmt::Write for Log {
@@ -6,9 +6,11 @@ pub mod satp;
 pub mod scause;
 pub mod sepc;
 pub mod sie;
+pub mod sip;
 pub mod sstatus;
 pub mod stval;
 pub mod stvec;
+pub mod time;
 
 macro_rules! csr_base_and_read {
     ($ty_name: ident, $csr_name: literal) => {
@@ -81,4 +83,22 @@ macro_rules! csr_clear {
     };
 }
 
-pub(crate) use {csr_base_and_read, csr_clear, csr_write};
+macro_rules! csr_set {
+    ($csr_name: literal) => {
+        /// Sets the given bits in the CSR
+        #[inline]
+        #[allow(unused_variables)]
+        unsafe fn _set(bits: usize) {
+            cfg_if::cfg_if! {
+                if #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))] {
+                    let _csr_name: &str = $csr_name;
+                    ::core::arch::asm!(concat!("csrrs x0, ", $csr_name, ", {0}"), in(reg) bits)
+                } else {
+                    unimplemented!()
+                }
+            }
+        }
+    };
+}
+
+pub(crate) use {csr_base_and_read, csr_clear, csr_set, csr_write};
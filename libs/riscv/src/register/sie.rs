@@ -1,23 +1,23 @@
 //! Supervisor Interrupt Enable Register
 
-use super::{csr_base_and_read, csr_clear, csr_write};
+use super::{csr_base_and_read, csr_clear, csr_set};
 use core::fmt;
 use core::fmt::Formatter;
 
 csr_base_and_read!(Sie, "sie");
-csr_write!("sie");
+csr_set!("sie");
 csr_clear!("sie");
 
 pub unsafe fn set_ssie() {
-    _write(1 << 1);
+    _set(1 << 1);
 }
 
 pub unsafe fn set_stie() {
-    _write(1 << 5);
+    _set(1 << 5);
 }
 
 pub unsafe fn set_seie() {
-    _write(1 << 9);
+    _set(1 << 9);
 }
 
 pub unsafe fn clear_ssie() {
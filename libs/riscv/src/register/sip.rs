@@ -0,0 +1,39 @@
+//! Supervisor Interrupt Pending Register
+
+use super::{csr_base_and_read, csr_clear};
+use core::fmt;
+use core::fmt::Formatter;
+
+csr_base_and_read!(Sip, "sip");
+csr_clear!("sip");
+
+pub unsafe fn clear_ssip() {
+    _clear(1 << 1);
+}
+
+impl Sip {
+    #[must_use]
+    pub fn ssip(&self) -> bool {
+        self.bits & (1 << 1) != 0
+    }
+
+    #[must_use]
+    pub fn stip(&self) -> bool {
+        self.bits & (1 << 5) != 0
+    }
+
+    #[must_use]
+    pub fn seip(&self) -> bool {
+        self.bits & (1 << 9) != 0
+    }
+}
+
+impl fmt::Debug for Sip {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sip")
+            .field("ssip", &self.ssip())
+            .field("stip", &self.stip())
+            .field("seip", &self.seip())
+            .finish()
+    }
+}
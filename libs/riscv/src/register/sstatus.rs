@@ -1,16 +1,17 @@
 //! Supervisor Status Register
 
-use super::{csr_base_and_read, csr_clear, csr_write};
+use super::{csr_base_and_read, csr_clear, csr_set, csr_write};
 use core::fmt;
 use core::fmt::Formatter;
 
 csr_base_and_read!(Sstatus, "sstatus");
 csr_write!("sstatus");
 csr_clear!("sstatus");
+csr_set!("sstatus");
 
 /// Supervisor Interrupt Enable
 pub unsafe fn set_sie() {
-    _write(1 << 1);
+    _set(1 << 1);
 }
 
 /// Supervisor Interrupt Enable
@@ -20,18 +21,28 @@ pub unsafe fn clear_sie() {
 
 /// Supervisor Previous Interrupt Enable
 pub unsafe fn set_spie() {
-    _write(1 << 5);
+    _set(1 << 5);
 }
 
 /// Supervisor Previous Privilege Mode
 #[inline]
 pub unsafe fn set_spp(spp: SPP) {
     match spp {
-        SPP::Supervisor => _write(1 << 8),
+        SPP::Supervisor => _set(1 << 8),
         SPP::User => _clear(1 << 8),
     }
 }
 
+/// Permit Supervisor User Memory access
+pub unsafe fn set_sum() {
+    _set(1 << 18);
+}
+
+/// Permit Supervisor User Memory access
+pub unsafe fn clear_sum() {
+    _clear(1 << 18);
+}
+
 /// Floating-Point Unit Status
 pub unsafe fn set_fs(fs: FS) {
     let mut value = read().bits;
@@ -40,6 +51,16 @@ pub unsafe fn set_fs(fs: FS) {
     _write(value);
 }
 
+/// Vector Unit Status
+///
+/// The field is hardwired to zero if the vector extension isn't implemented.
+pub unsafe fn set_vs(vs: FS) {
+    let mut value = read().bits;
+    value &= !(0x3 << 9); // clear previous value
+    value |= (vs as usize) << 9;
+    _write(value);
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum SPP {
     Supervisor = 1,
@@ -107,7 +128,7 @@ impl Sstatus {
     #[inline]
     #[must_use]
     pub fn vs(&self) -> FS {
-        let fs = (self.bits >> 9) & 0x3; // bits 13-14
+        let fs = (self.bits >> 9) & 0x3; // bits 9-10
         match fs {
             0 => FS::Off,
             1 => FS::Initial,
@@ -0,0 +1,15 @@
+//! IPI Extension
+
+use super::{sbi_call, EID_IPI};
+
+/// Sends a supervisor software interrupt to the harts in `hart_mask`, which starts at hart
+/// `hart_mask_base`. A `hart_mask_base` of `usize::MAX` targets all harts.
+///
+/// # Errors
+///
+/// Returns an error if the SBI call fails.
+#[inline]
+pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> super::Result<()> {
+    sbi_call!(ext: EID_IPI, func: 0, "a0": hart_mask, "a1": hart_mask_base)?;
+    Ok(())
+}
@@ -11,6 +11,7 @@ pub mod base;
 pub mod dbcn;
 mod error;
 pub mod hsm;
+pub mod ipi;
 pub mod rfence;
 pub mod time;
 
@@ -18,6 +19,7 @@ pub use error::Error;
 
 const EID_BASE: usize = 0x10;
 const EID_HSM: usize = 0x0048_534D;
+const EID_IPI: usize = 0x0073_5049;
 const EID_TIME: usize = 0x5449_4D45;
 const EID_RFENCE: usize = 0x5246_4E43;
 const EID_DBCN: usize = 0x4442_434E;
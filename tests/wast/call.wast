@@ -0,0 +1,47 @@
+;; Direct and indirect calls, recursion and imports between modules.
+
+(module $lib
+  (func (export "double") (param i32) (result i32) (i32.mul (local.get 0) (i32.const 2)))
+  (global (export "answer") i32 (i32.const 42))
+)
+(register "lib" $lib)
+
+(module
+  (import "lib" "double" (func $double (param i32) (result i32)))
+  (import "lib" "answer" (global $answer i32))
+
+  (type $unary (func (param i32) (result i32)))
+  (type $nullary (func (result i32)))
+  (table 3 funcref)
+  (elem (i32.const 0) $double $answer)
+
+  (func $answer (type $nullary) (global.get $answer))
+  (func $fac (export "fac") (param i64) (result i64)
+    (if (result i64) (i64.eqz (local.get 0))
+      (then (i64.const 1))
+      (else (i64.mul (local.get 0) (call $fac (i64.sub (local.get 0) (i64.const 1)))))))
+  (func (export "quadruple") (param i32) (result i32)
+    (call $double (call $double (local.get 0))))
+  (func (export "call_indirect") (param i32 i32) (result i32)
+    (call_indirect (type $unary) (local.get 0) (local.get 1)))
+  (func (export "unreachable") (unreachable))
+  (func $loop (export "loop") (call $loop))
+)
+
+(assert_return (invoke "fac" (i64.const 20)) (i64.const 2432902008176640000))
+(assert_return (invoke "quadruple" (i32.const 5)) (i32.const 20))
+(assert_return (invoke "call_indirect" (i32.const 21) (i32.const 0)) (i32.const 42))
+(assert_trap (invoke "call_indirect" (i32.const 0) (i32.const 1)) "indirect call type mismatch")
+(assert_trap (invoke "call_indirect" (i32.const 0) (i32.const 2)) "uninitialized element")
+(assert_trap (invoke "call_indirect" (i32.const 0) (i32.const 3)) "undefined element")
+(assert_trap (invoke "unreachable") "unreachable")
+(assert_exhaustion (invoke "loop") "call stack exhausted")
+
+(assert_unlinkable
+  (module (import "lib" "missing" (func)))
+  "unknown import"
+)
+(assert_unlinkable
+  (module (import "lib" "double" (func (param i64))))
+  "incompatible import type"
+)
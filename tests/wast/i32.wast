@@ -0,0 +1,58 @@
+;; Integer arithmetic, comparisons and the traps they can raise.
+
+(module
+  (func (export "add") (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1)))
+  (func (export "sub") (param i32 i32) (result i32) (i32.sub (local.get 0) (local.get 1)))
+  (func (export "mul") (param i32 i32) (result i32) (i32.mul (local.get 0) (local.get 1)))
+  (func (export "div_s") (param i32 i32) (result i32) (i32.div_s (local.get 0) (local.get 1)))
+  (func (export "div_u") (param i32 i32) (result i32) (i32.div_u (local.get 0) (local.get 1)))
+  (func (export "rem_s") (param i32 i32) (result i32) (i32.rem_s (local.get 0) (local.get 1)))
+  (func (export "shr_s") (param i32 i32) (result i32) (i32.shr_s (local.get 0) (local.get 1)))
+  (func (export "rotl") (param i32 i32) (result i32) (i32.rotl (local.get 0) (local.get 1)))
+  (func (export "clz") (param i32) (result i32) (i32.clz (local.get 0)))
+  (func (export "popcnt") (param i32) (result i32) (i32.popcnt (local.get 0)))
+  (func (export "lt_s") (param i32 i32) (result i32) (i32.lt_s (local.get 0) (local.get 1)))
+  (func (export "lt_u") (param i32 i32) (result i32) (i32.lt_u (local.get 0) (local.get 1)))
+  (func (export "extend8_s") (param i32) (result i32) (i32.extend8_s (local.get 0)))
+  (func (export "wrap") (param i64) (result i32) (i32.wrap_i64 (local.get 0)))
+  (func (export "trunc_f32_s") (param f32) (result i32) (i32.trunc_f32_s (local.get 0)))
+  (func (export "trunc_sat_f32_s") (param f32) (result i32) (i32.trunc_sat_f32_s (local.get 0)))
+)
+
+(assert_return (invoke "add" (i32.const 1) (i32.const 1)) (i32.const 2))
+(assert_return (invoke "add" (i32.const 0x7fffffff) (i32.const 1)) (i32.const 0x80000000))
+(assert_return (invoke "sub" (i32.const 0) (i32.const 1)) (i32.const -1))
+(assert_return (invoke "mul" (i32.const 0x10000) (i32.const 0x10000)) (i32.const 0))
+(assert_return (invoke "mul" (i32.const -7) (i32.const 6)) (i32.const -42))
+
+(assert_return (invoke "div_s" (i32.const -7) (i32.const 2)) (i32.const -3))
+(assert_return (invoke "div_u" (i32.const -7) (i32.const 2)) (i32.const 0x7ffffffc))
+(assert_trap (invoke "div_s" (i32.const 1) (i32.const 0)) "integer divide by zero")
+(assert_trap (invoke "div_u" (i32.const 1) (i32.const 0)) "integer divide by zero")
+(assert_trap (invoke "div_s" (i32.const 0x80000000) (i32.const -1)) "integer overflow")
+(assert_return (invoke "rem_s" (i32.const 0x80000000) (i32.const -1)) (i32.const 0))
+(assert_return (invoke "rem_s" (i32.const -7) (i32.const 2)) (i32.const -1))
+(assert_trap (invoke "rem_s" (i32.const 1) (i32.const 0)) "integer divide by zero")
+
+(assert_return (invoke "shr_s" (i32.const -8) (i32.const 1)) (i32.const -4))
+(assert_return (invoke "shr_s" (i32.const -8) (i32.const 33)) (i32.const -4))
+(assert_return (invoke "rotl" (i32.const 0x80000001) (i32.const 1)) (i32.const 3))
+(assert_return (invoke "clz" (i32.const 0)) (i32.const 32))
+(assert_return (invoke "clz" (i32.const 0x00008000)) (i32.const 16))
+(assert_return (invoke "popcnt" (i32.const -1)) (i32.const 32))
+
+(assert_return (invoke "lt_s" (i32.const -1) (i32.const 1)) (i32.const 1))
+(assert_return (invoke "lt_u" (i32.const -1) (i32.const 1)) (i32.const 0))
+(assert_return (invoke "extend8_s" (i32.const 0x80)) (i32.const -128))
+(assert_return (invoke "wrap" (i64.const 0x1_0000_0005)) (i32.const 5))
+
+(assert_return (invoke "trunc_f32_s" (f32.const -1.9)) (i32.const -1))
+(assert_trap (invoke "trunc_f32_s" (f32.const nan)) "invalid conversion to integer")
+(assert_trap (invoke "trunc_f32_s" (f32.const 2147483648.0)) "integer overflow")
+(assert_return (invoke "trunc_sat_f32_s" (f32.const 2147483648.0)) (i32.const 0x7fffffff))
+(assert_return (invoke "trunc_sat_f32_s" (f32.const nan)) (i32.const 0))
+
+(assert_invalid
+  (module (func (result i32) (i32.add (i32.const 0) (i64.const 0))))
+  "type mismatch"
+)
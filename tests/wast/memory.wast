@@ -0,0 +1,71 @@
+;; Linear memory accesses, bounds checks, growth and data segments.
+
+(module
+  (memory 1 3)
+  (data (i32.const 0) "\01\02\03\04")
+  (data (i32.const 0xfffc) "\aa\bb\cc\dd")
+
+  (func (export "load8_u") (param i32) (result i32) (i32.load8_u (local.get 0)))
+  (func (export "load8_s") (param i32) (result i32) (i32.load8_s (local.get 0)))
+  (func (export "load") (param i32) (result i32) (i32.load (local.get 0)))
+  (func (export "load_offset") (param i32) (result i32) (i32.load offset=4 (local.get 0)))
+  (func (export "load64") (param i32) (result i64) (i64.load (local.get 0)))
+  (func (export "store") (param i32 i32) (i32.store (local.get 0) (local.get 1)))
+  (func (export "size") (result i32) (memory.size))
+  (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+  (func (export "fill") (param i32 i32 i32)
+    (memory.fill (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "copy") (param i32 i32 i32)
+    (memory.copy (local.get 0) (local.get 1) (local.get 2)))
+)
+
+(assert_return (invoke "load" (i32.const 0)) (i32.const 0x04030201))
+(assert_return (invoke "load8_u" (i32.const 0xfffc)) (i32.const 0xaa))
+(assert_return (invoke "load8_s" (i32.const 0xfffc)) (i32.const -86))
+(assert_return (invoke "load" (i32.const 0xfffc)) (i32.const 0xddccbbaa))
+(assert_return (invoke "load64" (i32.const 0)) (i64.const 0x04030201))
+
+;; accesses straddling or beyond the end of memory trap
+(assert_trap (invoke "load" (i32.const 0xfffd)) "out of bounds memory access")
+(assert_trap (invoke "load" (i32.const 0x10000)) "out of bounds memory access")
+(assert_trap (invoke "load" (i32.const -1)) "out of bounds memory access")
+(assert_trap (invoke "load_offset" (i32.const 0xfffc)) "out of bounds memory access")
+(assert_trap (invoke "load_offset" (i32.const -4)) "out of bounds memory access")
+(assert_trap (invoke "store" (i32.const 0xfffd) (i32.const 0)) "out of bounds memory access")
+
+(assert_return (invoke "store" (i32.const 8) (i32.const 0x12345678)))
+(assert_return (invoke "load8_u" (i32.const 8)) (i32.const 0x78))
+(assert_return (invoke "load_offset" (i32.const 4)) (i32.const 0x12345678))
+
+;; growing makes the new pages accessible and zeroed, up to the maximum
+(assert_return (invoke "size") (i32.const 1))
+(assert_return (invoke "grow" (i32.const 1)) (i32.const 1))
+(assert_return (invoke "size") (i32.const 2))
+(assert_return (invoke "load" (i32.const 0x10000)) (i32.const 0))
+(assert_return (invoke "load" (i32.const 0xfffc)) (i32.const 0xddccbbaa))
+(assert_return (invoke "grow" (i32.const 0)) (i32.const 2))
+(assert_return (invoke "grow" (i32.const 2)) (i32.const -1))
+(assert_return (invoke "grow" (i32.const 1)) (i32.const 2))
+(assert_return (invoke "size") (i32.const 3))
+(assert_trap (invoke "load" (i32.const 0x30000)) "out of bounds memory access")
+
+;; bulk memory operations are bounds checked as a whole
+(assert_return (invoke "fill" (i32.const 16) (i32.const 0xff) (i32.const 4)))
+(assert_return (invoke "load" (i32.const 16)) (i32.const -1))
+(assert_return (invoke "copy" (i32.const 20) (i32.const 0) (i32.const 4)))
+(assert_return (invoke "load" (i32.const 20)) (i32.const 0x04030201))
+(assert_trap (invoke "fill" (i32.const 0x2fffe) (i32.const 0) (i32.const 4))
+  "out of bounds memory access")
+(assert_trap (invoke "copy" (i32.const 0) (i32.const 0x2fffe) (i32.const 4))
+  "out of bounds memory access")
+
+;; data segments that don't fit fail instantiation
+(assert_trap
+  (module (memory 1) (data (i32.const 0xffff) "\00\00"))
+  "out of bounds memory access"
+)
+
+(assert_invalid
+  (module (func (drop (i32.load (i32.const 0)))))
+  "unknown memory"
+)
@@ -0,0 +1,80 @@
+;; 64-bit memories: i64 addresses, explicit bounds checks and i64 results of memory.grow.
+
+(module
+  (memory i64 1 4)
+  (data (i64.const 0) "\01\02\03\04")
+  (data (i64.const 0xfffc) "\aa\bb\cc\dd")
+
+  (func (export "load") (param i64) (result i32) (i32.load (local.get 0)))
+  (func (export "load_offset") (param i64) (result i32)
+    (i32.load offset=0x1_0000_0000 (local.get 0)))
+  (func (export "store") (param i64 i32) (i32.store (local.get 0) (local.get 1)))
+  (func (export "size") (result i64) (memory.size))
+  (func (export "grow") (param i64) (result i64) (memory.grow (local.get 0)))
+  (func (export "fill") (param i64 i32 i64)
+    (memory.fill (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "copy") (param i64 i64 i64)
+    (memory.copy (local.get 0) (local.get 1) (local.get 2)))
+)
+
+(assert_return (invoke "load" (i64.const 0)) (i32.const 0x04030201))
+(assert_return (invoke "load" (i64.const 0xfffc)) (i32.const 0xddccbbaa))
+
+;; addresses aren't truncated to 32 bits, guard pages can't cover them
+(assert_trap (invoke "load" (i64.const 0xfffd)) "out of bounds memory access")
+(assert_trap (invoke "load" (i64.const 0x1_0000_0000)) "out of bounds memory access")
+(assert_trap (invoke "load" (i64.const 0x8000_0000_0000_0000)) "out of bounds memory access")
+(assert_trap (invoke "load" (i64.const -1)) "out of bounds memory access")
+(assert_trap (invoke "load" (i64.const -4)) "out of bounds memory access")
+(assert_trap (invoke "load_offset" (i64.const 0)) "out of bounds memory access")
+(assert_trap (invoke "load_offset" (i64.const -0x1_0000_0000)) "out of bounds memory access")
+(assert_trap (invoke "store" (i64.const 0x1_0000_0000) (i32.const 0)) "out of bounds memory access")
+
+(assert_return (invoke "size") (i64.const 1))
+(assert_return (invoke "grow" (i64.const 1)) (i64.const 1))
+(assert_return (invoke "size") (i64.const 2))
+(assert_return (invoke "load" (i64.const 0x1fffc)) (i32.const 0))
+(assert_return (invoke "grow" (i64.const 3)) (i64.const -1))
+(assert_return (invoke "grow" (i64.const 0x1_0000_0000)) (i64.const -1))
+(assert_return (invoke "grow" (i64.const -1)) (i64.const -1))
+(assert_return (invoke "size") (i64.const 2))
+
+(assert_return (invoke "fill" (i64.const 16) (i32.const 0xff) (i64.const 4)))
+(assert_return (invoke "load" (i64.const 16)) (i32.const -1))
+(assert_return (invoke "copy" (i64.const 20) (i64.const 0) (i64.const 4)))
+(assert_return (invoke "load" (i64.const 20)) (i32.const 0x04030201))
+(assert_trap (invoke "fill" (i64.const 0x1fffe) (i32.const 0) (i64.const 4))
+  "out of bounds memory access")
+(assert_trap (invoke "copy" (i64.const 0) (i64.const 0) (i64.const 0x1_0000_0000))
+  "out of bounds memory access")
+
+;; data segments of 64-bit memories take i64 offsets, also from globals
+(module
+  (global i64 (i64.const 0x10))
+  (memory i64 1)
+  (data (global.get 0) "\2a")
+  (func (export "load8") (param i64) (result i32) (i32.load8_u (local.get 0)))
+)
+(assert_return (invoke "load8" (i64.const 0x10)) (i32.const 42))
+
+(assert_trap
+  (module (memory i64 1) (data (i64.const 0x1_0000_0000) "\00"))
+  "out of bounds memory access"
+)
+
+;; memory64 can be mixed with 32-bit memories, but their types don't match
+(module $m64 (memory (export "m") i64 1))
+(register "m64" $m64)
+(assert_unlinkable
+  (module (import "m64" "m" (memory 1)))
+  "incompatible import type"
+)
+
+(assert_invalid
+  (module (memory i64 1) (func (drop (i32.load (i32.const 0)))))
+  "type mismatch"
+)
+(assert_invalid
+  (module (memory i64 1) (func (result i32) (memory.size)))
+  "type mismatch"
+)
@@ -0,0 +1,73 @@
+;; Multiple memories: each memory is bounds checked, grown and initialized on its own.
+
+(module $lib
+  (memory (export "mem") 1)
+  (data (i32.const 0) "\2a")
+  (func (export "load8") (param i32) (result i32) (i32.load8_u (local.get 0)))
+)
+(register "lib" $lib)
+
+(module
+  (import "lib" "mem" (memory $imported 1))
+  (memory $small 1 2)
+  (memory $large 2)
+  (data (memory $small) (i32.const 0) "\01\02\03\04")
+  (data (memory $large) (i32.const 0x1fffc) "\aa\bb\cc\dd")
+
+  (func (export "load_imported") (param i32) (result i32)
+    (i32.load8_u $imported (local.get 0)))
+  (func (export "store_imported") (param i32 i32)
+    (i32.store8 $imported (local.get 0) (local.get 1)))
+  (func (export "load_small") (param i32) (result i32) (i32.load $small (local.get 0)))
+  (func (export "load_large") (param i32) (result i32) (i32.load $large (local.get 0)))
+  (func (export "size_small") (result i32) (memory.size $small))
+  (func (export "size_large") (result i32) (memory.size $large))
+  (func (export "grow_small") (param i32) (result i32) (memory.grow $small (local.get 0)))
+  (func (export "copy_small_to_large") (param i32 i32 i32)
+    (memory.copy $large $small (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "fill_large") (param i32 i32 i32)
+    (memory.fill $large (local.get 0) (local.get 1) (local.get 2)))
+)
+
+(assert_return (invoke "load_imported" (i32.const 0)) (i32.const 42))
+(assert_return (invoke "store_imported" (i32.const 1) (i32.const 7)))
+(assert_return (invoke $lib "load8" (i32.const 1)) (i32.const 7))
+
+(assert_return (invoke "load_small" (i32.const 0)) (i32.const 0x04030201))
+(assert_return (invoke "load_large" (i32.const 0)) (i32.const 0))
+(assert_return (invoke "load_large" (i32.const 0x1fffc)) (i32.const 0xddccbbaa))
+(assert_trap (invoke "load_small" (i32.const 0xfffd)) "out of bounds memory access")
+(assert_trap (invoke "load_large" (i32.const 0x1fffd)) "out of bounds memory access")
+
+;; growing one memory leaves the others alone
+(assert_return (invoke "size_small") (i32.const 1))
+(assert_return (invoke "size_large") (i32.const 2))
+(assert_return (invoke "grow_small" (i32.const 1)) (i32.const 1))
+(assert_return (invoke "grow_small" (i32.const 1)) (i32.const -1))
+(assert_return (invoke "size_small") (i32.const 2))
+(assert_return (invoke "size_large") (i32.const 2))
+(assert_return (invoke "load_small" (i32.const 0x1fffc)) (i32.const 0))
+
+;; bulk operations are checked against the bounds of the memory they touch
+(assert_return (invoke "copy_small_to_large" (i32.const 0x100) (i32.const 0) (i32.const 4)))
+(assert_return (invoke "load_large" (i32.const 0x100)) (i32.const 0x04030201))
+(assert_return (invoke "fill_large" (i32.const 0x1fff0) (i32.const 0xff) (i32.const 4)))
+(assert_return (invoke "load_large" (i32.const 0x1fff0)) (i32.const -1))
+(assert_trap (invoke "copy_small_to_large" (i32.const 0x1fffe) (i32.const 0) (i32.const 4))
+  "out of bounds memory access")
+(assert_trap (invoke "fill_large" (i32.const 0x20000) (i32.const 0) (i32.const 1))
+  "out of bounds memory access")
+
+;; data segments are bounds checked against their own memory
+(assert_trap
+  (module
+    (memory 2)
+    (memory 1)
+    (data (memory 1) (i32.const 0x10000) "\00"))
+  "out of bounds memory access"
+)
+
+(assert_invalid
+  (module (memory 1) (func (drop (i32.load 1 (i32.const 0)))))
+  "unknown memory"
+)
@@ -0,0 +1,77 @@
+;; Tail calls: deep mutual recursion must run in constant stack space.
+
+(module $lib
+  (func (export "id") (param i64) (result i64) (local.get 0))
+)
+(register "lib" $lib)
+
+(module
+  (import "lib" "id" (func $id (param i64) (result i64)))
+
+  (type $step (func (param i64 i64) (result i64)))
+  (table funcref (elem $count_indirect $id_step))
+
+  (func $count (export "count") (param i64 i64) (result i64)
+    (if (result i64) (i64.eqz (local.get 0))
+      (then (local.get 1))
+      (else
+        (return_call $count
+          (i64.sub (local.get 0) (i64.const 1))
+          (i64.add (local.get 1) (i64.const 1))))))
+
+  (func $even (export "even") (param i64) (result i32)
+    (if (result i32) (i64.eqz (local.get 0))
+      (then (i32.const 1))
+      (else (return_call $odd (i64.sub (local.get 0) (i64.const 1))))))
+  (func $odd (export "odd") (param i64) (result i32)
+    (if (result i32) (i64.eqz (local.get 0))
+      (then (i32.const 0))
+      (else (return_call $even (i64.sub (local.get 0) (i64.const 1))))))
+
+  (func $count_indirect (type $step)
+    (if (result i64) (i64.eqz (local.get 0))
+      (then (local.get 1))
+      (else
+        (return_call_indirect (type $step)
+          (i64.sub (local.get 0) (i64.const 1))
+          (i64.add (local.get 1) (i64.const 1))
+          (i32.const 0)))))
+  (func $id_step (type $step) (return_call $id (local.get 1)))
+
+  (func (export "count_indirect") (param i64 i64 i32) (result i64)
+    (return_call_indirect (type $step) (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "bad_signature") (result i32)
+    (return_call_indirect (param i32) (result i32) (i32.const 0) (i32.const 0)))
+
+  (func $count_plain (export "count_plain") (param i64) (result i64)
+    (if (result i64) (i64.eqz (local.get 0))
+      (then (i64.const 0))
+      (else (call $count_plain (i64.sub (local.get 0) (i64.const 1))))))
+)
+
+(assert_return (invoke "count" (i64.const 0) (i64.const 7)) (i64.const 7))
+(assert_return (invoke "count" (i64.const 1_000_000) (i64.const 0)) (i64.const 1_000_000))
+(assert_return (invoke "even" (i64.const 1_000_001)) (i32.const 0))
+(assert_return (invoke "odd" (i64.const 1_000_001)) (i32.const 1))
+
+(assert_return
+  (invoke "count_indirect" (i64.const 1_000_000) (i64.const 0) (i32.const 0))
+  (i64.const 1_000_000))
+;; tail calls through a local function into an import
+(assert_return
+  (invoke "count_indirect" (i64.const 0) (i64.const 5) (i32.const 1))
+  (i64.const 5))
+(assert_trap
+  (invoke "count_indirect" (i64.const 0) (i64.const 0) (i32.const 2))
+  "undefined element")
+(assert_trap (invoke "bad_signature") "indirect call type mismatch")
+
+;; the same recursion without tail calls still overflows the stack
+(assert_exhaustion (invoke "count_plain" (i64.const 1_000_000)) "call stack exhausted")
+
+(assert_invalid
+  (module
+    (func $f (result i32) (i32.const 0))
+    (func (result i64) (return_call $f)))
+  "type mismatch"
+)
@@ -0,0 +1,158 @@
+//! Host-side tool that precompiles WebAssembly modules into k23 module objects.
+//!
+//! The produced object is the same artifact the kernel's `Module::serialize` returns, so it can be
+//! shipped in the boot image and loaded with `Module::deserialize` without compiling it at boot.
+//! The tool is built from the kernel's own translation and compilation code, only the parts of the
+//! runtime that depend on kernel services are left out.
+
+extern crate alloc;
+
+/// The kernel configuration values the shared runtime code depends on.
+///
+/// These must be kept in sync with `kernel/src/kconfig.rs`.
+mod kconfig {
+    /// The size of a page on the target.
+    pub const PAGE_SIZE: usize = 4096;
+    /// The size of the stack WASM runs on in pages
+    pub const GUEST_STACK_SIZE_PAGES: usize = 64;
+}
+
+#[path = "../../../kernel/src/runtime/mod.rs"]
+mod runtime;
+
+use runtime::{compile_module, Config, Engine, OptLevel};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+use target_lexicon::Triple;
+
+/// The target the kernel runs on.
+const DEFAULT_TARGET: &str = "riscv64gc-unknown-none-elf";
+
+const USAGE: &str = "\
+Usage: k23-compile [OPTIONS] <INPUT>
+
+Precompiles the WebAssembly module <INPUT> into a k23 module object.
+
+Options:
+  -o, --output <PATH>          Where to write the object [default: <INPUT> with `.k23` extension]
+      --target <TRIPLE>        The target to generate code for [default: riscv64gc-unknown-none-elf]
+  -O, --opt-level <LEVEL>      One of `none`, `speed` or `speed_and_size` [default: speed_and_size]
+      --no-spectre-mitigations Disable Spectre mitigations for heap and table accesses
+      --debug-assertions       Emit runtime checks of the runtime's invariants
+      --no-debug-assertions    Don't emit runtime checks of the runtime's invariants
+      --consume-fuel           Meter execution with fuel
+      --epoch-interruption     Check the epoch deadline in loops and on function entry
+      --riscv-vector           Target harts with the vector extension, this enables SIMD
+  -h, --help                   Print this help
+
+Code generation options must match the configuration of the engine loading the object.";
+
+struct Args {
+    input: PathBuf,
+    output: PathBuf,
+    config: Config,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut config = Config::default();
+    config.target(Triple::from_str(DEFAULT_TARGET).unwrap());
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("missing value for `{name}`"))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => output = Some(PathBuf::from(value(&arg)?)),
+            "--target" => {
+                let target = value(&arg)?;
+                let triple = Triple::from_str(&target)
+                    .map_err(|err| format!("invalid target `{target}`: {err}"))?;
+                config.target(triple);
+            }
+            "-O" | "--opt-level" => {
+                let level = match value(&arg)?.as_str() {
+                    "none" => OptLevel::None,
+                    "speed" => OptLevel::Speed,
+                    "speed_and_size" => OptLevel::SpeedAndSize,
+                    level => return Err(format!("invalid optimization level `{level}`")),
+                };
+                config.opt_level(level);
+            }
+            "--no-spectre-mitigations" => {
+                config.spectre_mitigations(false);
+            }
+            "--debug-assertions" => {
+                config.debug_assertions(true);
+            }
+            "--no-debug-assertions" => {
+                config.debug_assertions(false);
+            }
+            "--consume-fuel" => {
+                config.consume_fuel(true);
+            }
+            "--epoch-interruption" => {
+                config.epoch_interruption(true);
+            }
+            "--riscv-vector" => {
+                config
+                    .riscv_vector(true)
+                    .wasm_simd(true)
+                    .wasm_relaxed_simd(true);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+            _ if input.is_none() => input = Some(PathBuf::from(&arg)),
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+
+    let input = input.ok_or("missing input file")?;
+    let output = output.unwrap_or_else(|| input.with_extension("k23"));
+
+    Ok(Some(Args {
+        input,
+        output,
+        config,
+    }))
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let engine = Engine::new(&args.config).map_err(|err| err.to_string())?;
+
+    let wasm = std::fs::read(&args.input)
+        .map_err(|err| format!("failed to read `{}`: {err}", args.input.display()))?;
+
+    let mut artifact = Vec::new();
+    compile_module(&engine, &wasm, &mut artifact)
+        .map_err(|err| format!("failed to compile `{}`: {err}", args.input.display()))?;
+
+    std::fs::write(&args.output, &artifact)
+        .map_err(|err| format!("failed to write `{}`: {err}", args.output.display()))
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}